@@ -0,0 +1,105 @@
+use std::{fs, path::Path};
+
+use pulldown_cmark::{CowStr, Event, Tag};
+
+use crate::Frontmatter;
+
+/// What a page says about one of its images' alt text.
+enum Alt {
+    Text(String),
+    /// Purely decorative, so it needs none
+    Decorative,
+}
+
+// `alt.<src>` in the frontmatter, or `alt` (or `decorative = true`) in
+// `<image>.toml` next to the image
+fn lookup(
+    src: &str,
+    frontmatter: &Frontmatter,
+    page_dir: &Path,
+    content_dir: &Path,
+) -> Option<Alt> {
+    if let Some(alt) = frontmatter.0.get(&format!("alt.{src}")) {
+        let alt = alt.trim();
+        return Some(if alt.is_empty() {
+            Alt::Decorative
+        } else {
+            Alt::Text(alt.to_string())
+        });
+    }
+
+    if src.contains("://") {
+        return None;
+    }
+    let image = match src.strip_prefix('/') {
+        Some(src) => content_dir.join(src),
+        None => page_dir.join(src),
+    };
+    let mut sidecar = image.into_os_string();
+    sidecar.push(".toml");
+    let sidecar = fs::read_to_string(sidecar).ok()?;
+    let sidecar: toml::Table = toml::from_str(&sidecar).ok()?;
+
+    if sidecar.get("decorative").and_then(|v| v.as_bool()) == Some(true) {
+        return Some(Alt::Decorative);
+    }
+    sidecar
+        .get("alt")
+        .and_then(|v| v.as_str())
+        .map(|alt| Alt::Text(alt.to_string()))
+}
+
+/// Fills in the alt text of images that have none in the markdown from the
+/// frontmatter or the image's sidecar, returning the sources of images
+/// still without any.
+pub fn fill<'a>(
+    events: Vec<Event<'a>>,
+    frontmatter: &Frontmatter,
+    page_dir: &Path,
+    content_dir: &Path,
+) -> (Vec<Event<'a>>, Vec<String>) {
+    let mut filled = Vec::with_capacity(events.len());
+    let mut missing = Vec::new();
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let src = match &event {
+            Event::Start(Tag::Image(_, src, _)) => src.to_string(),
+            _ => {
+                filled.push(event);
+                continue;
+            }
+        };
+
+        let mut inner = Vec::new();
+        let mut end = None;
+        for event in events.by_ref() {
+            match event {
+                Event::End(Tag::Image(..)) => {
+                    end = Some(event);
+                    break;
+                }
+                event => inner.push(event),
+            }
+        }
+
+        let has_alt = inner.iter().any(|event| match event {
+            Event::Text(text) | Event::Code(text) => !text.trim().is_empty(),
+            _ => false,
+        });
+
+        filled.push(event);
+        if has_alt {
+            filled.extend(inner);
+        } else {
+            match lookup(&src, frontmatter, page_dir, content_dir) {
+                Some(Alt::Text(alt)) => filled.push(Event::Text(CowStr::from(alt))),
+                Some(Alt::Decorative) => {}
+                None => missing.push(src),
+            }
+        }
+        filled.extend(end);
+    }
+
+    (filled, missing)
+}
@@ -0,0 +1,326 @@
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag};
+
+// the 16 standard terminal colours, normal then bright
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+#[derive(Default, Clone, PartialEq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    fg: Option<String>,
+    bg: Option<String>,
+}
+
+impl Style {
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if self.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity:0.7".to_string());
+        }
+        if self.italic {
+            css.push("font-style:italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration:underline".to_string());
+        }
+        if let Some(fg) = &self.fg {
+            css.push(format!("color:{fg}"));
+        }
+        if let Some(bg) = &self.bg {
+            css.push(format!("background-color:{bg}"));
+        }
+        css.join(";")
+    }
+
+    // applies the parameters of one `ESC[...m` sequence
+    fn apply(&mut self, params: &[u32]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                n @ 30..=37 => self.fg = Some(PALETTE[(n - 30) as usize].to_string()),
+                n @ 90..=97 => self.fg = Some(PALETTE[(n - 90 + 8) as usize].to_string()),
+                n @ 40..=47 => self.bg = Some(PALETTE[(n - 40) as usize].to_string()),
+                n @ 100..=107 => self.bg = Some(PALETTE[(n - 100 + 8) as usize].to_string()),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                n @ (38 | 48) => {
+                    let (color, used) = extended_color(&params[i + 1..]);
+                    if n == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+// `5;n` for the 256 colour palette or `2;r;g;b` for true colour, returning
+// the colour and how many parameters it used
+fn extended_color(params: &[u32]) -> (Option<String>, usize) {
+    match params {
+        [5, n, ..] => (Some(color_256(*n)), 2),
+        [2, r, g, b, ..] => (Some(format!("#{:02x}{:02x}{:02x}", r, g, b)), 4),
+        _ => (None, params.len()),
+    }
+}
+
+fn color_256(n: u32) -> String {
+    match n {
+        0..=15 => PALETTE[n as usize].to_string(),
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u32| if v == 0 { 0 } else { 55 + v * 40 };
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level((n / 6) % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let gray = 8 + (n.min(255) - 232) * 10;
+            format!("#{gray:02x}{gray:02x}{gray:02x}")
+        }
+    }
+}
+
+fn escape(c: char, out: &mut String) {
+    match c {
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '&' => out.push_str("&amp;"),
+        '"' => out.push_str("&quot;"),
+        _ => out.push(c),
+    }
+}
+
+// terminal output pasted into markdown often has the escape written out
+fn normalize_escapes(text: &str) -> String {
+    text.replace("\\x1b[", "\x1b[")
+        .replace("\\033[", "\x1b[")
+        .replace("\\e[", "\x1b[")
+}
+
+/// Converts text containing ANSI escape codes into html, with colours and
+/// text styles as inline styled spans. Other escape sequences are dropped.
+pub fn to_html(text: &str) -> String {
+    let text = normalize_escapes(text);
+    let mut out = String::new();
+    let mut style = Style::default();
+    let mut open = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            escape(c, &mut out);
+            continue;
+        }
+
+        if chars.peek() != Some(&'[') {
+            // not a control sequence, drop the next character with the escape
+            chars.next();
+            continue;
+        }
+        chars.next();
+
+        let mut sequence = String::new();
+        let mut command = None;
+        for c in chars.by_ref() {
+            if ('\x40'..='\x7e').contains(&c) {
+                command = Some(c);
+                break;
+            }
+            sequence.push(c);
+        }
+
+        if command != Some('m') {
+            continue;
+        }
+
+        let params: Vec<u32> = sequence
+            .split(';')
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| p.parse().ok())
+            .collect();
+
+        let mut next = style.clone();
+        next.apply(&params);
+        if next == style {
+            continue;
+        }
+
+        if open {
+            out.push_str("</span>");
+            open = false;
+        }
+
+        style = next;
+        if style != Style::default() {
+            out.push_str(&format!("<span style=\"{}\">", style.css()));
+            open = true;
+        }
+    }
+
+    if open {
+        out.push_str("</span>");
+    }
+
+    out
+}
+
+fn is_ansi_block(lang: &str) -> bool {
+    matches!(
+        lang.split_whitespace().next(),
+        Some("ansi") | Some("console")
+    )
+}
+
+/// Replaces fenced code blocks tagged `ansi` or `console` with their
+/// coloured html, leaving every other event alone. The html is kept out of
+/// the page's template, like other code, so `{{` in the output stays text.
+pub fn render_blocks<'a, I: Iterator<Item = Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut out = Vec::new();
+    let mut block: Option<String> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang)))
+                if is_ansi_block(lang) =>
+            {
+                block = Some(String::new());
+            }
+            Event::Text(ref text) if block.is_some() => {
+                if let Some(block) = block.as_mut() {
+                    block.push_str(text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) if block.is_some() => {
+                if let Some(text) = block.take() {
+                    let html = format!(
+                        "{{% raw %}}<pre class=\"ansi\"><code>{}</code></pre>{{% endraw %}}\n",
+                        to_html(&text)
+                    );
+                    out.push(Event::Html(CowStr::from(html)));
+                }
+            }
+            event => out.push(event),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+    use tera::{Context, Tera};
+
+    // `markdown` as html, after the page's template has rendered it
+    fn page(markdown: &str) -> String {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(
+            &mut html,
+            render_blocks(Parser::new(markdown)).into_iter(),
+        );
+        Tera::one_off(&html, &Context::new(), false).unwrap()
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            to_html("\x1b[31mred\x1b[0m plain"),
+            "<span style=\"color:#cd3131\">red</span> plain"
+        );
+        assert_eq!(
+            to_html("\x1b[92mok\x1b[39m"),
+            "<span style=\"color:#23d18b\">ok</span>"
+        );
+        assert_eq!(
+            to_html("\x1b[44mbg\x1b[49m"),
+            "<span style=\"background-color:#2472c8\">bg</span>"
+        );
+    }
+
+    #[test]
+    fn extended_colors() {
+        assert_eq!(
+            to_html("\x1b[38;5;196mx"),
+            "<span style=\"color:#ff0000\">x</span>"
+        );
+        assert_eq!(
+            to_html("\x1b[38;5;244mx"),
+            "<span style=\"color:#808080\">x</span>"
+        );
+        assert_eq!(
+            to_html("\x1b[48;2;1;2;3mx"),
+            "<span style=\"background-color:#010203\">x</span>"
+        );
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            to_html("\x1b[1;4mx\x1b[22my\x1b[m"),
+            "<span style=\"font-weight:bold;text-decoration:underline\">x</span>\
+             <span style=\"text-decoration:underline\">y</span>"
+        );
+        // the same style again doesn't start another span
+        assert_eq!(
+            to_html("\x1b[3ma\x1b[3mb"),
+            "<span style=\"font-style:italic\">ab</span>"
+        );
+    }
+
+    #[test]
+    fn other_sequences() {
+        assert_eq!(to_html("\x1b[2Kline\x1b[1A"), "line");
+        assert_eq!(to_html("\x1b]title"), "title");
+        assert_eq!(to_html("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+
+    #[test]
+    fn written_out_escapes() {
+        assert_eq!(
+            to_html("\\x1b[32mok\\033[0m \\e[1mbold"),
+            "<span style=\"color:#0dbc79\">ok</span> <span style=\"font-weight:bold\">bold</span>"
+        );
+    }
+
+    #[test]
+    fn template_syntax_in_blocks() {
+        assert_eq!(
+            page("```console\n$ echo '{{ name }} {% if %} {# #}'\n```\n"),
+            "<pre class=\"ansi\"><code>$ echo '{{ name }} {% if %} {# #}'\n</code></pre>\n"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{interrupt, Options};
+
+const EXTENSIONS: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".zip",
+];
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+// the file name of `source`, without the query of a url
+fn file_name(source: &str) -> &str {
+    let path = source.split(['?', '#']).next().unwrap_or_default();
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+/// Whether `--content` is an archive (or a link to one) rather than a
+/// folder. Links are archives whatever their name.
+pub fn is_archive(source: &str) -> bool {
+    let name = file_name(source).to_ascii_lowercase();
+    is_url(source) || EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{program} {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Where the content of the site built to `output` is extracted, apart from
+/// other builds running at the same time, in this process or another.
+fn temp_dir(output: &str) -> PathBuf {
+    let site = format!("{:x}", Sha256::digest(output.as_bytes()));
+    std::env::temp_dir().join(format!(
+        "roxy-content-{}-{}",
+        std::process::id(),
+        &site[..12]
+    ))
+}
+
+/// Extracts the archive at `source`, downloading it first if it's a link,
+/// into `dest` and returns the content folder in it: the one folder the
+/// archive holds, when it holds nothing else, like those of a repository.
+pub fn extract(source: &str, dest: &Path) -> io::Result<PathBuf> {
+    let _ = fs::remove_dir_all(dest);
+    let files = dest.join("files");
+    fs::create_dir_all(&files)?;
+    let archive = if is_url(source) {
+        let name = Some(file_name(source)).filter(|name| !name.is_empty());
+        let archive = dest.join(name.unwrap_or("content.tar"));
+        run("curl", &["-fsSL", "-o", &archive.to_string_lossy(), source])?;
+        archive
+    } else {
+        PathBuf::from(source)
+    };
+
+    let (archive, files_dir) = (archive.to_string_lossy(), files.to_string_lossy());
+    if file_name(&archive).to_ascii_lowercase().ends_with(".zip") {
+        run("unzip", &["-q", "-o", &archive, "-d", &files_dir])?;
+    } else {
+        // tar works out the compression itself
+        run("tar", &["-xf", &archive, "-C", &files_dir])?;
+    }
+
+    let entries: Vec<PathBuf> = fs::read_dir(&files)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| !crate::is_hidden(path))
+        .collect();
+    match entries.as_slice() {
+        [only] if only.is_dir() => Ok(only.to_path_buf()),
+        _ => Ok(files),
+    }
+}
+
+/// Runs `f` with `opts`, or when `--content` is an archive, with it
+/// extracted to a temporary folder that is removed once `f` is done.
+pub fn with_content<T>(opts: &Options, f: impl FnOnce(&Options) -> io::Result<T>) -> io::Result<T> {
+    if !is_archive(&opts.content) {
+        return f(opts);
+    }
+
+    let dest = temp_dir(&opts.output);
+    interrupt::remove_on_interrupt(opts, &dest);
+    let result = extract(&opts.content, &dest).and_then(|content| {
+        let mut opts = opts.clone();
+        opts.content = format!("{}/", content.to_string_lossy());
+        f(&opts)
+    });
+    let _ = fs::remove_dir_all(&dest);
+    result
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostics::{Diagnostic, Failure},
+    svg,
+};
+
+fn is_sass(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("scss" | "sass")
+    )
+}
+
+/// Where the static file `from` ends up, given the path it would be copied
+/// to: stylesheets compile to `.css`, and Sass partials (`_name.scss`),
+/// which are only imported, aren't written at all.
+pub fn output_path(from: &Path, to: PathBuf) -> Option<PathBuf> {
+    if !is_sass(from) {
+        return Some(to);
+    }
+    let partial = from
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('_'));
+    (!partial).then(|| to.with_extension("css"))
+}
+
+/// `html` with the whitespace, comments and optional tags browsers don't
+/// need taken out, along with those of its inline styles and scripts.
+pub fn minify_html(html: &str) -> String {
+    let cfg = minify_html::Cfg {
+        minify_css: true,
+        minify_js: true,
+        ..minify_html::Cfg::default()
+    };
+    String::from_utf8(minify_html::minify(html.as_bytes(), &cfg))
+        .unwrap_or_else(|_| html.to_string())
+}
+
+// files the minifier doesn't understand are left as they are
+fn minify_css(css: &str) -> String {
+    minifier::css::minify(css)
+        .map(|css| css.to_string())
+        .unwrap_or_else(|_| css.to_string())
+}
+
+fn minify_js(js: &str) -> String {
+    minifier::js::minify(js).to_string()
+}
+
+fn compile_sass(from: &Path, minify: bool) -> Result<String, String> {
+    let style = if minify {
+        grass::OutputStyle::Compressed
+    } else {
+        grass::OutputStyle::Expanded
+    };
+    grass::from_path(from, &grass::Options::default().style(style)).map_err(|err| err.to_string())
+}
+
+/// What a static file is written as.
+pub enum Output {
+    /// The file as it is
+    Copy,
+    /// Compiled or minified
+    Text(String),
+    /// Nothing, the file couldn't be compiled
+    Skip,
+}
+
+/// What the static file `from` is written as, compiling Sass and, with
+/// `minify`, minifying CSS and JavaScript and optimizing SVGs. Stylesheets that don't compile
+/// are reported and skipped.
+pub fn output(from: &Path, minify: bool) -> io::Result<Output> {
+    if is_sass(from) {
+        return Ok(match compile_sass(from, minify) {
+            Ok(css) => Output::Text(css),
+            Err(err) => {
+                Diagnostic::error("Couldn't compile stylesheet")
+                    .failure(Failure::Content)
+                    .in_file(&from.to_string_lossy())
+                    .note(err)
+                    .print();
+                Output::Skip
+            }
+        });
+    }
+
+    // `.min.js` and the like come minified by their own build
+    let name = from.to_string_lossy();
+    let minified = name.ends_with(".min.css") || name.ends_with(".min.js");
+    let minifier: fn(&str) -> String = match from.extension().and_then(|ext| ext.to_str()) {
+        Some("css") if minify && !minified => minify_css,
+        Some("js" | "mjs") if minify && !minified => minify_js,
+        Some("svg") if minify => svg::optimize,
+        _ => return Ok(Output::Copy),
+    };
+    Ok(Output::Text(minifier(&fs::read_to_string(from)?)))
+}
+
+/// Writes the static file `from` to `to` (see `output_path` and `output`).
+pub fn copy(from: &Path, to: &Path, minify: bool) -> io::Result<()> {
+    match output(from, minify)? {
+        Output::Copy => fs::copy(from, to).map(|_| ()),
+        Output::Text(text) => fs::write(to, text),
+        Output::Skip => Ok(()),
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use glob::glob;
+use regex::Regex;
+
+use crate::{config::distance, Content};
+
+// words of the template language, rather than variables
+const KEYWORDS: &[&str] = &[
+    "if",
+    "elif",
+    "else",
+    "endif",
+    "for",
+    "endfor",
+    "in",
+    "set",
+    "set_global",
+    "block",
+    "endblock",
+    "extends",
+    "include",
+    "import",
+    "as",
+    "macro",
+    "endmacro",
+    "filter",
+    "endfilter",
+    "raw",
+    "endraw",
+    "break",
+    "continue",
+    "not",
+    "and",
+    "or",
+    "is",
+    "true",
+    "false",
+    "True",
+    "False",
+    "loop",
+    "self",
+    "super",
+    "__tera_context",
+    "ignore",
+    "missing",
+    "with",
+    "endwith",
+];
+
+// frontmatter fields roxy reads itself, so they aren't dead even when no
+// layout mentions them
+const BUILTIN_FIELDS: &[&str] = &[
+    "layout",
+    "lang",
+    "title",
+    "weight",
+    "resources",
+    "section_tree",
+    "changelog",
+    "changelog_path",
+    "glossary",
+    "environments",
+    "short_url",
+    "locked",
+    "date",
+    "updated",
+    "feed_content",
+    "expiry_date",
+    "draft",
+    "docs_version",
+    "permalink",
+    "slug",
+    "search",
+    "sitemap",
+    "safe_html",
+    "headers",
+];
+
+/// A variable a template refers to, and where.
+struct Reference {
+    template: String,
+    path: String,
+    /// Whether the template defines the variable itself, like a loop variable
+    local: bool,
+}
+
+// the code inside `{{ ... }}` and `{% ... %}` tags, without string literals
+fn tags(source: &str) -> Vec<String> {
+    let tag = Regex::new(r"(?s)\{\{-?(.*?)-?\}\}|\{%-?(.*?)-?%\}").unwrap();
+    let strings = Regex::new(r#""[^"]*"|'[^']*'|`[^`]*`"#).unwrap();
+    let comments = Regex::new(r"(?s)\{#.*?#\}").unwrap();
+    let source = comments.replace_all(source, "");
+
+    tag.captures_iter(&source)
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|code| strings.replace_all(code.as_str(), "\"\"").to_string())
+        .collect()
+}
+
+// names a template introduces itself with `for`, `set`, `macro` and `import`
+fn locals(tags: &[String]) -> BTreeSet<String> {
+    let for_loop = Regex::new(r"^\s*for\s+(\w+)(?:\s*,\s*(\w+))?\s+in\b").unwrap();
+    let set = Regex::new(r"^\s*(?:set|set_global)\s+(\w+)\s*=").unwrap();
+    let import = Regex::new(r"\bas\s+(\w+)\s*$").unwrap();
+    let macro_def = Regex::new(r"^\s*macro\s+\w+\s*\(([^)]*)\)").unwrap();
+    let param = Regex::new(r"(\w+)\s*(?:=|,|$)").unwrap();
+
+    let mut names = BTreeSet::new();
+    for tag in tags.iter() {
+        for re in [&for_loop, &set, &import] {
+            if let Some(captures) = re.captures(tag) {
+                names.extend(
+                    captures
+                        .iter()
+                        .skip(1)
+                        .flatten()
+                        .map(|m| m.as_str().to_string()),
+                );
+            }
+        }
+        if let Some(params) = macro_def.captures(tag).and_then(|c| c.get(1)) {
+            for captures in param.captures_iter(params.as_str()) {
+                names.insert(captures[1].to_string());
+            }
+        }
+    }
+    names
+}
+
+// dotted variable paths, skipping filters, tests, functions, named
+// arguments, macro namespaces and attribute accesses
+fn variables(tag: &str) -> Vec<String> {
+    let path = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*(?:\.[A-Za-z_][A-Za-z0-9_]*)*").unwrap();
+    let mut found = Vec::new();
+
+    for m in path.find_iter(tag) {
+        let before = tag[..m.start()].trim_end();
+        let after = tag[m.end()..].trim_start();
+        let previous = tag[..m.start()].chars().last();
+
+        if previous.is_some_and(|c| c == '.' || c.is_alphanumeric() || c == ']') {
+            continue;
+        }
+        if before.ends_with('|') || before.ends_with(" is") || before.ends_with(" is not") {
+            continue;
+        }
+        if after.starts_with('(') || after.starts_with("::") {
+            continue;
+        }
+        if after.starts_with('=') && !after.starts_with("==") {
+            continue;
+        }
+        // block names
+        if ["block", "endblock", "endmacro", "endfilter"]
+            .iter()
+            .any(|keyword| before.split_whitespace().last() == Some(keyword))
+        {
+            continue;
+        }
+
+        let root = m.as_str().split('.').next().unwrap_or("");
+        if KEYWORDS.contains(&root) || root.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        found.push(m.as_str().to_string());
+    }
+
+    found
+}
+
+fn scan(dirs: &[String]) -> Vec<Reference> {
+    let mut references = Vec::new();
+
+    for dir in dirs.iter() {
+        let pattern = format!("{}/**/*", glob::Pattern::escape(dir.trim_end_matches('/')));
+        for entry in glob(&pattern).into_iter().flatten().flatten() {
+            let source = match fs::read_to_string(&entry) {
+                Ok(source) => source,
+                Err(_) => continue,
+            };
+            let template = entry.to_string_lossy().to_string();
+            let tags = tags(&source);
+            let locals = locals(&tags);
+            // shortcodes are rendered with their arguments rather than the context
+            let shortcode = entry
+                .strip_prefix(dir)
+                .is_ok_and(|path| path.starts_with("shortcodes"));
+
+            for tag in tags.iter() {
+                for path in variables(tag) {
+                    let root = path.split('.').next().unwrap_or("");
+                    references.push(Reference {
+                        template: template.to_string(),
+                        local: shortcode || locals.contains(root),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+
+    references
+}
+
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    candidates
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn with_suggestion(problem: String, suggestion: Option<&String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("{problem}, did you mean `{suggestion}`?"),
+        None => problem,
+    }
+}
+
+/// Compares the variables `layouts` refer to with what the context
+/// provides, and the frontmatter of `contents` with what layouts read.
+/// `context` holds the top level keys every page is rendered with.
+pub fn run(layouts: &[String], contents: &[Content], context: &BTreeSet<String>) -> Vec<String> {
+    let references = scan(layouts);
+    let mut problems = Vec::new();
+
+    let mut fields: BTreeMap<String, usize> = BTreeMap::new();
+    for content in contents.iter() {
+        for key in content.frontmatter.0.keys() {
+            *fields.entry(key.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut read: BTreeSet<String> = BTreeSet::new();
+    let mut reported = BTreeSet::new();
+    for reference in references.iter() {
+        let parts: Vec<&str> = reference.path.split('.').collect();
+        let root = parts[0].to_string();
+
+        if !reference.local && !context.contains(&root) {
+            let problem = format!("{}: `{root}` isn't in the context", reference.template);
+            if reported.insert(problem.to_string()) {
+                problems.push(with_suggestion(problem, closest(&root, context.iter())));
+            }
+            continue;
+        }
+
+        // `page.frontmatter.<key>`, `post.frontmatter.<key>` in a loop and so on
+        let field = match parts.iter().position(|part| *part == "frontmatter") {
+            Some(i) => match parts.get(i + 1) {
+                Some(field) => *field,
+                None => continue,
+            },
+            None => continue,
+        };
+        read.insert(field.to_string());
+
+        if !fields.contains_key(field) && !BUILTIN_FIELDS.contains(&field) {
+            let problem = format!(
+                "{}: no page has a `{field}` frontmatter field",
+                reference.template
+            );
+            if reported.insert(problem.to_string()) {
+                problems.push(with_suggestion(problem, closest(field, fields.keys())));
+            }
+        }
+    }
+
+    // a layout that goes through all of the frontmatter (or indexes it with
+    // a variable) could be reading any field
+    let reads_everything = references
+        .iter()
+        .any(|reference| reference.path.ends_with("frontmatter"));
+    if reads_everything {
+        return problems;
+    }
+
+    for (field, count) in fields.iter() {
+        let builtin = BUILTIN_FIELDS.contains(&field.as_str()) || field.starts_with("cascade.");
+        if !builtin && !read.contains(field) {
+            problems.push(format!(
+                "`{field}` is set on {count} page{} but no layout reads it",
+                if *count == 1 { "" } else { "s" }
+            ));
+        }
+    }
+
+    problems
+}
+
+/// The folders layouts are read from.
+pub fn layout_dirs(layouts: &str, theme: Option<&Path>) -> Vec<String> {
+    let mut dirs = vec![layouts.to_string()];
+    dirs.extend(theme.map(|dir| dir.to_string_lossy().to_string()));
+    dirs
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tera::Context;
+
+use crate::{build_info, functions, layout, resources, template_deps::TemplateGraph, Content};
+
+/// What compiling a page used besides its own source and what every page
+/// is compiled with, each with the hash it had then.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Dependencies {
+    /// Templates the markdown includes, imports or caches, by their name,
+    /// with the `TemplateGraph::key` of each
+    templates: BTreeMap<String, String>,
+    /// Data files, like the glossary, and those `include_code` read, by
+    /// their path
+    files: BTreeMap<String, String>,
+}
+
+/// A compiled page as it's kept in the cache, with the images that are
+/// copied next to it and what it was compiled from.
+#[derive(Deserialize)]
+struct CachedPage {
+    page: Content,
+    images: Vec<resources::Resource>,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    numbering: functions::Numbering,
+    #[serde(default)]
+    dependencies: Dependencies,
+}
+
+#[derive(Serialize)]
+struct CachedPageRef<'a> {
+    page: &'a Content,
+    images: &'a [resources::Resource],
+    text: &'a str,
+    numbering: functions::Numbering,
+    dependencies: &'a Dependencies,
+}
+
+fn hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts.iter() {
+        hasher.update(part);
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// with the keys of maps in order, so the same value always gives the same
+// text
+fn canonical<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+}
+
+/// The hash of each top level value of the context pages are rendered
+/// with, from `BuildCache::site_keys`.
+pub struct SiteKeys(BTreeMap<String, String>);
+
+// names in a layout that read the whole site without naming its variables
+const WHOLE_SITE: &[&str] = &["__tera_context", "get_page", "section_tree"];
+
+/// Compiled pages, and what every page was last rendered from, kept between
+/// builds so pages whose inputs haven't changed are neither compiled nor
+/// rendered again.
+///
+/// A page is compiled again when its source, the translations, config or
+/// the settings it's compiled with change, or any template or data file it
+/// used (see `Dependencies`) does. It is rendered
+/// again when its layout or a template that uses (see `TemplateGraph`)
+/// changes, or a variable of its layout's context those templates name
+/// does. Editing one page compiles only it, and renders again the pages
+/// whose layouts list pages, through `data` or `get_page`, but not those of
+/// layouts that don't.
+///
+/// Entries that a build doesn't use are removed when it's saved, so the
+/// cache holds only what the last build was made of.
+pub struct BuildCache {
+    dir: PathBuf,
+    /// Build everything, still updating the cache
+    force: bool,
+    /// Use the cache without writing to it
+    read_only: bool,
+    /// The hash of the translations, config and settings
+    shared: String,
+    templates: TemplateGraph,
+    previous: BTreeMap<String, String>,
+    rendered: Mutex<BTreeMap<String, String>>,
+    /// The compiled pages this build read or stored, by key
+    used: Mutex<BTreeSet<String>>,
+}
+
+impl BuildCache {
+    /// The cache in `dir` for pages rendered with `templates`. `shared` are
+    /// the files every page depends on, like the translations and config;
+    /// `settings` are everything else pages are compiled and rendered with.
+    pub fn open(
+        dir: &Path,
+        force: bool,
+        templates: TemplateGraph,
+        shared: &[&str],
+        settings: &str,
+    ) -> io::Result<Self> {
+        let shared = build_info::fingerprint(shared)?;
+        let shared = hash(&[shared.as_bytes(), settings.as_bytes()]);
+
+        let previous = fs::read_to_string(dir.join("rendered.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            force,
+            read_only: false,
+            shared,
+            templates,
+            previous,
+            rendered: Mutex::new(BTreeMap::new()),
+            used: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// The cache, used without writing anything to it.
+    pub fn read_only(self) -> Self {
+        Self {
+            read_only: true,
+            ..self
+        }
+    }
+
+    fn page_file(&self, key: &str) -> PathBuf {
+        self.dir.join("pages").join(format!("{key}.json"))
+    }
+
+    fn use_page(&self, key: &str) {
+        self.used
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(key.to_string());
+    }
+
+    /// What compiling the page at `file_path` from `source` is cached under.
+    /// The path is the page's in the content folder it's compiled from, so
+    /// docs versions, checked out to a different folder for every build,
+    /// are found again. `data` are the data files pages can be compiled
+    /// with, so adding or removing one compiles every page again.
+    pub fn compile_key(&self, file_path: &str, source: &[u8], data: &[&Path]) -> String {
+        let data: Vec<String> = data
+            .iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect();
+        hash(&[
+            self.shared.as_bytes(),
+            data.join("\n").as_bytes(),
+            file_path.replace('\\', "/").as_bytes(),
+            source,
+        ])
+    }
+
+    /// What compiling the page from `source` uses: the templates it names,
+    /// and the data and included `files` it was compiled with, as they are
+    /// now.
+    pub fn dependencies(&self, source: &str, files: &[&Path]) -> Dependencies {
+        let files = files
+            .iter()
+            .map(|file| {
+                let hash = build_info::hash_file(file).unwrap_or_default();
+                (file.to_string_lossy().to_string(), hash)
+            })
+            .collect();
+        Dependencies {
+            templates: self.templates.used_by(source),
+            files,
+        }
+    }
+
+    // whether nothing the page was compiled from has changed since
+    fn is_current(&self, dependencies: &Dependencies) -> bool {
+        let templates = dependencies
+            .templates
+            .iter()
+            .all(|(name, key)| self.templates.key(name) == *key);
+        templates
+            && dependencies.files.iter().all(|(file, hash)| {
+                build_info::hash_file(Path::new(file)).is_ok_and(|current| current == *hash)
+            })
+    }
+
+    /// The page compiled under `key`, unless anything it depends on has
+    /// changed, with its resources and images found again in `source_dir`
+    /// in case they changed.
+    pub fn compiled(&self, key: &str, page_path: &str, source_dir: &Path) -> Option<Content> {
+        if self.force {
+            return None;
+        }
+        let text = fs::read_to_string(self.page_file(key)).ok()?;
+        let cached: CachedPage = serde_json::from_str(&text).ok()?;
+        if !self.is_current(&cached.dependencies) {
+            return None;
+        }
+        self.use_page(key);
+
+        let mut page = cached.page;
+        page.resources = resources::resolve(page.resources, page_path, source_dir, &page.slug);
+        page.images = resources::resolve(cached.images, page_path, source_dir, &page.slug);
+        page.text = cached.text;
+        page.numbering = cached.numbering;
+        Some(page)
+    }
+
+    /// Keeps the compiled `page` under `key`, along with its `dependencies`.
+    pub fn store(&self, key: &str, page: &Content, dependencies: &Dependencies) {
+        if self.read_only {
+            return;
+        }
+        self.use_page(key);
+        // the cache only saves time, so failing to write to it isn't an error
+        let cached = CachedPageRef {
+            page,
+            images: &page.images,
+            text: &page.text,
+            numbering: page.numbering,
+            dependencies,
+        };
+        let file = self.page_file(key);
+        if let (Some(dir), Ok(text)) = (file.parent(), serde_json::to_string(&cached)) {
+            if fs::create_dir_all(dir).is_ok() {
+                let _ = fs::write(file, text);
+            }
+        }
+    }
+
+    /// The hash of each variable of the `context` every page is rendered
+    /// with, hashed once for all the pages.
+    pub fn site_keys(&self, context: &Context) -> SiteKeys {
+        let keys = match context.clone().into_json() {
+            tera::Value::Object(map) => map
+                .iter()
+                .map(|(name, value)| (name.to_string(), hash(&[canonical(value).as_bytes()])))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        SiteKeys(keys)
+    }
+
+    /// What rendering `page` is cached under: its layout and the templates
+    /// that uses, the page itself, and the variables of the site's context
+    /// (see `site_keys`) those templates name. Layouts that read the whole
+    /// context or every page depend on all of it.
+    pub fn render_key(&self, site_keys: &SiteKeys, page: &Content) -> String {
+        let layout = layout(page);
+        let whole = WHOLE_SITE
+            .iter()
+            .any(|name| self.templates.mentions(layout, name));
+        let template_key = self.templates.key(layout);
+        let page_key = canonical(page);
+        let extra_key = canonical(&page.extra);
+        let mut parts: Vec<&[u8]> = vec![self.shared.as_bytes()];
+        for (name, key) in site_keys.0.iter() {
+            if whole || self.templates.mentions(layout, name) {
+                parts.push(name.as_bytes());
+                parts.push(key.as_bytes());
+            }
+        }
+        parts.extend([
+            template_key.as_bytes(),
+            page_key.as_bytes(),
+            extra_key.as_bytes(),
+        ]);
+        hash(&parts)
+    }
+
+    /// Whether `output` is still what rendering with `key` would write,
+    /// recording it as such if so.
+    pub fn is_rendered(&self, output: &Path, key: &str) -> bool {
+        let output = output.to_string_lossy().to_string();
+        let current = !self.force
+            && Path::new(&output).is_file()
+            && self.previous.get(&output).map(|k| k.as_str()) == Some(key);
+        if current {
+            self.record(&output, key);
+        }
+        current
+    }
+
+    fn record(&self, output: &str, key: &str) {
+        self.rendered
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(output.to_string(), key.to_string());
+    }
+
+    /// Records that `output` was rendered with `key`.
+    pub fn rendered(&self, output: &Path, key: &str) {
+        self.record(&output.to_string_lossy(), key);
+    }
+
+    /// Saves what every page was rendered with, for the next build, and
+    /// removes the compiled pages this build didn't use.
+    pub fn save(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let rendered = self.rendered.lock().unwrap_or_else(|err| err.into_inner());
+        let text = serde_json::to_string_pretty(&*rendered)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join("rendered.json"), text + "\n")?;
+
+        let used = self.used.lock().unwrap_or_else(|err| err.into_inner());
+        let Ok(pages) = fs::read_dir(self.dir.join("pages")) else {
+            return Ok(());
+        };
+        for page in pages.flatten() {
+            let path = page.path();
+            let key = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string());
+            let cached = path.extension().is_some_and(|ext| ext == "json");
+            if cached && key.is_some_and(|key| !used.contains(&key)) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use glob::glob;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tera::{Function, Value};
+
+use crate::parallel;
+
+// hex digits of the inputs' hash kept for the build id
+const ID_LENGTH: usize = 12;
+
+/// Details of the current build, available to layouts as `build`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    /// Changes whenever any input of the build does
+    pub id: String,
+    pub version: &'static str,
+}
+
+/// Hashes every file below `inputs` (files or folders, missing ones are
+/// skipped) along with roxy's version into a build id that stays the same
+/// until one of them changes.
+pub fn compute(inputs: &[&str]) -> io::Result<BuildInfo> {
+    let hash = fingerprint(inputs)?;
+    Ok(BuildInfo {
+        id: hash[..ID_LENGTH].to_string(),
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// The full hex encoded hash `compute` makes its build id from.
+pub fn fingerprint(inputs: &[&str]) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+
+    for input in inputs.iter() {
+        let mut files = if Path::new(input).is_file() {
+            vec![Path::new(input).to_path_buf()]
+        } else {
+            let pattern = format!(
+                "{}/**/*",
+                glob::Pattern::escape(input.trim_end_matches('/'))
+            );
+            glob(&pattern)
+                .map(|paths| paths.flatten().filter(|p| p.is_file()).collect())
+                .unwrap_or_default()
+        };
+        // so the id doesn't depend on the order the filesystem lists files in
+        files.sort();
+
+        // files are hashed in parallel, then their hashes in order
+        let hashes = parallel::map(&files, |file| hash_file(file));
+        for (file, hash) in files.iter().zip(hashes) {
+            hasher.update(file.to_string_lossy().as_bytes());
+            hasher.update(hash?.as_bytes());
+        }
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Read at a time when hashing a file, so large files (like videos) are
+/// never in memory all at once.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// The hex encoded SHA-256 of the file at `path`, read in chunks.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&chunk[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// `asset(path, bust=true)` links to a file with the build id as a `v`
+/// query parameter, so browsers fetch it again after a change.
+pub struct Asset {
+    build_id: String,
+}
+
+impl Asset {
+    pub fn new(build_id: &str) -> Self {
+        Self {
+            build_id: build_id.to_string(),
+        }
+    }
+}
+
+impl Function for Asset {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`asset` requires a `path` argument"))?;
+
+        if !args.get("bust").and_then(Value::as_bool).unwrap_or(true) {
+            return Ok(Value::String(path.to_string()));
+        }
+
+        let separator = if path.contains('?') { '&' } else { '?' };
+        Ok(Value::String(format!(
+            "{path}{separator}v={}",
+            self.build_id
+        )))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,175 @@
+use std::{fs, io, path::Path, process::Command};
+
+use serde::Serialize;
+
+use crate::{functions, slugify};
+
+#[derive(Debug, Serialize)]
+pub struct Section {
+    pub name: String,
+    /// Entries rendered to inline html
+    pub entries: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Release {
+    pub version: String,
+    pub date: Option<String>,
+    /// Fragment id for linking to this release
+    pub anchor: String,
+    pub sections: Vec<Section>,
+}
+
+impl Release {
+    fn new(version: &str, date: Option<String>) -> Self {
+        Self {
+            version: version.to_string(),
+            date,
+            anchor: slugify(version),
+            sections: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, section: &str, entry: String) {
+        match self.sections.iter_mut().find(|s| s.name == section) {
+            Some(section) => section.entries.push(entry),
+            None => self.sections.push(Section {
+                name: section.to_string(),
+                entries: vec![entry],
+            }),
+        }
+    }
+}
+
+fn inline_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(markdown));
+    let html = html.trim();
+    html.strip_prefix("<p>")
+        .and_then(|h| h.strip_suffix("</p>"))
+        .unwrap_or(html)
+        .to_string()
+}
+
+/// Reads a changelog in the Keep a Changelog style: `## [1.0.0] - 2023-07-01`
+/// starts a release, `### Added` a section, and list items are its entries.
+pub fn from_markdown(text: &str) -> Vec<Release> {
+    let mut releases: Vec<Release> = Vec::new();
+    let mut section = "Changes".to_string();
+
+    for line in text.lines() {
+        let line = line.trim_end();
+        if let Some(heading) = line.strip_prefix("## ") {
+            let (version, date) = match heading.split_once(" - ") {
+                Some((version, date)) => (version, Some(date.trim().to_string())),
+                None => (heading, None),
+            };
+            let version = version.trim().trim_start_matches('[').trim_end_matches(']');
+            releases.push(Release::new(version, date));
+            section = "Changes".to_string();
+        } else if let Some(heading) = line.strip_prefix("### ") {
+            section = heading.trim().to_string();
+        } else if let Some(entry) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            if let Some(release) = releases.last_mut() {
+                release.push(&section, inline_html(entry.trim()));
+            }
+        }
+    }
+
+    releases
+}
+
+// conventional commit types and the section they are listed under
+fn section_for(subject: &str) -> (&'static str, &str) {
+    let (kind, rest) = match subject.split_once(':') {
+        Some((kind, rest)) => (kind, rest.trim()),
+        None => return ("Other", subject),
+    };
+
+    let kind = kind.split('(').next().unwrap_or(kind).trim_end_matches('!');
+    let section = match kind {
+        "feat" => "Features",
+        "fix" => "Fixes",
+        "perf" => "Performance",
+        "docs" => "Documentation",
+        "refactor" => "Refactoring",
+        _ => return ("Other", subject),
+    };
+
+    (section, rest)
+}
+
+/// Builds releases from the git history, one per tag, with commits grouped
+/// by their conventional commit type. Commits after the newest tag are
+/// listed as `Unreleased`. `path` limits the history to commits touching it.
+pub fn from_git(path: Option<&str>) -> io::Result<Vec<Release>> {
+    let mut command = Command::new("git");
+    command.args(["log", "--date=short", "--format=%s%x1f%ad%x1f%D"]);
+    if let Some(path) = path {
+        command.args(["--", path]);
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git log failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let mut releases: Vec<Release> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.split('\x1f');
+        let subject = fields.next().unwrap_or_default();
+        let date = fields.next().unwrap_or_default();
+        let refs = fields.next().unwrap_or_default();
+
+        let tag = refs
+            .split(", ")
+            .find_map(|r| r.strip_prefix("tag: "))
+            .map(|t| t.to_string());
+
+        if let Some(tag) = tag {
+            releases.push(Release::new(&tag, Some(date.to_string())));
+        } else if releases.is_empty() {
+            releases.push(Release::new("Unreleased", None));
+        }
+
+        let (section, entry) = section_for(subject);
+        if let Some(release) = releases.last_mut() {
+            release.push(section, inline_html(entry));
+        }
+    }
+
+    Ok(releases)
+}
+
+/// Loads the changelog for a page's `changelog` frontmatter field, which is
+/// either `git` or the path of a markdown changelog in `content_dir`.
+/// `sandbox` builds can't run git, like they can't read files with
+/// `include_html`.
+pub fn load(
+    source: &str,
+    path: Option<&str>,
+    content_dir: &Path,
+    sandbox: bool,
+) -> io::Result<Vec<Release>> {
+    let contained = |path: &str| {
+        functions::sandboxed(content_dir, path)
+            .map_err(|err| io::Error::new(io::ErrorKind::PermissionDenied, err.to_string()))
+    };
+    if source == "git" {
+        if sandbox {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "`changelog: git` isn't available in sandboxed builds",
+            ));
+        }
+        if let Some(path) = path {
+            contained(path)?;
+        }
+        from_git(path)
+    } else {
+        Ok(from_markdown(&fs::read_to_string(contained(source)?)?))
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glob::glob;
+
+use crate::{build_info::hash_file, diagnostics::Diagnostic, parallel};
+
+/// The name of the checksum file in the output folder.
+pub const FILE_NAME: &str = "SHA256SUMS";
+
+// every file below `dir` but the checksum file, by its path from `dir` with
+// `/` between folders
+fn files(dir: &Path) -> Vec<(String, PathBuf)> {
+    let pattern = format!("{}/**/*", glob::Pattern::escape(&dir.to_string_lossy()));
+    let mut files: Vec<(String, PathBuf)> = glob(&pattern)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let relative = path.strip_prefix(dir).ok()?;
+            let name = relative
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            Some((name, path))
+        })
+        .filter(|(name, _)| name != FILE_NAME)
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes the SHA-256 of every file in `output` to its `SHA256SUMS`, in
+/// the format of `sha256sum`, so `sha256sum -c` can check a copy too.
+pub fn write(output: &str) -> io::Result<()> {
+    let dir = Path::new(output.trim_end_matches('/'));
+    let files = files(dir);
+    let hashes = parallel::map(&files, |(_, path)| hash_file(path));
+
+    let mut sums = String::new();
+    for ((name, _), hash) in files.iter().zip(hashes) {
+        sums.push_str(&format!("{}  {name}\n", hash?));
+    }
+    fs::write(dir.join(FILE_NAME), sums)
+}
+
+// the hash of each file `SHA256SUMS` lists, and problems with its lines
+fn read(path: &Path) -> io::Result<(BTreeMap<String, String>, Vec<Diagnostic>)> {
+    let text = fs::read_to_string(path)?;
+    let file = path.to_string_lossy();
+    let mut sums = BTreeMap::new();
+    let mut problems = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // `sha256sum` puts `*` before the names of files read as binary
+        match line.split_once(' ') {
+            Some((hash, name)) if hash.len() == 64 => {
+                let name = name.trim_start_matches([' ', '*']);
+                sums.insert(name.to_string(), hash.to_ascii_lowercase());
+            }
+            _ => problems.push(
+                Diagnostic::error("Not a checksum line")
+                    .at_line(&file, i + 1, line)
+                    .label("expected a SHA-256 and a file name"),
+            ),
+        }
+    }
+    Ok((sums, problems))
+}
+
+/// Checks the files in `dir`, a copy of a built site, against its
+/// `SHA256SUMS`: each one listed must be there with the same hash, and no
+/// others may be. Returns how many files matched, and the problems found.
+pub fn verify(dir: &str) -> io::Result<(usize, Vec<Diagnostic>)> {
+    let dir = Path::new(dir.trim_end_matches('/'));
+    let sums_path = dir.join(FILE_NAME);
+    let (mut sums, mut problems) = read(&sums_path)?;
+    let sums_file = sums_path.to_string_lossy().to_string();
+
+    let files = files(dir);
+    let hashes = parallel::map(&files, |(_, path)| hash_file(path));
+    let mut matched = 0;
+    for ((name, path), hash) in files.iter().zip(hashes) {
+        let (file, hash) = (path.to_string_lossy(), hash?);
+        match sums.remove(name) {
+            Some(expected) if expected == hash => matched += 1,
+            Some(_) => problems.push(
+                Diagnostic::error(format!("{name} has changed"))
+                    .in_file(&file)
+                    .note(format!("its SHA-256 isn't the one in {sums_file}")),
+            ),
+            None => problems.push(
+                Diagnostic::error(format!("{name} isn't part of the site"))
+                    .in_file(&file)
+                    .note(format!("{sums_file} doesn't list it")),
+            ),
+        }
+    }
+    for name in sums.keys() {
+        problems.push(
+            Diagnostic::error(format!("{name} is missing"))
+                .in_file(&sums_file)
+                .note("the site was built with it"),
+        );
+    }
+    Ok((matched, problems))
+}
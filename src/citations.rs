@@ -0,0 +1,476 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Tag};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::diagnostics::Diagnostic;
+
+/// One work that pages can cite.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub id: String,
+    /// Family names, in order
+    pub authors: Vec<String>,
+    pub year: Option<String>,
+    pub title: Option<String>,
+    /// The journal, book or proceedings it appeared in
+    pub container: Option<String>,
+    pub url: Option<String>,
+}
+
+/// The works in `data/bibliography.json` (CSL-JSON) or
+/// `data/bibliography.bib` (BibTeX).
+pub struct Bibliography {
+    references: Vec<Reference>,
+    /// The file the works were read from
+    pub source: PathBuf,
+    pattern: Regex,
+}
+
+fn invalid(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {err}", path.to_string_lossy()),
+    )
+}
+
+#[derive(Deserialize)]
+struct CslName {
+    family: Option<String>,
+    literal: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CslDate {
+    #[serde(rename = "date-parts", default)]
+    date_parts: Vec<Vec<serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct CslItem {
+    id: String,
+    #[serde(default)]
+    author: Vec<CslName>,
+    issued: Option<CslDate>,
+    title: Option<String>,
+    #[serde(rename = "container-title")]
+    container_title: Option<String>,
+    #[serde(rename = "URL")]
+    url: Option<String>,
+    #[serde(rename = "DOI")]
+    doi: Option<String>,
+}
+
+fn from_csl(text: &str) -> serde_json::Result<Vec<Reference>> {
+    let items: Vec<CslItem> = serde_json::from_str(text)?;
+    Ok(items
+        .into_iter()
+        .map(|item| Reference {
+            id: item.id,
+            authors: item
+                .author
+                .into_iter()
+                .filter_map(|name| name.family.or(name.literal))
+                .collect(),
+            year: item
+                .issued
+                .and_then(|date| date.date_parts.first()?.first().cloned())
+                .map(|year| year.to_string().trim_matches('"').to_string()),
+            title: item.title,
+            container: item.container_title,
+            url: item
+                .url
+                .or_else(|| item.doi.map(|doi| format!("https://doi.org/{doi}"))),
+        })
+        .collect())
+}
+
+// reads `{...}` or `"..."` values, which may nest braces
+fn bibtex_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut value = String::new();
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut depth = 1;
+            for c in chars.by_ref() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                if c != '{' && c != '}' {
+                    value.push(c);
+                }
+            }
+        }
+        Some('"') => {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                if c != '{' && c != '}' {
+                    value.push(c);
+                }
+            }
+        }
+        _ => {
+            while let Some(c) = chars.peek() {
+                if *c == ',' || *c == '}' {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+        }
+    }
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn from_bibtex(text: &str) -> Vec<Reference> {
+    let mut references = Vec::new();
+    let entry_start = Regex::new(r"(?m)^\s*@").unwrap();
+
+    for entry in entry_start.split(text).skip(1) {
+        let (kind, body) = match entry.split_once('{') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        if matches!(
+            kind.trim().to_lowercase().as_str(),
+            "comment" | "string" | "preamble"
+        ) {
+            continue;
+        }
+        let (id, fields) = match body.split_once(',') {
+            Some((id, fields)) => (id.trim().to_string(), fields),
+            None => continue,
+        };
+
+        let mut reference = Reference {
+            id,
+            authors: Vec::new(),
+            year: None,
+            title: None,
+            container: None,
+            url: None,
+        };
+
+        let mut chars = fields.chars().peekable();
+        loop {
+            let name: String = chars
+                .by_ref()
+                .take_while(|c| *c != '=')
+                .collect::<String>()
+                .trim_matches(|c: char| c.is_whitespace() || c == ',')
+                .to_lowercase();
+            if name.is_empty() || name.contains('}') {
+                break;
+            }
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            let value = bibtex_value(&mut chars);
+
+            match name.as_str() {
+                "author" => {
+                    reference.authors = value
+                        .split(" and ")
+                        .map(|name| match name.split_once(',') {
+                            // `Knuth, Donald E.`
+                            Some((family, _)) => family.trim().to_string(),
+                            // `Donald E. Knuth`
+                            None => name.split_whitespace().last().unwrap_or("").to_string(),
+                        })
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                }
+                "year" => reference.year = Some(value),
+                "title" => reference.title = Some(value),
+                "journal" | "booktitle" | "publisher" if reference.container.is_none() => {
+                    reference.container = Some(value)
+                }
+                "url" => reference.url = Some(value),
+                "doi" if reference.url.is_none() => {
+                    reference.url = Some(format!("https://doi.org/{value}"))
+                }
+                _ => {}
+            }
+        }
+
+        references.push(reference);
+    }
+
+    references
+}
+
+/// Reads the bibliography from the data folder, if there is one.
+pub fn load(data_dir: &str) -> io::Result<Option<Bibliography>> {
+    let dir = Path::new(data_dir);
+    let json = dir.join("bibliography.json");
+    let bib = dir.join("bibliography.bib");
+
+    let (references, source) = if json.is_file() {
+        let references =
+            from_csl(&fs::read_to_string(&json)?).map_err(|err| invalid(&json, err))?;
+        (references, json)
+    } else if bib.is_file() {
+        (from_bibtex(&fs::read_to_string(&bib)?), bib)
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(Bibliography {
+        references,
+        source,
+        pattern: Regex::new(r"\[(@[^\[\]]+)\]").unwrap(),
+    }))
+}
+
+fn escaped(text: &str) -> String {
+    let mut out = String::new();
+    let _ = escape_html(&mut out, text);
+    out
+}
+
+impl Reference {
+    // `Knuth`, `Knuth & Levy` or `Knuth et al.`
+    fn short_authors(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone().unwrap_or_else(|| self.id.to_string()),
+            [one] => one.to_string(),
+            [one, two] => format!("{one} & {two}"),
+            [one, ..] => format!("{one} et al."),
+        }
+    }
+
+    fn html(&self) -> String {
+        let mut html = escaped(&self.authors.join(", "));
+        if let Some(year) = &self.year {
+            html.push_str(&format!(" ({})", escaped(year)));
+        }
+        if !html.is_empty() {
+            html.push_str(". ");
+        }
+        if let Some(title) = &self.title {
+            html.push_str(&format!("<em>{}</em>. ", escaped(title)));
+        }
+        if let Some(container) = &self.container {
+            html.push_str(&format!("{}. ", escaped(container)));
+        }
+        if let Some(url) = &self.url {
+            let url = escaped(url);
+            html.push_str(&format!("<a href=\"{url}\">{url}</a>"));
+        }
+        html.trim_end().to_string()
+    }
+}
+
+impl Bibliography {
+    /// Whether `markdown` cites anything, known or not.
+    pub fn cites(&self, markdown: &str) -> bool {
+        self.pattern.is_match(markdown)
+    }
+
+    fn get(&self, id: &str) -> Option<&Reference> {
+        self.references.iter().find(|r| r.id == id)
+    }
+
+    // `[@knuth84, p. 4; @lamport94]` as a linked author-year citation, or
+    // `None` if any key is unknown
+    fn cite(&self, keys: &str, page: &str, cited: &mut Vec<String>) -> Option<String> {
+        let mut parts = Vec::new();
+        for key in keys.split(';') {
+            let key = key.trim().strip_prefix('@')?;
+            let (id, locator) = match key.split_once(',') {
+                Some((id, locator)) => (id.trim(), Some(locator.trim())),
+                None => (key.trim(), None),
+            };
+
+            let reference = match self.get(id) {
+                Some(reference) => reference,
+                None => {
+                    Diagnostic::warning(format!("Unknown citation @{id}"))
+                        .in_file(page)
+                        .print();
+                    return None;
+                }
+            };
+            if !cited.iter().any(|c| c == id) {
+                cited.push(id.to_string());
+            }
+
+            let mut text = reference.short_authors();
+            if let Some(year) = &reference.year {
+                text.push_str(&format!(" {year}"));
+            }
+            if let Some(locator) = locator {
+                text.push_str(&format!(", {locator}"));
+            }
+            parts.push(format!(
+                "<a href=\"#ref-{}\">{}</a>",
+                escaped(id),
+                escaped(&text)
+            ));
+        }
+
+        Some(format!("<cite>({})</cite>", parts.join("; ")))
+    }
+
+    fn flush(&self, text: &mut String, page: &str, cited: &mut Vec<String>, out: &mut Vec<Event>) {
+        if text.is_empty() {
+            return;
+        }
+
+        if !self.pattern.is_match(text) {
+            out.push(Event::Text(CowStr::from(std::mem::take(text))));
+            return;
+        }
+
+        let mut html = String::new();
+        let mut last = 0;
+        for captures in self.pattern.captures_iter(text) {
+            let (whole, keys) = match (captures.get(0), captures.get(1)) {
+                (Some(whole), Some(keys)) => (whole, keys),
+                _ => continue,
+            };
+            html.push_str(&escaped(&text[last..whole.start()]));
+            match self.cite(keys.as_str(), page, cited) {
+                Some(citation) => html.push_str(&citation),
+                None => html.push_str(&escaped(whole.as_str())),
+            }
+            last = whole.end();
+        }
+        html.push_str(&escaped(&text[last..]));
+        text.clear();
+
+        out.push(Event::Html(CowStr::from(html)));
+    }
+
+    /// Replaces `[@key]` citations with author-year links and appends the
+    /// references cited on the page, in the order they were first cited.
+    pub fn render<'a, I: Iterator<Item = Event<'a>>>(
+        &self,
+        events: I,
+        page: &str,
+    ) -> Vec<Event<'a>> {
+        let mut out = Vec::new();
+        let mut cited = Vec::new();
+        let mut text = String::new();
+        let mut in_code = false;
+
+        // markdown splits text around brackets, so neighbouring text is
+        // joined up before looking for citations
+        for event in events {
+            match event {
+                Event::Text(t) if !in_code => text.push_str(&t),
+                event => {
+                    self.flush(&mut text, page, &mut cited, &mut out);
+                    match &event {
+                        Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                        Event::End(Tag::CodeBlock(_)) => in_code = false,
+                        _ => {}
+                    }
+                    out.push(event);
+                }
+            }
+        }
+        self.flush(&mut text, page, &mut cited, &mut out);
+
+        if !cited.is_empty() {
+            let mut html = String::from("<section class=\"references\">\n<ol>\n");
+            for id in cited.iter() {
+                if let Some(reference) = self.get(id) {
+                    html.push_str(&format!(
+                        "<li id=\"ref-{}\">{}</li>\n",
+                        escaped(id),
+                        reference.html()
+                    ));
+                }
+            }
+            html.push_str("</ol>\n</section>\n");
+            out.push(Event::Html(CowStr::from(html)));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bibtex() {
+        let references = from_bibtex(
+            r#"@string{jan = "January"}
+@article{knuth84,
+  author = {Knuth, Donald E. and Leslie Lamport},
+  title = {Literate {P}rogramming},
+  journal = "The Computer Journal",
+  year = 1984,
+  doi = {10.1093/comjnl/27.2.97}
+}
+@comment{not a reference}
+@book{other, title = "Quoted {Title}", publisher = {A, B and C}}
+"#,
+        );
+        assert_eq!(references.len(), 2);
+
+        let knuth = &references[0];
+        assert_eq!(knuth.id, "knuth84");
+        assert_eq!(knuth.authors, ["Knuth", "Lamport"]);
+        assert_eq!(knuth.title.as_deref(), Some("Literate Programming"));
+        assert_eq!(knuth.container.as_deref(), Some("The Computer Journal"));
+        assert_eq!(knuth.year.as_deref(), Some("1984"));
+        assert_eq!(
+            knuth.url.as_deref(),
+            Some("https://doi.org/10.1093/comjnl/27.2.97")
+        );
+
+        let other = &references[1];
+        assert_eq!(other.id, "other");
+        assert!(other.authors.is_empty());
+        assert_eq!(other.title.as_deref(), Some("Quoted Title"));
+        assert_eq!(other.container.as_deref(), Some("A, B and C"));
+    }
+
+    #[test]
+    fn bibtex_values() {
+        let value = |text: &str| bibtex_value(&mut text.chars().peekable());
+        assert_eq!(value("{A {Nested} one}, rest"), "A Nested one");
+        assert_eq!(value("\"In {Quotes}\" }"), "In Quotes");
+        assert_eq!(value("2024,"), "2024");
+        assert_eq!(value("{Spread\n   over   lines}"), "Spread over lines");
+    }
+
+    #[test]
+    fn csl() {
+        let references = from_csl(
+            r#"[{
+  "id": "doe20",
+  "author": [{"family": "Doe", "given": "Jane"}, {"literal": "ACME"}],
+  "issued": {"date-parts": [[2020, 1]]},
+  "title": "A Paper",
+  "container-title": "A Journal",
+  "DOI": "10.1/x"
+}]"#,
+        )
+        .unwrap();
+        let doe = &references[0];
+        assert_eq!(doe.id, "doe20");
+        assert_eq!(doe.authors, ["Doe", "ACME"]);
+        assert_eq!(doe.year.as_deref(), Some("2020"));
+        assert_eq!(doe.container.as_deref(), Some("A Journal"));
+        assert_eq!(doe.url.as_deref(), Some("https://doi.org/10.1/x"));
+    }
+}
@@ -0,0 +1,639 @@
+use std::{collections::BTreeMap, fs, io};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::diagnostics::{self, Diagnostic};
+
+/// What a config value should be.
+pub enum Kind {
+    String,
+    Bool,
+    Integer,
+    /// A table with these keys
+    Table(&'static [(&'static str, Kind)]),
+    /// A table with any keys, each holding a `Kind`
+    Map(&'static Kind),
+    /// A table with anything in it, like `[extra]`
+    Any,
+    ArrayOf(&'static Kind),
+}
+
+const THEME: Kind = Kind::Table(&[("git", Kind::String), ("commit", Kind::String)]);
+
+const ENVIRONMENT: Kind = Kind::Table(&[("base_url", Kind::String)]);
+
+const MARKDOWN: Kind = Kind::Table(&[
+    ("heading_offset", Kind::Integer),
+    ("hard_breaks", Kind::Bool),
+    ("tables", Kind::Bool),
+    ("footnotes", Kind::Bool),
+    ("strikethrough", Kind::Bool),
+    ("tasklists", Kind::Bool),
+    ("smart_punctuation", Kind::Bool),
+    ("heading_attributes", Kind::Bool),
+]);
+
+const REPLACEMENT: Kind = Kind::Table(&[
+    ("pattern", Kind::String),
+    ("replacement", Kind::String),
+    ("regex", Kind::Bool),
+    ("paths", Kind::String),
+    ("stage", Kind::String),
+]);
+
+const LINT_RULES: Kind = Kind::Table(&[
+    ("bare_urls", Kind::String),
+    ("heading_increment", Kind::String),
+    ("duplicate_headings", Kind::String),
+    ("heading_punctuation", Kind::String),
+    ("line_length", Kind::String),
+    ("max_line_length", Kind::Integer),
+]);
+
+const IMAGES: Kind = Kind::Table(&[
+    ("widths", Kind::ArrayOf(&Kind::Integer)),
+    ("formats", Kind::ArrayOf(&Kind::String)),
+]);
+
+const FEED_SECTION: Kind = Kind::Table(&[("content", Kind::String)]);
+
+const FEED_LANGUAGE: Kind = Kind::Table(&[("enabled", Kind::Bool), ("title", Kind::String)]);
+
+/// Every key roxy.toml can have.
+pub const SCHEMA: Kind = Kind::Table(&[
+    ("title", Kind::String),
+    ("base_url", Kind::String),
+    ("theme", Kind::String),
+    ("highlight_theme", Kind::String),
+    ("output", Kind::String),
+    ("content", Kind::String),
+    ("layouts", Kind::String),
+    ("i18n", Kind::String),
+    ("data", Kind::String),
+    ("includes", Kind::String),
+    ("icons", Kind::String),
+    ("default_language", Kind::String),
+    ("languages", Kind::ArrayOf(&Kind::String)),
+    ("autoescape", Kind::ArrayOf(&Kind::String)),
+    ("safe_html", Kind::ArrayOf(&Kind::String)),
+    ("feeds", Kind::Bool),
+    ("minify", Kind::Bool),
+    ("page_data", Kind::String),
+    ("search_index", Kind::Bool),
+    ("robots", Kind::Bool),
+    ("check_links", Kind::Bool),
+    ("strict", Kind::Bool),
+    ("lint", Kind::Bool),
+    ("checksums", Kind::Bool),
+    ("subset_fonts", Kind::Bool),
+    ("headers", Kind::Bool),
+    ("feed_links", Kind::Bool),
+    ("s3_endpoint", Kind::String),
+    ("s3_cache_control", Kind::String),
+    ("extra", Kind::Any),
+    ("themes", Kind::Map(&THEME)),
+    ("environments", Kind::Map(&ENVIRONMENT)),
+    ("replacements", Kind::ArrayOf(&REPLACEMENT)),
+    ("markdown", MARKDOWN),
+    ("images", IMAGES),
+    ("lint_rules", LINT_RULES),
+    ("permalinks", Kind::Map(&Kind::String)),
+    ("csp", Kind::Map(&Kind::ArrayOf(&Kind::String))),
+    ("feed_languages", Kind::Map(&FEED_LANGUAGE)),
+    ("feed_sections", Kind::Map(&FEED_SECTION)),
+]);
+
+// keys that were renamed, as (old, new)
+const DEPRECATED: &[(&str, &str)] = &[];
+
+fn describe(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::String => "a string",
+        Kind::Bool => "true or false",
+        Kind::Integer => "a whole number",
+        Kind::Table(_) | Kind::Map(_) | Kind::Any => "a table",
+        Kind::ArrayOf(_) => "an array",
+    }
+}
+
+/// The number of single character edits between `a` and `b`.
+pub fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn suggestion(key: &str, keys: &[(&'static str, Kind)]) -> Option<&'static str> {
+    let key = key.to_lowercase().replace('-', "_");
+    keys.iter()
+        .map(|(name, _)| (distance(&key, name), *name))
+        .filter(|(distance, name)| *distance <= 2.max(name.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// A problem with one setting, by its dotted path.
+struct Problem {
+    path: String,
+    message: String,
+    label: Option<String>,
+}
+
+fn validate(value: &toml::Value, kind: &Kind, path: &str, problems: &mut Vec<Problem>) {
+    let at = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+
+    match (kind, value) {
+        (Kind::String, toml::Value::String(_)) | (Kind::Bool, toml::Value::Boolean(_)) => {}
+        (Kind::Integer, toml::Value::Integer(_)) => {}
+        (Kind::Any, toml::Value::Table(_)) => {}
+        (Kind::Table(keys), toml::Value::Table(table)) => {
+            for (key, value) in table.iter() {
+                if let Some((_, new)) = DEPRECATED.iter().find(|(old, _)| old == key) {
+                    problems.push(Problem {
+                        path: at(key),
+                        message: format!("`{}` has been renamed to `{}`", at(key), at(new)),
+                        label: Some(format!("use `{new}`")),
+                    });
+                    continue;
+                }
+
+                match keys.iter().find(|(name, _)| name == key) {
+                    Some((_, kind)) => validate(value, kind, &at(key), problems),
+                    None => problems.push(Problem {
+                        path: at(key),
+                        message: format!("Unknown setting `{}`", at(key)),
+                        label: suggestion(key, keys).map(|name| format!("did you mean `{name}`?")),
+                    }),
+                }
+            }
+        }
+        (Kind::Map(kind), toml::Value::Table(table)) => {
+            for (key, value) in table.iter() {
+                validate(value, kind, &at(key), problems);
+            }
+        }
+        (Kind::ArrayOf(kind), toml::Value::Array(items)) => {
+            for (i, value) in items.iter().enumerate() {
+                validate(value, kind, &format!("{path}[{i}]"), problems);
+            }
+        }
+        (kind, value) => problems.push(Problem {
+            path: path.to_string(),
+            message: format!(
+                "`{path}` should be {}, not {}",
+                describe(kind),
+                value.type_str()
+            ),
+            label: Some(format!("expected {}", describe(kind))),
+        }),
+    }
+}
+
+// finds the line setting `path`, either `key = ...` inside its table or the
+// table's own `[header]`
+fn locate<'a>(text: &'a str, path: &str) -> Option<(usize, &'a str)> {
+    let path = path
+        .split('.')
+        .map(|part| part.split('[').next().unwrap_or(part))
+        .collect::<Vec<_>>();
+    let (key, parent) = path.split_last()?;
+    let parent = parent.join(".");
+    let whole = path.join(".");
+
+    let mut table = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            table = trimmed
+                .trim_matches(|c| c == '[' || c == ']')
+                .trim()
+                .to_string();
+            if table == whole {
+                return Some((i + 1, line));
+            }
+            continue;
+        }
+
+        let name = trimmed
+            .split('=')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .trim_matches('"');
+        if table == parent && name == *key && trimmed.contains('=') {
+            return Some((i + 1, line));
+        }
+    }
+
+    None
+}
+
+/// Checks the site config against `SCHEMA`, returning a diagnostic for every
+/// unknown key, renamed key and value of the wrong type. A missing config is
+/// fine.
+pub fn check(path: &str) -> Vec<Diagnostic> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => return vec![Diagnostic::error(format!("Couldn't read {path}: {err}"))],
+    };
+
+    let value = match toml::from_str::<toml::Value>(&text) {
+        Ok(value) => value,
+        Err(err) => {
+            let diagnostic = Diagnostic::error(err.message().to_string());
+            return vec![match err.span() {
+                Some(span) => diagnostic.at(path, &text, span.start, span.len()),
+                None => diagnostic.in_file(path),
+            }];
+        }
+    };
+
+    let mut problems = Vec::new();
+    validate(&value, &SCHEMA, "", &mut problems);
+    problems
+        .into_iter()
+        .map(|problem| {
+            let diagnostic = match locate(&text, &problem.path) {
+                Some((line, text)) => Diagnostic::error(problem.message).at_line(path, line, text),
+                None => Diagnostic::error(problem.message).in_file(path),
+            };
+            match problem.label {
+                Some(label) => diagnostic.label(label),
+                None => diagnostic,
+            }
+        })
+        .collect()
+}
+
+/// The settings of one `[environments.<name>]` of the site config.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentConfig {
+    /// The `base_url` of builds for the environment
+    pub base_url: Option<String>,
+}
+
+/// The settings of the site config that stand in for command line
+/// options, and those given to layouts as `site.*`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub title: Option<String>,
+    pub base_url: Option<String>,
+    /// The theme package, like `--site-theme`
+    pub theme: Option<String>,
+    /// Like `--theme`
+    pub highlight_theme: Option<String>,
+    pub output: Option<String>,
+    pub content: Option<String>,
+    pub layouts: Option<String>,
+    pub i18n: Option<String>,
+    pub data: Option<String>,
+    pub includes: Option<String>,
+    pub icons: Option<String>,
+    pub default_language: Option<String>,
+    /// Like `--languages`
+    pub languages: Option<Vec<String>>,
+    /// Like `--autoescape`
+    pub autoescape: Option<Vec<String>>,
+    /// Like `--safe-html`
+    pub safe_html: Option<Vec<String>>,
+    /// Like `--minify`
+    pub minify: Option<bool>,
+    /// Like `--search-index`
+    pub search_index: Option<bool>,
+    /// Like `--robots`
+    pub robots: Option<bool>,
+    /// Like `--check-links`
+    pub check_links: Option<bool>,
+    /// Like `--strict`
+    pub strict: Option<bool>,
+    /// Like `--lint`
+    pub lint: Option<bool>,
+    /// Like `--checksums`
+    pub checksums: Option<bool>,
+    /// Like `--subset-fonts`
+    pub subset_fonts: Option<bool>,
+    /// Like `--headers`
+    pub headers: Option<bool>,
+    /// Like `--feed-links`
+    pub feed_links: Option<bool>,
+    /// Like `--s3-endpoint`
+    pub s3_endpoint: Option<String>,
+    /// Like `--s3-cache-control`
+    pub s3_cache_control: Option<String>,
+    /// Like `--page-data`
+    pub page_data: Option<String>,
+    pub extra: toml::Table,
+    /// Settings of each `--env`
+    pub environments: BTreeMap<String, EnvironmentConfig>,
+}
+
+/// Reads the site config. One that is missing or invalid reads as empty;
+/// `check` reports why.
+pub fn load(path: &str) -> SiteConfig {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|text| toml::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// The `key` table of the site config at `path`, read the way its module
+/// needs it. A missing config or table is the default.
+pub fn table<T: DeserializeOwned + Default>(path: &str, key: &str) -> io::Result<T> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(err) => return Err(err),
+    };
+    let invalid =
+        |err: toml::de::Error| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {err}"));
+    let mut config: toml::Table = toml::from_str(&text).map_err(invalid)?;
+    match config.remove(key) {
+        Some(value) => value.try_into().map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path}: `{key}`: {err}"),
+            )
+        }),
+        None => Ok(T::default()),
+    }
+}
+
+// what each top level key does, and its default
+const ABOUT: &[(&str, &str, &str)] = &[
+    ("title", "The site's title", "unset"),
+    ("base_url", "Where the site is served from", "unset"),
+    ("theme", "Theme package to build with", "unset"),
+    (
+        "highlight_theme",
+        "Code highlighting theme",
+        "base16-ocean.dark",
+    ),
+    (
+        "output",
+        "Where the site is built to, a folder, `s3://` bucket or `git:` branch",
+        "build/",
+    ),
+    (
+        "content",
+        "The content folder, or an archive of it",
+        "content/",
+    ),
+    ("layouts", "The layouts folder", "layouts/"),
+    ("i18n", "The translations folder", "i18n/"),
+    ("data", "The site data folder", "data/"),
+    (
+        "includes",
+        "Html snippets inlined with include_html",
+        "includes/",
+    ),
+    ("icons", "SVG icons drawn with icon", "icons/"),
+    (
+        "default_language",
+        "Language of pages without a `lang`",
+        "en",
+    ),
+    (
+        "languages",
+        "Languages pages' paths can put them in",
+        "those in the i18n folder",
+    ),
+    (
+        "autoescape",
+        "Layout extensions whose pages get their frontmatter html escaped",
+        "[]",
+    ),
+    (
+        "safe_html",
+        "Frontmatter fields trusted to hold html, never escaped",
+        "[]",
+    ),
+    ("feeds", "Generate feeds", "false"),
+    (
+        "minify",
+        "Minify the html, CSS and JavaScript written",
+        "false",
+    ),
+    (
+        "search_index",
+        "Write search_index.json for search in the browser",
+        "false",
+    ),
+    (
+        "robots",
+        "Write a robots.txt allowing every crawler",
+        "false",
+    ),
+    (
+        "check_links",
+        "Fail the build on links to pages or files that weren't written",
+        "false",
+    ),
+    (
+        "strict",
+        "Stop the build at the first page that fails to render",
+        "false",
+    ),
+    (
+        "lint",
+        "Lint the markdown of pages as they're compiled",
+        "false",
+    ),
+    (
+        "checksums",
+        "Write SHA256SUMS with the hash of every file",
+        "false",
+    ),
+    (
+        "subset_fonts",
+        "Subset the site's fonts to the characters its pages use",
+        "false",
+    ),
+    (
+        "headers",
+        "Write a _headers file with each page's headers",
+        "false",
+    ),
+    (
+        "feed_links",
+        "Link to each page's feeds from its <head>",
+        "false",
+    ),
+    (
+        "s3_endpoint",
+        "The S3-compatible service an `s3://` output is uploaded to",
+        "AWS",
+    ),
+    (
+        "s3_cache_control",
+        "Cache-Control of uploaded files besides pages",
+        "public, max-age=3600",
+    ),
+    (
+        "page_data",
+        "How much of every page `data` holds: metadata, summary or full",
+        "full",
+    ),
+    ("extra", "Settings available to layouts as site.<key>", "{}"),
+    (
+        "themes",
+        "Themes installed from git, pinned to a commit",
+        "{}",
+    ),
+    (
+        "environments",
+        "The base_url of each --env, checked for in other builds",
+        "{}",
+    ),
+    ("replacements", "Substitution rules for pages", "[]"),
+    (
+        "markdown",
+        "Markdown extensions and options, which pages can override",
+        "{}",
+    ),
+    (
+        "images",
+        "Widths and formats static images are resized and converted to",
+        "{}",
+    ),
+    (
+        "lint_rules",
+        "The level of each markdown lint rule: off, warning or error",
+        "{}",
+    ),
+    (
+        "permalinks",
+        "Url patterns for pages, by section or \"*\" for all",
+        "{}",
+    ),
+    (
+        "csp",
+        "Sources added to directives of pages' Content-Security-Policy",
+        "{}",
+    ),
+];
+
+// options the site config can set, as (option id, config key)
+const OPTION_KEYS: &[(&str, &str)] = &[
+    ("output", "output"),
+    ("content", "content"),
+    ("layouts", "layouts"),
+    ("theme", "highlight_theme"),
+    ("i18n", "i18n"),
+    ("data", "data"),
+    ("includes", "includes"),
+    ("icons", "icons"),
+    ("default_language", "default_language"),
+    ("languages", "languages"),
+    ("autoescape", "autoescape"),
+    ("safe_html", "safe_html"),
+    ("site_theme", "theme"),
+    ("minify", "minify"),
+    ("page_data", "page_data"),
+    ("search_index", "search_index"),
+    ("robots", "robots"),
+    ("check_links", "check_links"),
+    ("strict", "strict"),
+    ("lint", "lint"),
+    ("checksums", "checksums"),
+    ("subset_fonts", "subset_fonts"),
+    ("headers", "headers"),
+    ("feed_links", "feed_links"),
+    ("s3_endpoint", "s3_endpoint"),
+    ("s3_cache_control", "s3_cache_control"),
+];
+
+fn source(matches: &clap::ArgMatches, id: &str) -> &'static str {
+    match matches.value_source(id) {
+        Some(clap::parser::ValueSource::CommandLine) => "command line",
+        Some(clap::parser::ValueSource::EnvVariable) => "environment",
+        Some(clap::parser::ValueSource::DefaultValue) => "default",
+        _ => "unset",
+    }
+}
+
+/// Prints every setting of the config file and every option, each with
+/// its default, its current value and where that value came from.
+pub fn explain(path: &str, command: &clap::Command, matches: &clap::ArgMatches) {
+    let file = fs::read_to_string(path)
+        .ok()
+        .and_then(|text| toml::from_str::<toml::Table>(&text).ok())
+        .unwrap_or_default();
+
+    diagnostics::info(path);
+    for (key, about, default) in ABOUT.iter() {
+        diagnostics::info(format!("  {key}  {about} (default: {default})"));
+        match file.get(*key) {
+            Some(value) => diagnostics::info(format!("    = {value}  ({path})")),
+            None => diagnostics::info(format!("    = {default}  (default)")),
+        }
+    }
+
+    diagnostics::info("");
+    diagnostics::info("Options");
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let long = match arg.get_long() {
+            Some(long) => long,
+            None => continue,
+        };
+        if matches!(id, "help" | "version") {
+            continue;
+        }
+
+        let about = arg
+            .get_help()
+            .map(|help| help.to_string())
+            .unwrap_or_default();
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy().to_string())
+            .collect();
+        let values: Vec<String> = matches
+            .get_raw(id)
+            .map(|values| values.map(|v| v.to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+
+        if defaults.is_empty() {
+            diagnostics::info(format!("  --{long}  {about}"));
+        } else {
+            diagnostics::info(format!(
+                "  --{long}  {about} (default: {})",
+                defaults.join(", ")
+            ));
+        }
+        let from_file = OPTION_KEYS
+            .iter()
+            .find(|(option, _)| *option == id)
+            .and_then(|(_, key)| file.get(*key))
+            .filter(|_| !matches!(source(matches, id), "command line" | "environment"));
+        match from_file {
+            Some(value) => diagnostics::info(format!("    = {value}  ({path})")),
+            None => diagnostics::info(format!(
+                "    = {}  ({})",
+                values.join(", "),
+                source(matches, id)
+            )),
+        }
+    }
+}
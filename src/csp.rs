@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    sync::OnceLock,
+};
+
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tera::Value;
+
+use crate::integrity::base64;
+
+/// What `csp()` renders as, filled in with the page's policy once the page
+/// is rendered and minified, when its inline scripts and styles are final.
+/// The spaces keep the minifier from taking the quotes off the attribute.
+pub const PLACEHOLDER: &str = "roxy content security policy";
+
+/// Sources the site config adds to directives of every page's policy, from
+/// `[csp]`, like `script-src = ["https://plausible.io"]`.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Policy(BTreeMap<String, Vec<String>>);
+
+/// Reads the `[csp]` table of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<Policy> {
+    crate::config::table(config, "csp")
+}
+
+/// `csp()`, the Content-Security-Policy of the page it's rendered in, for
+/// `<meta http-equiv="Content-Security-Policy" content="{{ csp() }}">`.
+pub fn csp(_: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(PLACEHOLDER.to_string()))
+}
+
+fn hash(text: &str) -> String {
+    format!("'sha256-{}'", base64(&Sha256::digest(text.as_bytes())))
+}
+
+// `https://cdn.example.com` of `https://cdn.example.com/app.js`, if `url`
+// is on another site
+fn origin(url: &str) -> Option<String> {
+    let url = url.trim();
+    let (scheme, rest) = match url.strip_prefix("//") {
+        Some(rest) => ("https", rest),
+        None => {
+            let (scheme, rest) = url.split_once("://")?;
+            (scheme, rest)
+        }
+    };
+    if !matches!(scheme, "http" | "https") {
+        return None;
+    }
+    let host = rest
+        .split(['/', '?', '#'])
+        .next()
+        .filter(|host| !host.is_empty())?;
+    Some(format!("{scheme}://{host}"))
+}
+
+/// The value of the attribute `name` among a tag's `attributes`, however
+/// it's quoted.
+pub fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r#"(?:^|\s)([^\s=/>"']+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+    });
+    let c = pattern
+        .captures_iter(attributes)
+        .find(|c| c[1].eq_ignore_ascii_case(name))?;
+    c.get(2)
+        .or_else(|| c.get(3))
+        .or_else(|| c.get(4))
+        .map(|value| value.as_str())
+}
+
+impl Policy {
+    /// The policy of a page, worked out from its `html`: its own scripts,
+    /// stylesheets and images, the hashes of its inline scripts and styles,
+    /// the other sites it loads them and frames from, and the config's
+    /// sources.
+    pub fn for_page(&self, html: &str) -> String {
+        let mut directives: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+        let mut add = |directive: &'static str, source: String| {
+            directives.entry(directive).or_default().insert(source);
+        };
+        for directive in ["script-src", "style-src", "img-src"] {
+            add(directive, "'self'".to_string());
+        }
+        add("img-src", "data:".to_string());
+
+        let scripts = Regex::new(r"(?is)<script\b([^>]*)>(.*?)</script\s*>").unwrap();
+        for c in scripts.captures_iter(html) {
+            let attributes = &c[1];
+            // data like json-ld isn't run, so the policy doesn't cover it
+            let kind = attribute(attributes, "type").unwrap_or_default();
+            if kind.to_ascii_lowercase().contains("json") {
+                continue;
+            }
+            match attribute(attributes, "src") {
+                Some(src) => add(
+                    "script-src",
+                    origin(src).unwrap_or_else(|| "'self'".to_string()),
+                ),
+                None if !c[2].trim().is_empty() => add("script-src", hash(&c[2])),
+                None => {}
+            }
+        }
+
+        // style attributes can't be allowed by hash, and hashes would turn
+        // `'unsafe-inline'` off, so pages with them allow every inline style
+        let style_attributes = Regex::new(r#"(?i)<[a-z][^>]*\sstyle\s*="#).unwrap();
+        if style_attributes.is_match(html) {
+            add("style-src", "'unsafe-inline'".to_string());
+        } else {
+            let styles = Regex::new(r"(?is)<style\b[^>]*>(.*?)</style\s*>").unwrap();
+            for c in styles.captures_iter(html) {
+                add("style-src", hash(&c[1]));
+            }
+        }
+
+        let tags = Regex::new(r"(?is)<(link|img|source|iframe|video|audio)\b([^>]*)>").unwrap();
+        for c in tags.captures_iter(html) {
+            let attributes = &c[2];
+            let (directive, url) = match c[1].to_ascii_lowercase().as_str() {
+                "link" => {
+                    let rel = attribute(attributes, "rel").unwrap_or_default();
+                    match rel.to_ascii_lowercase().as_str() {
+                        "stylesheet" => ("style-src", attribute(attributes, "href")),
+                        "icon" | "apple-touch-icon" => ("img-src", attribute(attributes, "href")),
+                        _ => continue,
+                    }
+                }
+                "iframe" => ("frame-src", attribute(attributes, "src")),
+                "video" | "audio" => ("media-src", attribute(attributes, "src")),
+                _ => ("img-src", attribute(attributes, "src")),
+            };
+            if let Some(origin) = url.and_then(origin) {
+                add(directive, origin);
+            }
+        }
+
+        for (directive, sources) in self.0.iter() {
+            let entry = directives.entry(directive.as_str()).or_default();
+            if entry.is_empty() {
+                entry.insert("'self'".to_string());
+            }
+            entry.extend(sources.iter().cloned());
+        }
+        // `'self'` first, then other keywords, sites and hashes
+        let order = |source: &String| match source.as_str() {
+            "'self'" => 0,
+            source if source.starts_with("'sha") => 3,
+            source if source.starts_with('\'') => 1,
+            _ => 2,
+        };
+
+        let mut policy = vec![
+            "default-src 'self'".to_string(),
+            "object-src 'none'".to_string(),
+            "base-uri 'self'".to_string(),
+        ];
+        for (directive, sources) in directives.iter() {
+            let mut sources: Vec<&String> = sources.iter().collect();
+            sources.sort_by_key(|source| order(source));
+            let sources: Vec<&str> = sources.iter().map(|source| source.as_str()).collect();
+            policy.push(format!("{directive} {}", sources.join(" ")));
+        }
+        policy.join("; ")
+    }
+
+    /// `html` with the `csp()` calls in it filled in with its policy,
+    /// escaped to go in an attribute quoted either way.
+    pub fn fill(&self, html: String) -> String {
+        if !html.contains(PLACEHOLDER) {
+            return html;
+        }
+        let policy = self.for_page(&html).replace('\'', "&#39;");
+        html.replace(PLACEHOLDER, &policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes() {
+        let tag = r#" src="/app.js" TYPE='module' data-x=1 async"#;
+        assert_eq!(attribute(tag, "src"), Some("/app.js"));
+        assert_eq!(attribute(tag, "type"), Some("module"));
+        assert_eq!(attribute(tag, "data-x"), Some("1"));
+        assert_eq!(attribute(tag, "async"), None);
+        // only attributes, not what's in their values
+        assert_eq!(attribute(r#" alt="a src=b""#, "src"), None);
+    }
+
+    #[test]
+    fn origins() {
+        assert_eq!(
+            origin("https://cdn.example.com/app.js?v=1").as_deref(),
+            Some("https://cdn.example.com")
+        );
+        assert_eq!(
+            origin("//cdn.example.com/app.js").as_deref(),
+            Some("https://cdn.example.com")
+        );
+        assert_eq!(origin("/app.js"), None);
+        assert_eq!(origin("data:image/png;base64,AAAA"), None);
+        assert_eq!(origin("ftp://example.com/file"), None);
+    }
+
+    #[test]
+    fn pages() {
+        let html = r#"<link rel="stylesheet" href="https://fonts.example.com/a.css">
+<script src="/app.js"></script>
+<script src="https://cdn.example.com/lib.js"></script>
+<script type="application/ld+json">{"a": 1}</script>
+<img src="https://images.example.com/a.png">
+<iframe src="https://www.youtube.com/embed/x"></iframe>"#;
+        assert_eq!(
+            Policy::default().for_page(html),
+            "default-src 'self'; object-src 'none'; base-uri 'self'; \
+             frame-src https://www.youtube.com; \
+             img-src 'self' data: https://images.example.com; \
+             script-src 'self' https://cdn.example.com; \
+             style-src 'self' https://fonts.example.com"
+        );
+    }
+
+    #[test]
+    fn inline_scripts_and_styles() {
+        let html = "<script>run()</script><style>p{}</style>";
+        let policy = Policy::default().for_page(html);
+        assert!(policy.contains(&format!("script-src 'self' {}", hash("run()"))));
+        assert!(policy.contains(&format!("style-src 'self' {}", hash("p{}"))));
+
+        let html = r#"<p style="color: red"></p><style>p{}</style>"#;
+        let policy = Policy::default().for_page(html);
+        assert!(
+            policy.contains("style-src 'self' 'unsafe-inline'"),
+            "{policy}"
+        );
+        assert!(!policy.contains("sha256"), "{policy}");
+    }
+
+    #[test]
+    fn config_sources() {
+        let policy = Policy(BTreeMap::from([
+            (
+                "script-src".to_string(),
+                vec!["https://plausible.io".to_string()],
+            ),
+            (
+                "connect-src".to_string(),
+                vec!["https://api.example.com".to_string()],
+            ),
+        ]));
+        let policy = policy.for_page("");
+        assert!(
+            policy.contains("script-src 'self' https://plausible.io"),
+            "{policy}"
+        );
+        assert!(
+            policy.contains("connect-src 'self' https://api.example.com"),
+            "{policy}"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{build_site, diagnostics, interrupt, lock, schedule, Options};
+
+/// How the last build went.
+#[derive(Serialize)]
+struct LastBuild {
+    /// The exit code a one-off build would have had
+    code: i32,
+    finished_at: String,
+    duration_ms: u128,
+    pages: usize,
+}
+
+#[derive(Default)]
+struct State {
+    /// Build requests waiting; one build covers all of them
+    queued: usize,
+    building: bool,
+    builds: u64,
+    last: Option<LastBuild>,
+    pages: Vec<(lock::RenderedPage, bool)>,
+    shutdown: bool,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Queue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // a build that panicked leaves the state as it was, which is fine
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// builds whenever there are requests queued, until shut down
+fn work(opts: &Options, queue: &Queue) {
+    loop {
+        {
+            let mut state = queue.lock();
+            while state.queued == 0 && !state.shutdown {
+                state = queue
+                    .wake
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner());
+            }
+            if state.shutdown {
+                return;
+            }
+            state.queued = 0;
+            state.building = true;
+        }
+
+        diagnostics::reset();
+        let started = Instant::now();
+        let pages = match build_site(opts) {
+            Ok(pages) => pages,
+            Err(err) => {
+                diagnostics::report(&err);
+                Vec::new()
+            }
+        };
+
+        let mut state = queue.lock();
+        state.building = false;
+        state.builds += 1;
+        state.last = Some(LastBuild {
+            code: diagnostics::exit_code(opts.max_warnings),
+            finished_at: schedule::now(),
+            duration_ms: started.elapsed().as_millis(),
+            pages: pages.len(),
+        });
+        state.pages = pages;
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+// reads the request line, skipping the headers and body
+fn read_request(stream: &TcpStream) -> io::Result<(String, String)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let _ = io::copy(&mut reader.take(length), &mut io::sink());
+
+    Ok((method, path))
+}
+
+// answers one request, returning whether to shut down
+fn handle(mut stream: TcpStream, queue: &Queue) -> io::Result<bool> {
+    let (method, path) = read_request(&stream)?;
+    let path = path.split('?').next().unwrap_or_default();
+
+    match (method.as_str(), path) {
+        ("POST", "/build") => {
+            let mut state = queue.lock();
+            state.queued += 1;
+            queue.wake.notify_one();
+            respond(
+                &mut stream,
+                "202 Accepted",
+                &json!({ "queued": state.queued, "building": state.building }),
+            )?;
+        }
+        ("GET", "/status") => {
+            let state = queue.lock();
+            respond(
+                &mut stream,
+                "200 OK",
+                &json!({
+                    "state": if state.building { "building" } else { "idle" },
+                    "queued": state.queued,
+                    "builds": state.builds,
+                    "last": state.last,
+                }),
+            )?;
+        }
+        ("GET", "/manifest") => {
+            let manifest = lock::manifest(&queue.lock().pages);
+            respond(&mut stream, "200 OK", &json!(manifest))?;
+        }
+        ("POST", "/shutdown") => {
+            let mut state = queue.lock();
+            state.shutdown = true;
+            queue.wake.notify_one();
+            respond(&mut stream, "200 OK", &json!({ "shutdown": true }))?;
+            return Ok(true);
+        }
+        (_, "/build" | "/status" | "/manifest" | "/shutdown") => {
+            respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                &json!({ "error": format!("{method} isn't allowed on {path}") }),
+            )?;
+        }
+        _ => respond(
+            &mut stream,
+            "404 Not Found",
+            &json!({ "error": format!("no such endpoint {path}") }),
+        )?,
+    }
+
+    Ok(false)
+}
+
+/// Listens on `address` for build requests, building one at a time in the
+/// background. Endpoints:
+///
+/// - `POST /build` queues a build; requests made while one is running are
+///   covered by a single build after it
+/// - `GET /status` is whether a build is running, how many are queued and
+///   how the last one went
+/// - `GET /manifest` is the SHA-256 of every page of the last build
+/// - `POST /shutdown` stops once the running build is done, as does Ctrl-C
+pub fn run(opts: &Options, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    interrupt::serving(opts, listener.local_addr()?);
+    diagnostics::info(format!("Listening on http://{address}"));
+
+    let queue = Queue {
+        state: Mutex::new(State::default()),
+        wake: Condvar::new(),
+    };
+
+    thread::scope(|scope| {
+        // the builds' problems count towards the command's
+        let tally = diagnostics::current_tally();
+        scope.spawn(|| diagnostics::counting(tally, || work(opts, &queue)));
+
+        for stream in listener.incoming() {
+            // Ctrl-C shuts down like `POST /shutdown`
+            if interrupt::requested(opts) {
+                queue.lock().shutdown = true;
+                queue.wake.notify_one();
+                break;
+            }
+            let shutdown = match stream {
+                Ok(stream) => handle(stream, &queue).unwrap_or_else(|err| {
+                    diagnostics::info(format!("Couldn't answer a request: {err}"));
+                    false
+                }),
+                Err(_) => false,
+            };
+            if shutdown {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
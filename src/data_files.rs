@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use glob::glob;
+use serde_json::{Map, Value};
+
+use crate::{diagnostics::Diagnostic, is_hidden};
+
+fn invalid(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {err}", path.to_string_lossy()),
+    )
+}
+
+// the fields of one line of `text` from `start`, and where the next starts;
+// quoted fields can hold commas, newlines and `""` for a quote
+fn csv_record(text: &str, start: usize) -> (Vec<String>, usize) {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text[start..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.next_if(|(_, c)| *c == '"').is_some() {
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                fields.push(field.trim_end_matches('\r').to_string());
+                return (fields, start + i + 1);
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim_end_matches('\r').to_string());
+    (fields, text.len())
+}
+
+// the rows of a csv file with a header line, as maps of the headers to
+// their fields
+fn csv(text: &str) -> Value {
+    let (headers, mut start) = csv_record(text, 0);
+    let mut rows = Vec::new();
+    while start < text.len() {
+        let (fields, next) = csv_record(text, start);
+        start = next;
+        if fields.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        let row: Map<String, Value> = headers
+            .iter()
+            .zip(fields.into_iter().chain(std::iter::repeat(String::new())))
+            .map(|(header, field)| (header.to_string(), Value::String(field)))
+            .collect();
+        rows.push(Value::Object(row));
+    }
+    Value::Array(rows)
+}
+
+fn read(path: &Path) -> io::Result<Option<Value>> {
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    if !matches!(ext, Some("toml" | "json" | "yaml" | "yml" | "csv")) {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    let value = match ext {
+        Some("toml") => toml::from_str::<toml::Value>(&text)
+            .map_err(|err| invalid(path, err))
+            .and_then(|value| serde_json::to_value(value).map_err(|err| invalid(path, err)))?,
+        Some("json") => serde_json::from_str(&text).map_err(|err| invalid(path, err))?,
+        Some("csv") => csv(&text),
+        _ => serde_yaml::from_str(&text).map_err(|err| invalid(path, err))?,
+    };
+    Ok(Some(value))
+}
+
+/// Reads every TOML, JSON, YAML and CSV file in the data folder, by file
+/// name without its extension, so `data/nav/main.toml` is `nav.main`. CSV
+/// files are a list of rows, each a map of the header line to its fields.
+pub fn load(data_dir: &str) -> io::Result<Map<String, Value>> {
+    let root = Path::new(data_dir.trim_end_matches('/'));
+    let pattern = format!("{}/**/*", glob::Pattern::escape(&root.to_string_lossy()));
+    let mut files: Vec<_> = glob(&pattern)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+
+    let mut data = Map::new();
+    for path in files.iter() {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        if relative.iter().any(is_hidden) {
+            continue;
+        }
+        let Some(value) = read(path)? else {
+            continue;
+        };
+
+        let mut keys: Vec<String> = relative
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.iter())
+            .map(|part| part.to_string_lossy().to_string())
+            .collect();
+        keys.extend(
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+        );
+        let Some(name) = keys.pop() else {
+            continue;
+        };
+
+        let mut map = &mut data;
+        for key in keys.iter() {
+            map = match map
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                Value::Object(map) => map,
+                _ => return Err(invalid(path, format!("`{key}` is already a data file"))),
+            };
+        }
+        if map.contains_key(&name) {
+            return Err(invalid(
+                path,
+                format!("`{name}` is already a data file or folder"),
+            ));
+        }
+        map.insert(name, value);
+    }
+    Ok(data)
+}
+
+/// The `data` layouts get: the site's pages by section, and the data files.
+/// A section has the name to itself, and a data file it hides is reported.
+pub fn with_pages(pages: HashMap<String, Vec<Value>>, files: &Map<String, Value>) -> Value {
+    let mut data: Map<String, Value> = pages
+        .into_iter()
+        .map(|(key, pages)| (key, Value::Array(pages)))
+        .collect();
+    for (key, value) in files.iter() {
+        if data.contains_key(key) {
+            Diagnostic::warning(format!("Data file `{key}` has the name of a section"))
+                .note(format!("`data.{key}` is the pages of the section"))
+                .print();
+            continue;
+        }
+        data.insert(key.to_string(), value.clone());
+    }
+    Value::Object(data)
+}
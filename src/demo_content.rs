@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const WORDS: [&str; 64] = [
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "et",
+    "dolore",
+    "magna",
+    "aliqua",
+    "enim",
+    "ad",
+    "minim",
+    "veniam",
+    "quis",
+    "nostrud",
+    "exercitation",
+    "ullamco",
+    "laboris",
+    "nisi",
+    "aliquip",
+    "ex",
+    "ea",
+    "commodo",
+    "consequat",
+    "duis",
+    "aute",
+    "irure",
+    "in",
+    "reprehenderit",
+    "voluptate",
+    "velit",
+    "esse",
+    "cillum",
+    "fugiat",
+    "nulla",
+    "pariatur",
+    "excepteur",
+    "sint",
+    "occaecat",
+    "cupidatat",
+    "non",
+    "proident",
+    "sunt",
+    "culpa",
+    "qui",
+    "officia",
+    "deserunt",
+    "mollit",
+    "anim",
+    "id",
+    "est",
+    "laborum",
+    "vitae",
+    "semper",
+];
+
+const SECTIONS: [&str; 3] = ["blog", "notes", "docs"];
+
+const CODE_SAMPLES: [(&str, &str); 3] = [
+    ("rust", "fn main() {\n    println!(\"Hello, Roxy!\");\n}"),
+    (
+        "js",
+        "const greet = (name) => `Hello, ${name}!`;\nconsole.log(greet(\"Roxy\"));",
+    ),
+    ("sh", "roxy --content ./content --output ./build"),
+];
+
+// small deterministic generator so the same seed always gives the same corpus
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn word(&mut self) -> &'static str {
+        WORDS[self.below(WORDS.len())]
+    }
+
+    fn sentence(&mut self, min: usize, max: usize) -> String {
+        let len = min + self.below(max - min + 1);
+        let mut words: Vec<String> = (0..len).map(|_| self.word().to_string()).collect();
+        if let Some(first) = words.first_mut() {
+            first[..1].make_ascii_uppercase();
+        }
+        format!("{}.", words.join(" "))
+    }
+
+    fn paragraph(&mut self) -> String {
+        let len = 2 + self.below(5);
+        (0..len)
+            .map(|_| self.sentence(6, 16))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+fn title_case(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let mut word = word.to_string();
+            if !word.is_empty() {
+                word[..1].make_ascii_uppercase();
+            }
+            word
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn page(rng: &mut Rng, index: usize, tags: &[String]) -> String {
+    let title_words: Vec<&str> = (0..2 + rng.below(4)).map(|_| rng.word()).collect();
+    let title = title_case(&title_words.join(" "));
+
+    let year = 2018 + rng.below(6);
+    let month = 1 + rng.below(12);
+    let day = 1 + rng.below(28);
+
+    let mut page_tags: Vec<&str> = Vec::new();
+    if !tags.is_empty() {
+        for _ in 0..1 + rng.below(3) {
+            let tag = tags[rng.below(tags.len())].as_str();
+            if !page_tags.contains(&tag) {
+                page_tags.push(tag);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("---\n");
+    out.push_str(&format!("title: {title}\n"));
+    out.push_str(&format!("date: {year:04}-{month:02}-{day:02}\n"));
+    out.push_str(&format!("description: {}\n", rng.sentence(8, 14)));
+    if !page_tags.is_empty() {
+        out.push_str(&format!("tags: {}\n", page_tags.join(", ")));
+    }
+    out.push_str(&format!("weight: {}\n", index + 1));
+    if rng.below(10) == 0 {
+        out.push_str("draft: true\n");
+    }
+    out.push_str("---\n");
+
+    for section in 0..1 + rng.below(4) {
+        if section > 0 {
+            out.push_str(&format!("\n## {}\n", title_case(&rng.sentence(2, 5))));
+        }
+
+        out.push_str(&format!("\n{}\n", rng.paragraph()));
+
+        match rng.below(4) {
+            0 => {
+                out.push('\n');
+                for _ in 0..2 + rng.below(4) {
+                    out.push_str(&format!("- {}\n", rng.sentence(3, 8)));
+                }
+            }
+            1 => {
+                let (lang, code) = CODE_SAMPLES[rng.below(CODE_SAMPLES.len())];
+                out.push_str(&format!("\n```{lang}\n{code}\n```\n"));
+            }
+            2 => out.push_str(&format!("\n> {}\n", rng.sentence(8, 20))),
+            _ => {}
+        }
+    }
+
+    out
+}
+
+/// Writes `pages` lorem ipsum pages spread over a few sections, tagged from a
+/// pool of `tags` terms. The same seed always produces the same content.
+pub fn generate(dir: &Path, pages: usize, tags: usize, seed: u64) -> io::Result<()> {
+    // xorshift gets stuck on zero
+    let mut rng = Rng(seed.wrapping_add(0x9E3779B97F4A7C15) | 1);
+    let tags: Vec<String> = (0..tags).map(|i| format!("{}-{i}", rng.word())).collect();
+
+    for section in SECTIONS.iter() {
+        let path = dir.join(section);
+        fs::create_dir_all(&path)?;
+
+        let mut file = fs::File::create(path.join("index.md"))?;
+        file.write_all(format!("---\ntitle: {}\n---\n", title_case(section)).as_bytes())?;
+    }
+
+    for index in 0..pages {
+        let section = SECTIONS[index % SECTIONS.len()];
+        let path = dir.join(section).join(format!("page-{index:04}.md"));
+        let mut file = fs::File::create(path)?;
+        file.write_all(page(&mut rng, index, &tags).as_bytes())?;
+    }
+
+    let mut file = fs::File::create(dir.join("index.md"))?;
+    file.write_all(
+        b"---\ntitle: Demo\n---\n# Demo content\n\nGenerated by `roxy demo-content`.\n",
+    )?;
+
+    Ok(())
+}
+
+pub fn default_dir(seed: u64) -> PathBuf {
+    std::env::temp_dir().join(format!("roxy-demo-{seed}"))
+}
@@ -0,0 +1,438 @@
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt::{self, Write as _},
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// What went wrong, for the exit code. Each kind has its own so CI can
+/// branch on it; 1 is left for anything else and 2 is clap's bad arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Config = 3,
+    Content = 4,
+    Template = 5,
+    Io = 6,
+    /// More warnings than `--max-warnings` allows
+    Warnings = 7,
+}
+
+/// How much is printed besides errors, from `--quiet` and `--verbose`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Verbosity {
+    #[default]
+    Normal = 0,
+    /// Errors only; warnings still count towards `--max-warnings`
+    Quiet = 1,
+    /// Every page as it's written, too
+    Verbose = 2,
+}
+
+/// The failures and warnings diagnostics count towards, so builds running
+/// in one process at the same time each have their own, and how much and
+/// where they print.
+#[derive(Debug, Default)]
+pub struct Tally {
+    // the first failure reported, which decides the exit code
+    failure: AtomicI32,
+    warnings: AtomicUsize,
+    verbosity: AtomicU8,
+    // whether messages go to stderr, see `to_stderr`
+    stderr: AtomicBool,
+}
+
+thread_local! {
+    // diagnostics printed on this thread, see `printed`
+    static PRINTED: Cell<usize> = const { Cell::new(0) };
+    // the tally of the build running on this thread, see `counting`
+    static TALLY: RefCell<Option<Arc<Tally>>> = const { RefCell::new(None) };
+    // what diagnostics count towards on a thread that isn't `counting`,
+    // which nothing reads
+    static UNCOUNTED: Tally = const {
+        Tally {
+            failure: AtomicI32::new(0),
+            warnings: AtomicUsize::new(0),
+            verbosity: AtomicU8::new(0),
+            stderr: AtomicBool::new(false),
+        }
+    };
+}
+
+fn with_tally<R>(f: impl FnOnce(&Tally) -> R) -> R {
+    TALLY.with(|tally| match tally.borrow().as_deref() {
+        Some(tally) => f(tally),
+        None => UNCOUNTED.with(f),
+    })
+}
+
+/// The tally diagnostics printed on this thread count towards, to hand to
+/// threads doing its work.
+pub fn current_tally() -> Option<Arc<Tally>> {
+    TALLY.with(|tally| tally.borrow().clone())
+}
+
+/// Runs `f` with the diagnostics printed on this thread counted in `tally`.
+/// Those printed without one count towards nothing.
+pub fn counting<R>(tally: Option<Arc<Tally>>, f: impl FnOnce() -> R) -> R {
+    let previous = TALLY.with(|current| current.replace(tally));
+    let result = f();
+    TALLY.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+impl Tally {
+    fn verbosity(&self) -> Verbosity {
+        match self.verbosity.load(Ordering::Relaxed) {
+            1 => Verbosity::Quiet,
+            2 => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
+
+    fn fail(&self, failure: Failure) {
+        let _ =
+            self.failure
+                .compare_exchange(0, failure as i32, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// What the first error counted was, or `Failure::Warnings` when there
+    /// were more than `max_warnings`.
+    pub fn failure(&self, max_warnings: Option<usize>) -> Option<Failure> {
+        let failure = match self.failure.load(Ordering::Relaxed) {
+            3 => Some(Failure::Config),
+            4 => Some(Failure::Content),
+            5 => Some(Failure::Template),
+            6 => Some(Failure::Io),
+            7 => Some(Failure::Warnings),
+            _ => None,
+        };
+        failure.or(match max_warnings {
+            Some(max) if self.warnings.load(Ordering::Relaxed) > max => Some(Failure::Warnings),
+            _ => None,
+        })
+    }
+}
+
+/// How many diagnostics this thread has printed, to tell whether some piece
+/// of work had any problems.
+pub fn printed() -> usize {
+    PRINTED.with(|printed| printed.get())
+}
+
+/// Prints the diagnostics of the command running on this thread to stderr
+/// from now on, for commands whose stdout is read by another program.
+pub fn to_stderr() {
+    with_tally(|tally| tally.stderr.store(true, Ordering::Relaxed));
+}
+
+/// Sets how much the build running on this thread prints.
+pub fn set_verbosity(verbosity: Verbosity) {
+    with_tally(|tally| tally.verbosity.store(verbosity as u8, Ordering::Relaxed));
+}
+
+fn printing_to_stderr() -> bool {
+    with_tally(|tally| tally.stderr.load(Ordering::Relaxed))
+}
+
+fn say(message: &str) {
+    if printing_to_stderr() {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// Prints what a command did, like where the site was written, unless it's
+/// `--quiet`.
+pub fn info(message: impl fmt::Display) {
+    if with_tally(Tally::verbosity) != Verbosity::Quiet {
+        say(&message.to_string());
+    }
+}
+
+/// Prints a step of a command with `--verbose`, like each page written.
+pub fn detail(message: impl fmt::Display) {
+    if with_tally(Tally::verbosity) == Verbosity::Verbose {
+        say(&message.to_string());
+    }
+}
+
+/// Ends a command whose problems have already been printed; see
+/// `reported`.
+#[derive(Debug)]
+pub struct Reported(pub Failure);
+
+impl fmt::Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed with {:?} errors", self.0)
+    }
+}
+
+impl Error for Reported {}
+
+/// An error to return once the diagnostics explaining it are printed, so
+/// commands can give up without exiting the process.
+pub fn reported(failure: Failure) -> io::Error {
+    with_tally(|tally| tally.fail(failure));
+    io::Error::new(io::ErrorKind::Other, Reported(failure))
+}
+
+/// Prints `err` as an IO error, unless it's one returned by `reported`.
+pub fn report(err: &io::Error) {
+    let reported = err
+        .get_ref()
+        .map(|inner| inner.is::<Reported>())
+        .unwrap_or(false);
+    if !reported {
+        Diagnostic::error(err.to_string())
+            .failure(Failure::Io)
+            .print();
+    }
+}
+
+/// Forgets the failures and warnings so far, for running another build in
+/// the same process.
+pub fn reset() {
+    with_tally(|tally| {
+        tally.failure.store(0, Ordering::Relaxed);
+        tally.warnings.store(0, Ordering::Relaxed);
+    });
+}
+
+/// Exits right away with the code for `failure`.
+pub fn exit(failure: Failure) -> ! {
+    ::std::process::exit(failure as i32)
+}
+
+/// The code to exit with once everything has run: that of the first error
+/// printed, `Failure::Warnings` when there were more than `max_warnings`,
+/// otherwise 0.
+pub fn exit_code(max_warnings: Option<usize>) -> i32 {
+    failure(max_warnings).map_or(0, |failure| failure as i32)
+}
+
+/// What the first error printed was, or `Failure::Warnings` when there
+/// were more than `max_warnings`.
+pub fn failure(max_warnings: Option<usize>) -> Option<Failure> {
+    with_tally(|tally| tally.failure(max_warnings))
+}
+
+/// Where in a file a diagnostic points, by line.
+#[derive(Debug)]
+struct Location {
+    file: String,
+    /// 1-based; 0 when only the file is known
+    line: usize,
+    /// 1-based
+    column: usize,
+    len: usize,
+    text: String,
+}
+
+/// A problem to show the user, with an excerpt of the file it's in and a
+/// caret under the offending part when that is known.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// What an error counts as; content unless said otherwise
+    failure: Failure,
+    location: Option<Location>,
+    label: Option<String>,
+    notes: Vec<String>,
+}
+
+fn colors() -> bool {
+    let terminal = if printing_to_stderr() {
+        io::stderr().is_terminal()
+    } else {
+        io::stdout().is_terminal()
+    };
+    std::env::var_os("NO_COLOR").is_none() && terminal
+}
+
+fn paint(text: &str, code: &str, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            failure: Failure::Content,
+            location: None,
+            label: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    pub fn failure(mut self, failure: Failure) -> Self {
+        self.failure = failure;
+        self
+    }
+
+    /// Points at `file` as a whole.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.location = Some(Location {
+            file: file.to_string(),
+            line: 0,
+            column: 0,
+            len: 0,
+            text: String::new(),
+        });
+        self
+    }
+
+    /// Points at `len` bytes from `offset` in `source`, the text of `file`.
+    pub fn at(mut self, file: &str, source: &str, offset: usize, len: usize) -> Self {
+        let offset = offset.min(source.len());
+        let start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let end = source[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or(source.len());
+
+        self.location = Some(Location {
+            file: file.to_string(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[start..offset].chars().count() + 1,
+            len: source[offset..end].chars().count().min(len).max(1),
+            text: source[start..end].to_string(),
+        });
+        self
+    }
+
+    /// Points at `line` (1-based) of `file`, which reads `text`.
+    pub fn at_line(mut self, file: &str, line: usize, text: &str) -> Self {
+        let indent = text.len() - text.trim_start().len();
+        self.location = Some(Location {
+            file: file.to_string(),
+            line,
+            column: indent + 1,
+            len: text.trim().chars().count().max(1),
+            text: text.trim_end().to_string(),
+        });
+        self
+    }
+
+    /// Text shown next to the caret.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Adds every cause of `err`, which is how tera explains what went
+    /// wrong in a template.
+    pub fn causes(mut self, err: &dyn Error) -> Self {
+        let mut source = err.source();
+        while let Some(cause) = source {
+            self.notes.push(cause.to_string());
+            source = cause.source();
+        }
+        self
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let mut out = String::new();
+        let (name, code) = match self.severity {
+            Severity::Error => ("error", "1;31"),
+            Severity::Warning => ("warning", "1;33"),
+        };
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(name, code, color),
+            paint(&format!(": {}", self.message), "1", color)
+        );
+
+        if let Some(location) = &self.location {
+            let arrow = paint("-->", "34", color);
+            if location.line == 0 {
+                let _ = writeln!(out, "  {arrow} {}", location.file);
+            } else {
+                let number = location.line.to_string();
+                let pad = " ".repeat(number.len());
+                let bar = paint("|", "34", color);
+                let _ = writeln!(
+                    out,
+                    "{pad}{arrow} {}:{}:{}",
+                    location.file, location.line, location.column
+                );
+                let _ = writeln!(out, "{pad} {bar}");
+                let _ = writeln!(
+                    out,
+                    "{} {bar} {}",
+                    paint(&number, "34", color),
+                    location.text
+                );
+                let carets = format!(
+                    "{}{} {}",
+                    " ".repeat(location.column - 1),
+                    "^".repeat(location.len),
+                    self.label.as_deref().unwrap_or("")
+                );
+                let _ = writeln!(out, "{pad} {bar} {}", paint(carets.trim_end(), code, color));
+            }
+        }
+
+        for note in self.notes.iter() {
+            for (i, line) in note.lines().enumerate() {
+                if i == 0 {
+                    let _ = writeln!(out, "  {} {line}", paint("=", "34", color));
+                } else {
+                    let _ = writeln!(out, "    {line}");
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Prints the diagnostic and counts it towards the exit code. Warnings
+    /// are counted but not shown with `--quiet`.
+    pub fn print(&self) {
+        match self.severity {
+            Severity::Error => with_tally(|tally| tally.fail(self.failure)),
+            Severity::Warning => {
+                with_tally(|tally| tally.warnings.fetch_add(1, Ordering::Relaxed));
+            }
+        }
+        // a hidden warning is still a problem of the page it's about
+        PRINTED.with(|printed| printed.set(printed.get() + 1));
+        if self.severity == Severity::Warning && with_tally(Tally::verbosity) == Verbosity::Quiet {
+            return;
+        }
+        if printing_to_stderr() {
+            eprint!("{}", self.render(colors()));
+        } else {
+            print!("{}", self.render(colors()));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use tera::{Context, Map, Value};
+
+/// Which layouts get the frontmatter of pages html escaped, so a `<` in a
+/// title can't become markup, and the fields trusted to hold html.
+#[derive(Debug, Default)]
+pub struct Escaping {
+    /// Layout extensions, like `html`
+    extensions: Vec<String>,
+    /// Fields never escaped, besides those a page lists in its `safe_html`
+    safe_html: Vec<String>,
+}
+
+impl Escaping {
+    pub fn new(extensions: &[String], safe_html: &[String]) -> Self {
+        Self {
+            extensions: extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            safe_html: safe_html.to_vec(),
+        }
+    }
+
+    /// Whether pages rendered with `layout` get escaped frontmatter.
+    pub fn applies(&self, layout: &str) -> bool {
+        let name = layout.to_ascii_lowercase();
+        self.extensions
+            .iter()
+            .any(|ext| name.ends_with(&format!(".{ext}")))
+    }
+
+    /// `text` escaped, when `layout` gets escaped frontmatter.
+    pub fn text(&self, layout: &str, text: &str) -> String {
+        match self.applies(layout) {
+            true => tera::escape_html(text),
+            false => text.to_string(),
+        }
+    }
+
+    /// `context` with the string fields of every page's frontmatter in it
+    /// escaped, when `layout` gets escaped frontmatter: the page's own and
+    /// those of the pages listed in `data`, `languages` and the like.
+    pub fn context(&self, layout: &str, context: Context) -> tera::Result<Context> {
+        if !self.applies(layout) {
+            return Ok(context);
+        }
+        let mut value = context.into_json();
+        self.escape_pages(&mut value);
+        Context::from_value(value)
+    }
+
+    // every `frontmatter` table in `value`
+    fn escape_pages(&self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (key, value) in fields.iter_mut() {
+                    match value {
+                        Value::Object(frontmatter) if key == "frontmatter" => {
+                            self.escape_fields(frontmatter)
+                        }
+                        value => self.escape_pages(value),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.escape_pages(item)),
+            _ => {}
+        }
+    }
+
+    fn escape_fields(&self, frontmatter: &mut Map<String, Value>) {
+        let page_safe: Vec<String> = match frontmatter.get("safe_html") {
+            Some(Value::Array(fields)) => fields
+                .iter()
+                .filter_map(|field| field.as_str().map(str::to_string))
+                .collect(),
+            Some(Value::String(field)) => vec![field.to_string()],
+            _ => Vec::new(),
+        };
+        for (key, value) in frontmatter.iter_mut() {
+            if !self.safe_html.contains(key) && !page_safe.contains(key) {
+                escape_strings(value);
+            }
+        }
+    }
+}
+
+fn escape_strings(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = tera::escape_html(text),
+        Value::Array(items) => items.iter_mut().for_each(escape_strings),
+        Value::Object(fields) => fields.values_mut().for_each(escape_strings),
+        _ => {}
+    }
+}
@@ -0,0 +1,696 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{page_data, schedule, Content};
+
+/// Entries in each feed, newest first.
+const FEED_LENGTH: usize = 20;
+
+/// The feed of one language, from `[feed_languages.<lang>]` in the site
+/// config, like `title = "Mon site"` or `enabled = false`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LanguageFeed {
+    /// Whether the language gets a feed of its own
+    pub enabled: bool,
+    /// The feed's title, rather than the site's with the language after it
+    pub title: Option<String>,
+}
+
+impl Default for LanguageFeed {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            title: None,
+        }
+    }
+}
+
+/// What the entries of a section's pages carry of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// The page's whole content
+    #[default]
+    Full,
+    /// Only a summary of the page
+    Summary,
+}
+
+/// The entries of one top level section's pages, from
+/// `[feed_sections.<section>]` in the site config, like
+/// `content = "summary"`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct SectionFeed {
+    pub content: FeedContent,
+}
+
+/// How the site config says feeds are made.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    /// By language
+    pub languages: BTreeMap<String, LanguageFeed>,
+    /// By top level section
+    pub sections: BTreeMap<String, SectionFeed>,
+}
+
+/// Reads the feed settings of `config`, which are fine to be missing.
+pub fn load(config: &str) -> io::Result<Settings> {
+    Ok(Settings {
+        languages: crate::config::table(config, "feed_languages")?,
+        sections: crate::config::table(config, "feed_sections")?,
+    })
+}
+
+impl Settings {
+    /// The title of the feed of `lang`, if it has one.
+    fn language_title(&self, lang: &str, site_title: &str) -> Option<String> {
+        let feed = self.languages.get(lang).cloned().unwrap_or_default();
+        feed.enabled.then(|| {
+            feed.title
+                .unwrap_or_else(|| format!("{site_title} ({lang})"))
+        })
+    }
+
+    /// What the entry of `page` carries: what its `feed_content` field
+    /// says, or else what its section's settings do.
+    fn content(&self, page: &Content) -> FeedContent {
+        match page
+            .frontmatter
+            .0
+            .get("feed_content")
+            .map(|mode| mode.trim())
+        {
+            Some("summary") => FeedContent::Summary,
+            Some("full") => FeedContent::Full,
+            _ => page
+                .path
+                .split_once(std::path::MAIN_SEPARATOR_STR)
+                .and_then(|(section, _)| self.sections.get(section))
+                .map(|feed| feed.content)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// (year, month, day) to days since 1970-01-01, after Howard Hinnant's
+// `days_from_civil`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// a normalized date (`YYYY-MM-DDTHH:MM:SS`, UTC) as RSS wants it, like
+// `Wed, 01 May 2024 09:30:00 +0000`
+fn rfc822(date: &str) -> Option<String> {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (day, time) = date.split_once('T')?;
+    let mut parts = day.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    let weekday = DAYS[days_from_civil(year, month, day).rem_euclid(7) as usize];
+    let month_name = MONTHS.get(usize::try_from(month - 1).ok()?)?;
+
+    Some(format!(
+        "{weekday}, {day:02} {month_name} {year:04} {} +0000",
+        time.get(..8)?
+    ))
+}
+
+/// A dated page, as an entry in a feed.
+struct Entry<'a> {
+    title: &'a str,
+    url: String,
+    /// When it was published, normalized (see `schedule::normalize`)
+    date: String,
+    /// Its `updated` field, normalized, or else its `date`
+    updated: String,
+    summary: Option<String>,
+    /// Whether the summary is html, an excerpt of the page
+    summary_html: bool,
+    /// Left out for pages with `feed_content: summary`
+    content: Option<&'a str>,
+    /// Its tags, as the entry's categories
+    tags: Vec<String>,
+}
+
+/// The dated pages of `pages` already published, newest first.
+fn entries<'a>(
+    pages: &[&'a Content],
+    base_url: &str,
+    now: &str,
+    settings: &Settings,
+) -> Vec<Entry<'a>> {
+    let mut entries: Vec<Entry> = pages
+        .iter()
+        .filter_map(|page| {
+            let frontmatter = &page.frontmatter.0;
+            let date = schedule::normalize(frontmatter.get("date")?);
+            let summary_only = settings.content(page) == FeedContent::Summary;
+            let mut summary = frontmatter
+                .get("description")
+                .or_else(|| frontmatter.get("summary"))
+                .map(|summary| summary.to_string());
+            // a summary only entry has to say something of the page
+            let summary_html = summary_only && summary.is_none();
+            if summary_html {
+                summary = page_data::excerpt(&page.content);
+            }
+            Some(Entry {
+                title: frontmatter
+                    .get("title")
+                    .map(|title| title.as_str())
+                    .unwrap_or(&page.slug),
+                url: format!("{base_url}{}", page.slug),
+                updated: frontmatter
+                    .get("updated")
+                    .map_or_else(|| date.to_string(), |updated| schedule::normalize(updated)),
+                date,
+                summary,
+                summary_html,
+                content: (!summary_only).then_some(page.content.as_str()),
+                tags: page.frontmatter.list("tags").unwrap_or_default(),
+            })
+        })
+        .filter(|entry| entry.date.as_str() <= now)
+        .collect();
+
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.url.cmp(&b.url)));
+    entries.truncate(FEED_LENGTH);
+    entries
+}
+
+fn atom(title: &str, site_url: &str, feed_url: &str, entries: &[Entry]) -> String {
+    // the feed changed when its latest entry did, which an update to an
+    // older page can be
+    let updated = entries
+        .iter()
+        .map(|entry| entry.updated.to_string())
+        .max()
+        .unwrap_or_else(schedule::now);
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <title>{}</title>\n  <link href=\"{}\"/>\n  <link rel=\"self\" href=\"{}\"/>\n  <id>{}</id>\n  <updated>{updated}Z</updated>\n",
+        escape(title),
+        escape(site_url),
+        escape(feed_url),
+        escape(feed_url),
+    );
+    for entry in entries.iter() {
+        xml.push_str(&format!(
+            "  <entry>\n    <title>{}</title>\n    <link href=\"{}\"/>\n    <id>{}</id>\n    <published>{}Z</published>\n    <updated>{}Z</updated>\n",
+            escape(entry.title),
+            escape(&entry.url),
+            escape(&entry.url),
+            entry.date,
+            entry.updated,
+        ));
+        for tag in entry.tags.iter() {
+            xml.push_str(&format!("    <category term=\"{}\"/>\n", escape(tag)));
+        }
+        if let Some(summary) = &entry.summary {
+            let kind = if entry.summary_html {
+                " type=\"html\""
+            } else {
+                ""
+            };
+            xml.push_str(&format!(
+                "    <summary{kind}>{}</summary>\n",
+                escape(summary)
+            ));
+        }
+        if let Some(content) = entry.content {
+            xml.push_str(&format!(
+                "    <content type=\"html\">{}</content>\n",
+                escape(content)
+            ));
+        }
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rss(title: &str, site_url: &str, feed_url: &str, entries: &[Entry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n  <channel>\n    <title>{}</title>\n    <link>{}</link>\n    <description>{}</description>\n    <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape(title),
+        escape(site_url),
+        escape(title),
+        escape(feed_url),
+    );
+    for entry in entries.iter() {
+        xml.push_str(&format!(
+            "    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <guid>{}</guid>\n",
+            escape(entry.title),
+            escape(&entry.url),
+            escape(&entry.url),
+        ));
+        if let Some(date) = rfc822(&entry.date) {
+            xml.push_str(&format!("      <pubDate>{date}</pubDate>\n"));
+        }
+        for tag in entry.tags.iter() {
+            xml.push_str(&format!("      <category>{}</category>\n", escape(tag)));
+        }
+        let description = entry.summary.as_deref().or(entry.content);
+        xml.push_str(&format!(
+            "      <description>{}</description>\n    </item>\n",
+            escape(description.unwrap_or_default())
+        ));
+    }
+    xml.push_str("  </channel>\n</rss>\n");
+    xml
+}
+
+/// A feed of the site, a section or a language, with the entries it has.
+struct Feed<'a> {
+    /// The section's or language's url, `/blog` or `/fr`, or nothing for
+    /// the site's
+    prefix: String,
+    title: String,
+    entries: Vec<Entry<'a>>,
+}
+
+/// The feeds of the site, of each top level section of `content_map` and,
+/// on a site in more than one of `languages`, of each language `settings`
+/// don't turn off, which have a published dated page, and the site's url
+/// they link to. Feeds need absolute links, so there are none without a
+/// `base_url`.
+fn feeds<'a>(
+    content_map: &HashMap<String, Vec<&'a Content>>,
+    site: &toml::Table,
+    languages: &[String],
+    settings: &Settings,
+) -> Option<(String, Vec<Feed<'a>>)> {
+    let base_url = site
+        .get("base_url")
+        .and_then(|url| url.as_str())?
+        .trim_end_matches('/');
+    let site_title = site
+        .get("title")
+        .and_then(|title| title.as_str())
+        .unwrap_or(base_url);
+    let now = schedule::now();
+
+    let all: Vec<&Content> = content_map.values().flatten().copied().collect();
+    let mut feeds = vec![Feed {
+        prefix: String::new(),
+        title: site_title.to_string(),
+        entries: entries(&all, base_url, &now, settings),
+    }];
+    let multilingual = languages.len() > 1;
+    for (section, pages) in content_map.iter() {
+        // `content/fr/` is the French pages, whose feed is the language's
+        let language = multilingual && languages.contains(section);
+        if section != "default" && !language {
+            feeds.push(Feed {
+                prefix: format!("/{section}"),
+                title: format!("{site_title}: {section}"),
+                entries: entries(pages, base_url, &now, settings),
+            });
+        }
+    }
+    if multilingual {
+        for lang in languages.iter() {
+            let Some(title) = settings.language_title(lang, site_title) else {
+                continue;
+            };
+            let pages: Vec<&Content> = all
+                .iter()
+                .filter(|page| &page.lang == lang)
+                .copied()
+                .collect();
+            feeds.push(Feed {
+                prefix: format!("/{lang}"),
+                title,
+                entries: entries(&pages, base_url, &now, settings),
+            });
+        }
+    }
+    feeds.retain(|feed| !feed.entries.is_empty());
+    Some((base_url.to_string(), feeds))
+}
+
+fn mime(format: &str) -> &'static str {
+    match format {
+        "rss" => "application/rss+xml",
+        _ => "application/atom+xml",
+    }
+}
+
+/// A feed a page can link to, as its layout gets it in `page.feeds`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub url: String,
+    pub title: String,
+    #[serde(rename = "type")]
+    pub mime: String,
+}
+
+/// Gives every page of `contents` the feeds it belongs to in `page.feeds`:
+/// its section's, its language's, then the site's.
+pub fn link_pages(
+    contents: &mut [Content],
+    site: &toml::Table,
+    format: &str,
+    languages: &[String],
+    settings: &Settings,
+) {
+    let content_map = crate::compile_content_map(contents);
+    let Some((base_url, feeds)) = feeds(&content_map, site, languages, settings) else {
+        return;
+    };
+    // by the section's url
+    let links: BTreeMap<String, Link> = feeds
+        .iter()
+        .map(|feed| {
+            let link = Link {
+                url: format!("{base_url}{}/feed.xml", feed.prefix),
+                title: feed.title.to_string(),
+                mime: mime(format).to_string(),
+            };
+            (feed.prefix.to_string(), link)
+        })
+        .collect();
+
+    for page in contents.iter_mut() {
+        let section = page
+            .path
+            .split_once(std::path::MAIN_SEPARATOR_STR)
+            .map(|(section, _)| format!("/{section}"));
+        let language = format!("/{}", page.lang);
+        page.feeds = section
+            .into_iter()
+            .filter(|section| section != &language)
+            .chain([language, String::new()])
+            .filter_map(|prefix| links.get(&prefix).cloned())
+            .collect();
+    }
+}
+
+/// `html` with a `<link rel="alternate">` in its `<head>` for each of
+/// `feeds` it doesn't link to already, so feed readers find them.
+pub fn add_links(html: String, feeds: &[Link]) -> String {
+    let Some(head) = html.find("</head>") else {
+        return html;
+    };
+    static LINKED: OnceLock<Regex> = OnceLock::new();
+    let linked = LINKED.get_or_init(|| {
+        Regex::new(r#"(?is)<link\b[^>]*\brel\s*=\s*["']?alternate\b[^>]*>"#).unwrap()
+    });
+    let links: String = feeds
+        .iter()
+        .filter(|feed| {
+            !linked
+                .find_iter(&html)
+                .any(|link| link.as_str().contains(&escape(&feed.url)))
+        })
+        .map(|feed| {
+            format!(
+                r#"<link rel="alternate" type="{}" title="{}" href="{}">"#,
+                feed.mime,
+                escape(&feed.title),
+                escape(&feed.url)
+            )
+        })
+        .collect();
+    format!("{}{links}{}", &html[..head], &html[head..])
+}
+
+/// A `feed.xml` of the dated pages for the whole site, for each top level
+/// section of `content_map` and, on a site in more than one of `languages`,
+/// for each language `settings` don't turn off (`fr/feed.xml`), as Atom or
+/// (with `format` `rss`) RSS 2.0, by where each is written. Feeds need
+/// absolute links, so there are none without a `base_url`.
+pub fn render(
+    content_map: &HashMap<String, Vec<&Content>>,
+    site: &toml::Table,
+    format: &str,
+    languages: &[String],
+    settings: &Settings,
+) -> BTreeMap<PathBuf, String> {
+    let Some((base_url, feeds)) = feeds(content_map, site, languages, settings) else {
+        return BTreeMap::new();
+    };
+
+    feeds
+        .iter()
+        .map(|feed| {
+            let site_url = format!("{base_url}{}/", feed.prefix);
+            let feed_url = format!("{base_url}{}/feed.xml", feed.prefix);
+            let xml = match format {
+                "rss" => rss(&feed.title, &site_url, &feed_url, &feed.entries),
+                _ => atom(&feed.title, &site_url, &feed_url, &feed.entries),
+            };
+            let path = Path::new(feed.prefix.trim_start_matches('/')).join("feed.xml");
+            (path, xml)
+        })
+        .collect()
+}
+
+/// Writes the feeds of `render` to `output`.
+pub fn write(
+    output: &str,
+    content_map: &HashMap<String, Vec<&Content>>,
+    site: &toml::Table,
+    format: &str,
+    languages: &[String],
+    settings: &Settings,
+) -> io::Result<()> {
+    for (path, xml) in render(content_map, site, format, languages, settings) {
+        let path = Path::new(output).join(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, xml)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::MAIN_SEPARATOR_STR;
+
+    use super::*;
+    use crate::Frontmatter;
+
+    // a page at `path` in the content folder, in `lang`, with `fields`
+    fn page(path: &str, lang: &str, fields: &[(&str, &str)]) -> Content {
+        let path = path.replace('/', MAIN_SEPARATOR_STR);
+        Content {
+            slug: format!("/{}", path.trim_end_matches(".md")),
+            path,
+            permalink: None,
+            lang: lang.to_string(),
+            lang_dir: "ltr".to_string(),
+            frontmatter: Frontmatter::from_text(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            content: "<p>Text</p>".to_string(),
+            resources: Vec::new(),
+            images: Vec::new(),
+            text: String::new(),
+            numbering: Default::default(),
+            headings: Vec::new(),
+            docs_prev: None,
+            docs_next: None,
+            translations: Vec::new(),
+            feeds: Vec::new(),
+            extra: HashMap::new(),
+        }
+    }
+
+    fn site() -> toml::Table {
+        toml::from_str("title = \"Site\"\nbase_url = \"https://example.com\"").unwrap()
+    }
+
+    // the prefix and title of each feed of `contents`
+    fn feed_titles(contents: &[Content], settings: &Settings) -> Vec<(String, String)> {
+        let content_map = crate::compile_content_map(contents);
+        let languages = ["en".to_string(), "fr".to_string()];
+        let (_, feeds) = feeds(&content_map, &site(), &languages, settings).unwrap();
+        let mut titles: Vec<(String, String)> = feeds
+            .into_iter()
+            .map(|feed| (feed.prefix, feed.title))
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    fn titles(feeds: &[(&str, &str)]) -> Vec<(String, String)> {
+        feeds
+            .iter()
+            .map(|(prefix, title)| (prefix.to_string(), title.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn section_content() {
+        let contents = [
+            page("blog/a.md", "en", &[("date", "2024-01-01")]),
+            page(
+                "blog/b.md",
+                "en",
+                &[("date", "2024-01-02"), ("feed_content", "full")],
+            ),
+            page("notes/c.md", "en", &[("date", "2024-01-03")]),
+            page(
+                "notes/d.md",
+                "en",
+                &[("date", "2024-01-04"), ("feed_content", "summary")],
+            ),
+        ];
+        let pages: Vec<&Content> = contents.iter().collect();
+        let summaries = |settings: &Settings| -> Vec<(String, bool)> {
+            let mut entries: Vec<(String, bool)> = entries(&pages, "", "9999", settings)
+                .into_iter()
+                .map(|entry| (entry.url, entry.content.is_none()))
+                .collect();
+            entries.sort();
+            entries
+        };
+        let expected = |summary: &[bool]| -> Vec<(String, bool)> {
+            ["/blog/a", "/blog/b", "/notes/c", "/notes/d"]
+                .iter()
+                .zip(summary)
+                .map(|(url, summary)| (url.to_string(), *summary))
+                .collect()
+        };
+
+        assert_eq!(
+            summaries(&Settings::default()),
+            expected(&[false, false, false, true])
+        );
+        let settings = Settings {
+            sections: toml::from_str("[blog]\ncontent = \"summary\"").unwrap(),
+            ..Settings::default()
+        };
+        assert_eq!(summaries(&settings), expected(&[true, false, false, true]));
+    }
+
+    #[test]
+    fn language_feeds() {
+        let contents = [
+            page("blog/a.md", "en", &[("date", "2024-01-01")]),
+            page("fr/b.md", "fr", &[("date", "2024-01-02")]),
+        ];
+        assert_eq!(
+            feed_titles(&contents, &Settings::default()),
+            titles(&[
+                ("", "Site"),
+                ("/blog", "Site: blog"),
+                ("/en", "Site (en)"),
+                ("/fr", "Site (fr)"),
+            ])
+        );
+
+        let settings = Settings {
+            languages: toml::from_str("[fr]\nenabled = false\n[en]\ntitle = \"English\"").unwrap(),
+            ..Settings::default()
+        };
+        assert_eq!(
+            feed_titles(&contents, &settings),
+            titles(&[("", "Site"), ("/blog", "Site: blog"), ("/en", "English")])
+        );
+    }
+
+    fn entry() -> Entry<'static> {
+        Entry {
+            title: "Fish & Chips",
+            url: "https://example.com/blog/fish".to_string(),
+            date: "2024-05-01T09:30:00".to_string(),
+            updated: "2024-05-02T00:00:00".to_string(),
+            summary: Some("<b>Crisp</b>".to_string()),
+            summary_html: true,
+            content: Some("<p>Text</p>"),
+            tags: vec!["food".to_string()],
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(
+            rfc822("2024-05-01T09:30:00").as_deref(),
+            Some("Wed, 01 May 2024 09:30:00 +0000")
+        );
+        assert_eq!(
+            rfc822("2024-02-29T23:59:59").as_deref(),
+            Some("Thu, 29 Feb 2024 23:59:59 +0000")
+        );
+        assert_eq!(rfc822("2024-13-01T00:00:00"), None);
+        assert_eq!(rfc822("2024-05-01"), None);
+    }
+
+    #[test]
+    fn atom_feeds() {
+        let xml = atom(
+            "A <Site>",
+            "https://example.com/",
+            "https://example.com/atom.xml",
+            &[entry()],
+        );
+        for line in [
+            "<title>A &lt;Site&gt;</title>",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <title>A &lt;Site&gt;</title>",
+            "  <updated>2024-05-02T00:00:00Z</updated>\n  <entry>",
+            "<title>Fish &amp; Chips</title>",
+            "<published>2024-05-01T09:30:00Z</published>",
+            "<category term=\"food\"/>",
+            "<summary type=\"html\">&lt;b&gt;Crisp&lt;/b&gt;</summary>",
+            "<content type=\"html\">&lt;p&gt;Text&lt;/p&gt;</content>",
+        ] {
+            assert!(xml.contains(line), "{line} isn't in {xml}");
+        }
+    }
+
+    #[test]
+    fn rss_feeds() {
+        let summaryless = Entry {
+            summary: None,
+            ..entry()
+        };
+        let xml = rss(
+            "Site",
+            "https://example.com/",
+            "https://example.com/rss.xml",
+            &[entry(), summaryless],
+        );
+        for line in [
+            "<pubDate>Wed, 01 May 2024 09:30:00 +0000</pubDate>",
+            "<category>food</category>",
+            "<description>&lt;b&gt;Crisp&lt;/b&gt;</description>",
+            "<description>&lt;p&gt;Text&lt;/p&gt;</description>",
+        ] {
+            assert!(xml.contains(line), "{line} isn't in {xml}");
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{escape::escape_html, Options};
+use tera::{Filter, Value};
+
+use crate::{page_data, schedule, typography};
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+// elements html never closes
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+fn string_value<'a>(value: &'a Value, filter: &str) -> tera::Result<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg(format!("`{filter}` can only be applied to strings")))
+}
+
+/// A filter whose result is html, so it isn't escaped again.
+pub struct Html(pub fn(&Value, &HashMap<String, Value>) -> tera::Result<Value>);
+
+impl Filter for Html {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        (self.0)(value, args)
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `slugify`, the way roxy makes slugs and heading ids, so a layout can
+/// link to `#{{ title | slugify }}`.
+pub fn slugify(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(crate::slugify(string_value(
+        value, "slugify",
+    )?)))
+}
+
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    // a frontmatter date, like `2024-05-01` or `2024-05-01 09:30`, or
+    // seconds since 1970
+    fn parse(value: &Value) -> Option<DateTime> {
+        if let Some(secs) = value.as_i64() {
+            let (year, month, day) = schedule::civil_from_days(secs.div_euclid(86400));
+            let time = secs.rem_euclid(86400) as u32;
+            return Some(DateTime {
+                year,
+                month,
+                day,
+                hour: time / 3600,
+                minute: (time / 60) % 60,
+                second: time % 60,
+            });
+        }
+
+        let date = schedule::normalize(value.as_str()?);
+        let part = |range: std::ops::Range<usize>| date.get(range)?.parse().ok();
+        let date = DateTime {
+            year: part(0..4)?,
+            month: part(5..7)?,
+            day: part(8..10)?,
+            hour: part(11..13)?,
+            minute: part(14..16)?,
+            second: part(17..19)?,
+        };
+        let valid = (1..=12).contains(&date.month) && (1..=31).contains(&date.day);
+        valid.then_some(date)
+    }
+
+    fn weekday(&self) -> usize {
+        let days = schedule::days_from_civil(self.year, self.month, self.day);
+        // 1970-01-01 was a Thursday
+        (days + 4).rem_euclid(7) as usize
+    }
+
+    // `format` with strftime's `%Y`, `%m`, `%d`, `%B` and the like, `%-d`
+    // leaving out the padding
+    fn format(&self, format: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let mut spec = chars.next();
+            let padded = spec != Some('-');
+            if !padded {
+                spec = chars.next();
+            }
+            let number = |n: u32| {
+                if padded {
+                    format!("{n:02}")
+                } else {
+                    n.to_string()
+                }
+            };
+            let month = MONTHS[self.month as usize - 1];
+            let weekday = WEEKDAYS[self.weekday()];
+            match spec {
+                Some('Y') => out.push_str(&self.year.to_string()),
+                Some('y') => out.push_str(&number(self.year.rem_euclid(100) as u32)),
+                Some('m') => out.push_str(&number(self.month)),
+                Some('d') => out.push_str(&number(self.day)),
+                Some('e') => out.push_str(&format!("{:>2}", self.day)),
+                Some('B') => out.push_str(month),
+                Some('b') => out.push_str(&month[..3]),
+                Some('A') => out.push_str(weekday),
+                Some('a') => out.push_str(&weekday[..3]),
+                Some('H') => out.push_str(&number(self.hour)),
+                Some('I') => out.push_str(&number((self.hour + 11) % 12 + 1)),
+                Some('p') => out.push_str(if self.hour < 12 { "AM" } else { "PM" }),
+                Some('M') => out.push_str(&number(self.minute)),
+                Some('S') => out.push_str(&number(self.second)),
+                Some('F') => {
+                    out.push_str(&format!("{}-{:02}-{:02}", self.year, self.month, self.day))
+                }
+                Some('%') => out.push('%'),
+                Some(spec) => return Err(format!("`%{spec}` isn't a date format roxy knows")),
+                None => return Err("the format ends in `%`".to_string()),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// `date(format="%B %-d, %Y")` formats a frontmatter date, like `2024-05-01`
+/// or `2024-05-01 09:30`, or a number of seconds since 1970. The format is
+/// `%Y-%m-%d` by default.
+pub fn date(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let format = args
+        .get("format")
+        .and_then(Value::as_str)
+        .unwrap_or("%Y-%m-%d");
+    let date = DateTime::parse(value)
+        .ok_or_else(|| tera::Error::msg(format!("`date` can't read {value} as a date")))?;
+    date.format(format)
+        .map(Value::String)
+        .map_err(|err| tera::Error::msg(format!("`date`: {err}")))
+}
+
+/// `truncate_html(length=200, end="…")` cuts html down to `length`
+/// characters of text, closing the tags that were open where it was cut.
+pub fn truncate_html(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let html = string_value(value, "truncate_html")?;
+    let length = args
+        .get("length")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| tera::Error::msg("`truncate_html` requires a `length` argument"))?
+        as usize;
+    let end = args.get("end").and_then(Value::as_str).unwrap_or("…");
+
+    let mut out = String::new();
+    let mut open: Vec<String> = Vec::new();
+    let mut count = 0;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let tag = match rest.find('>') {
+                Some(i) => &rest[..=i],
+                None => rest,
+            };
+            let name: String = tag
+                .trim_start_matches(['<', '/'])
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if tag.starts_with("</") {
+                if let Some(i) = open.iter().rposition(|open| *open == name) {
+                    open.truncate(i);
+                }
+            } else if !name.is_empty() && !tag.ends_with("/>") && !VOID.contains(&name.as_str()) {
+                open.push(name);
+            }
+            out.push_str(tag);
+            rest = &rest[tag.len()..];
+            continue;
+        }
+
+        if count == length {
+            let kept = out.trim_end().len();
+            out.truncate(kept);
+            out.push_str(end);
+            for name in open.iter().rev() {
+                out.push_str(&format!("</{name}>"));
+            }
+            return Ok(Value::String(out));
+        }
+        // an entity like `&amp;` is one character of text
+        let text = match rest.find(';').filter(|_| c == '&') {
+            Some(i) if i < 10 && !rest[..i].contains(char::is_whitespace) => &rest[..=i],
+            _ => &rest[..c.len_utf8()],
+        };
+        out.push_str(text);
+        rest = &rest[text.len()..];
+        count += 1;
+    }
+    Ok(Value::String(out))
+}
+
+/// `excerpt`, the content of a page before its `<!-- more -->` comment, or
+/// its first paragraph.
+pub fn excerpt(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let html = string_value(value, "excerpt")?;
+    Ok(Value::String(page_data::excerpt(html).unwrap_or_default()))
+}
+
+/// `markdown` renders markdown to html with the site's markdown options;
+/// `markdown(inline=true)` leaves out the paragraph around it.
+pub struct Markdown {
+    options: Options,
+}
+
+impl Markdown {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+}
+
+impl Filter for Markdown {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let text = string_value(value, "markdown")?;
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(
+            &mut html,
+            typography::parse(text, self.options).into_iter(),
+        );
+
+        if args.get("inline").and_then(Value::as_bool).unwrap_or(false) {
+            let trimmed = html.trim();
+            if let Some(inline) = trimmed
+                .strip_prefix("<p>")
+                .and_then(|html| html.strip_suffix("</p>"))
+            {
+                html = inline.to_string();
+            }
+        }
+        Ok(Value::String(html))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `page.headings | toc(max_level=3)`, a nested list of links to a page's
+/// headings, down to `max_level`.
+pub fn toc(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let headings = value
+        .as_array()
+        .ok_or_else(|| tera::Error::msg("`toc` can only be applied to a page's headings"))?;
+    let max_level = args.get("max_level").and_then(Value::as_u64).unwrap_or(6);
+
+    let mut html = String::new();
+    // the levels of the lists open around the last heading
+    let mut lists: Vec<u64> = Vec::new();
+    for heading in headings.iter() {
+        let level = heading.get("level").and_then(Value::as_u64).unwrap_or(1);
+        if level > max_level {
+            continue;
+        }
+        let anchor = heading.get("anchor").and_then(Value::as_str).unwrap_or("");
+        let title = heading.get("title").and_then(Value::as_str).unwrap_or("");
+
+        match lists.last() {
+            Some(&last) if level <= last => {
+                while lists.len() > 1 && lists.last().is_some_and(|&last| level < last) {
+                    html.push_str("</li></ul>");
+                    lists.pop();
+                }
+                html.push_str("</li>");
+            }
+            _ => {
+                html.push_str("<ul>");
+                lists.push(level);
+            }
+        }
+        html.push_str("<li><a href=\"#");
+        let _ = escape_html(&mut html, anchor);
+        html.push_str("\">");
+        let _ = escape_html(&mut html, title);
+        html.push_str("</a>");
+    }
+    for _ in lists.iter() {
+        html.push_str("</li></ul>");
+    }
+    Ok(Value::String(html))
+}
+
+/// `page | meta_tags(site=site)`, the description and Open Graph tags of a
+/// page, in its language and text direction.
+pub fn meta_tags(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let page = value
+        .as_object()
+        .ok_or_else(|| tera::Error::msg("`meta_tags` can only be applied to a page"))?;
+    let field = |name: &str| {
+        page.get("frontmatter")
+            .and_then(|frontmatter| frontmatter.get(name))
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+    };
+    let site = args.get("site");
+    let site_field = |name: &str| site.and_then(|site| site.get(name)).and_then(Value::as_str);
+
+    let lang = page.get("lang").and_then(Value::as_str).unwrap_or("");
+    // only the tags holding the page's own text take its direction
+    let dir = match page.get("lang_dir").and_then(Value::as_str) {
+        Some("rtl") => " dir=\"rtl\"",
+        _ => "",
+    };
+    let path = page
+        .get("permalink")
+        .and_then(Value::as_str)
+        .or_else(|| page.get("slug").and_then(Value::as_str))
+        .unwrap_or("");
+    let kind = match page.get("frontmatter").and_then(|f| f.get("date")) {
+        Some(Value::Null) | None => "website",
+        Some(_) => "article",
+    };
+
+    let mut html = String::new();
+    let mut tag = |attribute: &str, name: &str, content: &str, dir: &str| {
+        html.push_str(&format!("<meta {attribute}=\"{name}\" content=\""));
+        let _ = escape_html(&mut html, content);
+        html.push_str(&format!("\"{dir}>\n"));
+    };
+    let description = field("description").or_else(|| field("summary"));
+    if let Some(description) = description {
+        tag("name", "description", description, dir);
+    }
+    if let Some(title) = field("title").or_else(|| site_field("title")) {
+        tag("property", "og:title", title, dir);
+    }
+    if let Some(description) = description {
+        tag("property", "og:description", description, dir);
+    }
+    tag("property", "og:type", kind, "");
+    if let Some(base_url) = site_field("base_url") {
+        tag(
+            "property",
+            "og:url",
+            &format!("{}{path}", base_url.trim_end_matches('/')),
+            "",
+        );
+    }
+    if let Some(name) = site_field("title") {
+        tag("property", "og:site_name", name, "");
+    }
+    if !lang.is_empty() {
+        tag("property", "og:locale", &lang.replace('-', "_"), "");
+    }
+    if let Some(image) = field("image") {
+        let image = match site_field("base_url") {
+            Some(base_url) if image.starts_with('/') => {
+                format!("{}{image}", base_url.trim_end_matches('/'))
+            }
+            _ => image.to_string(),
+        };
+        tag("property", "og:image", &image, "");
+        tag("name", "twitter:card", "summary_large_image", "");
+    } else {
+        tag("name", "twitter:card", "summary", "");
+    }
+    Ok(Value::String(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(page: Value) -> String {
+        let site = serde_json::json!({ "title": "Site", "base_url": "https://example.com" });
+        let args = HashMap::from([("site".to_string(), site)]);
+        meta_tags(&page, &args)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn meta_tags_describe_the_page() {
+        let html = meta(serde_json::json!({
+            "slug": "/blog/hello",
+            "lang": "en-GB",
+            "lang_dir": "ltr",
+            "frontmatter": { "title": "Hello & welcome", "summary": "A post", "date": "2024-05-01" },
+        }));
+        assert!(html.contains("<meta property=\"og:title\" content=\"Hello &amp; welcome\">"));
+        assert!(html.contains("<meta name=\"description\" content=\"A post\">"));
+        assert!(html.contains("<meta property=\"og:type\" content=\"article\">"));
+        assert!(html.contains("content=\"https://example.com/blog/hello\""));
+        assert!(html.contains("<meta property=\"og:locale\" content=\"en_GB\">"));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary\">"));
+        assert!(!html.contains("dir="));
+    }
+
+    #[test]
+    fn meta_tags_keep_the_direction_of_rtl_pages() {
+        let html = meta(serde_json::json!({
+            "slug": "/ar/salam",
+            "lang": "ar",
+            "lang_dir": "rtl",
+            "frontmatter": { "title": "سلام", "image": "/salam.png" },
+        }));
+        assert!(html.contains("<meta property=\"og:title\" content=\"سلام\" dir=\"rtl\">"));
+        assert!(html.contains("<meta property=\"og:type\" content=\"website\">"));
+        assert!(
+            html.contains("<meta property=\"og:image\" content=\"https://example.com/salam.png\">")
+        );
+        assert!(html.contains("<meta property=\"og:locale\" content=\"ar\">"));
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use glob::glob;
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+
+use crate::{csp::attribute, parallel};
+
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf", "woff", "woff2"];
+
+// attributes browsers show as text
+const TEXT_ATTRIBUTES: &[&str] = &["alt", "title", "placeholder", "aria-label"];
+
+fn files(output: &Path, ext: &str) -> Vec<PathBuf> {
+    let pattern = format!(
+        "{}/**/*.{ext}",
+        glob::Pattern::escape(&output.to_string_lossy())
+    );
+    let mut files: Vec<PathBuf> = glob(&pattern).into_iter().flatten().flatten().collect();
+    files.sort();
+    files
+}
+
+fn decode_entities(text: &str) -> String {
+    let entities = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+    entities
+        .replace_all(text, |c: &Captures| {
+            let entity = &c[1];
+            let code = match entity.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+                Some(decimal) => decimal.parse().ok(),
+                None => match entity {
+                    "amp" => Some('&' as u32),
+                    "lt" => Some('<' as u32),
+                    "gt" => Some('>' as u32),
+                    "quot" => Some('"' as u32),
+                    "apos" => Some('\'' as u32),
+                    "nbsp" => Some(0xa0),
+                    _ => None,
+                },
+            };
+            code.and_then(char::from_u32)
+                .map(String::from)
+                .unwrap_or_else(|| c[0].to_string())
+        })
+        .into_owned()
+}
+
+// the text of a page that fonts draw: what's between its tags, and the
+// attributes shown as text
+fn page_text(html: &str) -> String {
+    let hidden =
+        Regex::new(r"(?is)<script\b.*?</script\s*>|<style\b.*?</style\s*>|<!--.*?-->").unwrap();
+    let html = hidden.replace_all(html, " ");
+    let tags = Regex::new(r"(?s)<[^>]*>").unwrap();
+    let mut text = String::new();
+    for tag in tags.find_iter(&html) {
+        for name in TEXT_ATTRIBUTES {
+            if let Some(value) = attribute(tag.as_str(), name) {
+                text.push_str(value);
+            }
+        }
+    }
+    text.push_str(&tags.replace_all(&html, " "));
+    decode_entities(&text)
+}
+
+// the characters of `content: "\e900"` in stylesheets, like those of icons
+fn generated_text(css: &str) -> String {
+    let content = Regex::new(r#"(?i)content\s*:\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let escapes = Regex::new(r"\\([0-9a-fA-F]{1,6})\s?").unwrap();
+    let mut text = String::new();
+    for c in content.captures_iter(css) {
+        let value = c
+            .get(1)
+            .or_else(|| c.get(2))
+            .map_or("", |value| value.as_str());
+        text.push_str(&escapes.replace_all(value, |c: &Captures| {
+            u32::from_str_radix(&c[1], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .map(String::from)
+                .unwrap_or_default()
+        }));
+    }
+    text
+}
+
+/// The characters the pages written to `output` use, by the `lang` of
+/// their `<html>`. The default language's have those the stylesheets
+/// generate and printable ASCII too, for text scripts add.
+fn used_characters(
+    output: &Path,
+    stylesheets: &[(PathBuf, String)],
+    default_language: &str,
+) -> io::Result<BTreeMap<String, BTreeSet<char>>> {
+    let root = Regex::new(r"(?is)<html\b([^>]*)>").unwrap();
+    let mut used: BTreeMap<String, BTreeSet<char>> = BTreeMap::new();
+    for page in files(output, "html") {
+        let html = fs::read_to_string(&page)?;
+        let lang = root
+            .captures(&html)
+            .and_then(|c| attribute(c.get(1)?.as_str(), "lang").map(str::to_ascii_lowercase))
+            .filter(|lang| !lang.is_empty())
+            .unwrap_or_else(|| default_language.to_string());
+        used.entry(lang)
+            .or_default()
+            .extend(page_text(&html).chars().filter(|c| !c.is_control()));
+    }
+
+    let default = used.entry(default_language.to_string()).or_default();
+    default.extend((' '..='~').chain(['\u{a0}']));
+    for (_, css) in stylesheets.iter() {
+        default.extend(generated_text(css).chars());
+    }
+    Ok(used)
+}
+
+/// The characters each language's subset of a font holds: the default
+/// language's first, then those of each other language the ones before
+/// don't have, so a page only downloads the subsets its text needs.
+fn subsets(
+    mut used: BTreeMap<String, BTreeSet<char>>,
+    default_language: &str,
+) -> Vec<(String, BTreeSet<char>)> {
+    let mut languages = vec![default_language.to_string()];
+    languages.extend(
+        used.keys()
+            .filter(|lang| *lang != default_language)
+            .cloned(),
+    );
+
+    let mut covered = BTreeSet::new();
+    let mut subsets = Vec::new();
+    for lang in languages {
+        let chars: BTreeSet<char> = used
+            .remove(&lang)
+            .unwrap_or_default()
+            .difference(&covered)
+            .copied()
+            .collect();
+        if chars.is_empty() {
+            continue;
+        }
+        covered.extend(chars.iter().copied());
+        subsets.push((lang, chars));
+    }
+    subsets
+}
+
+/// `chars` as a css `unicode-range`, like `U+20-7E, U+A0`.
+fn unicode_range(chars: &BTreeSet<char>) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for c in chars.iter().map(|c| *c as u32) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == c => *end = c,
+            _ => ranges.push((c, c)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| match start == end {
+            true => format!("U+{start:X}"),
+            false => format!("U+{start:X}-{end:X}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// `url` of a stylesheet at `css`, as a file of the site, if it's one
+fn local_file(output: &Path, css: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next()?;
+    if path.contains("://") || path.starts_with("//") || path.starts_with("data:") {
+        return None;
+    }
+    let file = match path.strip_prefix('/') {
+        Some(path) => output.join(path),
+        None => css.parent()?.join(path),
+    };
+    let ext = file.extension()?.to_str()?.to_ascii_lowercase();
+    (FONT_EXTENSIONS.contains(&ext.as_str()) && file.is_file()).then_some(file)
+}
+
+/// Writes the subset of `font` for `chars` next to it as woff2, named after
+/// the font, the language and a hash of the subset, with `pyftsubset` of
+/// fonttools. Returns its file name.
+fn write_subset(font: &Path, lang: &str, chars: &BTreeSet<char>) -> io::Result<String> {
+    let key = format!("{}:{lang}", font.to_string_lossy());
+    let job = format!("{:x}", Sha256::digest(key.as_bytes()));
+    let temp =
+        std::env::temp_dir().join(format!("roxy-font-{}-{}", std::process::id(), &job[..12]));
+    let (unicodes, subset) = (temp.with_extension("txt"), temp.with_extension("woff2"));
+    fs::write(&unicodes, unicode_range(chars).replace(", ", "\n"))?;
+
+    let result = (|| {
+        let output = Command::new("pyftsubset")
+            .arg(font)
+            .arg(format!("--unicodes-file={}", unicodes.to_string_lossy()))
+            .arg(format!("--output-file={}", subset.to_string_lossy()))
+            .args(["--flavor=woff2", "--layout-features=*"])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "pyftsubset {} failed: {}",
+                font.to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let bytes = fs::read(&subset)?;
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let stem = font.file_stem().unwrap_or_default().to_string_lossy();
+        let name = format!("{stem}.{lang}.{}.woff2", &hash[..8]);
+        fs::write(font.with_file_name(&name), bytes)?;
+        Ok(name)
+    })();
+    let _ = fs::remove_file(&unicodes);
+    let _ = fs::remove_file(&subset);
+    result
+}
+
+struct Face {
+    /// The font file of the face, as it's linked and where it is
+    url: String,
+    font: PathBuf,
+}
+
+// the font files a stylesheet's `@font-face`s could be subset from: the
+// first local one of each face. Faces with a `unicode-range` are already
+// split up and left alone.
+fn faces(output: &Path, css_path: &Path, css: &str) -> Vec<Face> {
+    let faces = Regex::new(r"(?is)@font-face\s*\{([^}]*)\}").unwrap();
+    let urls = Regex::new(r#"(?i)url\(\s*["']?([^"')]+?)["']?\s*\)"#).unwrap();
+    let unicode_range = Regex::new(r"(?i)unicode-range\s*:").unwrap();
+    faces
+        .captures_iter(css)
+        .filter(|face| !unicode_range.is_match(&face[1]))
+        .filter_map(|face| {
+            urls.captures_iter(&face[1]).find_map(|url| {
+                let font = local_file(output, css_path, &url[1])?;
+                Some(Face {
+                    url: url[1].to_string(),
+                    font,
+                })
+            })
+        })
+        .collect()
+}
+
+// the `@font-face` `declarations` of one subset
+fn subset_face(declarations: &str, url: &str, chars: &BTreeSet<char>) -> String {
+    let src = Regex::new(r"(?is)(?:^|;)\s*src\s*:[^;]*").unwrap();
+    let rest = src.replace_all(declarations, "");
+    let rest = rest.trim().trim_matches(';').trim();
+    let mut face = format!(
+        "@font-face{{src:url(\"{url}\") format(\"woff2\");unicode-range:{}",
+        unicode_range(chars)
+    );
+    if !rest.is_empty() {
+        face.push(';');
+        face.push_str(rest);
+    }
+    face.push('}');
+    face
+}
+
+// `url` with its file name in place of the font's
+fn with_file_name(url: &str, name: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Subsets the local fonts of the stylesheets written to `output` to the
+/// characters its pages use, writing a woff2 of each font for each
+/// language's characters, and gives each `@font-face` a face of its own for
+/// each of them, with its `unicode-range`. Preload hints of a font are
+/// pointed at its default language's subset. Returns how many fonts were
+/// subset.
+pub fn subset(output: &str, default_language: &str) -> io::Result<usize> {
+    let output = Path::new(output.trim_end_matches('/'));
+    let stylesheets: Vec<(PathBuf, String)> = files(output, "css")
+        .into_iter()
+        .map(|path| fs::read_to_string(&path).map(|css| (path, css)))
+        .collect::<io::Result<_>>()?;
+    let subsets = subsets(
+        used_characters(output, &stylesheets, default_language)?,
+        default_language,
+    );
+
+    let fonts: BTreeSet<PathBuf> = stylesheets
+        .iter()
+        .flat_map(|(path, css)| faces(output, path, css))
+        .map(|face| face.font)
+        .collect();
+    let fonts: Vec<PathBuf> = fonts.into_iter().collect();
+    let written = parallel::map(&fonts, |font| {
+        subsets
+            .iter()
+            .map(|(lang, chars)| Ok((write_subset(font, lang, chars)?, chars)))
+            .collect::<io::Result<Vec<_>>>()
+    });
+    let mut names: HashMap<&Path, Vec<(String, &BTreeSet<char>)>> = HashMap::new();
+    for (font, written) in fonts.iter().zip(written) {
+        names.insert(font.as_path(), written?);
+    }
+
+    let face_blocks = Regex::new(r"(?is)@font-face\s*\{([^}]*)\}").unwrap();
+    // the url of each font from the root of the site, for preload hints
+    let mut hinted: HashMap<String, String> = HashMap::new();
+    for (path, css) in stylesheets.iter() {
+        let rewritten = face_blocks.replace_all(css, |c: &Captures| {
+            let Some(face) = faces(output, path, &c[0]).pop() else {
+                return c[0].to_string();
+            };
+            let Some(subsets) = names.get(face.font.as_path()) else {
+                return c[0].to_string();
+            };
+            if let (Ok(relative), Some((first, _))) =
+                (face.font.strip_prefix(output), subsets.first())
+            {
+                let url: Vec<String> = relative
+                    .iter()
+                    .map(|part| part.to_string_lossy().to_string())
+                    .collect();
+                let url = format!("/{}", url.join("/"));
+                hinted.insert(url.clone(), with_file_name(&url, first));
+            }
+            subsets
+                .iter()
+                .map(|(name, chars)| subset_face(&c[1], &with_file_name(&face.url, name), chars))
+                .collect::<String>()
+        });
+        if rewritten != *css {
+            fs::write(path, rewritten.as_bytes())?;
+        }
+    }
+
+    let preloads = Regex::new(r"(?is)<link\b[^>]*>").unwrap();
+    for page in files(output, "html") {
+        let html = fs::read_to_string(&page)?;
+        let pointed = preloads.replace_all(&html, |c: &Captures| {
+            let tag = &c[0];
+            let preload =
+                attribute(tag, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("preload"));
+            match attribute(tag, "href").and_then(|href| hinted.get(href)) {
+                Some(subset) if preload => {
+                    let href = attribute(tag, "href").unwrap_or_default();
+                    tag.replacen(href, subset, 1)
+                }
+                _ => tag.to_string(),
+            }
+        });
+        if pointed != html {
+            fs::write(&page, pointed.as_bytes())?;
+        }
+    }
+    Ok(fonts.len())
+}
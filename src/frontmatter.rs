@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use tera::Value;
+
+use crate::LIST_FIELDS;
+
+/// Frontmatter parsed from YAML or TOML.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    /// Every field as text, with tables also flattened into `table.key`
+    /// fields
+    pub text: HashMap<String, String>,
+    /// The typed value of each top level field, with the text it was read
+    /// as
+    pub typed: HashMap<String, (String, Value)>,
+}
+
+/// A frontmatter problem, with the line of the frontmatter it's on when
+/// known.
+pub struct Problem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.to_string(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Adds the fields of a table `value` to `fields` as `prefix.key`, and
+/// those of tables in it as `prefix.key.key`.
+pub fn flatten(prefix: &str, value: &Value, fields: &mut HashMap<String, String>) {
+    if let Value::Object(table) = value {
+        for (key, value) in table.iter() {
+            let key = format!("{prefix}.{key}");
+            flatten(&key, value, fields);
+            fields.insert(key, text(value));
+        }
+    }
+}
+
+impl Parsed {
+    fn insert(&mut self, key: String, value: Value) {
+        let as_text = text(&value);
+        flatten(&key, &value, &mut self.text);
+        self.text.insert(key.to_string(), as_text.to_string());
+
+        // strings of list fields are left for `coerce` to split
+        let list_text = value.is_string() && LIST_FIELDS.contains(&key.as_str());
+        if !list_text {
+            self.typed.insert(key, (as_text, value));
+        }
+    }
+}
+
+// dates and times are given to layouts as the text they were written as
+fn from_toml_value(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(text) => Value::String(text),
+        toml::Value::Integer(n) => Value::from(n),
+        toml::Value::Float(n) => Value::from(n),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(date) => Value::String(date.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(from_toml_value).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Parses YAML frontmatter, which must be a map of fields.
+pub fn from_yaml(source: &str) -> Result<Parsed, Problem> {
+    let fields: serde_yaml::Mapping = serde_yaml::from_str(source).map_err(|err| Problem {
+        line: err.location().map(|location| location.line()),
+        message: err.to_string(),
+    })?;
+
+    let mut parsed = Parsed::default();
+    for (key, value) in fields.into_iter() {
+        let key = match key {
+            serde_yaml::Value::String(key) => key,
+            key => serde_json::to_value(&key)
+                .map(|key| text(&key))
+                .unwrap_or_default(),
+        };
+        parsed.insert(key, serde_json::to_value(&value).unwrap_or(Value::Null));
+    }
+    Ok(parsed)
+}
+
+/// Parses TOML frontmatter, written between `+++` lines.
+pub fn from_toml(source: &str) -> Result<Parsed, Problem> {
+    let fields: toml::Table = toml::from_str(source).map_err(|err| Problem {
+        line: err
+            .span()
+            .map(|span| source[..span.start.min(source.len())].matches('\n').count() + 1),
+        message: err.message().to_string(),
+    })?;
+
+    let mut parsed = Parsed::default();
+    for (key, value) in fields.into_iter() {
+        parsed.insert(key, from_toml_value(value));
+    }
+    Ok(parsed)
+}
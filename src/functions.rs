@@ -0,0 +1,578 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use serde::{Deserialize, Serialize};
+use syntect::{highlighting::Theme, parsing::SyntaxSet};
+use tera::{Context, Function, Tera, Value};
+
+use crate::Content;
+
+fn string_arg<'a>(args: &'a HashMap<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+/// Resolves `path` below `root`, refusing absolute paths and `..`.
+pub fn sandboxed(root: &Path, path: &str) -> tera::Result<PathBuf> {
+    let relative = Path::new(path);
+    let contained = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+
+    if !contained {
+        return Err(tera::Error::msg(format!(
+            "`{path}` must be a relative path inside {}",
+            root.to_string_lossy()
+        )));
+    }
+
+    Ok(root.join(relative))
+}
+
+// parses `10-42`, `10-`, `-42` or `7` into a 1-based inclusive range
+fn line_range(lines: &str, len: usize) -> tera::Result<(usize, usize)> {
+    let parse = |s: &str, default: usize| -> tera::Result<usize> {
+        if s.trim().is_empty() {
+            Ok(default)
+        } else {
+            s.trim()
+                .parse()
+                .map_err(|_| tera::Error::msg(format!("`{lines}` isn't a line range like 10-42")))
+        }
+    };
+
+    let (start, end) = match lines.split_once('-') {
+        Some((start, end)) => (parse(start, 1)?, parse(end, len)?),
+        None => {
+            let line = parse(lines, 1)?;
+            (line, line)
+        }
+    };
+
+    if start == 0 || start > end || end > len {
+        return Err(tera::Error::msg(format!(
+            "lines `{lines}` are out of bounds, the file has {len} lines"
+        )));
+    }
+
+    Ok((start, end))
+}
+
+fn dedent(lines: &[&str]) -> Vec<String> {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or("").to_string())
+        .collect()
+}
+
+/// `include_code(path, lines, lang, dedent)` pulls a (highlighted) snippet of
+/// a file in the project into the page at build time.
+pub struct IncludeCode {
+    root: PathBuf,
+    syntax_set: SyntaxSet,
+    theme: Theme,
+}
+
+impl IncludeCode {
+    pub fn new(root: PathBuf, theme: Theme) -> Self {
+        Self {
+            root,
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme,
+        }
+    }
+}
+
+impl Function for IncludeCode {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = string_arg(args, "path")
+            .ok_or_else(|| tera::Error::msg("`include_code` requires a `path` argument"))?;
+        let file = sandboxed(&self.root, path)?;
+        let text = fs::read_to_string(&file)
+            .map_err(|err| tera::Error::msg(format!("Couldn't read `{path}`: {err}")))?;
+        INCLUDED.with(|included| included.borrow_mut().insert(file.clone()));
+
+        let all_lines: Vec<&str> = text.lines().collect();
+        let lines = match string_arg(args, "lines") {
+            Some(lines) => {
+                let (start, end) = line_range(lines, all_lines.len())?;
+                &all_lines[start - 1..end]
+            }
+            None => &all_lines[..],
+        };
+
+        let lines = if args.get("dedent").and_then(Value::as_bool).unwrap_or(false) {
+            dedent(lines)
+        } else {
+            lines.iter().map(|line| line.to_string()).collect()
+        };
+        let code = lines.join("\n") + "\n";
+
+        let lang = string_arg(args, "lang").map(|s| s.to_string()).or_else(|| {
+            file.extension()
+                .map(|ext| ext.to_string_lossy().to_string())
+        });
+        let syntax = lang
+            .and_then(|lang| self.syntax_set.find_syntax_by_token(&lang))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+
+        syntect::html::highlighted_html_for_string(&code, &self.syntax_set, syntax, &self.theme)
+            .map(Value::String)
+            .map_err(|err| tera::Error::msg(format!("Couldn't highlight `{path}`: {err}")))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `include_html(path)` inlines a file of the includes folder as it is,
+/// for snippets like embeds that shouldn't be rendered as templates.
+pub struct IncludeHtml {
+    root: PathBuf,
+}
+
+impl IncludeHtml {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl Function for IncludeHtml {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = string_arg(args, "path")
+            .ok_or_else(|| tera::Error::msg("`include_html` requires a `path` argument"))?;
+        let file = sandboxed(&self.root, path)?;
+        fs::read_to_string(&file)
+            .map(Value::String)
+            .map_err(|err| tera::Error::msg(format!("Couldn't read `{path}`: {err}")))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Functions that read files or the environment, which `--sandbox` turns
+/// off for builds of content that isn't trusted.
+pub const SANDBOXED: &[&str] = &["include_code", "include_html", "get_env"];
+
+/// Stands in for a function `--sandbox` turned off, failing the render of
+/// anything that calls it.
+pub struct Disabled(pub &'static str);
+
+impl Function for Disabled {
+    fn call(&self, _: &HashMap<String, Value>) -> tera::Result<Value> {
+        Err(tera::Error::msg(format!(
+            "`{}` isn't available in sandboxed builds",
+            self.0
+        )))
+    }
+}
+
+const ASCIINEMA_SCRIPT: &str = "asciinema-player.min.js";
+const ASCIINEMA_STYLE: &str = "asciinema-player.css";
+
+/// `asciinema(src, ...)` embeds a terminal recording with a locally hosted
+/// copy of asciinema-player. The player's script and stylesheet are loaded
+/// on demand the first time a page embeds a recording.
+pub struct Asciinema {
+    player: Option<PathBuf>,
+}
+
+impl Asciinema {
+    /// `player` is the `dist` folder of the asciinema-player npm package.
+    pub fn new(player: Option<PathBuf>) -> Self {
+        Self { player }
+    }
+
+    /// Copies the player from `player` into `<output>/asciinema/`.
+    pub fn copy_assets(player: &Path, output: &Path) -> io::Result<()> {
+        fs::create_dir_all(output.join("asciinema"))?;
+        for (path, source) in Self::assets(player) {
+            fs::copy(source, output.join(path))?;
+        }
+
+        Ok(())
+    }
+
+    /// The player's files in `player`, by where they're served in the site.
+    pub fn assets(player: &Path) -> Vec<(PathBuf, PathBuf)> {
+        [ASCIINEMA_SCRIPT, ASCIINEMA_STYLE]
+            .iter()
+            .map(|asset| (Path::new("asciinema").join(asset), player.join(asset)))
+            .collect()
+    }
+}
+
+impl Function for Asciinema {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        if self.player.is_none() {
+            return Err(tera::Error::msg(
+                "`asciinema` needs a local copy of the player, pass its folder with --asciinema-player",
+            ));
+        }
+
+        let src = string_arg(args, "src")
+            .ok_or_else(|| tera::Error::msg("`asciinema` requires a `src` argument"))?;
+
+        // everything but `src` is handed to the player as options, e.g.
+        // cols, rows, autoPlay, loop, speed, idleTimeLimit, theme, poster
+        let options: serde_json::Map<String, Value> = args
+            .iter()
+            .filter(|(k, _)| k.as_str() != "src")
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+
+        let number = RECORDINGS.with(|count| {
+            count.set(count.get() + 1);
+            count.get()
+        });
+        let id = format!("asciinema-{number}");
+        let src = serde_json::to_string(src).map_err(tera::Error::json)?;
+        let options = serde_json::to_string(&options).map_err(tera::Error::json)?;
+
+        Ok(Value::String(format!(
+            r#"<div class="asciinema" id="{id}"></div>
+<script>
+(function () {{
+  var el = document.getElementById("{id}");
+  function create() {{ AsciinemaPlayer.create({src}, el, {options}); }}
+  if (window.AsciinemaPlayer) {{ return create(); }}
+  var script = document.querySelector("script[data-asciinema]");
+  if (!script) {{
+    var style = document.createElement("link");
+    style.rel = "stylesheet";
+    style.href = "/asciinema/{ASCIINEMA_STYLE}";
+    document.head.appendChild(style);
+    script = document.createElement("script");
+    script.src = "/asciinema/{ASCIINEMA_SCRIPT}";
+    script.setAttribute("data-asciinema", "");
+    document.head.appendChild(script);
+  }}
+  script.addEventListener("load", create);
+}})();
+</script>"#
+        )))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `cache(key, template)` renders `template` the first time a key is used
+/// in a build and gives every later page the same html, for fragments like
+/// a nav built from all the pages that would otherwise be rendered again
+/// for each one. The fragment is rendered with the site's context (not the
+/// page's) and any other arguments given the first time, and can cache
+/// fragments of its own.
+pub struct Cache {
+    fragments: Fragments,
+}
+
+// the templates fragments are rendered with have a `cache` of their own,
+// which only refers back to the one on the site's templates so they don't
+// keep each other alive
+enum Fragments {
+    Site(Arc<CacheState>),
+    Nested(Weak<CacheState>),
+}
+
+struct CacheState {
+    templates: OnceLock<Tera>,
+    context: Context,
+    rendered: Mutex<HashMap<String, String>>,
+}
+
+thread_local! {
+    // the keys being rendered on this thread, so a fragment that caches
+    // itself is an error rather than endless
+    static RENDERING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Cache {
+    /// Registers `cache` on `templates`, rendering fragments with them and
+    /// `context`.
+    pub fn register(templates: &mut Tera, context: Context) {
+        let state = Arc::new(CacheState {
+            templates: OnceLock::new(),
+            context,
+            rendered: Mutex::new(HashMap::new()),
+        });
+        let mut fragments = templates.clone();
+        fragments.register_function(
+            "cache",
+            Cache {
+                fragments: Fragments::Nested(Arc::downgrade(&state)),
+            },
+        );
+        let _ = state.templates.set(fragments);
+        templates.register_function(
+            "cache",
+            Cache {
+                fragments: Fragments::Site(state),
+            },
+        );
+    }
+}
+
+impl Function for Cache {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let key = string_arg(args, "key")
+            .ok_or_else(|| tera::Error::msg("`cache` requires a `key` argument"))?;
+        let template = string_arg(args, "template")
+            .ok_or_else(|| tera::Error::msg("`cache` requires a `template` argument"))?;
+        let state = match &self.fragments {
+            Fragments::Site(state) => state.clone(),
+            Fragments::Nested(state) => state
+                .upgrade()
+                .ok_or_else(|| tera::Error::msg("`cache` was called after its build"))?,
+        };
+
+        let cached = state
+            .rendered
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(key)
+            .cloned();
+        if let Some(html) = cached {
+            return Ok(Value::String(html));
+        }
+
+        if RENDERING.with(|keys| keys.borrow().iter().any(|k| k == key)) {
+            return Err(tera::Error::msg(format!(
+                "`cache` key `{key}` is used inside its own fragment"
+            )));
+        }
+        // rendered without the lock held, so the fragment can use `cache`
+        // itself and other threads' pages aren't kept waiting on it
+        let mut context = state.context.clone();
+        for (name, value) in args.iter() {
+            if name != "key" && name != "template" {
+                context.insert(name.as_str(), value);
+            }
+        }
+        let templates = state
+            .templates
+            .get()
+            .ok_or_else(|| tera::Error::msg("`cache` isn't registered"))?;
+        RENDERING.with(|keys| keys.borrow_mut().push(key.to_string()));
+        let html = templates.render(template, &context);
+        RENDERING.with(|keys| keys.borrow_mut().pop());
+        let html = html?;
+        // a thread that rendered the key first keeps its html, so every
+        // page still gets the same
+        let mut rendered = state.rendered.lock().unwrap_or_else(|err| err.into_inner());
+        let html = rendered.entry(key.to_string()).or_insert(html);
+        Ok(Value::String(html.to_string()))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `get_page(path=...)`, the page at `path` in the content folder, like
+/// `blog/hello.md`, for layouts to link to or show any page wherever it
+/// comes in the site. Paths use `/` on every platform.
+pub struct GetPage {
+    pages: HashMap<String, Value>,
+}
+
+impl GetPage {
+    pub fn new(contents: &[Content]) -> Self {
+        let pages = contents
+            .iter()
+            .filter_map(|content| {
+                let page = serde_json::to_value(content).ok()?;
+                Some((content.path.replace('\\', "/"), page))
+            })
+            .collect();
+        Self { pages }
+    }
+}
+
+impl Function for GetPage {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = string_arg(args, "path")
+            .ok_or_else(|| tera::Error::msg("`get_page` requires a `path` argument"))?;
+        let path = path.trim_start_matches("@/").trim_start_matches('/');
+        self.pages
+            .get(path)
+            .cloned()
+            .ok_or_else(|| tera::Error::msg(format!("`get_page` couldn't find {path}")))
+    }
+}
+
+thread_local! {
+    // sidenotes numbered so far on the page being compiled or laid out on
+    // this thread, its layout carrying on from its content
+    static SIDENOTES: Cell<usize> = const { Cell::new(0) };
+    // and recordings embedded, so their ids don't depend on which pages
+    // were compiled before
+    static RECORDINGS: Cell<usize> = const { Cell::new(0) };
+    // files `include_code` read for the page, which its compiled content
+    // has to be compiled again for when they change
+    static INCLUDED: RefCell<BTreeSet<PathBuf>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// How far a page's content numbered its sidenotes and recordings, which
+/// its layout carries on from so their ids stay unique on the page.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Numbering {
+    sidenotes: usize,
+    recordings: usize,
+}
+
+/// Restarts sidenote and recording numbering, and the files included, before
+/// each page is compiled.
+pub fn start_page() {
+    continue_page(Numbering::default());
+    INCLUDED.with(|included| included.borrow_mut().clear());
+}
+
+/// The files `include_code` read since the page started.
+pub fn included_files() -> Vec<PathBuf> {
+    INCLUDED.with(|included| included.borrow().iter().cloned().collect())
+}
+
+/// How far the page being compiled on this thread has numbered.
+pub fn numbering() -> Numbering {
+    Numbering {
+        sidenotes: SIDENOTES.with(Cell::get),
+        recordings: RECORDINGS.with(Cell::get),
+    }
+}
+
+/// Carries on numbering from where a page's content got to, before its
+/// layout renders.
+pub fn continue_page(numbering: Numbering) {
+    SIDENOTES.with(|count| count.set(numbering.sidenotes));
+    RECORDINGS.with(|count| count.set(numbering.recordings));
+}
+
+// renders inline markdown, without the paragraph it would be wrapped in
+fn inline_markdown(text: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(text));
+    let html = html.trim();
+    html.strip_prefix("<p>")
+        .and_then(|html| html.strip_suffix("</p>"))
+        .unwrap_or(html)
+        .to_string()
+}
+
+/// `sidenote(text, margin=false)` is a Tufte style margin note. Sidenotes
+/// are numbered through the page; `margin=true` leaves a note unnumbered.
+/// Without the stylesheet a note reads inline, after its number.
+pub fn sidenote(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = string_arg(args, "text")
+        .ok_or_else(|| tera::Error::msg("`sidenote` requires a `text` argument"))?;
+    let margin = args.get("margin").and_then(Value::as_bool).unwrap_or(false);
+
+    let number = SIDENOTES.with(|count| {
+        count.set(count.get() + 1);
+        count.get()
+    });
+    let id = format!("sn-{number}");
+    let text = inline_markdown(text);
+
+    let html = if margin {
+        format!(
+            r#"<label for="{id}" class="margin-toggle" aria-label="Margin note">&#8853;</label><input type="checkbox" id="{id}" class="margin-toggle"><small class="marginnote" role="note">{text}</small>"#
+        )
+    } else {
+        format!(
+            r#"<label for="{id}" class="margin-toggle sidenote-number" aria-label="Sidenote {number}"><sup>{number}</sup></label><input type="checkbox" id="{id}" class="margin-toggle"><small class="sidenote" role="note"><sup class="sidenote-number">{number}</sup> {text}</small>"#
+        )
+    };
+
+    Ok(Value::String(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_carry_on_numbering() {
+        let mut templates = Tera::default();
+        templates.register_function("sidenote", sidenote);
+        let note = "{{ sidenote(text=\"note\") }}";
+
+        start_page();
+        let content = templates.render_str(note, &Context::new()).unwrap();
+        let numbering = numbering();
+        // another page compiled on the thread in between
+        start_page();
+        templates.render_str(note, &Context::new()).unwrap();
+
+        continue_page(numbering);
+        let layout = templates.render_str(note, &Context::new()).unwrap();
+        assert!(content.contains("id=\"sn-1\""), "{content}");
+        assert!(layout.contains("id=\"sn-2\""), "{layout}");
+    }
+
+    #[test]
+    fn pages_record_included_code() {
+        let root = std::env::temp_dir().join(format!("roxy-include-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        let theme =
+            syntect::highlighting::ThemeSet::load_defaults().themes["base16-ocean.dark"].clone();
+        let mut templates = Tera::default();
+        templates.register_function("include_code", IncludeCode::new(root.clone(), theme));
+
+        start_page();
+        templates
+            .render_str("{{ include_code(path=\"main.rs\") }}", &Context::new())
+            .unwrap();
+        let included = included_files();
+        start_page();
+        let next = included_files();
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(included, vec![root.join("main.rs")]);
+        assert!(next.is_empty());
+    }
+
+    #[test]
+    fn nested_caches() {
+        let mut templates = Tera::default();
+        templates
+            .add_raw_templates(vec![
+                (
+                    "nav.html",
+                    "<nav>{{ cache(key=\"item\", template=\"item.html\") }}</nav>",
+                ),
+                ("item.html", "<li>{{ title }}</li>"),
+                (
+                    "loop.html",
+                    "{{ cache(key=\"loop\", template=\"loop.html\") }}",
+                ),
+            ])
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("title", "Site");
+        Cache::register(&mut templates, context);
+
+        let page = "{{ cache(key=\"nav\", template=\"nav.html\") }}";
+        assert_eq!(
+            templates.render_str(page, &Context::new()).unwrap(),
+            "<nav><li>Site</li></nav>"
+        );
+        let page = "{{ cache(key=\"loop\", template=\"loop.html\") }}";
+        assert!(templates.render_str(page, &Context::new()).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{diagnostics, interrupt, Options};
+
+/// The branch `--output` commits the site to, from `git:gh-pages`.
+pub fn branch(output: &str) -> Option<&str> {
+    output
+        .strip_prefix("git:")
+        .map(|branch| branch.trim_matches('/'))
+        .filter(|branch| !branch.is_empty())
+}
+
+fn git(command: &mut Command, args: &[&str]) -> io::Result<String> {
+    let output = command.args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// where the site for `branch` is built before it's committed, apart from
+// other builds running at the same time, in this process or another
+fn temp_dir(branch: &str) -> PathBuf {
+    let repository = std::env::current_dir().unwrap_or_default();
+    let key = format!("{}:{branch}", repository.to_string_lossy());
+    let site = format!("{:x}", Sha256::digest(key.as_bytes()));
+    std::env::temp_dir().join(format!(
+        "roxy-branch-{}-{}",
+        std::process::id(),
+        &site[..12]
+    ))
+}
+
+// what the commit of a build says, after the commit it was built from
+fn message() -> String {
+    let built_from = git(
+        &mut Command::new("git"),
+        &["log", "-1", "--format=%h %s", "HEAD"],
+    );
+    match built_from {
+        Ok(built_from) if !built_from.is_empty() => {
+            format!("Build the site\n\nBuilt with roxy from {built_from}")
+        }
+        _ => "Build the site\n\nBuilt with roxy".to_string(),
+    }
+}
+
+/// Commits the files in `dir` to `branch` of the repository in the working
+/// directory, as its whole tree, on top of the branch's last commit. The
+/// working tree, index and checked out branch are left alone. Returns the
+/// new commit, or `None` when the site is the same as the branch has it.
+pub fn commit(dir: &Path, branch: &str) -> io::Result<Option<String>> {
+    let git_dir = git(
+        &mut Command::new("git"),
+        &["rev-parse", "--absolute-git-dir"],
+    )?;
+    let index = dir.with_extension("index");
+    let _ = fs::remove_file(&index);
+    // the build's files are staged in an index of their own
+    let staged = || {
+        let mut command = Command::new("git");
+        command
+            .env("GIT_DIR", &git_dir)
+            .env("GIT_INDEX_FILE", &index)
+            .env("GIT_WORK_TREE", dir)
+            .current_dir(dir);
+        command
+    };
+    let result = (|| {
+        git(&mut staged(), &["add", "--all", "--force", "."])?;
+        let tree = git(&mut staged(), &["write-tree"])?;
+
+        let reference = format!("refs/heads/{branch}");
+        let parent = git(
+            &mut Command::new("git"),
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{reference}^{{commit}}"),
+            ],
+        )
+        .ok();
+        if let Some(parent) = &parent {
+            let parent_tree = git(
+                &mut Command::new("git"),
+                &["rev-parse", &format!("{parent}^{{tree}}")],
+            )?;
+            if parent_tree == tree {
+                return Ok(None);
+            }
+        }
+
+        let message = message();
+        let mut args = vec!["commit-tree", tree.as_str(), "-m", message.as_str()];
+        if let Some(parent) = &parent {
+            args.extend(["-p", parent.as_str()]);
+        }
+        let commit = git(&mut Command::new("git"), &args)?;
+
+        let mut args = vec!["update-ref", reference.as_str(), commit.as_str()];
+        // fails if something else moved the branch meanwhile
+        args.extend(parent.as_deref());
+        git(&mut Command::new("git"), &args)?;
+        Ok(Some(commit))
+    })();
+    let _ = fs::remove_file(&index);
+    result
+}
+
+/// Runs `f` with `opts`, or when `--output` is a branch, with the site
+/// built to a temporary folder that is committed to the branch when the
+/// build succeeded, then removed.
+pub fn with_output<T>(opts: &Options, f: impl FnOnce(&Options) -> io::Result<T>) -> io::Result<T> {
+    let Some(branch) = branch(&opts.output) else {
+        return f(opts);
+    };
+
+    let dest = temp_dir(branch);
+    interrupt::remove_on_interrupt(opts, &dest);
+    interrupt::remove_on_interrupt(opts, &dest.with_extension("index"));
+    let _ = fs::remove_dir_all(&dest);
+    let mut built = opts.clone();
+    built.output = format!("{}/", dest.to_string_lossy());
+    let result = f(&built).and_then(|value| {
+        // a build with errors keeps going, but isn't published
+        if diagnostics::failure(opts.max_warnings).is_none() {
+            match commit(&dest, branch)? {
+                Some(commit) => {
+                    diagnostics::info(format!("Committed the site to {branch} as {commit}"))
+                }
+                None => diagnostics::info(format!("The site is the same as {branch} has it")),
+            }
+        }
+        Ok(value)
+    });
+    let _ = fs::remove_dir_all(&dest);
+    result
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Tag};
+use regex::Regex;
+use serde::Serialize;
+
+/// One term of `data/glossary.*`, available to layouts as `glossary`.
+#[derive(Debug, Serialize)]
+pub struct Term {
+    pub term: String,
+    pub definition: String,
+    pub anchor: String,
+}
+
+/// Terms defined in the site's glossary, wrapped in `<abbr>` the first time
+/// each appears on a page.
+pub struct Glossary {
+    pub terms: Vec<Term>,
+    /// The file the glossary was read from
+    pub source: PathBuf,
+    pattern: Regex,
+}
+
+fn invalid(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {err}", path.to_string_lossy()),
+    )
+}
+
+/// Reads `glossary.toml`, `glossary.yaml` or `glossary.json` from the data
+/// folder, a table of terms and their definitions.
+pub fn load(data_dir: &str) -> io::Result<Option<Glossary>> {
+    let dir = Path::new(data_dir);
+    let path = match ["toml", "yaml", "yml", "json"]
+        .iter()
+        .map(|ext| dir.join(format!("glossary.{ext}")))
+        .find(|path| path.is_file())
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let text = fs::read_to_string(&path)?;
+    let definitions: BTreeMap<String, String> = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|err| invalid(&path, err))?,
+        Some("json") => serde_json::from_str(&text).map_err(|err| invalid(&path, err))?,
+        _ => serde_yaml::from_str(&text).map_err(|err| invalid(&path, err))?,
+    };
+
+    if definitions.is_empty() {
+        return Ok(None);
+    }
+
+    // longest first, so `HTTP/2` wins over `HTTP`
+    let mut alternatives: Vec<&String> = definitions.keys().collect();
+    alternatives.sort_by(|a, b| b.len().cmp(&a.len()));
+    let alternatives: Vec<String> = alternatives.iter().map(|t| regex::escape(t)).collect();
+    let pattern = Regex::new(&format!(r"\b(?:{})\b", alternatives.join("|")))
+        .map_err(|err| invalid(&path, err))?;
+
+    let terms = definitions
+        .into_iter()
+        .map(|(term, definition)| Term {
+            anchor: crate::slugify(&term),
+            term,
+            definition,
+        })
+        .collect();
+
+    Ok(Some(Glossary {
+        terms,
+        source: path,
+        pattern,
+    }))
+}
+
+impl Glossary {
+    fn definition(&self, term: &str) -> Option<&str> {
+        self.terms
+            .iter()
+            .find(|t| t.term == term)
+            .map(|t| t.definition.as_str())
+    }
+
+    // wraps the first unseen occurrence of each term in `text`
+    fn annotate(&self, text: &str, seen: &mut HashSet<String>) -> Option<String> {
+        let mut out = String::new();
+        let mut last = 0;
+
+        for found in self.pattern.find_iter(text) {
+            if !seen.insert(found.as_str().to_string()) {
+                continue;
+            }
+            let definition = match self.definition(found.as_str()) {
+                Some(definition) => definition,
+                None => continue,
+            };
+
+            let _ = escape_html(&mut out, &text[last..found.start()]);
+            out.push_str("<abbr title=\"");
+            let _ = escape_html(&mut out, definition);
+            out.push_str("\">");
+            let _ = escape_html(&mut out, found.as_str());
+            out.push_str("</abbr>");
+            last = found.end();
+        }
+
+        if last == 0 {
+            return None;
+        }
+
+        let _ = escape_html(&mut out, &text[last..]);
+        Some(out)
+    }
+
+    /// Wraps the first occurrence of every term on a page in an `<abbr>`
+    /// with its definition, leaving code, links, headings and images alone.
+    pub fn render<'a, I: Iterator<Item = Event<'a>>>(&self, events: I) -> Vec<Event<'a>> {
+        let mut out = Vec::new();
+        let mut seen = HashSet::new();
+        let mut skip = 0usize;
+
+        for event in events {
+            match &event {
+                Event::Start(
+                    Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..) | Tag::Heading(..),
+                ) => skip += 1,
+                Event::End(
+                    Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..) | Tag::Heading(..),
+                ) => skip = skip.saturating_sub(1),
+                Event::Text(text) if skip == 0 => {
+                    if let Some(html) = self.annotate(text, &mut seen) {
+                        out.push(Event::Html(CowStr::from(html)));
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            out.push(event);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glob::glob;
+
+use crate::{diagnostics::Diagnostic, output_dir, Content};
+
+/// The headers file hosts like Netlify and Cloudflare Pages read from the
+/// root of the site.
+pub const FILE_NAME: &str = "_headers";
+
+/// The headers of each page, by its url.
+pub type Headers = BTreeMap<String, Vec<(String, String)>>;
+
+/// The url of the page written to `path` in `output`: `/blog/post/` for
+/// `blog/post/index.html`.
+pub fn url(output: &str, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(output.trim_end_matches('/')).ok()?;
+    let mut url: Vec<String> = relative
+        .iter()
+        .map(|part| part.to_string_lossy().to_string())
+        .collect();
+    if url.last().is_some_and(|name| name == "index.html") {
+        url.pop();
+        url.push(String::new());
+    }
+    Some(format!("/{}", url.join("/")))
+}
+
+// every page written to `output`, by its url
+fn pages(output: &Path) -> Vec<(String, PathBuf)> {
+    let pattern = format!(
+        "{}/**/*.html",
+        glob::Pattern::escape(&output.to_string_lossy())
+    );
+    glob(&pattern)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|path| Some((url(&output.to_string_lossy(), &path)?, path)))
+        .collect()
+}
+
+// a header name is a token of the HTTP spec
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+/// The headers a page sets itself in its frontmatter, like
+/// `headers: { X-Robots-Tag: noindex }`, sorted by name. Those that
+/// couldn't be written to a headers file are reported and left out.
+pub fn from_frontmatter(content: &Content) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = content
+        .frontmatter
+        .0
+        .iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix("headers.")?;
+            let value = value.trim();
+            if !is_name(name) || value.contains(['\r', '\n']) {
+                Diagnostic::warning(format!("Ignoring the header `{name}`"))
+                    .in_file(&content.path)
+                    .note("a header has a name of letters, digits and dashes, and a single line value")
+                    .print();
+                return None;
+            }
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+    headers.sort();
+    headers
+}
+
+/// The headers each page of `content` sets itself, by the url it's written
+/// at in `output`.
+pub fn own(output: &str, content: &[Content]) -> HashMap<String, Vec<(String, String)>> {
+    content
+        .iter()
+        .filter_map(|page| {
+            let dir = output_dir(output, page)?;
+            let url = url(output, &dir.join("index.html"))?;
+            Some((url, from_frontmatter(page)))
+        })
+        .filter(|(_, headers)| !headers.is_empty())
+        .collect()
+}
+
+/// `headers` with `own` in place of those of the same names, the headers a
+/// page sets itself.
+pub fn merge(headers: &mut Vec<(String, String)>, own: &[(String, String)]) {
+    headers.retain(|(name, _)| !own.iter().any(|(own, _)| own.eq_ignore_ascii_case(name)));
+    headers.extend(own.iter().cloned());
+}
+
+/// The headers of every page written to `output`, from `page_headers` of
+/// its url and html.
+pub fn collect(
+    output: &str,
+    page_headers: impl Fn(&str, &str) -> Vec<(String, String)>,
+) -> io::Result<Headers> {
+    let output = Path::new(output.trim_end_matches('/'));
+    let mut headers = Headers::new();
+    for (url, path) in pages(output) {
+        let html = fs::read_to_string(&path)?;
+        let page = page_headers(&url, &html);
+        if !page.is_empty() {
+            headers.entry(url).or_default().extend(page);
+        }
+    }
+    Ok(headers)
+}
+
+/// `headers` as the `_headers` file lists them.
+pub fn text(headers: &Headers) -> String {
+    let mut text = String::new();
+    for (url, headers) in headers.iter() {
+        text.push_str(url);
+        text.push('\n');
+        for (name, value) in headers.iter() {
+            text.push_str(&format!("  {name}: {value}\n"));
+        }
+    }
+    text
+}
+
+/// Writes `headers` to the `_headers` file of `output`.
+pub fn write(output: &str, headers: &Headers) -> io::Result<()> {
+    fs::write(Path::new(output).join(FILE_NAME), text(headers))
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashSet, sync::OnceLock};
+
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Tag};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::slugify;
+
+/// A heading of a page, for tables of contents and search results that
+/// link to the section rather than the top of the page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heading {
+    pub level: u32,
+    pub title: String,
+    pub anchor: String,
+    /// The plain text of the section, up to the next heading
+    pub text: String,
+}
+
+fn unique(anchor: String, used: &mut HashSet<String>) -> String {
+    let anchor = if anchor.is_empty() {
+        "section".to_string()
+    } else {
+        anchor
+    };
+
+    let mut candidate = anchor.to_string();
+    let mut n = 1;
+    while !used.insert(candidate.to_string()) {
+        candidate = format!("{anchor}-{n}");
+        n += 1;
+    }
+    candidate
+}
+
+// `Setup {#setup}` as `Setup` and `setup`, the id written after a heading
+// for an anchor that doesn't change with its title, like in a translation
+fn explicit_id(title: &str) -> Option<(&str, &str)> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"\s*\{#([A-Za-z0-9_:.-]+)\}\s*$").unwrap());
+    let found = pattern.captures(title)?;
+    let start = found.get(0)?.start();
+    Some((&title[..start], found.get(1)?.as_str()))
+}
+
+/// Finds the page's headings, with an anchor for each (its own id, or one
+/// made from its title) and the text of its section. Ids written as
+/// `{#id}` after a heading are read even without the `heading_attributes`
+/// markdown option.
+pub fn collect(events: &[Event]) -> Vec<Heading> {
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut ids: Vec<Option<String>> = Vec::new();
+    let mut in_heading = false;
+
+    for event in events.iter() {
+        match event {
+            Event::Start(Tag::Heading(level, id, _)) => {
+                in_heading = true;
+                ids.push(id.map(|id| id.to_string()));
+                headings.push(Heading {
+                    level: *level as u32,
+                    title: String::new(),
+                    anchor: String::new(),
+                    text: String::new(),
+                });
+            }
+            Event::End(Tag::Heading(..)) => in_heading = false,
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = headings.last_mut() {
+                    if in_heading {
+                        heading.title.push_str(text);
+                    } else {
+                        heading.text.push_str(text);
+                    }
+                }
+            }
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => {
+                if let Some(heading) = headings.last_mut() {
+                    if !in_heading && !heading.text.is_empty() && !heading.text.ends_with(' ') {
+                        heading.text.push(' ');
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (heading, id) in headings.iter_mut().zip(ids.iter_mut()) {
+        if let Some((title, explicit)) = explicit_id(&heading.title).filter(|_| id.is_none()) {
+            *id = Some(explicit.to_string());
+            heading.title = title.to_string();
+        }
+    }
+
+    // explicit ids are claimed first so generated ones don't repeat them
+    let mut used: HashSet<String> = ids.iter().flatten().cloned().collect();
+    for (heading, id) in headings.iter_mut().zip(ids.into_iter()) {
+        heading.title = heading.title.trim().to_string();
+        heading.text = heading.text.trim().to_string();
+        heading.anchor = match id {
+            Some(id) => id,
+            None => unique(slugify(&heading.title), &mut used),
+        };
+    }
+
+    headings
+}
+
+/// Writes each heading's opening tag with its anchor as the `id`, taking
+/// any `{#id}` out of its text.
+pub fn add_ids<'a, I: Iterator<Item = Event<'a>>>(
+    events: I,
+    headings: &[Heading],
+) -> Vec<Event<'a>> {
+    let mut anchors = headings.iter().map(|heading| heading.anchor.as_str());
+    let mut out = Vec::new();
+    // where the heading being written starts in `out`
+    let mut heading_start = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(level, _, classes)) => {
+                let mut tag = format!("<{level}");
+                if let Some(anchor) = anchors.next() {
+                    tag.push_str(" id=\"");
+                    let _ = escape_html(&mut tag, anchor);
+                    tag.push('"');
+                }
+                if !classes.is_empty() {
+                    tag.push_str(" class=\"");
+                    let _ = escape_html(&mut tag, &classes.join(" "));
+                    tag.push('"');
+                }
+                tag.push('>');
+                heading_start = Some(out.len());
+                out.push(Event::Html(CowStr::from(tag)));
+            }
+            Event::End(Tag::Heading(level, ..)) => {
+                if let Some(start) = heading_start.take() {
+                    let mut heading = out.split_off(start);
+                    strip_id(&mut heading);
+                    out.extend(heading);
+                }
+                out.push(Event::Html(CowStr::from(format!("</{level}>\n"))));
+            }
+            event => out.push(event),
+        }
+    }
+    out
+}
+
+// takes a trailing `{#id}` out of the text a heading ends with, which
+// markdown can have split up around underscores
+fn strip_id(heading: &mut Vec<Event>) {
+    let start = heading
+        .iter()
+        .rposition(|event| !matches!(event, Event::Text(_)))
+        .map_or(0, |i| i + 1);
+    let text: String = heading[start..]
+        .iter()
+        .filter_map(|event| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect();
+    if let Some((title, _)) = explicit_id(&text) {
+        heading.truncate(start);
+        heading.push(Event::Text(CowStr::from(title.to_string())));
+    }
+}
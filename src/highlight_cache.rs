@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use highlight_pulldown::PulldownHighlighter;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag};
+use sha2::{Digest, Sha256};
+
+/// Highlighted code blocks kept on disk between builds, by a hash of the
+/// code, its info string, the theme and the version of roxy that highlighted
+/// it.
+pub struct HighlightCache {
+    dir: PathBuf,
+    theme: String,
+    /// Keep blocks in memory only
+    read_only: bool,
+    /// Blocks already seen this build, so repeated snippets skip the disk
+    seen: Mutex<HashMap<String, String>>,
+}
+
+impl HighlightCache {
+    /// A cache in `<cache_dir>/highlight` for blocks highlighted with `theme`.
+    pub fn new(cache_dir: &Path, theme: &str) -> Self {
+        Self {
+            dir: cache_dir.join("highlight"),
+            theme: theme.to_string(),
+            read_only: false,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cache, without writing the blocks it highlights to disk.
+    pub fn read_only(self) -> Self {
+        Self {
+            read_only: true,
+            ..self
+        }
+    }
+
+    fn key(&self, lang: &str, code: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [env!("CARGO_PKG_VERSION"), &self.theme, lang, code] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let mut seen = self.seen.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(html) = seen.get(key) {
+            return Some(html.to_string());
+        }
+        let html = fs::read_to_string(self.dir.join(key)).ok()?;
+        seen.insert(key.to_string(), html.to_string());
+        Some(html)
+    }
+
+    // the cache only saves time, so failing to write to it isn't an error
+    fn put(&self, key: &str, html: &str) {
+        self.seen
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(key.to_string(), html.to_string());
+        if !self.read_only && fs::create_dir_all(&self.dir).is_ok() {
+            let _ = fs::write(self.dir.join(key), html);
+        }
+    }
+
+    fn highlight_block<'a>(
+        &self,
+        highlighter: &PulldownHighlighter,
+        lang: &str,
+        block: Vec<Event<'a>>,
+    ) -> Result<Event<'a>, String> {
+        let code: String = block
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let key = self.key(lang, &code);
+        if let Some(html) = self.get(&key) {
+            return Ok(Event::Html(CowStr::from(html)));
+        }
+
+        let highlighted = highlighter
+            .highlight(block.into_iter())
+            .map_err(|err| err.to_string())?;
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, highlighted.into_iter());
+        self.put(&key, &html);
+        Ok(Event::Html(CowStr::from(html)))
+    }
+
+    /// Highlights the code blocks in `events` with `highlighter`, reusing the
+    /// html of blocks highlighted before.
+    pub fn highlight<'a>(
+        &self,
+        highlighter: &PulldownHighlighter,
+        events: Vec<Event<'a>>,
+    ) -> Result<Vec<Event<'a>>, String> {
+        let mut highlighted = Vec::with_capacity(events.len());
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            let lang = match &event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => info.to_string(),
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => String::new(),
+                _ => {
+                    highlighted.push(event);
+                    continue;
+                }
+            };
+
+            let mut block = vec![event];
+            for event in events.by_ref() {
+                let end = matches!(event, Event::End(Tag::CodeBlock(_)));
+                block.push(event);
+                if end {
+                    break;
+                }
+            }
+            highlighted.push(self.highlight_block(highlighter, &lang, block)?);
+        }
+
+        Ok(highlighted)
+    }
+}
@@ -0,0 +1,145 @@
+use std::{cell::RefCell, collections::HashMap, fs, io, path::Path};
+
+use glob::glob;
+use tera::{Function, Value};
+
+thread_local! {
+    // the language of the page currently being rendered on this thread
+    static CURRENT_LANGUAGE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+pub fn set_current_language(lang: &str) {
+    CURRENT_LANGUAGE.with(|current| *current.borrow_mut() = Some(lang.to_string()));
+}
+
+pub fn current_language() -> Option<String> {
+    CURRENT_LANGUAGE.with(|current| current.borrow().clone())
+}
+
+// primary language subtags which are written right-to-left
+const RTL_LANGUAGES: [&str; 12] = [
+    "ar", "arc", "ckb", "dv", "fa", "he", "ks", "ps", "sd", "ug", "ur", "yi",
+];
+
+/// Returns `rtl` or `ltr` for a language tag such as `ar` or `he-IL`.
+/// `extra_rtl` adds languages beyond the built-in list.
+pub fn text_direction(lang: &str, extra_rtl: &[String]) -> &'static str {
+    let primary = lang
+        .split(['-', '_'])
+        .next()
+        .unwrap_or(lang)
+        .to_ascii_lowercase();
+
+    let is_rtl = RTL_LANGUAGES.contains(&primary.as_str())
+        || extra_rtl
+            .iter()
+            .any(|l| l.eq_ignore_ascii_case(lang) || l.eq_ignore_ascii_case(&primary));
+
+    if is_rtl {
+        "rtl"
+    } else {
+        "ltr"
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Translations {
+    pub default_language: String,
+    catalogs: HashMap<String, HashMap<String, String>>,
+}
+
+impl Translations {
+    /// Loads every `<lang>.toml` catalog in `dir`. Nested tables are
+    /// flattened into dotted keys, so `[post] read_more = "..."` becomes
+    /// `post.read_more`.
+    pub fn load(dir: &str, default_language: &str) -> io::Result<Self> {
+        let mut catalogs = HashMap::new();
+        let path = format!("{}/*.toml", glob::Pattern::escape(dir));
+
+        for entry in glob(path.as_str()).into_iter().flatten() {
+            if let Ok(entry) = entry {
+                if let Some(lang) = entry.file_stem().and_then(|s| s.to_str()) {
+                    let catalog = read_catalog(&entry)?;
+                    catalogs.insert(lang.to_string(), catalog);
+                }
+            }
+        }
+
+        Ok(Self {
+            default_language: default_language.to_string(),
+            catalogs,
+        })
+    }
+
+    /// The languages there are catalogs for.
+    pub fn languages(&self) -> Vec<String> {
+        self.catalogs.keys().cloned().collect()
+    }
+
+    /// Looks `key` up in `lang`, falling back to the default language.
+    pub fn get(&self, lang: &str, key: &str) -> Option<&String> {
+        self.catalogs
+            .get(lang)
+            .and_then(|catalog| catalog.get(key))
+            .or_else(|| {
+                self.catalogs
+                    .get(&self.default_language)
+                    .and_then(|catalog| catalog.get(key))
+            })
+    }
+}
+
+fn read_catalog(path: &Path) -> io::Result<HashMap<String, String>> {
+    let text = fs::read_to_string(path)?;
+    let value: toml::Value = toml::from_str(&text)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+    let mut catalog = HashMap::new();
+    flatten(&mut catalog, "", &value);
+    Ok(catalog)
+}
+
+fn flatten(catalog: &mut HashMap<String, String>, prefix: &str, value: &toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (k, v) in table.iter() {
+                let key = if prefix.is_empty() {
+                    k.to_string()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+                flatten(catalog, &key, v);
+            }
+        }
+        toml::Value::String(s) => {
+            catalog.insert(prefix.to_string(), s.to_string());
+        }
+        other => {
+            catalog.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+impl Function for Translations {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let key = args
+            .get("key")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`trans` requires a `key` argument"))?;
+
+        let lang = args
+            .get("lang")
+            .and_then(Value::as_str)
+            .map(|s| s.to_string())
+            .or_else(current_language)
+            .unwrap_or_else(|| self.default_language.clone());
+
+        match self.get(&lang, key) {
+            Some(value) => Ok(Value::String(value.to_string())),
+            None => Err(tera::Error::msg(format!(
+                "No translation for `{key}` in `{lang}` or `{}`",
+                self.default_language
+            ))),
+        }
+    }
+}
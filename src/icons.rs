@@ -0,0 +1,158 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use glob::glob;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tera::{Function, Value};
+
+use crate::{csp::attribute, svg};
+
+/// The name of the sprite sheet at the root of the site.
+pub const FILE_NAME: &str = "icons.svg";
+
+/// The icons of the icons folder, as the `<symbol>`s of one sprite sheet.
+pub struct Sprite {
+    /// The `<symbol>` of each icon, by its name
+    symbols: BTreeMap<String, String>,
+}
+
+// `icon` as a `<symbol>` with the id `id`: its `viewBox` and what's inside
+// its `<svg>`
+fn symbol(id: &str, icon: &str) -> Option<String> {
+    let root = Regex::new(r"(?is)<svg\b([^>]*)>(.*)</svg\s*>").unwrap();
+    let c = root.captures(icon)?;
+    let attributes = c.get(1).map_or("", |attributes| attributes.as_str());
+    let view_box = attribute(attributes, "viewBox")
+        .map(str::to_string)
+        .or_else(|| {
+            let width = attribute(attributes, "width")?.trim_end_matches("px");
+            let height = attribute(attributes, "height")?.trim_end_matches("px");
+            Some(format!("0 0 {width} {height}"))
+        });
+    let view_box = view_box.map_or(String::new(), |view_box| {
+        format!(r#" viewBox="{view_box}""#)
+    });
+    Some(format!(r#"<symbol id="{id}"{view_box}>{}</symbol>"#, &c[2]))
+}
+
+/// Reads the SVGs below `dir` as icons, each named by its path without
+/// `.svg` and with `-` between folders: `social/github.svg` is
+/// `social-github`. A missing folder has no icons.
+pub fn load(dir: &str) -> io::Result<Sprite> {
+    let root = Path::new(dir);
+    let pattern = format!(
+        "{}/**/*.svg",
+        glob::Pattern::escape(dir.trim_end_matches('/'))
+    );
+    let mut symbols = BTreeMap::new();
+    for path in glob(&pattern).into_iter().flatten().flatten() {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let name = relative
+            .with_extension("")
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("-");
+        let icon = svg::optimize(&fs::read_to_string(&path)?);
+        let symbol = symbol(&format!("icon-{name}"), &icon).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} isn't an SVG", path.to_string_lossy()),
+            )
+        })?;
+        symbols.insert(name, symbol);
+    }
+    Ok(Sprite { symbols })
+}
+
+impl Sprite {
+    /// The sprite sheet, with a `<symbol>` for each icon, unless there are
+    /// none.
+    pub fn sheet(&self) -> Option<String> {
+        if self.symbols.is_empty() {
+            return None;
+        }
+        let symbols: String = self.symbols.values().map(String::as_str).collect();
+        Some(format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" style="display:none">{symbols}</svg>"#
+        ))
+    }
+
+    /// Writes the sprite sheet to the root of `output`, if there are icons.
+    pub fn write(&self, output: &str) -> io::Result<()> {
+        match self.sheet() {
+            Some(sheet) => fs::write(Path::new(output).join(FILE_NAME), sheet),
+            None => Ok(()),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+/// `icon(name="github", class="big")` draws an icon of the icons folder
+/// from the sprite sheet, with `<use>`, so pages load every icon in one
+/// request. Icons are hidden from screen readers unless given a `label`.
+pub struct Icon {
+    names: Vec<String>,
+    /// The sprite sheet's url, with a hash of it so browsers fetch it again
+    /// after a change
+    url: String,
+}
+
+impl Icon {
+    pub fn new(sprite: &Sprite) -> Self {
+        let hash = format!(
+            "{:x}",
+            Sha256::digest(sprite.sheet().unwrap_or_default().as_bytes())
+        );
+        Self {
+            names: sprite.symbols.keys().cloned().collect(),
+            url: format!("/{FILE_NAME}?v={}", &hash[..8]),
+        }
+    }
+}
+
+impl Function for Icon {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let name = args
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`icon` requires a `name` argument"))?;
+        if !self.names.iter().any(|icon| icon == name) {
+            return Err(tera::Error::msg(format!(
+                "`icon`: there's no icon named `{name}` in the icons folder"
+            )));
+        }
+
+        let mut class = "icon".to_string();
+        if let Some(extra) = args.get("class").and_then(Value::as_str) {
+            class.push(' ');
+            class.push_str(extra);
+        }
+        let label = match args.get("label").and_then(Value::as_str) {
+            Some(label) => format!(r#" role="img" aria-label="{}""#, escape(label)),
+            None => r#" aria-hidden="true""#.to_string(),
+        };
+        Ok(Value::String(format!(
+            r##"<svg class="{}"{label} focusable="false"><use href="{}#icon-{}"></use></svg>"##,
+            escape(&class),
+            self.url,
+            escape(name)
+        )))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
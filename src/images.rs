@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tera::{Function, Value};
+
+use crate::{
+    build_info,
+    diagnostics::{Diagnostic, Failure},
+};
+
+/// Static images that get resized and converted, by extension. Animated
+/// gifs would lose their animation, so they're left alone.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// How static images are resized and converted, from `[images]` in the site
+/// config.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageOptions {
+    /// Widths in pixels every image gets a variant at, like `photo-480w.jpg`
+    pub widths: Vec<u32>,
+    /// Formats every image and variant is also converted to, `webp` or `avif`
+    pub formats: Vec<String>,
+}
+
+/// Reads the `[images]` table of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<ImageOptions> {
+    crate::config::table(config, "images")
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+/// Whether `path` is an image variants are made of.
+pub fn is_image(path: &Path) -> bool {
+    extension(path).is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Where the variant of the image at `path` is: `photo.jpg` at 480 pixels
+/// wide as webp is `photo-480w.webp`.
+pub fn variant_path(path: &Path, width: Option<u32>, format: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match width {
+        Some(width) => format!("{stem}-{width}w.{format}"),
+        None => format!("{stem}.{format}"),
+    };
+    path.with_file_name(name)
+}
+
+/// The images the variant at `path` could have been made from, with its
+/// width and format, for finding a variant nobody wrote, like when serving
+/// from memory.
+pub fn variant_sources(path: &Path) -> Option<(Vec<PathBuf>, Option<u32>, String)> {
+    let format = extension(path)?;
+    ImageFormat::from_extension(&format)?;
+    let name = path.file_stem()?.to_str()?;
+    let resized = name.rsplit_once('-').and_then(|(stem, width)| {
+        let width = width.strip_suffix('w')?.parse().ok()?;
+        Some((stem, width))
+    });
+    let (stem, width) = match resized {
+        Some((stem, width)) => (stem, Some(width)),
+        None => (name, None),
+    };
+
+    let sources = IMAGE_EXTENSIONS
+        .iter()
+        .map(|ext| path.with_file_name(format!("{stem}.{ext}")))
+        .filter(|source| source != path)
+        .collect();
+    Some((sources, width, format))
+}
+
+fn encode(source: &Path, width: Option<u32>, format: &str) -> io::Result<Vec<u8>> {
+    let invalid = |err: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, err);
+    let kind = ImageFormat::from_extension(format).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown image format `{format}`"),
+        )
+    })?;
+
+    let mut image = image::open(source).map_err(invalid)?;
+    // images are made smaller, never larger
+    if let Some(width) = width.filter(|width| *width < image.width()) {
+        image = image.resize(width, u32::MAX, FilterType::Lanczos3);
+    }
+    if kind == ImageFormat::Jpeg {
+        image = DynamicImage::ImageRgb8(image.to_rgb8());
+    }
+
+    let mut bytes = io::Cursor::new(Vec::new());
+    image.write_to(&mut bytes, kind).map_err(invalid)?;
+    Ok(bytes.into_inner())
+}
+
+/// Resizes and converts images, keeping the results in the cache between
+/// builds by a hash of the image, the width and the format.
+pub struct Images {
+    options: ImageOptions,
+    dir: PathBuf,
+    read_only: bool,
+    /// Where `image` looks for the images it's given, in order
+    roots: Vec<PathBuf>,
+    /// Variants layouts asked for with `image`, by source image
+    requested: Mutex<HashMap<PathBuf, BTreeSet<(Option<u32>, String)>>>,
+}
+
+impl Images {
+    /// Images with `options`, cached in `<cache_dir>/images` unless
+    /// `read_only`, found for `image` in `roots`.
+    pub fn new(
+        options: ImageOptions,
+        cache_dir: &Path,
+        read_only: bool,
+        roots: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            options,
+            dir: cache_dir.join("images"),
+            read_only,
+            roots,
+            requested: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The variant of `source` at `width` (or its own size) as `format`.
+    pub fn variant(&self, source: &Path, width: Option<u32>, format: &str) -> io::Result<Vec<u8>> {
+        let mut hasher = Sha256::new();
+        for part in [
+            env!("CARGO_PKG_VERSION"),
+            &build_info::hash_file(source)?,
+            &width.map(|width| width.to_string()).unwrap_or_default(),
+            format,
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        let cached = self.dir.join(format!("{:x}", hasher.finalize()));
+        if let Ok(bytes) = fs::read(&cached) {
+            return Ok(bytes);
+        }
+
+        let bytes = encode(source, width, format)?;
+        // the cache only saves time, so failing to write to it isn't an error
+        if !self.read_only && fs::create_dir_all(&self.dir).is_ok() {
+            let _ = fs::write(cached, &bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Writes the variants of the image `source`, copied to `to`: one at
+    /// every configured width, in its own format and the configured ones,
+    /// along with any asked for by layouts. Images that can't be read are
+    /// reported and get none.
+    pub fn write_variants(&self, source: &Path, to: &Path) -> io::Result<()> {
+        let Some(own) = extension(source) else {
+            return Ok(());
+        };
+        let formats: Vec<String> = self
+            .options
+            .formats
+            .iter()
+            .map(|format| format.to_ascii_lowercase())
+            .collect();
+
+        let mut variants = BTreeSet::new();
+        for format in formats.iter().filter(|format| **format != own) {
+            variants.insert((None, format.to_string()));
+        }
+        for width in self.options.widths.iter() {
+            variants.insert((Some(*width), own.to_string()));
+            for format in formats.iter() {
+                variants.insert((Some(*width), format.to_string()));
+            }
+        }
+        let requested = self.requested.lock().unwrap_or_else(|err| err.into_inner());
+        variants.extend(requested.get(source).into_iter().flatten().cloned());
+        drop(requested);
+
+        for (width, format) in variants.iter() {
+            match self.variant(source, *width, format) {
+                Ok(bytes) => fs::write(variant_path(to, *width, format), bytes)?,
+                Err(err) => {
+                    Diagnostic::error("Couldn't resize image")
+                        .failure(Failure::Content)
+                        .in_file(&source.to_string_lossy())
+                        .note(err.to_string())
+                        .print();
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find(&self, src: &str) -> Option<PathBuf> {
+        let relative = src.trim_start_matches('/');
+        self.roots
+            .iter()
+            .map(|root| root.join(relative))
+            .find(|path| path.is_file())
+    }
+}
+
+/// `image(src=..., width=..., format=...)`, the path of a variant of the
+/// static image at `src` (from the site root), `width` pixels wide and
+/// converted to `format`. Both are optional; the variant is written with
+/// the static files.
+pub struct Image {
+    images: Arc<Images>,
+}
+
+impl Image {
+    pub fn new(images: Arc<Images>) -> Self {
+        Self { images }
+    }
+}
+
+impl Function for Image {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let src = args
+            .get("src")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`image` requires a `src` argument"))?;
+        let width = match args.get("width") {
+            Some(width) => Some(
+                width
+                    .as_u64()
+                    .and_then(|width| u32::try_from(width).ok())
+                    .filter(|width| *width > 0)
+                    .ok_or_else(|| tera::Error::msg("`width` should be a number of pixels"))?,
+            ),
+            None => None,
+        };
+
+        let source = self
+            .images
+            .find(src)
+            .ok_or_else(|| tera::Error::msg(format!("`image` couldn't find {src}")))?;
+        if !is_image(&source) {
+            return Err(tera::Error::msg(format!(
+                "{src} isn't an image `image` can resize, like a jpg, png or webp"
+            )));
+        }
+        let format = match args.get("format").and_then(Value::as_str) {
+            Some(format) => format.to_ascii_lowercase(),
+            None => extension(&source).unwrap_or_default(),
+        };
+        if ImageFormat::from_extension(&format).is_none() {
+            return Err(tera::Error::msg(format!("Unknown image format `{format}`")));
+        }
+
+        let own = extension(&source).is_some_and(|ext| ext == format);
+        if width.is_none() && own {
+            return Ok(Value::String(src.to_string()));
+        }
+        self.images
+            .requested
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .entry(source)
+            .or_default()
+            .insert((width, format.to_string()));
+
+        let path = variant_path(Path::new(src), width, &format);
+        Ok(Value::String(path.to_string_lossy().replace('\\', "/")))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{diagnostics, scaffold, theme_git, Options};
+
+const INDEX_PAGE: &str = "---
+title: Home
+---
+Welcome to my new site!
+";
+
+const FIRST_POST: &str = "---
+title: Hello, Roxy!
+layout: page.html
+---
+This is the first post. Edit or delete it in `content/blog/hello-roxy.md`.
+";
+
+struct Answers {
+    title: String,
+    base_url: String,
+    /// `None` for the starter layouts
+    theme: Option<String>,
+    feeds: bool,
+}
+
+// asks `question` on stdout, returning `default` for an empty answer
+fn ask(input: &mut impl BufRead, question: &str, default: &str) -> io::Result<String> {
+    print!("{question} [{default}]: ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+fn ask_yes_no(input: &mut impl BufRead, question: &str, default: bool) -> io::Result<bool> {
+    let answer = ask(input, question, if default { "Y/n" } else { "y/N" })?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+fn installed_themes(themes_dir: &str) -> Vec<String> {
+    let mut themes: Vec<String> = fs::read_dir(themes_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.path().join("theme.toml").is_file())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    themes.sort();
+    themes
+}
+
+fn questions(input: &mut impl BufRead, opts: &Options, defaults: bool) -> io::Result<Answers> {
+    let mut answers = Answers {
+        title: "My Roxy site".to_string(),
+        base_url: "https://example.com".to_string(),
+        theme: None,
+        feeds: true,
+    };
+    if defaults {
+        return Ok(answers);
+    }
+
+    answers.title = ask(input, "Site title", &answers.title)?;
+    answers.base_url = ask(input, "Base url", &answers.base_url)?;
+
+    let themes = installed_themes(&opts.themes);
+    if !themes.is_empty() {
+        diagnostics::info(format!("Installed themes: {}", themes.join(", ")));
+    }
+    let theme = ask(
+        input,
+        "Theme (`starter` for plain layouts, an installed theme or a git url)",
+        "starter",
+    )?;
+    answers.theme = if theme == "starter" {
+        None
+    } else {
+        Some(theme)
+    };
+
+    answers.feeds = ask_yes_no(input, "Generate feeds?", answers.feeds)?;
+    Ok(answers)
+}
+
+fn quoted(text: &str) -> String {
+    toml::Value::String(text.to_string()).to_string()
+}
+
+fn config(answers: &Answers, theme: Option<&str>) -> String {
+    let mut config = format!(
+        "title = {}\nbase_url = {}\n",
+        quoted(&answers.title),
+        quoted(&answers.base_url),
+    );
+    if let Some(theme) = theme {
+        config.push_str(&format!("theme = {}\n", quoted(theme)));
+    }
+    config.push_str(&format!("feeds = {}\n", answers.feeds));
+    config.push_str("\n# available to layouts as site.<key>\n[extra]\n");
+    config
+}
+
+/// Asks for the basics of a new site (or takes the defaults with `yes`)
+/// and writes its config, starter content and layouts or theme.
+pub fn run(opts: &Options, yes: bool) -> io::Result<()> {
+    if Path::new(&opts.config).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", opts.config),
+        ));
+    }
+
+    let stdin = io::stdin();
+    let answers = questions(&mut stdin.lock(), opts, yes)?;
+
+    let is_url = |theme: &str| theme.contains("://") || theme.starts_with("git@");
+    let theme_name = match answers.theme.as_deref() {
+        Some(theme) if is_url(theme) => theme_git::name_from_url(theme),
+        theme => theme.map(|t| t.to_string()),
+    };
+
+    scaffold::write_file(
+        &opts.config,
+        config(&answers, theme_name.as_deref()).as_bytes(),
+    )?;
+
+    match answers.theme.as_deref() {
+        Some(url) if is_url(url) => {
+            let (name, commit) =
+                theme_git::install(&opts.themes, &opts.config, url, theme_name.as_deref(), None)?;
+            diagnostics::info(format!("Installed theme {name} at {commit}"));
+        }
+        Some(_) => {}
+        None => scaffold::starter_layouts(Path::new(&opts.layouts))?,
+    }
+
+    let content = Path::new(&opts.content);
+    if !content.join("index.md").exists() {
+        scaffold::write_file(content.join("index.md"), INDEX_PAGE.as_bytes())?;
+        scaffold::write_file(content.join("blog/hello-roxy.md"), FIRST_POST.as_bytes())?;
+    }
+
+    diagnostics::info(format!("Created {} for {}", opts.config, answers.title));
+    Ok(())
+}
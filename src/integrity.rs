@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use sha2::{Digest, Sha384};
+use tera::{Function, Value};
+
+use crate::assets::{self, Output};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// `integrity(path="/app.js")` is the subresource integrity hash of a
+/// static file of the site, as it's written (compiled and minified), for
+/// the `integrity` attribute of the `<script>` or `<link>` loading it.
+/// Hashes are worked out the first time a file is asked for.
+pub struct Integrity {
+    /// The source of each static file by its url; the content folder's
+    /// take the place of the theme's, as they do when copied
+    sources: HashMap<String, PathBuf>,
+    minify: bool,
+    hashes: Mutex<HashMap<String, String>>,
+}
+
+/// The source of each of the static `files`, by its url.
+pub fn by_url(files: Vec<(PathBuf, PathBuf)>) -> HashMap<String, PathBuf> {
+    files
+        .into_iter()
+        .map(|(from, to)| {
+            let url: Vec<String> = to
+                .iter()
+                .map(|part| part.to_string_lossy().to_string())
+                .collect();
+            (format!("/{}", url.join("/")), from)
+        })
+        .collect()
+}
+
+impl Integrity {
+    pub fn new(files: Vec<(PathBuf, PathBuf)>, minify: bool) -> Self {
+        Self {
+            sources: by_url(files),
+            minify,
+            hashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn hash(&self, from: &Path) -> tera::Result<String> {
+        let err = |err: std::io::Error| {
+            tera::Error::msg(format!("Couldn't read {}: {err}", from.to_string_lossy()))
+        };
+        let bytes = match assets::output(from, self.minify).map_err(err)? {
+            Output::Copy => fs::read(from).map_err(err)?,
+            Output::Text(text) => text.into_bytes(),
+            Output::Skip => {
+                return Err(tera::Error::msg(format!(
+                    "{} isn't written, so it has no integrity hash",
+                    from.to_string_lossy()
+                )))
+            }
+        };
+        Ok(format!("sha384-{}", base64(&Sha384::digest(bytes))))
+    }
+}
+
+impl Function for Integrity {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`integrity` requires a `path` argument"))?;
+        // `asset(path=...)` links work too, the query doesn't change the file
+        let url = path.split(['?', '#']).next().unwrap_or_default();
+        let url = format!("/{}", url.trim_start_matches('/'));
+
+        if let Some(hash) = self
+            .hashes
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&url)
+        {
+            return Ok(Value::String(hash.to_string()));
+        }
+        let from = self.sources.get(&url).ok_or_else(|| {
+            tera::Error::msg(format!(
+                "`integrity`: {url} isn't a static file of the site"
+            ))
+        })?;
+        let hash = self.hash(from)?;
+        self.hashes
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(url, hash.to_string());
+        Ok(Value::String(hash))
+    }
+}
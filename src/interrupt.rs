@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::Options;
+
+/// The exit code of a command stopped with Ctrl-C, as shells report it.
+pub const EXIT_CODE: i32 = 130;
+
+/// What Ctrl-C does to the command line's command, which it's handed in
+/// its options. Builds run as a library have none, so nothing of theirs is
+/// shared with other builds in the process.
+#[derive(Debug, Default)]
+pub struct Interrupt {
+    requested: AtomicBool,
+    // temporary files and folders to remove if the command is interrupted
+    temporary: Mutex<Vec<PathBuf>>,
+    // the listener of a running server, woken so it notices the interrupt
+    listener: Mutex<Option<SocketAddr>>,
+}
+
+impl Interrupt {
+    /// Handles Ctrl-C from now on. Temporary files are removed, then a
+    /// server stops taking connections and finishes what it's doing, while
+    /// anything else exits right away.
+    pub fn install() -> Arc<Self> {
+        let interrupt = Arc::new(Self::default());
+        let handler = interrupt.clone();
+        let _ = ctrlc::set_handler(move || handler.handle());
+        interrupt
+    }
+
+    fn handle(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.remove_temporary();
+
+        let listener = *self.listener.lock().unwrap_or_else(|err| err.into_inner());
+        match listener {
+            // unblocks the listener waiting for its next connection
+            Some(address) => drop(TcpStream::connect(address)),
+            None => ::std::process::exit(EXIT_CODE),
+        }
+    }
+
+    fn remove_temporary(&self) {
+        let temporary = self.temporary.lock().unwrap_or_else(|err| err.into_inner());
+        for path in temporary.iter() {
+            let _ = fs::remove_dir_all(path).or_else(|_| fs::remove_file(path));
+        }
+    }
+
+    /// Whether Ctrl-C has been pressed.
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Whether Ctrl-C has been pressed during the command `opts` are for.
+pub fn requested(opts: &Options) -> bool {
+    opts.interrupt
+        .as_ref()
+        .is_some_and(|interrupt| interrupt.requested())
+}
+
+/// Removes `path` if the command `opts` are for is interrupted before it's
+/// done with it.
+pub fn remove_on_interrupt(opts: &Options, path: &Path) {
+    let Some(interrupt) = &opts.interrupt else {
+        return;
+    };
+    let mut temporary = interrupt
+        .temporary
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if !temporary.iter().any(|p| p == path) {
+        temporary.push(path.to_path_buf());
+    }
+}
+
+/// Lets the server listening on `address` shut down gracefully on Ctrl-C,
+/// checking `requested` after every connection.
+pub fn serving(opts: &Options, mut address: SocketAddr) {
+    let Some(interrupt) = &opts.interrupt else {
+        return;
+    };
+    // listening on every interface, so any of them reaches it
+    if address.ip().is_unspecified() {
+        address.set_ip(match address {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    *interrupt
+        .listener
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = Some(address);
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Content;
+
+/// The language a page's path puts it in.
+pub struct PathLanguage {
+    pub lang: String,
+    /// The path without the language, which every translation of the page
+    /// shares: `about.md` for `about.fr.md` and `fr/about.md`
+    pub path: String,
+}
+
+fn key(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// The language of the page at `path` (in the content folder), from a
+/// suffix like `about.fr.md` or a first folder like `fr/about.md`, if it's
+/// one of `languages`.
+pub fn from_path(path: &str, languages: &[String]) -> Option<PathLanguage> {
+    let path = key(path);
+    let known = |lang: &str| languages.iter().any(|known| known == lang);
+
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path.as_str()),
+    };
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts.len() >= 3 && known(parts[parts.len() - 2]) {
+        let lang = parts[parts.len() - 2];
+        let mut name = parts[..parts.len() - 2].join(".");
+        name.push('.');
+        name.push_str(parts[parts.len() - 1]);
+        return Some(PathLanguage {
+            lang: lang.to_string(),
+            path: match dir {
+                Some(dir) => format!("{dir}/{name}"),
+                None => name,
+            },
+        });
+    }
+
+    let (first, rest) = path.split_once('/')?;
+    known(first).then(|| PathLanguage {
+        lang: first.to_string(),
+        path: rest.to_string(),
+    })
+}
+
+/// `url` under the prefix of `lang`, `/fr/about/` for `/about/`. Pages in
+/// the default language have none.
+pub fn prefixed(url: &str, lang: &str, default_language: &str) -> String {
+    if lang == default_language {
+        url.to_string()
+    } else {
+        format!("/{lang}{url}")
+    }
+}
+
+/// One version of a page, in `page.translations`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Translation {
+    pub lang: String,
+    pub slug: String,
+    pub title: String,
+}
+
+/// Links every page to the versions of it in each language, itself
+/// included: the pages whose paths are the same without their language.
+pub fn link_translations(contents: &mut [Content], languages: &[String]) {
+    let keys: Vec<String> = contents
+        .iter()
+        .map(|content| match from_path(&content.path, languages) {
+            Some(language) => language.path,
+            None => key(&content.path),
+        })
+        .collect();
+
+    let mut versions: BTreeMap<&str, Vec<Translation>> = BTreeMap::new();
+    for (content, key) in contents.iter().zip(keys.iter()) {
+        versions.entry(key).or_default().push(Translation {
+            lang: content.lang.to_string(),
+            slug: content.slug.to_string(),
+            title: content
+                .frontmatter
+                .0
+                .get("title")
+                .cloned()
+                .unwrap_or_else(|| content.slug.to_string()),
+        });
+    }
+
+    let translations: Vec<Vec<Translation>> = keys
+        .iter()
+        .map(|key| match versions.get(key.as_str()) {
+            Some(versions) if versions.len() > 1 => {
+                let mut versions = versions.clone();
+                versions.sort_by(|a, b| a.lang.cmp(&b.lang));
+                versions
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+    for (content, translations) in contents.iter_mut().zip(translations) {
+        content.translations = translations;
+    }
+}
@@ -0,0 +1,2912 @@
+//! Roxy builds static sites from markdown and Tera layouts. The `roxy`
+//! command is a thin wrapper around this crate, so other tools can build a
+//! site the same way:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), roxy::Error> {
+//! let opts = roxy::BuildOptions::from_config("roxy.toml")?;
+//! match roxy::build(&opts) {
+//!     Ok(pages) => println!("Built {} pages", pages.len()),
+//!     Err(err) => eprintln!("{err}"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use clap::{
+    command, parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use glob::glob;
+use highlight_pulldown::PulldownHighlighter;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    parsing::SyntaxSet,
+};
+use tera::{Context, Tera};
+
+mod alt_text;
+mod ansi;
+mod archive;
+mod assets;
+mod audit;
+mod build_cache;
+mod build_info;
+mod changelog;
+mod checksums;
+mod citations;
+mod config;
+mod csp;
+mod daemon;
+mod data_files;
+mod demo_content;
+mod diagnostics;
+mod escaping;
+mod feeds;
+mod filters;
+mod fonts;
+mod frontmatter;
+mod functions;
+mod git_output;
+mod glossary;
+mod headers;
+mod headings;
+mod highlight_cache;
+mod i18n;
+mod icons;
+mod images;
+mod init;
+mod integrity;
+mod interrupt;
+mod languages;
+mod link_check;
+mod links;
+mod lint;
+mod lock;
+mod markdown;
+mod memory;
+mod newsletter;
+mod object_storage;
+mod openapi;
+mod page_data;
+mod parallel;
+mod permalinks;
+mod preload;
+mod preview;
+mod replacements;
+mod resources;
+mod scaffold;
+mod schedule;
+mod search;
+mod serve;
+mod short_urls;
+mod shortcodes;
+mod sitemap;
+mod svg;
+mod taxonomies;
+mod template_deps;
+mod template_tests;
+mod theme_git;
+mod theme_package;
+mod tree;
+mod typography;
+mod url_map;
+mod versions;
+
+use build_cache::{BuildCache, SiteKeys};
+use build_info::BuildInfo;
+use citations::Bibliography;
+pub use diagnostics::Failure;
+use diagnostics::{Diagnostic, Verbosity};
+use glossary::Glossary;
+use headings::Heading;
+use highlight_cache::HighlightCache;
+use i18n::Translations;
+use images::Images;
+use languages::Translation;
+pub use lock::RenderedPage as Page;
+use markdown::MarkdownOptions;
+use page_data::PageData;
+use permalinks::Permalinks;
+use replacements::{Replacements, Stage};
+use resources::Resource;
+use taxonomies::Taxonomies;
+use template_deps::TemplateGraph;
+use theme_package::ThemePackage;
+use tree::PageLink;
+
+#[derive(Serialize, Deserialize)]
+struct Content {
+    pub path: String,
+    pub slug: String,
+    /// The url the page is written at when that isn't where its path would
+    /// put it, from a permalink pattern or the language in its path
+    #[serde(default)]
+    pub permalink: Option<String>,
+    pub lang: String,
+    pub lang_dir: String,
+    pub frontmatter: Frontmatter,
+    pub content: String,
+    pub resources: Vec<Resource>,
+    /// Images the markdown refers to relative to the page, copied next to
+    /// it
+    #[serde(skip)]
+    pub images: Vec<Resource>,
+    /// The plain text of the markdown, for the search index
+    #[serde(skip)]
+    pub text: String,
+    /// How far its content numbered sidenotes, for its layout to carry on
+    #[serde(skip)]
+    pub numbering: functions::Numbering,
+    pub headings: Vec<Heading>,
+    pub docs_prev: Option<PageLink>,
+    pub docs_next: Option<PageLink>,
+    /// The page in every language it's written in, see `languages`
+    #[serde(default)]
+    pub translations: Vec<Translation>,
+    /// The feeds of the page's section and of the site, see `feeds`
+    #[serde(default)]
+    pub feeds: Vec<feeds::Link>,
+    /// Context added by generators, available to the page's layout
+    #[serde(skip)]
+    pub extra: HashMap<String, tera::Value>,
+}
+
+/// A page's frontmatter fields as text, and the typed values of those read
+/// from YAML or TOML. Layouts see the typed value of a field if it still
+/// has the text it was read as, otherwise the text coerced into a boolean,
+/// number or list, see `coerce`.
+#[derive(Debug)]
+struct Frontmatter(
+    HashMap<String, String>,
+    HashMap<String, (String, tera::Value)>,
+);
+
+// fields which are lists even when written without brackets, as `a, b, c`
+const LIST_FIELDS: &[&str] = &[
+    "tags",
+    "categories",
+    "authors",
+    "keywords",
+    "aliases",
+    "environments",
+    "safe_html",
+];
+
+fn unquote(value: &str) -> Option<&str> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+}
+
+fn coerce_scalar(value: &str) -> tera::Value {
+    let value = value.trim();
+    if let Some(text) = unquote(value) {
+        return tera::Value::String(text.to_string());
+    }
+
+    match value {
+        "true" => return tera::Value::Bool(true),
+        "false" => return tera::Value::Bool(false),
+        _ => {}
+    }
+
+    // `007` and `+1` are probably meant as written
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let is_integer = !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && (digits == "0" || !digits.starts_with('0'));
+    match value.parse::<i64>() {
+        Ok(n) if is_integer => tera::Value::from(n),
+        _ => tera::Value::String(value.to_string()),
+    }
+}
+
+/// How a frontmatter value is given to layouts:
+///
+/// - `"quoted"` or `'quoted'` values are strings, as written
+/// - `true` and `false` are booleans
+/// - whole numbers without leading zeros are integers
+/// - `[a, b, c]` is a list, as is `a, b, c` for the fields in `LIST_FIELDS`
+/// - anything else is a string
+fn coerce(key: &str, value: &str) -> tera::Value {
+    let value = value.trim();
+    let items = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(inner) => Some(inner),
+        None if LIST_FIELDS.contains(&key) => Some(value),
+        None => None,
+    };
+
+    match items {
+        Some(items) => tera::Value::Array(
+            items
+                .split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(coerce_scalar)
+                .collect(),
+        ),
+        None => coerce_scalar(value),
+    }
+}
+
+impl Serialize for Frontmatter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in self.0.iter() {
+            if self.is_flattened(key) {
+                continue;
+            }
+            match self.1.get(key) {
+                Some((text, typed)) if text == value => map.serialize_entry(key, typed)?,
+                _ => map.serialize_entry(key, &coerce(key, value))?,
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Frontmatter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = HashMap::<String, tera::Value>::deserialize(deserializer)?;
+        let mut frontmatter = Frontmatter(HashMap::new(), HashMap::new());
+        for (key, value) in fields.into_iter() {
+            match value {
+                tera::Value::String(text) => {
+                    frontmatter.0.insert(key, text);
+                }
+                value => {
+                    let text = value.to_string();
+                    frontmatter::flatten(&key, &value, &mut frontmatter.0);
+                    frontmatter.0.insert(key.to_string(), text.to_string());
+                    frontmatter.1.insert(key, (text, value));
+                }
+            }
+        }
+        Ok(frontmatter)
+    }
+}
+
+impl Frontmatter {
+    fn from_text(fields: HashMap<String, String>) -> Self {
+        Frontmatter(fields, HashMap::new())
+    }
+
+    // `table.key` fields made from a table that layouts see whole
+    fn is_flattened(&self, key: &str) -> bool {
+        key.split_once('.').is_some_and(|(table, _)| {
+            matches!(
+                (self.0.get(table), self.1.get(table)),
+                (Some(text), Some((typed_text, tera::Value::Object(_)))) if text == typed_text
+            )
+        })
+    }
+
+    fn language<'a>(&'a self, default_language: &'a str) -> &'a str {
+        self.0
+            .get("lang")
+            .map(|s| s.as_str())
+            .unwrap_or(default_language)
+    }
+
+    /// Reads a field written as a list (`[a, b]`) or as comma separated values.
+    fn list(&self, key: &str) -> Option<Vec<String>> {
+        let value = self.0.get(key)?;
+        let items = serde_yaml::from_str::<Vec<String>>(value).unwrap_or_else(|_| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        });
+        Some(items)
+    }
+}
+
+fn load_templates(dir: &str) -> io::Result<Tera> {
+    let path = format!("{dir}/**/*");
+    let mut tera = match Tera::new(path.as_str()) {
+        Ok(t) => t,
+        Err(e) => {
+            Diagnostic::error(format!("Couldn't load the layouts in {dir}"))
+                .failure(Failure::Template)
+                .causes(&e)
+                .print();
+            return Err(diagnostics::reported(Failure::Template));
+        }
+    };
+    tera.autoescape_on(vec![]);
+    Ok(tera)
+}
+
+fn page_context(content: &Content, base_context: &Context) -> tera::Result<Context> {
+    let mut context = Context::from_serialize(content)?;
+    context.extend(base_context.clone());
+    context.insert("page", content);
+    for (key, value) in content.extra.iter() {
+        context.insert(key.as_str(), value);
+    }
+    Ok(context)
+}
+
+/// Lowercases `text` and joins its words with dashes, for urls and anchors.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+// pages without an `environments` field are built everywhere
+fn in_environment(content: &Content, env: &str) -> bool {
+    content
+        .frontmatter
+        .list("environments")
+        .is_none_or(|envs| envs.iter().any(|e| e == env))
+}
+
+fn is_draft(content: &Content) -> bool {
+    content
+        .frontmatter
+        .0
+        .get("draft")
+        .is_some_and(|draft| draft.trim() == "true")
+}
+
+// dated after `now`, see `schedule::now`
+fn is_future(content: &Content, now: &str) -> bool {
+    content
+        .frontmatter
+        .0
+        .get("date")
+        .is_some_and(|date| schedule::normalize(date).as_str() > now)
+}
+
+// past its `expiry_date`, see `schedule::now`
+fn is_expired(content: &Content, now: &str) -> bool {
+    content
+        .frontmatter
+        .0
+        .get("expiry_date")
+        .is_some_and(|date| schedule::normalize(date).as_str() <= now)
+}
+
+/// The folder a page's `index.html` is written to.
+fn output_dir(output: &str, content: &Content) -> Option<PathBuf> {
+    if let Some(permalink) = &content.permalink {
+        return Some(Path::new(&output).join(permalink.trim_matches('/')));
+    }
+    let parent = Path::new(&content.path).parent()?;
+    let file_stem = Path::new(&content.path).file_stem().unwrap_or_default();
+
+    let path = Path::new(&output).join(parent);
+    if file_stem.is_empty()
+        || file_stem.eq_ignore_ascii_case("index")
+        || file_stem.eq_ignore_ascii_case("_index")
+    {
+        Some(path)
+    } else {
+        Some(path.join(file_stem))
+    }
+}
+
+/// The context a page's layout is rendered with.
+fn render_context(content: &Content, site: &Site) -> tera::Result<Context> {
+    let contents = &site.content;
+    let mut context = page_context(content, &site.context)?;
+
+    if let Some(section) = content.frontmatter.0.get("section_tree") {
+        context.insert("section_tree", &tree::for_section(contents, section));
+    }
+
+    if let Some(source) = content.frontmatter.0.get("changelog") {
+        let filter = content.frontmatter.0.get("changelog_path");
+        let releases = changelog::load(
+            source,
+            filter.map(|s| s.as_str()),
+            &site.content_dir,
+            site.sandbox,
+        );
+        match releases {
+            Ok(releases) => context.insert("changelog", &releases),
+            Err(err) => Diagnostic::error(format!("Couldn't read the changelog {source}"))
+                .in_file(&content.path)
+                .note(err.to_string())
+                .print(),
+        }
+    }
+
+    site.escaping.context(layout(content), context)
+}
+
+/// What became of a page in a build.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Written {
+    Built,
+    /// The build cache says it's as it was
+    Unchanged,
+    Failed,
+    /// Never rendered, as `--strict` stopped the build first
+    Skipped,
+}
+
+/// How many pages a build wrote, told at the end of it.
+#[derive(Debug, Default)]
+struct Summary {
+    built: usize,
+    unchanged: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl Summary {
+    fn count(&mut self, written: Written) {
+        match written {
+            Written::Built => self.built += 1,
+            Written::Unchanged => self.unchanged += 1,
+            Written::Failed => self.failed += 1,
+            Written::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} pages: {} built, {} unchanged, {} failed",
+            self.built + self.unchanged + self.failed + self.skipped,
+            self.built,
+            self.unchanged,
+            self.failed
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+// writes a page's `index.html` and resources, unless the build cache says
+// it's unchanged. With `--strict`, `stop` is set once a page fails and the
+// pages after it are skipped.
+fn write_page(
+    output: &str,
+    templates: &Tera,
+    site: &Site,
+    site_keys: &SiteKeys,
+    content: &Content,
+    stop: Option<&AtomicBool>,
+) -> io::Result<Written> {
+    if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+        return Ok(Written::Skipped);
+    }
+    let Some(path) = output_dir(output, content) else {
+        return Ok(Written::Skipped);
+    };
+    fs::create_dir_all(&path)?;
+    let copied = resources::copy(&content.resources, &path)
+        .and_then(|_| resources::copy(&content.images, &path));
+    if let Err(err) = copied {
+        Diagnostic::error(format!("Couldn't copy the resources of {}", content.path))
+            .failure(Failure::Io)
+            .note(err.to_string())
+            .print();
+    }
+
+    let mut file_path = path.join("index");
+    file_path.set_extension("html");
+    // changelogs are read from outside the site on every render
+    let key = site.cache.render_key(site_keys, content);
+    let cacheable = !content.frontmatter.0.contains_key("changelog");
+    if cacheable && site.cache.is_rendered(&file_path, &key) {
+        diagnostics::detail(format!("Unchanged {}", content.path));
+        return Ok(Written::Unchanged);
+    }
+
+    let result = render_page(templates, content, site);
+    match result {
+        Ok(result) => {
+            let result = site.finish(result);
+            let mut file = fs::File::create(&file_path)?;
+            let _ = file.write_all(result.as_bytes());
+            if cacheable {
+                site.cache.rendered(&file_path, &key);
+            }
+            diagnostics::detail(format!("Built {}", content.path));
+            Ok(Written::Built)
+        }
+        Err(err) => {
+            Diagnostic::error(format!("Couldn't render {}", layout(content)))
+                .failure(Failure::Template)
+                .in_file(&content.path)
+                .causes(&err)
+                .print();
+            if let Some(stop) = stop {
+                stop.store(true, Ordering::Relaxed);
+            }
+            Ok(Written::Failed)
+        }
+    }
+}
+
+/// Writes every page of `site`, on a thread per core, then the taxonomy
+/// listing pages. With `strict`, the first page that fails to render stops
+/// the build.
+fn create_files(
+    output: &str,
+    templates: &Tera,
+    site: &Site,
+    taxonomies: (&Taxonomies, &str),
+    strict: bool,
+) -> io::Result<Summary> {
+    let site_keys = site.cache.site_keys(&site.context);
+    let stop = AtomicBool::new(false);
+    let stop = strict.then_some(&stop);
+    let written = parallel::map(&site.content, |content| {
+        write_page(output, templates, site, &site_keys, content, stop)
+    });
+    let mut summary = Summary::default();
+    for written in written.into_iter() {
+        summary.count(written?);
+    }
+    if strict && summary.failed > 0 {
+        diagnostics::info(&summary);
+        return Err(diagnostics::reported(Failure::Template));
+    }
+
+    let (taxonomies, layout) = taxonomies;
+    let failed = taxonomies::write(output, templates, layout, taxonomies, site)?;
+    if strict && failed > 0 {
+        diagnostics::info(&summary);
+        return Err(diagnostics::reported(Failure::Template));
+    }
+
+    Ok(summary)
+}
+
+fn layout(content: &Content) -> &str {
+    content
+        .frontmatter
+        .0
+        .get("layout")
+        .map(|layout| layout.as_str())
+        .unwrap_or("index.html")
+}
+
+/// Renders `content` with its layout, as written to its `index.html`.
+fn render_page(templates: &Tera, content: &Content, site: &Site) -> tera::Result<String> {
+    let context = render_context(content, site)?;
+    i18n::set_current_language(&content.lang);
+    functions::continue_page(content.numbering);
+    let mut result = templates.render(layout(content), &context)?;
+    if site.feed_links {
+        result = feeds::add_links(result, &content.feeds);
+    }
+    Ok(site.replacements.apply(Stage::Html, &content.path, result))
+}
+
+fn compile_content_map<'a>(contents: &'a [Content]) -> HashMap<String, Vec<&'a Content>> {
+    let mut hm: HashMap<String, Vec<&'a Content>> = HashMap::new();
+    let mut default = Vec::new();
+
+    for content in contents.iter() {
+        if let Some((section, _)) = content.path.split_once(std::path::MAIN_SEPARATOR_STR) {
+            if let Some(vec) = hm.get_mut(section) {
+                vec.push(content);
+            } else {
+                hm.insert(section.to_string(), vec![content]);
+            }
+        } else {
+            default.push(content);
+        }
+    }
+
+    hm.insert("default".to_string(), default);
+    hm
+}
+
+fn compile_language_map<'a>(contents: &'a Vec<Content>) -> HashMap<String, Vec<&'a Content>> {
+    let mut hm: HashMap<String, Vec<&'a Content>> = HashMap::new();
+
+    for content in contents.iter() {
+        hm.entry(content.lang.clone()).or_default().push(content);
+    }
+
+    hm
+}
+
+fn is_section_index(path: &str) -> bool {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.eq_ignore_ascii_case("index") || stem.eq_ignore_ascii_case("_index"))
+        .unwrap_or(false)
+}
+
+// copies `cascade.<key>` fields from section index pages onto every page
+// below them, unless the page (or a nearer section) sets the key itself
+fn apply_cascade(contents: &mut [Content], default_language: &str, rtl_languages: &[String]) {
+    let mut cascades: HashMap<PathBuf, Vec<(String, String)>> = HashMap::new();
+
+    for content in contents.iter() {
+        if !is_section_index(&content.path) {
+            continue;
+        }
+
+        let cascade: Vec<(String, String)> = content
+            .frontmatter
+            .0
+            .iter()
+            .filter_map(|(k, v)| {
+                k.strip_prefix("cascade.")
+                    .map(|k| (k.to_string(), v.to_string()))
+            })
+            .collect();
+
+        if let Some(dir) = Path::new(&content.path).parent() {
+            if !cascade.is_empty() {
+                cascades.insert(dir.to_path_buf(), cascade);
+            }
+        }
+    }
+
+    if cascades.is_empty() {
+        return;
+    }
+
+    for content in contents.iter_mut() {
+        let path = Path::new(&content.path);
+        let dir = path.parent();
+        // a section's own cascade only applies to the pages below it
+        let dir = if is_section_index(&content.path) {
+            dir.and_then(|d| d.parent())
+        } else {
+            dir
+        };
+
+        for ancestor in dir.into_iter().flat_map(|d| d.ancestors()) {
+            if let Some(cascade) = cascades.get(ancestor) {
+                for (k, v) in cascade.iter() {
+                    content
+                        .frontmatter
+                        .0
+                        .entry(k.to_string())
+                        .or_insert_with(|| v.to_string());
+                }
+            }
+        }
+
+        content.lang = content.frontmatter.language(default_language).to_string();
+        content.lang_dir = i18n::text_direction(&content.lang, rtl_languages).to_string();
+    }
+}
+
+// whether `line` is the delimiter alone, with nothing after it but
+// spaces and the line break
+fn is_delimiter(line: &str, delimiter: &str) -> bool {
+    line.trim_end_matches([' ', '\t', '\r', '\n']) == delimiter
+}
+
+/// Reads the frontmatter between `---` lines as YAML, or between `+++`
+/// lines as TOML, along with its problems. Only a line that is just the
+/// delimiter ends it, so YAML lists and `----` rules inside it don't, and
+/// frontmatter that is never closed is the whole file. YAML that doesn't
+/// parse is read as `key: value` lines, as it was before frontmatter was
+/// YAML.
+fn read_frontmatter<R: BufRead + Seek>(
+    reader: &mut R,
+    file: &str,
+) -> io::Result<(Frontmatter, Vec<Diagnostic>)> {
+    let mut problems = Vec::new();
+    let mut buf = String::new();
+
+    reader.read_line(&mut buf)?;
+    // editors on Windows can start files with a byte order mark
+    let opening = buf.trim_start_matches('\u{feff}');
+    let delimiter = match ["---", "+++"].iter().find(|d| is_delimiter(opening, d)) {
+        Some(delimiter) => *delimiter,
+        None => {
+            // no frontmatter, reset the reader
+            reader.seek(io::SeekFrom::Start(0))?;
+            return Ok((Frontmatter::from_text(HashMap::new()), problems));
+        }
+    };
+    let opening = opening.trim_end().to_string();
+
+    let mut lines = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_line(&mut buf)? == 0 {
+            problems.push(
+                Diagnostic::warning(format!("Frontmatter has no closing `{delimiter}`"))
+                    .at_line(file, 1, &opening)
+                    .label("read to the end of the file from here")
+                    .note(format!(
+                        "frontmatter ends at a line with only `{delimiter}` on it"
+                    )),
+            );
+            break;
+        }
+        // YAML can also end with `...`, its end of document
+        if is_delimiter(&buf, delimiter) || (delimiter == "---" && is_delimiter(&buf, "...")) {
+            break;
+        }
+        lines.push(buf.trim_end_matches(['\r', '\n']).to_string());
+    }
+    let source = lines.join("\n");
+    // `---` right after `---` is frontmatter without fields
+    if source.trim().is_empty() {
+        return Ok((Frontmatter::from_text(HashMap::new()), problems));
+    }
+
+    let parsed = if delimiter == "+++" {
+        frontmatter::from_toml(&source)
+    } else {
+        frontmatter::from_yaml(&source)
+    };
+    // frontmatter lines are numbered from the one after the delimiter
+    let at = |diagnostic: Diagnostic, line: usize| match lines.get(line.wrapping_sub(1)) {
+        Some(text) => diagnostic.at_line(file, line + 1, text),
+        None => diagnostic.in_file(file),
+    };
+
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(problem) if delimiter == "+++" => {
+            let diagnostic =
+                Diagnostic::error(format!("Frontmatter isn't valid TOML: {}", problem.message));
+            problems.push(match problem.line {
+                Some(line) => at(diagnostic, line),
+                None => diagnostic.in_file(file),
+            });
+            frontmatter::Parsed::default()
+        }
+        Err(problem) => {
+            let diagnostic = Diagnostic::warning(
+                "Frontmatter isn't valid YAML, reading it as `key: value` lines",
+            )
+            .note(problem.message);
+            problems.push(match problem.line {
+                Some(line) => at(diagnostic, line),
+                None => diagnostic.in_file(file),
+            });
+
+            let mut fields = HashMap::new();
+            for (i, line) in lines.iter().enumerate() {
+                if let Some((k, v)) = line.split_once(':') {
+                    fields.insert(k.trim().to_string(), v.trim().to_string());
+                } else if !line.trim().is_empty() {
+                    problems.push(
+                        at(
+                            Diagnostic::warning("Frontmatter line isn't `key: value`"),
+                            i + 1,
+                        )
+                        .label("ignored"),
+                    );
+                }
+            }
+            frontmatter::Parsed {
+                text: fields,
+                typed: HashMap::new(),
+            }
+        }
+    };
+
+    Ok((Frontmatter(parsed.text, parsed.typed), problems))
+}
+
+/// Site-wide changes made to every page's markdown as it is compiled.
+struct Transforms<'a> {
+    replacements: &'a Replacements,
+    markdown: &'a MarkdownOptions,
+    permalinks: &'a Permalinks,
+    glossary: Option<&'a Glossary>,
+    bibliography: Option<&'a Bibliography>,
+}
+
+/// What compiling a page needs besides the page itself.
+struct PageSettings<'a> {
+    default_language: &'a str,
+    /// Languages a page's path can put it in
+    languages: &'a [String],
+    rtl_languages: &'a [String],
+    transforms: &'a Transforms<'a>,
+    /// Report images without alt text as errors
+    require_alt: bool,
+    /// Lint the markdown of pages with these rules
+    lint: Option<&'a lint::Rules>,
+    highlight_cache: &'a HighlightCache,
+    build_cache: &'a BuildCache,
+}
+
+fn slug_pattern() -> Regex {
+    Regex::new(r"/?(_?index)?\.?(md|html|tera)(.+)?").unwrap()
+}
+
+/// Compiles the page at `entry`, in the content folder `dir`, from
+/// `reader`.
+fn compile_page<R: BufRead + Seek>(
+    reader: &mut R,
+    entry: &Path,
+    dir: &str,
+    templates: &mut Tera,
+    highlighter: &PulldownHighlighter,
+    settings: &PageSettings,
+) -> io::Result<Option<Content>> {
+    let transforms = settings.transforms;
+    let file_path = match entry.strip_prefix(dir).ok().and_then(|p| p.to_str()) {
+        Some(file_path) => file_path,
+        None => {
+            Diagnostic::error("Content file names must be valid UTF-8")
+                .in_file(&entry.to_string_lossy())
+                .print();
+            return Ok(None);
+        }
+    };
+
+    let (mut frontmatter, problems) = read_frontmatter(reader, &entry.to_string_lossy())?;
+    for problem in problems.iter() {
+        problem.print();
+    }
+    let body_start = reader.stream_position()? as usize;
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let str = match std::str::from_utf8(&buf) {
+        Ok(str) => str,
+        Err(_) => {
+            Diagnostic::error("Content isn't valid UTF-8")
+                .in_file(&entry.to_string_lossy())
+                .print();
+            return Ok(None);
+        }
+    };
+
+    let str = transforms
+        .replacements
+        .apply(Stage::Markdown, file_path, str.to_string());
+    let markdown = transforms
+        .markdown
+        .for_page(&frontmatter)
+        .unwrap_or_else(|err| {
+            Diagnostic::warning(format!("Ignoring the page's markdown options: {err}"))
+                .in_file(&entry.to_string_lossy())
+                .print();
+            transforms.markdown.clone()
+        });
+    if let Some(rules) = settings.lint.filter(|_| lint::is_markdown(file_path)) {
+        let mut source = Vec::new();
+        reader.seek(io::SeekFrom::Start(0))?;
+        reader.read_to_end(&mut source)?;
+        let problems = lint::check(
+            &entry.to_string_lossy(),
+            &String::from_utf8_lossy(&source),
+            body_start,
+            rules,
+            markdown.parser_options(),
+        );
+        for problem in problems.iter() {
+            problem.print();
+        }
+    }
+    let expanded = shortcodes::expand(
+        &str,
+        markdown.parser_options(),
+        templates,
+        &entry.to_string_lossy(),
+    );
+    for problem in expanded.problems.iter() {
+        problem.print();
+    }
+    let parser = typography::parse(&expanded.markdown, markdown.parser_options());
+    let events = expanded.insert(markdown.apply(parser.into_iter()).into_iter());
+    let events = ansi::render_blocks(events.into_iter());
+    let (events, missing_alt) = alt_text::fill(
+        events,
+        &frontmatter,
+        entry.parent().unwrap_or(Path::new(dir)),
+        Path::new(dir),
+    );
+    if settings.require_alt {
+        for src in missing_alt.iter() {
+            Diagnostic::error(format!("Image {src} has no alt text"))
+                .in_file(&entry.to_string_lossy())
+                .note(format!(
+                    "describe it in the markdown, with `alt.{src}` in the frontmatter or with `alt` in {src}.toml"
+                ))
+                .print();
+        }
+    }
+    // `about.fr.md` and `fr/about.md` are `about.md` in French, at /fr/about
+    let language = languages::from_path(file_path, settings.languages);
+    let page_path = language
+        .as_ref()
+        .map_or(file_path, |language| language.path.as_str());
+    let mut slug = slug_pattern().replace(page_path, "").to_string();
+    slug.insert(0, '/');
+    let permalink = transforms
+        .permalinks
+        .for_page(page_path, &slug, &frontmatter)
+        .unwrap_or_else(|err| {
+            Diagnostic::warning(format!("Ignoring the page's permalink: {err}"))
+                .in_file(&entry.to_string_lossy())
+                .note("the page is written where its path puts it")
+                .print();
+            None
+        });
+    let permalink = match &language {
+        Some(language) => {
+            frontmatter
+                .0
+                .entry("lang".to_string())
+                .or_insert_with(|| language.lang.to_string());
+            let url = permalink.unwrap_or_else(|| format!("{}/", slug.trim_end_matches('/')));
+            Some(languages::prefixed(
+                &url,
+                &language.lang,
+                settings.default_language,
+            ))
+        }
+        None => permalink,
+    };
+    // resources and images are linked from where the page ends up
+    if let Some(permalink) = &permalink {
+        slug = permalink.trim_end_matches('/').to_string();
+        if slug.is_empty() {
+            slug.push('/');
+        }
+    }
+    let (events, images) = resources::images(events, file_path, Path::new(dir), &slug);
+    let events = links::mark(events.into_iter(), file_path);
+    let headings = headings::collect(&events);
+    let events = match transforms.bibliography {
+        Some(bibliography) => bibliography.render(events.into_iter(), file_path),
+        None => events,
+    };
+    let events = match transforms.glossary {
+        Some(glossary) if frontmatter.0.get("glossary").map(|v| v.trim()) != Some("false") => {
+            glossary.render(events.into_iter())
+        }
+        _ => events,
+    };
+    let events = headings::add_ids(events.into_iter(), &headings);
+    let text = search::plain_text(&events);
+    let events = shortcodes::raw_code(events.into_iter());
+    let events = match settings
+        .highlight_cache
+        .highlight(highlighter, events.clone())
+    {
+        Ok(highlighted) => highlighted,
+        Err(err) => {
+            Diagnostic::error("Couldn't highlight the code blocks")
+                .in_file(&entry.to_string_lossy())
+                .note(err)
+                .print();
+            events
+        }
+    };
+
+    let mut content = String::new();
+
+    pulldown_cmark::html::push_html(&mut content, events.into_iter());
+
+    let lang = frontmatter.language(settings.default_language).to_string();
+    let lang_dir = i18n::text_direction(&lang, settings.rtl_languages).to_string();
+    i18n::set_current_language(&lang);
+    functions::start_page();
+    let result = templates.render_str(content.as_str(), &Context::new());
+    if let Ok(rendered) = result {
+        content = rendered;
+    } else if let Err(err) = result {
+        Diagnostic::error(format!("Couldn't render {file_path}"))
+            .failure(Failure::Template)
+            .in_file(&entry.to_string_lossy())
+            .causes(&err)
+            .print();
+    }
+    let numbering = functions::numbering();
+
+    let path = file_path.to_string();
+
+    let resources = frontmatter
+        .0
+        .get("resources")
+        .map(|value| resources::parse(value))
+        .unwrap_or_default();
+    let resources = resources::resolve(
+        resources,
+        file_path,
+        entry.parent().unwrap_or(Path::new(dir)),
+        &slug,
+    );
+
+    Ok(Some(Content {
+        path,
+        slug,
+        permalink,
+        lang,
+        lang_dir,
+        frontmatter,
+        content,
+        resources,
+        images,
+        text,
+        numbering,
+        headings,
+        docs_prev: None,
+        docs_next: None,
+        translations: Vec::new(),
+        feeds: Vec::new(),
+        extra: HashMap::new(),
+    }))
+}
+
+// compiles the page at `entry`, or takes it from the build cache
+fn compile_entry(
+    entry: &Path,
+    dir: &str,
+    templates: &mut Tera,
+    highlighter: &PulldownHighlighter,
+    settings: &PageSettings,
+) -> io::Result<Option<Content>> {
+    let source = fs::read(entry)?;
+    let cache = settings.build_cache;
+    let transforms = settings.transforms;
+    let glossary = transforms
+        .glossary
+        .map(|glossary| glossary.source.as_path());
+    let bibliography = transforms
+        .bibliography
+        .map(|bibliography| bibliography.source.as_path());
+    let data: Vec<&Path> = glossary.into_iter().chain(bibliography).collect();
+    let file_path = entry
+        .strip_prefix(dir)
+        .ok()
+        .and_then(|file_path| file_path.to_str());
+    let key = cache.compile_key(
+        file_path.unwrap_or(&*entry.to_string_lossy()),
+        &source,
+        &data,
+    );
+    let source_dir = entry.parent().unwrap_or(Path::new(dir));
+    let cached = file_path.and_then(|file_path| cache.compiled(&key, file_path, source_dir));
+    if cached.is_some() {
+        return Ok(cached);
+    }
+
+    // pages with problems are compiled every time, so the problems are
+    // reported every time
+    let printed = diagnostics::printed();
+    let page = compile_page(
+        &mut Cursor::new(source.as_slice()),
+        entry,
+        dir,
+        templates,
+        highlighter,
+        settings,
+    )?;
+    if let Some(page) = &page {
+        if diagnostics::printed() == printed {
+            // only the data files the page used count as what it depends on
+            let markdown = String::from_utf8_lossy(&source);
+            let glossary = glossary
+                .filter(|_| page.frontmatter.0.get("glossary").map(|v| v.trim()) != Some("false"));
+            let bibliography = bibliography.filter(|_| {
+                transforms
+                    .bibliography
+                    .is_some_and(|bibliography| bibliography.cites(&markdown))
+            });
+            // and so do the files it included code from
+            let included = functions::included_files();
+            let files: Vec<&Path> = glossary
+                .into_iter()
+                .chain(bibliography)
+                .chain(included.iter().map(PathBuf::as_path))
+                .collect();
+            cache.store(&key, page, &cache.dependencies(&markdown, &files));
+        }
+    }
+    Ok(page)
+}
+
+/// Compiles every page in `dir`, on a thread per core. Pages come out in
+/// the order their files are found in, however long each one takes.
+fn compile_content(
+    dir: &str,
+    templates: &Tera,
+    theme: &Theme,
+    settings: &PageSettings,
+) -> io::Result<Vec<Content>> {
+    let re = slug_pattern();
+    let path = format!("{}/**/*", glob::Pattern::escape(dir));
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let highlighter = PulldownHighlighter::new(syntax_set, theme);
+
+    let mut entries = Vec::new();
+    for entry in glob(path.as_str()).into_iter().flatten().flatten() {
+        if !entry.is_file() || is_hidden(&entry) {
+            continue;
+        }
+        if let Ok(file_path) = entry.strip_prefix(dir) {
+            if let Some(ext) = file_path.extension() {
+                if let Some(ext) = ext.to_str() {
+                    if !re.is_match(ext) {
+                        continue;
+                    }
+                }
+            }
+            entries.push(entry.to_path_buf());
+        }
+    }
+
+    // each thread renders page bodies with its own copy of the layouts
+    let pages = parallel::map_with(
+        &entries,
+        || templates.clone(),
+        |templates, entry| compile_entry(entry, dir, templates, &highlighter, settings),
+    );
+
+    let mut contents = Vec::new();
+    for page in pages.into_iter() {
+        contents.extend(page?);
+    }
+    Ok(contents)
+}
+
+fn is_hidden<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if let Some(file_name) = path.file_name() {
+        return file_name.to_string_lossy().starts_with(".");
+    }
+
+    false
+}
+
+/// The static files of `in_dir`, each with where it's written below
+/// `out_dir`.
+fn static_files(in_dir: &str, out_dir: &str) -> Vec<(PathBuf, PathBuf)> {
+    let path = format!("{}/**/*", glob::Pattern::escape(in_dir));
+    let out_root = Path::new(out_dir);
+
+    let mut copies = Vec::new();
+    for entry in glob(path.as_str()).into_iter().flatten().flatten() {
+        if !entry.is_file() || is_hidden(&entry) {
+            continue;
+        }
+
+        // files without an extension (or with one that isn't UTF-8) are
+        // copied like any other static file
+        let ext = entry.extension().and_then(|ext| ext.to_str());
+        if !matches!(ext, Some("md" | "html" | "tera")) {
+            if let Ok(bare_path) = entry.strip_prefix(in_dir) {
+                if let Some(out_path) = assets::output_path(&entry, out_root.join(bare_path)) {
+                    copies.push((entry.to_path_buf(), out_path));
+                }
+            }
+        }
+    }
+    copies
+}
+
+/// The static files of the site and its theme, each with where it's
+/// written below the output folder. The content folder's are copied over
+/// the theme's, so they come last.
+fn static_sources(opts: &Options, theme: Option<&ThemePackage>) -> Vec<(PathBuf, PathBuf)> {
+    let mut sources = Vec::new();
+    if let Some(theme) = theme {
+        sources.extend(static_files(&theme.static_files().to_string_lossy(), ""));
+    }
+    sources.extend(static_files(&opts.content, ""));
+    sources
+}
+
+/// Copies every file in `in_dir` that isn't a page or hidden to the same
+/// place in `out_dir`. Folders are made first, then files are copied in
+/// parallel.
+fn copy_static(in_dir: &str, out_dir: &str, minify: bool, images: &Images) -> io::Result<()> {
+    let copies = static_files(in_dir, out_dir);
+    let dirs: BTreeSet<&Path> = copies.iter().filter_map(|(_, out)| out.parent()).collect();
+    for dir in dirs.iter() {
+        fs::create_dir_all(dir)?;
+    }
+
+    parallel::try_for_each(&copies, |(from, to)| {
+        assets::copy(from, to, minify)?;
+        if images::is_image(from) {
+            images.write_variants(from, to)?;
+        }
+        Ok(())
+    })
+}
+
+#[derive(Parser, Clone)]
+#[command(name = "Roxy")]
+#[command(author = "KitsuneCafe")]
+#[command(version = "1.0")]
+#[command(about = "A very small static site generator", long_about = None)]
+pub struct Options {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Output folder, a bucket to upload the site to, `s3://bucket/prefix`,
+    /// or a branch to commit it to, `git:gh-pages`
+    #[arg(short, long, default_value = "build/", global = true)]
+    pub output: String,
+    /// Content folder, or a tarball or zip of it, or a link to one
+    #[arg(short, long, default_value = "content/", global = true)]
+    pub content: String,
+    #[arg(short, long, default_value = "layouts/", global = true)]
+    pub layouts: String,
+    #[arg(short, long, default_value = "base16-ocean.dark", global = true)]
+    pub theme: String,
+    #[arg(long, default_value = "i18n/", global = true)]
+    pub i18n: String,
+    /// Site data files, like the glossary
+    #[arg(long, default_value = "data/", global = true)]
+    pub data: String,
+    /// Html snippets layouts and pages inline with `include_html`
+    #[arg(long, default_value = "includes/", global = true)]
+    pub includes: String,
+    /// SVG icons `icon(name)` draws from one sprite sheet
+    #[arg(long, default_value = "icons/", global = true)]
+    pub icons: String,
+    #[arg(long, default_value = "en", global = true)]
+    pub default_language: String,
+    /// Additional right-to-left languages, comma separated
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub rtl_languages: Vec<String>,
+    /// Languages pages can be written in besides the default, comma
+    /// separated; those with translations in the i18n folder unless set
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub languages: Vec<String>,
+    /// Site config file
+    #[arg(long, default_value = "roxy.toml", global = true)]
+    pub config: String,
+    /// Directory theme packages are kept in
+    #[arg(long, default_value = "themes/", global = true)]
+    pub themes: String,
+    /// Theme package to build with; the site's own layouts take precedence
+    #[arg(long, global = true)]
+    pub site_theme: Option<String>,
+    /// Build a version of the docs section from a git tag or branch, e.g. `v1=v1.0.0`
+    #[arg(long, value_name = "NAME=REV", value_parser = parse_setting, global = true)]
+    pub docs_version: Vec<(String, String)>,
+    /// The content folder built once per `--docs-version`
+    #[arg(long, default_value = "docs", global = true)]
+    pub docs_section: String,
+    /// OpenAPI document (YAML or JSON) to generate API reference pages from
+    #[arg(long, global = true)]
+    pub openapi: Option<String>,
+    /// Layout used for API reference pages
+    #[arg(long, default_value = "openapi.html", global = true)]
+    pub openapi_layout: String,
+    /// Where API reference pages are generated
+    #[arg(long, default_value = "api", global = true)]
+    pub openapi_prefix: String,
+    /// Folder with asciinema-player's script and stylesheet, served from /asciinema/
+    #[arg(long, global = true)]
+    pub asciinema_player: Option<String>,
+    /// Frontmatter fields whose terms get listing pages, comma separated
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "tags,categories",
+        global = true
+    )]
+    pub taxonomies: Vec<String>,
+    /// Layout used for taxonomy term listing pages
+    #[arg(long, default_value = "taxonomy.html", global = true)]
+    pub taxonomy_layout: String,
+    /// Layout extensions whose pages get their frontmatter html escaped,
+    /// comma separated
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub autoescape: Vec<String>,
+    /// Frontmatter fields left unescaped on every page, for trusted html,
+    /// comma separated
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub safe_html: Vec<String>,
+    /// Format of the site's and sections' `feed.xml`
+    #[arg(long, default_value = "atom", value_parser = ["atom", "rss"], global = true)]
+    pub feed_format: String,
+    /// Add a `<link rel="alternate">` for each of a page's feeds to its
+    /// `<head>`
+    #[arg(long, global = true)]
+    pub feed_links: bool,
+    /// Where `short_url: auto` pages get their short urls
+    #[arg(long, default_value = "s", global = true)]
+    pub short_url_prefix: String,
+    /// Records a hash of every rendered page here after each build
+    #[arg(long, global = true)]
+    pub manifest: Option<String>,
+    /// Fail if a locked page renders differently than in the `--manifest`
+    #[arg(long, requires = "manifest", global = true)]
+    pub check_locked: bool,
+    /// Pages dated before this (YYYY-MM-DD) are locked as well as `locked: true` pages
+    #[arg(long, global = true)]
+    pub locked_before: Option<String>,
+    /// Build pages with `draft: true` too
+    #[arg(long, global = true)]
+    pub drafts: bool,
+    /// Build pages dated in the future too
+    #[arg(long, global = true)]
+    pub future: bool,
+    /// Build profile; pages with `environments` are only built in the ones they list
+    #[arg(long, default_value = "production", global = true)]
+    pub env: String,
+    /// Write every content file's urls here, as JSON if it ends in `.json`
+    #[arg(long, value_name = "FILE", global = true)]
+    pub url_map: Option<String>,
+    /// Fail when an image has no alt text
+    #[arg(long, global = true)]
+    pub require_alt: bool,
+    /// Lint the markdown of pages as they're compiled, with the rules of
+    /// `[lint_rules]`
+    #[arg(long, global = true)]
+    pub lint: bool,
+    /// Endpoint of the S3-compatible service an `s3://` output is uploaded to
+    #[arg(long, value_name = "URL", global = true)]
+    pub s3_endpoint: Option<String>,
+    /// Cache-Control of the files uploaded to an `s3://` output besides its
+    /// pages, which are always `no-cache`
+    #[arg(long, default_value = "public, max-age=3600", global = true)]
+    pub s3_cache_control: String,
+    /// Where highlighted code and other build results are kept between builds
+    #[arg(long, default_value = ".roxy/cache", global = true)]
+    pub cache_dir: String,
+    /// Compile and render every page, ignoring the build cache
+    #[arg(long, global = true)]
+    pub force: bool,
+    /// Read the caches without writing to them, for builds kept in memory
+    #[arg(skip)]
+    pub read_only: bool,
+    /// What Ctrl-C does, which only the command line handles
+    #[arg(skip)]
+    pub(crate) interrupt: Option<Arc<interrupt::Interrupt>>,
+    /// Minify the html, CSS and JavaScript written
+    #[arg(long, global = true)]
+    pub minify: bool,
+    /// How much of every page `data` and `languages` hold in layouts
+    #[arg(long, value_enum, default_value_t = PageData::Full, global = true)]
+    pub page_data: PageData,
+    /// Write `search_index.json`, the plain text of every page for search
+    /// in the browser
+    #[arg(long, global = true)]
+    pub search_index: bool,
+    /// Write a `robots.txt` allowing every crawler, unless the site has one
+    #[arg(long, global = true)]
+    pub robots: bool,
+    /// Fail the build when a page links to a page or file of the site that
+    /// wasn't written, or to another environment's site
+    #[arg(long, global = true)]
+    pub check_links: bool,
+    /// Write a `_headers` file with each page's Content-Security-Policy and
+    /// preload hints
+    #[arg(long, global = true)]
+    pub headers: bool,
+    /// Write the SHA-256 of every file of the site to its `SHA256SUMS`
+    #[arg(long, global = true)]
+    pub checksums: bool,
+    /// Subset the site's fonts to the characters its pages use, with
+    /// `pyftsubset` of fonttools
+    #[arg(long, global = true)]
+    pub subset_fonts: bool,
+    /// Turn off the template functions that read files or the environment,
+    /// for building content that isn't trusted
+    #[arg(long, global = true)]
+    pub sandbox: bool,
+    /// Exit with code 7 when a build prints more warnings than this
+    #[arg(long, value_name = "N", global = true)]
+    pub max_warnings: Option<usize>,
+    /// Stop the build at the first page that fails to render
+    #[arg(long, global = true)]
+    pub strict: bool,
+    /// Print every page as it's written
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    pub verbose: bool,
+    /// Print only errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// A site setting available to layouts as `site.<key>`
+    #[arg(long = "site", value_name = "KEY=VALUE", value_parser = parse_setting, global = true)]
+    pub site: Vec<(String, String)>,
+    /// `[extra]` of the site config, along with its `title` and `base_url`
+    #[arg(skip)]
+    pub extra: toml::Table,
+    /// The `base_url` of each environment of the site config
+    #[arg(skip)]
+    pub base_urls: BTreeMap<String, String>,
+}
+
+fn parse_setting(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{s}`"))
+}
+
+impl Options {
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    /// Every language a page's path can put it in, the default included.
+    fn languages(&self) -> io::Result<Vec<String>> {
+        let mut languages = if self.languages.is_empty() {
+            Translations::load(&self.i18n, &self.default_language)?.languages()
+        } else {
+            self.languages.clone()
+        };
+        if !languages.contains(&self.default_language) {
+            languages.push(self.default_language.to_string());
+        }
+        languages.sort();
+        Ok(languages)
+    }
+
+    /// The folder the site config is in, which `include_code` paths are
+    /// relative to.
+    fn site_root(&self) -> PathBuf {
+        Path::new(&self.config)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    /// What layouts get as `site`: the `title`, `base_url` and `[extra]` of
+    /// the site config, overridden by `--site`.
+    pub fn site_values(&self) -> toml::Table {
+        let mut values = self.extra.clone();
+        for (key, value) in self.site.iter() {
+            values.insert(key.to_string(), toml::Value::String(value.to_string()));
+        }
+        values
+    }
+
+    pub fn site_settings(&self) -> HashMap<String, String> {
+        self.site_values()
+            .into_iter()
+            .map(|(key, value)| match value {
+                toml::Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect()
+    }
+
+    /// Takes the settings the command line left at their defaults from the
+    /// site config.
+    fn apply_config(&mut self, matches: &ArgMatches) {
+        let config = config::load(&self.config);
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        let paths = [
+            ("output", &mut self.output, config.output),
+            ("content", &mut self.content, config.content),
+            ("layouts", &mut self.layouts, config.layouts),
+            ("theme", &mut self.theme, config.highlight_theme),
+            ("i18n", &mut self.i18n, config.i18n),
+            ("data", &mut self.data, config.data),
+            ("includes", &mut self.includes, config.includes),
+            ("icons", &mut self.icons, config.icons),
+            (
+                "s3_cache_control",
+                &mut self.s3_cache_control,
+                config.s3_cache_control,
+            ),
+            (
+                "default_language",
+                &mut self.default_language,
+                config.default_language,
+            ),
+        ];
+        for (id, option, value) in paths.into_iter() {
+            if let Some(value) = value.filter(|_| unset(id)) {
+                *option = value;
+            }
+        }
+        if self.site_theme.is_none() {
+            self.site_theme = config.theme;
+        }
+        if self.s3_endpoint.is_none() {
+            self.s3_endpoint = config.s3_endpoint;
+        }
+        if unset("minify") {
+            self.minify = config.minify.unwrap_or(self.minify);
+        }
+        if unset("search_index") {
+            self.search_index = config.search_index.unwrap_or(self.search_index);
+        }
+        if unset("robots") {
+            self.robots = config.robots.unwrap_or(self.robots);
+        }
+        if unset("check_links") {
+            self.check_links = config.check_links.unwrap_or(self.check_links);
+        }
+        if unset("headers") {
+            self.headers = config.headers.unwrap_or(self.headers);
+        }
+        if unset("feed_links") {
+            self.feed_links = config.feed_links.unwrap_or(self.feed_links);
+        }
+        if unset("checksums") {
+            self.checksums = config.checksums.unwrap_or(self.checksums);
+        }
+        if unset("subset_fonts") {
+            self.subset_fonts = config.subset_fonts.unwrap_or(self.subset_fonts);
+        }
+        if unset("lint") {
+            self.lint = config.lint.unwrap_or(self.lint);
+        }
+        if unset("strict") {
+            self.strict = config.strict.unwrap_or(self.strict);
+        }
+        if let Some(languages) = config.languages.filter(|_| unset("languages")) {
+            self.languages = languages;
+        }
+        if let Some(extensions) = config.autoescape.filter(|_| unset("autoescape")) {
+            self.autoescape = extensions;
+        }
+        if let Some(fields) = config.safe_html.filter(|_| unset("safe_html")) {
+            self.safe_html = fields;
+        }
+        if let Some(value) = config.page_data.filter(|_| unset("page_data")) {
+            match PageData::from_str(&value, true) {
+                Ok(page_data) => self.page_data = page_data,
+                Err(_) => Diagnostic::warning(format!(
+                    "`page_data` should be metadata, summary or full, not {value}"
+                ))
+                .in_file(&self.config)
+                .print(),
+            }
+        }
+
+        let mut extra = toml::Table::new();
+        extra.extend(
+            config
+                .title
+                .map(|title| ("title".to_string(), toml::Value::String(title))),
+        );
+        self.base_urls = config
+            .environments
+            .iter()
+            .filter_map(|(env, settings)| Some((env.to_string(), settings.base_url.clone()?)))
+            .collect();
+        // the environment's own `base_url` takes the place of the site's
+        let base_url = self.base_urls.get(&self.env).cloned().or(config.base_url);
+        extra.extend(base_url.map(|url| ("base_url".to_string(), toml::Value::String(url))));
+        extra.extend(config.extra);
+        self.extra = extra;
+    }
+
+    /// Identifies this build by its content, layouts, translations, icons,
+    /// config and theme.
+    fn build_info(&self, theme: Option<&ThemePackage>) -> io::Result<BuildInfo> {
+        let theme_root = theme.map(|theme| theme.root.to_string_lossy().to_string());
+        let mut inputs = vec![
+            self.content.as_str(),
+            self.layouts.as_str(),
+            self.i18n.as_str(),
+            self.includes.as_str(),
+            self.icons.as_str(),
+            self.config.as_str(),
+        ];
+        inputs.extend(theme_root.as_deref());
+        build_info::compute(&inputs)
+    }
+
+    /// The cache of compiled and rendered pages, for the layouts and
+    /// settings of this build.
+    fn build_cache(&self, theme: Option<&ThemePackage>) -> io::Result<BuildCache> {
+        let theme_layouts = theme.map(|theme| theme.layouts().to_string_lossy().to_string());
+        let mut layouts = vec![self.layouts.as_str()];
+        layouts.extend(theme_layouts.as_deref());
+        let settings = format!(
+            "{} {:?} {:?} {} {} {} {} {:?} {:?} {} {:?}",
+            self.default_language,
+            self.rtl_languages,
+            self.languages()?,
+            self.require_alt,
+            self.theme,
+            self.minify,
+            self.sandbox,
+            self.autoescape,
+            self.safe_html,
+            self.feed_links,
+            // pages are only compiled again, and linted, when this changes
+            self.lint.then(|| lint::load(&self.config)).transpose()?
+        );
+        let cache = BuildCache::open(
+            Path::new(&self.cache_dir),
+            self.force,
+            TemplateGraph::load(&layouts)?,
+            &[
+                self.i18n.as_str(),
+                self.includes.as_str(),
+                self.icons.as_str(),
+                self.config.as_str(),
+            ],
+            &settings,
+        )?;
+        Ok(if self.read_only {
+            cache.read_only()
+        } else {
+            cache
+        })
+    }
+
+    /// Resizes and converts the static images of this build, finding those
+    /// layouts ask for in the content folder and then the theme's.
+    fn images(&self, theme: Option<&ThemePackage>) -> io::Result<Images> {
+        let mut roots = vec![PathBuf::from(&self.content)];
+        roots.extend(theme.map(|theme| theme.static_files()));
+        Ok(Images::new(
+            images::load(&self.config)?,
+            Path::new(&self.cache_dir),
+            self.read_only,
+            roots,
+        ))
+    }
+
+    /// The cache of highlighted code blocks, for the theme of this build.
+    fn highlight_cache(&self) -> HighlightCache {
+        let cache = HighlightCache::new(Path::new(&self.cache_dir), &self.theme);
+        if self.read_only {
+            cache.read_only()
+        } else {
+            cache
+        }
+    }
+
+    // prints the site config's problems and fails if it has any
+    fn check_config(&self) -> io::Result<()> {
+        let problems = config::check(&self.config);
+        if !problems.is_empty() {
+            for problem in problems.iter() {
+                problem.print();
+            }
+            return Err(diagnostics::reported(Failure::Config));
+        }
+        Ok(())
+    }
+
+    fn theme_package(&self) -> io::Result<Option<ThemePackage>> {
+        let name = match self.site_theme.as_ref() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match theme_package::load(&self.themes, name, &self.site_settings()) {
+            Ok(package) => Ok(Some(package)),
+            Err(problems) => {
+                for problem in problems.iter() {
+                    Diagnostic::error(problem).failure(Failure::Config).print();
+                }
+                Err(diagnostics::reported(Failure::Config))
+            }
+        }
+    }
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Render every layout against fixture contexts and report failures
+    TestTemplates {
+        /// Directory of `<template>.json` context fixtures
+        #[arg(long, default_value = "fixtures/")]
+        fixtures: String,
+    },
+    /// Report variables layouts use that the context lacks, and frontmatter no layout reads
+    AuditTemplates,
+    /// Lint the markdown of every page without building, failing on any problem
+    Lint,
+    /// Check a copy of the site against the `SHA256SUMS` it was built with
+    Verify {
+        /// The copy, e.g. a mirror's files
+        dir: String,
+    },
+    /// Generate a lorem ipsum content folder for theme development and benchmarks
+    DemoContent {
+        /// Number of pages to generate
+        #[arg(long, default_value_t = 200)]
+        pages: usize,
+        /// Number of distinct tags to spread over the pages
+        #[arg(long, default_value_t = 20)]
+        tags: usize,
+        /// Seed for the generator; the same seed always gives the same content
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Where to write the content, defaults to a new temporary folder
+        #[arg(long)]
+        dir: Option<String>,
+    },
+    /// Set up a new site, asking for its title, base url, theme and features
+    Init {
+        /// Don't ask, use the defaults
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Print a shell completion script, e.g. `roxy completions bash > /etc/bash_completion.d/roxy`
+    Completions { shell: clap_complete::Shell },
+    /// Print the man page, e.g. `roxy man > /usr/local/share/man/man1/roxy.1`
+    Man,
+    /// Keep running and build on request from a local HTTP control API
+    Daemon {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: String,
+    },
+    /// Render buffers sent as JSON-RPC on stdin, for live previews in editors
+    Preview,
+    /// Build, serve the output and rebuild and reload pages when the sources change
+    Serve {
+        /// Address to serve on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        listen: String,
+        /// Milliseconds without further changes to wait for before rebuilding
+        #[arg(long, default_value_t = 100)]
+        debounce: u64,
+        /// Render pages when they're requested instead of writing the site
+        #[arg(long)]
+        in_memory: bool,
+    },
+    /// Inspect the site's settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Look inside a build
+    Debug {
+        #[command(subcommand)]
+        action: DebugCommand,
+    },
+    /// Create something new from a starter skeleton
+    New {
+        #[command(subcommand)]
+        kind: NewCommand,
+    },
+    /// Install and update themes from git
+    Theme {
+        #[command(subcommand)]
+        action: ThemeCommand,
+    },
+    /// Write part of the site in another form, to standard output
+    Export {
+        #[command(subcommand)]
+        format: ExportCommand,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ConfigCommand {
+    /// List every setting and option with its default and current value
+    Explain,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum DebugCommand {
+    /// Print the context a content file's layout is rendered with, as JSON
+    Context {
+        /// The content file, relative to the content folder or not
+        file: String,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ExportCommand {
+    /// One email-ready html page of the posts since a date, with styles
+    /// inlined and absolute urls, for a newsletter service
+    Newsletter {
+        /// The first date whose posts go in, like 2024-05-01
+        #[arg(long)]
+        since: String,
+        /// Only posts of this top level section, like blog
+        #[arg(long)]
+        section: Option<String>,
+        /// Layout that renders the posts, given as `posts`; a plain one if the
+        /// site has none
+        #[arg(long, default_value = "newsletter.html")]
+        layout: String,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum NewCommand {
+    /// Create a theme package with starter layouts, styles and metadata
+    Theme { name: String },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ThemeCommand {
+    /// Clone a theme from git into the themes directory and pin its commit
+    Install {
+        url: String,
+        /// Name to install the theme as, defaults to the repository name
+        #[arg(long)]
+        name: Option<String>,
+        /// Branch, tag or commit to check out
+        #[arg(long)]
+        rev: Option<String>,
+    },
+    /// Move pinned themes to the latest commit of their default branch
+    Update { name: Option<String> },
+}
+
+// a theme file, or the name of one of syntect's default themes
+fn highlight_theme(name: &str) -> Theme {
+    let theme_set = ThemeSet::load_defaults();
+
+    let theme = if let Ok(file) = fs::File::open(name) {
+        let mut reader = BufReader::new(file);
+        let theme = ThemeSet::load_from_reader(&mut reader);
+        theme.ok()
+    } else {
+        None
+    };
+
+    let default_theme = theme_set.themes.get(name).cloned();
+    match theme.or(default_theme) {
+        Some(theme) => theme,
+        None => {
+            Diagnostic::warning(format!("Unknown highlighting theme {name}"))
+                .note("using base16-ocean.dark instead")
+                .print();
+            theme_set.themes["base16-ocean.dark"].clone()
+        }
+    }
+}
+
+fn setup_templates(
+    opts: &Options,
+    theme: Option<&ThemePackage>,
+    build: &BuildInfo,
+) -> io::Result<Tera> {
+    let mut templates = load_templates(&opts.layouts)?;
+    if let Some(theme) = theme {
+        // templates already loaded from the site's layouts are kept
+        let theme_templates = load_templates(&theme.layouts().to_string_lossy())?;
+        if let Err(err) = templates.extend(&theme_templates) {
+            Diagnostic::error(format!("Couldn't load theme {}", theme.metadata.name))
+                .failure(Failure::Template)
+                .note(err.to_string())
+                .causes(&err)
+                .print();
+            return Err(diagnostics::reported(Failure::Template));
+        }
+    }
+    let translations = Translations::load(&opts.i18n, &opts.default_language)?;
+    templates.register_function("trans", translations);
+    templates.register_filter("search_terms", search::search_terms);
+    templates.register_filter("slugify", filters::slugify);
+    templates.register_filter("date", filters::date);
+    templates.register_filter("truncate_html", filters::Html(filters::truncate_html));
+    templates.register_filter("excerpt", filters::Html(filters::excerpt));
+    templates.register_filter(
+        "markdown",
+        filters::Markdown::new(markdown::load(&opts.config)?.parser_options()),
+    );
+    templates.register_filter("toc", filters::Html(filters::toc));
+    templates.register_filter("meta_tags", filters::Html(filters::meta_tags));
+    templates.register_function(
+        "include_code",
+        functions::IncludeCode::new(opts.site_root(), highlight_theme(&opts.theme)),
+    );
+    templates.register_function(
+        "include_html",
+        functions::IncludeHtml::new(PathBuf::from(&opts.includes)),
+    );
+    templates.register_function(
+        "asciinema",
+        functions::Asciinema::new(opts.asciinema_player.as_ref().map(PathBuf::from)),
+    );
+    templates.register_function("sidenote", functions::sidenote);
+    templates.register_function("csp", csp::csp);
+    templates.register_function("preload", preload::preload);
+    templates.register_function("asset", build_info::Asset::new(&build.id));
+    let sources = static_sources(opts, theme);
+    templates.register_function(
+        "integrity",
+        integrity::Integrity::new(sources.clone(), opts.minify),
+    );
+    templates.register_function("inline_svg", svg::InlineSvg::new(sources));
+    templates.register_function("icon", icons::Icon::new(&icons::load(&opts.icons)?));
+    if opts.sandbox {
+        for name in functions::SANDBOXED.iter() {
+            templates.register_function(name, functions::Disabled(*name));
+        }
+    }
+    Ok(templates)
+}
+
+/// Runs the command line with the process's arguments, returning the code
+/// to exit with.
+pub fn cli() -> i32 {
+    let matches = Options::command().get_matches();
+    let mut opts = Options::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    opts.apply_config(&matches);
+    // pages served from memory aren't written anywhere, caches included
+    opts.read_only = matches!(
+        opts.command,
+        Some(Command::Serve {
+            in_memory: true,
+            ..
+        })
+    );
+    let ctrl_c = interrupt::Interrupt::install();
+    opts.interrupt = Some(ctrl_c.clone());
+    let tally = Arc::new(diagnostics::Tally::default());
+    diagnostics::counting(Some(tally), || {
+        diagnostics::set_verbosity(opts.verbosity());
+        if let Err(err) = with_locations(&opts, |opts| run(opts, &matches)) {
+            diagnostics::report(&err);
+        }
+        if ctrl_c.requested() {
+            return interrupt::EXIT_CODE;
+        }
+        diagnostics::exit_code(opts.max_warnings)
+    })
+}
+
+/// The settings of a build, the same as the command line's.
+pub type BuildOptions = Options;
+
+impl Options {
+    /// The default settings, with those of the site config at `config`
+    /// applied as the command line would.
+    pub fn from_config(config: &str) -> Result<Self, Error> {
+        let invalid =
+            |err: clap::Error| io::Error::new(io::ErrorKind::InvalidInput, err.to_string());
+        let matches = Options::command()
+            .try_get_matches_from(["roxy", "--config", config])
+            .map_err(invalid)?;
+        let mut opts = Options::from_arg_matches(&matches).map_err(invalid)?;
+        opts.apply_config(&matches);
+        Ok(opts)
+    }
+}
+
+/// Why a build failed. Problems are printed as they're found, like on the
+/// command line, so this only says what kind of problem it was.
+#[derive(Debug)]
+pub enum Error {
+    /// Diagnostics of this kind were printed
+    Failed(Failure),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Failed(failure) => write!(f, "the build failed with {failure:?} errors"),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Failed(_) => None,
+            Error::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        let reported = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<diagnostics::Reported>())
+            .map(|reported| reported.0);
+        match reported {
+            Some(failure) => Error::Failed(failure),
+            None => Error::Io(err),
+        }
+    }
+}
+
+/// Builds the site, returning every page written. Problems are printed as
+/// the build goes, and if any fail it (see `Failure`) that is the error.
+///
+/// A build keeps everything it needs in `opts` and counts only its own
+/// problems, so several sites can be built at once on different threads.
+/// Paths in `opts` are relative to the working directory, which roxy never
+/// changes; give absolute ones to build sites found elsewhere.
+pub fn build(opts: &BuildOptions) -> Result<Vec<Page>, Error> {
+    let tally = Arc::new(diagnostics::Tally::default());
+    let pages = diagnostics::counting(Some(tally.clone()), || {
+        diagnostics::set_verbosity(opts.verbosity());
+        with_locations(opts, build_site)
+    })?;
+    match tally.failure(opts.max_warnings) {
+        Some(failure) => Err(Error::Failed(failure)),
+        None => Ok(pages.into_iter().map(|(page, _)| page).collect()),
+    }
+}
+
+/// Runs `f` with `opts`, with a content archive extracted first and an
+/// output that isn't a folder built to a temporary one, then published.
+fn with_locations<T>(opts: &Options, f: impl FnOnce(&Options) -> io::Result<T>) -> io::Result<T> {
+    archive::with_content(opts, |opts| {
+        object_storage::with_output(opts, |opts| git_output::with_output(opts, f))
+    })
+}
+
+// `matches` are the command line's, which `config explain` shows the
+// sources of
+fn run(opts: &Options, matches: &ArgMatches) -> io::Result<()> {
+    match &opts.command {
+        Some(Command::TestTemplates { fixtures }) => {
+            opts.check_config()?;
+            let theme = opts.theme_package()?;
+            let build = opts.build_info(theme.as_ref())?;
+            let templates = setup_templates(opts, theme.as_ref(), &build)?;
+            let passed = template_tests::run(&templates, fixtures, opts, theme.as_ref(), &build);
+            if !passed {
+                diagnostics::exit(Failure::Template);
+            }
+            Ok(())
+        }
+        Some(Command::AuditTemplates) => {
+            opts.check_config()?;
+            let theme = opts.theme_package()?;
+            let build_info = opts.build_info(theme.as_ref())?;
+            let mut templates = setup_templates(opts, theme.as_ref(), &build_info)?;
+            let site = prepare(opts, &mut templates, theme.as_ref(), &build_info)?;
+            site.remove_checkouts();
+
+            let layouts = audit::layout_dirs(
+                &opts.layouts,
+                theme.as_ref().map(|theme| theme.layouts()).as_deref(),
+            );
+            let problems = audit::run(&layouts, &site.content, &site.context_keys());
+            for problem in problems.iter() {
+                diagnostics::info(problem);
+            }
+            if !problems.is_empty() {
+                diagnostics::exit(Failure::Template);
+            }
+            Ok(())
+        }
+        Some(Command::Lint) => lint_content(opts),
+        Some(Command::Verify { dir }) => {
+            let (matched, problems) = checksums::verify(dir)?;
+            for problem in problems.iter() {
+                problem.print();
+            }
+            if !problems.is_empty() {
+                return Err(diagnostics::reported(Failure::Content));
+            }
+            diagnostics::info(format!("{matched} files match {}", checksums::FILE_NAME));
+            Ok(())
+        }
+        Some(Command::DemoContent {
+            pages,
+            tags,
+            seed,
+            dir,
+        }) => {
+            let dir = dir
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| demo_content::default_dir(*seed));
+            demo_content::generate(&dir, *pages, *tags, *seed)?;
+            diagnostics::info(format!("Demo content at {}", dir.to_string_lossy()));
+            Ok(())
+        }
+        Some(Command::Init { yes }) => init::run(opts, *yes),
+        Some(Command::Completions { shell }) => {
+            let mut command = Options::command();
+            clap_complete::generate(*shell, &mut command, "roxy", &mut io::stdout());
+            Ok(())
+        }
+        Some(Command::Man) => clap_mangen::Man::new(Options::command()).render(&mut io::stdout()),
+        Some(Command::Config {
+            action: ConfigCommand::Explain,
+        }) => {
+            config::explain(&opts.config, &Options::command(), matches);
+            Ok(())
+        }
+        Some(Command::Debug {
+            action: DebugCommand::Context { file },
+        }) => {
+            opts.check_config()?;
+            let theme = opts.theme_package()?;
+            let build_info = opts.build_info(theme.as_ref())?;
+            let mut templates = setup_templates(opts, theme.as_ref(), &build_info)?;
+            debug_context(opts, &mut templates, theme.as_ref(), &build_info, file)
+        }
+        Some(Command::Export {
+            format:
+                ExportCommand::Newsletter {
+                    since,
+                    section,
+                    layout,
+                },
+        }) => {
+            let (templates, site, theme) = load_site(opts)?;
+            let sources = static_sources(opts, theme.as_ref());
+            match newsletter::export(
+                &templates,
+                &site,
+                sources,
+                layout,
+                since,
+                section.as_deref(),
+            )? {
+                Some(html) => io::stdout().write_all(html.as_bytes()),
+                None => {
+                    Diagnostic::warning(format!("No posts since {since}")).print();
+                    Ok(())
+                }
+            }
+        }
+        Some(Command::New {
+            kind: NewCommand::Theme { name },
+        }) => {
+            scaffold::new_theme(&opts.themes, name)?;
+            diagnostics::info(format!("Created theme {name} in {}", opts.themes));
+            Ok(())
+        }
+        Some(Command::Theme {
+            action: ThemeCommand::Install { url, name, rev },
+        }) => {
+            let (name, commit) = theme_git::install(
+                &opts.themes,
+                &opts.config,
+                url,
+                name.as_deref(),
+                rev.as_deref(),
+            )?;
+            diagnostics::info(format!("Installed theme {name} at {commit}"));
+            Ok(())
+        }
+        Some(Command::Theme {
+            action: ThemeCommand::Update { name },
+        }) => {
+            for (name, commit) in theme_git::update(&opts.themes, &opts.config, name.as_deref())? {
+                diagnostics::info(format!("Updated theme {name} to {commit}"));
+            }
+            Ok(())
+        }
+        Some(Command::Daemon { listen }) => daemon::run(opts, listen),
+        Some(Command::Preview) => preview::run(opts),
+        Some(Command::Serve {
+            listen,
+            debounce,
+            in_memory,
+        }) => serve::run(opts, listen, Duration::from_millis(*debounce), *in_memory),
+        None => build_site(opts).map(|_| ()),
+    }
+}
+
+/// Everything compiled for a build, before any of it is written.
+struct Site {
+    content: Vec<Content>,
+    context: Context,
+    replacements: Replacements,
+    markdown: MarkdownOptions,
+    permalinks: Permalinks,
+    glossary: Option<Glossary>,
+    bibliography: Option<Bibliography>,
+    short_urls: Vec<short_urls::ShortUrl>,
+    /// Every language a page's path can put it in
+    languages: Vec<String>,
+    cache: BuildCache,
+    /// The name, compiled folder and temporary checkout of each docs version
+    version_dirs: Vec<(String, String, PathBuf)>,
+    minify: bool,
+    /// The config's sources for pages' Content-Security-Policy
+    csp: csp::Policy,
+    /// Fills in the preload hints of pages that ask for them
+    preload: preload::Preload,
+    /// Which layouts get their pages' frontmatter escaped
+    escaping: escaping::Escaping,
+    /// Whether pages' heads get links to their feeds
+    feed_links: bool,
+    /// How the site config says feeds are made
+    feeds: feeds::Settings,
+    images: Arc<Images>,
+    /// When the site next needs building to publish or expire a page
+    next_rebuild: Option<schedule::NextRebuild>,
+    /// The content folder, where pages' changelogs are
+    content_dir: PathBuf,
+    /// Like `--sandbox`
+    sandbox: bool,
+}
+
+impl Site {
+    /// Every top level name a page's layout can be rendered with.
+    fn context_keys(&self) -> BTreeSet<String> {
+        let mut keys: BTreeSet<String> = ["page", "section_tree", "changelog"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+
+        if let tera::Value::Object(map) = self.context.clone().into_json() {
+            keys.extend(map.keys().cloned());
+        }
+        for content in self.content.iter() {
+            if let Ok(tera::Value::Object(map)) = serde_json::to_value(content) {
+                keys.extend(map.keys().cloned());
+            }
+            keys.extend(content.extra.keys().cloned());
+        }
+        keys
+    }
+
+    /// A rendered page as it's written: minified with `--minify`, and with
+    /// its `csp()` filled in.
+    fn finish(&self, html: String) -> String {
+        let html = if self.minify {
+            assets::minify_html(&html)
+        } else {
+            html
+        };
+        self.csp.fill(self.preload.fill(html))
+    }
+
+    /// The headers of a page that renders to `html`: its
+    /// Content-Security-Policy, a `Link` header for what it preloads, and
+    /// those it sets itself, `own`.
+    fn page_headers(
+        &self,
+        html: &str,
+        own: Option<&Vec<(String, String)>>,
+    ) -> Vec<(String, String)> {
+        let mut headers = vec![(
+            "Content-Security-Policy".to_string(),
+            self.csp.for_page(html),
+        )];
+        if let Some(link) = preload::link_header(html) {
+            headers.push(("Link".to_string(), link));
+        }
+        if let Some(own) = own {
+            headers::merge(&mut headers, own);
+        }
+        headers
+    }
+
+    fn remove_checkouts(&self) {
+        for (_, _, dest) in self.version_dirs.iter() {
+            let _ = fs::remove_dir_all(dest);
+        }
+    }
+}
+
+/// What a site's layouts get in their context besides `page`, once its
+/// pages are compiled.
+struct SiteParts<'a> {
+    content: &'a [Content],
+    /// The site's data files
+    data: &'a serde_json::Map<String, serde_json::Value>,
+    glossary: Option<&'a Glossary>,
+    docs_versions: &'a [versions::DocsVersion],
+    short_urls: &'a [short_urls::ShortUrl],
+}
+
+/// The context every layout is rendered with, `page` aside, for builds and
+/// `roxy test-templates` alike. The functions that need the pages or the
+/// context are registered with `templates` too.
+fn site_context(
+    opts: &Options,
+    templates: &mut Tera,
+    parts: SiteParts,
+    site_theme: Option<&ThemePackage>,
+    build_info: &BuildInfo,
+) -> Context {
+    let content_map = compile_content_map(parts.content);
+    let mut context = Context::new();
+    context.insert(
+        "data",
+        &data_files::with_pages(page_data::slim(&content_map, opts.page_data), parts.data),
+    );
+    context.insert(
+        "languages",
+        &page_data::slim(&compile_language_map(parts.content), opts.page_data),
+    );
+    context.insert("site", &opts.site_values());
+    context.insert("env", &opts.env);
+    context.insert("build", build_info);
+    if let Some(glossary) = parts.glossary {
+        context.insert("glossary", &glossary.terms);
+    }
+    context.insert("docs_versions", parts.docs_versions);
+    context.insert("short_urls", parts.short_urls);
+    context.insert(
+        "taxonomies",
+        &taxonomies::collect(parts.content, &opts.taxonomies),
+    );
+    if let Some(site_theme) = site_theme {
+        context.insert("theme", &site_theme.metadata);
+    }
+    templates.register_function("get_page", functions::GetPage::new(parts.content));
+    // cached fragments are rendered with the site's context, so it's only
+    // registered once that is complete
+    functions::Cache::register(templates, context.clone());
+    context
+}
+
+fn prepare(
+    opts: &Options,
+    templates: &mut Tera,
+    site_theme: Option<&ThemePackage>,
+    build_info: &BuildInfo,
+) -> io::Result<Site> {
+    let theme = highlight_theme(&opts.theme);
+    let replacements = replacements::load(&opts.config)?;
+    let markdown = markdown::load(&opts.config)?;
+    let lint_rules = match opts.lint {
+        true => Some(lint::load(&opts.config)?),
+        false => None,
+    };
+    let permalinks = permalinks::load(&opts.config)?;
+    let glossary = glossary::load(&opts.data)?;
+    let bibliography = citations::load(&opts.data)?;
+    let data = data_files::load(&opts.data)?;
+    let transforms = Transforms {
+        replacements: &replacements,
+        markdown: &markdown,
+        permalinks: &permalinks,
+        glossary: glossary.as_ref(),
+        bibliography: bibliography.as_ref(),
+    };
+    let cache = opts.build_cache(site_theme)?;
+    let images = Arc::new(opts.images(site_theme)?);
+    templates.register_function("image", images::Image::new(images.clone()));
+    let languages = opts.languages()?;
+    let settings = PageSettings {
+        default_language: &opts.default_language,
+        languages: &languages,
+        rtl_languages: &opts.rtl_languages,
+        transforms: &transforms,
+        require_alt: opts.require_alt,
+        lint: lint_rules.as_ref(),
+        highlight_cache: &opts.highlight_cache(),
+        build_cache: &cache,
+    };
+
+    let mut content = compile_content(&opts.content, templates, &theme, &settings)?;
+    apply_cascade(&mut content, &opts.default_language, &opts.rtl_languages);
+
+    let mut docs_versions = Vec::new();
+    let mut version_dirs = Vec::new();
+    for (name, rev) in opts.docs_version.iter() {
+        let section = Path::new(&opts.content).join(&opts.docs_section);
+        let dest = versions::temp_dir(&opts.output, name);
+        interrupt::remove_on_interrupt(opts, &dest);
+        let dir = versions::checkout(&section.to_string_lossy(), rev, &dest)?;
+        let dir = dir.to_string_lossy().to_string();
+
+        let mut version_content = compile_content(&dir, templates, &theme, &settings)?;
+        apply_cascade(
+            &mut version_content,
+            &opts.default_language,
+            &opts.rtl_languages,
+        );
+
+        // each version is built under its own prefix, e.g. /v1/
+        for page in version_content.iter_mut() {
+            page.path = Path::new(name)
+                .join(&page.path)
+                .to_string_lossy()
+                .to_string();
+            page.slug = format!("/{name}{}", page.slug.trim_end_matches('/'));
+            if let Some(permalink) = &mut page.permalink {
+                *permalink = format!("/{name}{permalink}");
+            }
+            page.frontmatter
+                .0
+                .insert("docs_version".to_string(), name.to_string());
+            for resource in page.resources.iter_mut() {
+                resource.url = format!("/{name}{}", resource.url);
+            }
+        }
+
+        content.extend(version_content);
+        docs_versions.push(versions::DocsVersion::new(name, rev));
+        version_dirs.push((name.to_string(), dir, dest));
+    }
+
+    if let Some(spec) = &opts.openapi {
+        let spec = openapi::load(spec)?;
+        let lang_dir = i18n::text_direction(&opts.default_language, &opts.rtl_languages);
+        content.extend(openapi::pages(
+            &spec,
+            &opts.openapi_prefix,
+            &opts.openapi_layout,
+            &opts.default_language,
+            lang_dir,
+        ));
+    }
+
+    content.retain(|page| in_environment(page, &opts.env));
+    let now = schedule::now();
+    content.retain(|page| opts.drafts || !is_draft(page));
+    // pages dated in the future still schedule the build that publishes them
+    let next_rebuild = schedule::next_rebuild(&content, &now);
+    content.retain(|page| opts.future || !is_future(page, &now));
+    content.retain(|page| !is_expired(page, &now));
+
+    // links between pages are only followed once every page is compiled, so
+    // a page can link to any other wherever it comes
+    let slugs = links::slugs(&content);
+    for page in content.iter_mut() {
+        links::resolve(page, &slugs);
+    }
+
+    tree::link_reading_order(&mut content);
+    languages::link_translations(&mut content, &languages);
+    let short_urls = short_urls::assign(&mut content, &opts.short_url_prefix);
+    let feed_settings = feeds::load(&opts.config)?;
+    feeds::link_pages(
+        &mut content,
+        &opts.site_values(),
+        &opts.feed_format,
+        &languages,
+        &feed_settings,
+    );
+
+    let parts = SiteParts {
+        content: &content,
+        data: &data,
+        glossary: glossary.as_ref(),
+        docs_versions: &docs_versions,
+        short_urls: &short_urls,
+    };
+    let context = site_context(opts, templates, parts, site_theme, build_info);
+
+    Ok(Site {
+        content,
+        context,
+        replacements,
+        markdown,
+        permalinks,
+        glossary,
+        bibliography,
+        short_urls,
+        languages,
+        cache,
+        version_dirs,
+        minify: opts.minify,
+        csp: csp::load(&opts.config)?,
+        preload: preload::Preload::new(static_sources(opts, site_theme), opts.minify),
+        escaping: escaping::Escaping::new(&opts.autoescape, &opts.safe_html),
+        feed_links: opts.feed_links,
+        feeds: feed_settings,
+        images,
+        next_rebuild,
+        content_dir: PathBuf::from(&opts.content),
+        sandbox: opts.sandbox,
+    })
+}
+
+fn debug_context(
+    opts: &Options,
+    templates: &mut Tera,
+    site_theme: Option<&ThemePackage>,
+    build_info: &BuildInfo,
+    file: &str,
+) -> io::Result<()> {
+    let site = prepare(opts, templates, site_theme, build_info)?;
+    site.remove_checkouts();
+
+    let relative = Path::new(file)
+        .strip_prefix(&opts.content)
+        .unwrap_or(Path::new(file));
+    let content = match site
+        .content
+        .iter()
+        .find(|content| Path::new(&content.path) == relative)
+    {
+        Some(content) => content,
+        None => {
+            Diagnostic::error(format!("{file} isn't a page of this build")).print();
+            return Err(diagnostics::reported(Failure::Content));
+        }
+    };
+
+    match render_context(content, &site) {
+        Ok(context) => {
+            let json = serde_json::to_string_pretty(&context.into_json())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            diagnostics::info(json);
+            Ok(())
+        }
+        Err(err) => {
+            Diagnostic::error(format!("Couldn't build the context of {file}"))
+                .failure(Failure::Template)
+                .in_file(file)
+                .note(err.to_string())
+                .causes(&err)
+                .print();
+            Err(diagnostics::reported(Failure::Template))
+        }
+    }
+}
+
+fn write_site(
+    opts: &Options,
+    mut templates: Tera,
+    site_theme: Option<&ThemePackage>,
+    build_info: &BuildInfo,
+) -> io::Result<Vec<(lock::RenderedPage, bool)>> {
+    let site = prepare(opts, &mut templates, site_theme, build_info)?;
+    let content = &site.content;
+
+    let pages = lock::outputs(content, &opts.output, opts.locked_before.as_deref());
+
+    if let Some(path) = &opts.url_map {
+        url_map::write(path, &url_map::compile(content))?;
+    }
+
+    let taxonomies = taxonomies::collect(content, &opts.taxonomies);
+    let summary = create_files(
+        &opts.output,
+        &templates,
+        &site,
+        (&taxonomies, &opts.taxonomy_layout),
+        opts.strict,
+    )?;
+    site.cache.save()?;
+    schedule::write(&opts.output, site.next_rebuild.as_ref())?;
+    if opts.search_index {
+        search::write_index(&opts.output, content)?;
+    }
+    feeds::write(
+        &opts.output,
+        &compile_content_map(content),
+        &opts.site_values(),
+        &opts.feed_format,
+        &site.languages,
+        &site.feeds,
+    )?;
+    sitemap::write(
+        &opts.output,
+        content,
+        &opts.site_values(),
+        &opts.content,
+        opts.robots,
+        &site.languages,
+    )?;
+    if let Some(path) = &opts.manifest {
+        let (manifest, problems) = lock::check(&pages, &lock::load(path)?);
+        if opts.check_locked && !problems.is_empty() {
+            for problem in problems.iter() {
+                Diagnostic::error(problem).print();
+            }
+            return Err(diagnostics::reported(Failure::Content));
+        }
+        lock::save(path, &manifest)?;
+    }
+    short_urls::write(&opts.output, &site.short_urls)?;
+    // the site's own static files take the place of the sprite sheet
+    icons::load(&opts.icons)?.write(&opts.output)?;
+    if let Some(site_theme) = site_theme {
+        let _ = copy_static(
+            &site_theme.static_files().to_string_lossy(),
+            &opts.output,
+            opts.minify,
+            &site.images,
+        );
+    }
+    let _ = copy_static(&opts.content, &opts.output, opts.minify, &site.images);
+    if let Some(player) = &opts.asciinema_player {
+        functions::Asciinema::copy_assets(Path::new(player), Path::new(&opts.output))?;
+    }
+    for (name, dir, _) in site.version_dirs.iter() {
+        let out_dir = Path::new(&opts.output).join(name);
+        let _ = copy_static(dir, &out_dir.to_string_lossy(), opts.minify, &site.images);
+    }
+    site.remove_checkouts();
+    if opts.subset_fonts {
+        let fonts = fonts::subset(&opts.output, &opts.default_language)?;
+        diagnostics::detail(format!("Subset {fonts} fonts"));
+    }
+    if opts.headers {
+        let own = headers::own(&opts.output, content);
+        let headers = headers::collect(&opts.output, |url, html| {
+            site.page_headers(html, own.get(url))
+        })?;
+        headers::write(&opts.output, &headers)?;
+    }
+
+    if opts.check_links {
+        let site = opts.site_values();
+        let base_url = site.get("base_url").and_then(|url| url.as_str());
+        let mut problems = link_check::check(&opts.output, base_url);
+        problems.extend(link_check::check_environment(
+            &opts.output,
+            &opts.env,
+            base_url,
+            &opts.base_urls,
+        ));
+        if !problems.is_empty() {
+            for problem in problems {
+                problem.print();
+            }
+            return Err(diagnostics::reported(Failure::Content));
+        }
+    }
+    if opts.checksums {
+        checksums::write(&opts.output)?;
+    }
+
+    diagnostics::info(summary);
+    diagnostics::info(format!(
+        "Output files at {}",
+        Path::new(&opts.output)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(&opts.output))
+            .to_string_lossy()
+    ));
+
+    Ok(pages)
+}
+
+/// Lints the markdown of every page in the content folder, failing when
+/// the rules find any problem.
+fn lint_content(opts: &Options) -> io::Result<()> {
+    opts.check_config()?;
+    let rules = lint::load(&opts.config)?;
+    let markdown = markdown::load(&opts.config)?;
+    let pattern = format!("{}/**/*.md", glob::Pattern::escape(&opts.content));
+    let (mut pages, mut problems) = (0, 0);
+    for entry in glob(&pattern).into_iter().flatten().flatten() {
+        if !entry.is_file() || is_hidden(&entry) {
+            continue;
+        }
+        let source = fs::read(&entry)?;
+        let file = entry.to_string_lossy();
+        let mut reader = Cursor::new(source.as_slice());
+        let (frontmatter, _) = read_frontmatter(&mut reader, &file)?;
+        let options = markdown
+            .for_page(&frontmatter)
+            .unwrap_or_else(|_| markdown.clone())
+            .parser_options();
+        let found = lint::check(
+            &file,
+            &String::from_utf8_lossy(&source),
+            reader.position() as usize,
+            &rules,
+            options,
+        );
+        for problem in found.iter() {
+            problem.print();
+        }
+        pages += 1;
+        problems += found.len();
+    }
+
+    if problems > 0 {
+        diagnostics::info(format!("{problems} problems in {pages} pages"));
+        return Err(diagnostics::reported(Failure::Content));
+    }
+    diagnostics::info(format!("No problems in {pages} pages"));
+    Ok(())
+}
+
+/// Checks the config and compiles the site without writing any of it, for
+/// serving from memory.
+fn load_site(opts: &Options) -> io::Result<(Tera, Site, Option<ThemePackage>)> {
+    opts.check_config()?;
+    let theme = opts.theme_package()?;
+    let build_info = opts.build_info(theme.as_ref())?;
+    let mut templates = setup_templates(opts, theme.as_ref(), &build_info)?;
+    let site = prepare(opts, &mut templates, theme.as_ref(), &build_info)?;
+    Ok((templates, site, theme))
+}
+
+/// Checks the config and builds the site, returning its pages.
+fn build_site(opts: &Options) -> io::Result<Vec<(lock::RenderedPage, bool)>> {
+    opts.check_config()?;
+    let theme = opts.theme_package()?;
+    let build_info = opts.build_info(theme.as_ref())?;
+    let templates = setup_templates(opts, theme.as_ref(), &build_info)?;
+    write_site(opts, templates, theme.as_ref(), &build_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the frontmatter of `text`, how many problems it has, and the rest of
+    // the file after it
+    fn read(text: &str) -> (Frontmatter, usize, String) {
+        let mut reader = Cursor::new(text.as_bytes());
+        let (frontmatter, problems) = read_frontmatter(&mut reader, "page.md").unwrap();
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        (frontmatter, problems.len(), body)
+    }
+
+    fn field<'a>(frontmatter: &'a Frontmatter, key: &str) -> Option<&'a str> {
+        frontmatter.0.get(key).map(String::as_str)
+    }
+
+    #[test]
+    fn yaml_lists_dont_close_frontmatter() {
+        let (frontmatter, problems, body) =
+            read("---\ntitle: Lists\ntags:\n- rust\n-  web\nauthor: fox\n---\n# Body\n");
+        assert_eq!(problems, 0);
+        assert_eq!(
+            frontmatter.list("tags"),
+            Some(vec!["rust".to_string(), "web".to_string()])
+        );
+        assert_eq!(field(&frontmatter, "author"), Some("fox"));
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn dashes_in_values_dont_close_frontmatter() {
+        let (frontmatter, problems, body) = read(
+            "---\nrule: ----\nnote: |\n  ---\n  indented\nsummary: \"--- dashes\"\nlast: done\n---\nbody\n",
+        );
+        assert_eq!(problems, 0);
+        assert_eq!(field(&frontmatter, "rule"), Some("----"));
+        assert_eq!(field(&frontmatter, "note"), Some("---\nindented\n"));
+        assert_eq!(field(&frontmatter, "summary"), Some("--- dashes"));
+        assert_eq!(field(&frontmatter, "last"), Some("done"));
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn lines_starting_with_the_delimiter_dont_close_frontmatter() {
+        // not YAML, so read as `key: value` lines, but all of them
+        let (frontmatter, problems, body) =
+            read("---\ntitle: Rules\n----\n--- trailing\nlast: done\n---\nbody\n");
+        assert!(problems > 0);
+        assert_eq!(field(&frontmatter, "title"), Some("Rules"));
+        assert_eq!(field(&frontmatter, "last"), Some("done"));
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn delimiters_can_have_trailing_spaces_and_crlf() {
+        let (frontmatter, problems, body) = read("---  \r\ntitle: Windows\r\n--- \t\r\nbody\r\n");
+        assert_eq!(problems, 0);
+        assert_eq!(field(&frontmatter, "title"), Some("Windows"));
+        assert_eq!(body, "body\r\n");
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let (frontmatter, problems, body) = read("\u{feff}---\ntitle: Marked\n---\nbody\n");
+        assert_eq!(problems, 0);
+        assert_eq!(field(&frontmatter, "title"), Some("Marked"));
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn yaml_end_of_document() {
+        let (frontmatter, problems, body) = read("---\ntitle: Dots\n...\nbody\n");
+        assert_eq!(problems, 0);
+        assert_eq!(field(&frontmatter, "title"), Some("Dots"));
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn toml_is_only_closed_by_plus_signs() {
+        let (frontmatter, problems, body) =
+            read("+++\ntitle = \"Toml\"\nrule = \"\"\"\n---\n...\n\"\"\"\n+++\nbody\n");
+        assert_eq!(problems, 0);
+        assert_eq!(field(&frontmatter, "title"), Some("Toml"));
+        assert_eq!(field(&frontmatter, "rule"), Some("---\n...\n"));
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn key_value_lines_are_coerced() {
+        // not YAML, so read as `key: value` lines and coerced
+        let (frontmatter, problems, _) = read(
+            "---\ncount: 3\ndraft: true\ndate: 2024-05-01\ntags: a, b\nids: [1, two]\n- not yaml\n---\n",
+        );
+        assert!(problems > 0);
+        let fields = serde_json::to_value(&frontmatter).unwrap();
+        assert_eq!(fields["count"], serde_json::json!(3));
+        assert_eq!(fields["draft"], serde_json::json!(true));
+        // dates stay text, for the `date` filter to read
+        assert_eq!(fields["date"], serde_json::json!("2024-05-01"));
+        assert_eq!(fields["tags"], serde_json::json!(["a", "b"]));
+        assert_eq!(fields["ids"], serde_json::json!([1, "two"]));
+    }
+
+    #[test]
+    fn values_that_dont_coerce_stay_text() {
+        for value in ["007", "+1", "1.5", "-", "yes", "True", "1, 2"] {
+            assert_eq!(
+                coerce("field", value),
+                tera::Value::String(value.to_string())
+            );
+        }
+        assert_eq!(
+            coerce("field", "\"42\""),
+            tera::Value::String("42".to_string())
+        );
+        assert_eq!(
+            coerce("field", "'true'"),
+            tera::Value::String("true".to_string())
+        );
+    }
+
+    #[test]
+    fn typed_values_are_kept() {
+        let (frontmatter, problems, _) =
+            read("---\ncount: 3\nversion: \"3\"\ndraft: false\ndate: 2024-05-01\n---\n");
+        assert_eq!(problems, 0);
+        let fields = serde_json::to_value(&frontmatter).unwrap();
+        assert_eq!(fields["count"], serde_json::json!(3));
+        assert_eq!(fields["version"], serde_json::json!("3"));
+        assert_eq!(fields["draft"], serde_json::json!(false));
+        assert_eq!(fields["date"], serde_json::json!("2024-05-01"));
+
+        let (frontmatter, problems, _) = read("+++\ndate = 2024-05-01\nweight = 2\n+++\n");
+        assert_eq!(problems, 0);
+        let fields = serde_json::to_value(&frontmatter).unwrap();
+        assert_eq!(fields["date"], serde_json::json!("2024-05-01"));
+        assert_eq!(fields["weight"], serde_json::json!(2));
+    }
+
+    #[test]
+    fn empty_frontmatter() {
+        let (frontmatter, problems, body) = read("---\n---\nbody\n");
+        assert_eq!(problems, 0);
+        assert!(frontmatter.0.is_empty());
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn unclosed_frontmatter_is_reported() {
+        let (frontmatter, problems, body) = read("---\ntitle: Open\n- not yaml\n");
+        // the unclosed warning, and the fallback to `key: value` lines
+        assert!(problems >= 1);
+        assert_eq!(field(&frontmatter, "title"), Some("Open"));
+        assert_eq!(body, "");
+
+        let (frontmatter, problems, _) = read("---\ntitle: Open\n");
+        assert_eq!(problems, 1);
+        assert_eq!(field(&frontmatter, "title"), Some("Open"));
+    }
+
+    #[test]
+    fn no_frontmatter() {
+        for text in [
+            "# Title\n---\nnot: frontmatter\n",
+            "----\ntitle: x\n----\n",
+            " ---\n",
+        ] {
+            let (frontmatter, problems, body) = read(text);
+            assert_eq!(problems, 0);
+            assert!(frontmatter.0.is_empty(), "{text:?}");
+            assert_eq!(body, text);
+        }
+    }
+}
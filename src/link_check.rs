@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use glob::glob;
+use regex::Regex;
+
+use crate::diagnostics::{Diagnostic, Failure};
+
+// `%20` and the like in a url, as the characters of the file name
+fn decode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// the path in the site `url` points at, if it's a link to the site itself
+fn internal<'u>(url: &'u str, base_url: Option<&str>) -> Option<&'u str> {
+    let url = match base_url.and_then(|base_url| url.strip_prefix(base_url)) {
+        Some(path) if path.is_empty() || path.starts_with(['/', '?', '#']) => path,
+        _ => url,
+    };
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    // `//cdn.example.com`, `https:`, `mailto:` and `data:` aren't the site's
+    let scheme = path
+        .split('/')
+        .next()
+        .is_some_and(|part| part.contains(':'));
+    if url.starts_with('#') || url.starts_with("//") || scheme || url.contains("{{") {
+        return None;
+    }
+    Some(path)
+}
+
+// where `path`, linked from the page at `page` in `output`, is written
+fn target(output: &Path, page: &Path, path: &str) -> Option<PathBuf> {
+    let path = decode(path);
+    let mut target = match path.strip_prefix('/') {
+        Some(_) => output.to_path_buf(),
+        None => page.parent()?.to_path_buf(),
+    };
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::ParentDir => {
+                // above the output folder is never part of the site
+                if !target.pop() || !target.starts_with(output) {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(target)
+}
+
+fn exists(target: &Path) -> bool {
+    target.is_file() || target.join("index.html").is_file()
+}
+
+/// Checks that every `href` and `src` in the html written to `output` that
+/// points at the site itself leads to a page or file that was written,
+/// reporting each one that doesn't. Links to `base_url` count as the site's.
+pub fn check(output: &str, base_url: Option<&str>) -> Vec<Diagnostic> {
+    let output = Path::new(output.trim_end_matches('/'));
+    let base_url = base_url.map(|url| url.trim_end_matches('/'));
+    let pattern = Regex::new(r#"(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+
+    let mut problems = Vec::new();
+    let pages = format!(
+        "{}/**/*.html",
+        glob::Pattern::escape(&output.to_string_lossy())
+    );
+    for page in glob(&pages).into_iter().flatten().flatten() {
+        let html = match fs::read_to_string(&page) {
+            Ok(html) => html,
+            Err(_) => continue,
+        };
+        for c in pattern.captures_iter(&html) {
+            let url = c.get(1).or_else(|| c.get(2)).map_or("", |url| url.as_str());
+            let Some(path) = internal(url.trim(), base_url) else {
+                continue;
+            };
+            if path.is_empty() {
+                continue;
+            }
+            if !target(output, &page, path).is_some_and(|target| exists(&target)) {
+                problems.push(
+                    Diagnostic::error(format!("Broken link to {url}"))
+                        .failure(Failure::Content)
+                        .in_file(&page.to_string_lossy())
+                        .note("no page or static file was written there"),
+                );
+            }
+        }
+    }
+    problems
+}
+
+// urls that only work on the machine building the site
+const LOCAL: &str = r"https?://(?:localhost|127\.0\.0\.1|0\.0\.0\.0|\[::1\])(?::[0-9]+)?";
+
+// the files of `output` that can hold links: pages, feeds and the like
+fn linking_files(output: &Path) -> Vec<PathBuf> {
+    let files = format!("{}/**/*", glob::Pattern::escape(&output.to_string_lossy()));
+    glob(&files)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("html" | "xml" | "json" | "txt")
+            )
+        })
+        .collect()
+}
+
+/// Checks that nothing written to `output` by a build for `env` has urls
+/// of the other environments in `base_urls`, or of the machine building
+/// it unless `base_url` is one, like a local link left in a page or a
+/// staging url pasted into the config, reporting each one found.
+pub fn check_environment(
+    output: &str,
+    env: &str,
+    base_url: Option<&str>,
+    base_urls: &BTreeMap<String, String>,
+) -> Vec<Diagnostic> {
+    let output = Path::new(output.trim_end_matches('/'));
+    let base_url = base_url.map(|url| url.trim_end_matches('/'));
+    let url_end = r#"[^\s"'<>)]*"#;
+
+    let mut wrong: Vec<(Regex, String)> = Vec::new();
+    for (name, url) in base_urls.iter() {
+        let url = url.trim_end_matches('/');
+        // environments sharing this build's url aren't wrong
+        if name == env || url.is_empty() || base_url.is_some_and(|base| base.starts_with(url)) {
+            continue;
+        }
+        let pattern = Regex::new(&format!("{}(?:[/?#]{url_end})?", regex::escape(url))).unwrap();
+        wrong.push((pattern, format!("links to the {name} site")));
+    }
+    let local = Regex::new(LOCAL).unwrap();
+    if !base_url.is_some_and(|base| local.is_match(base)) {
+        let pattern = Regex::new(&format!("{LOCAL}{url_end}")).unwrap();
+        wrong.push((
+            pattern,
+            "only works on the machine that built it".to_string(),
+        ));
+    }
+
+    let mut problems = Vec::new();
+    for file in linking_files(output) {
+        let Ok(text) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let mut found = BTreeSet::new();
+        for (pattern, why) in wrong.iter() {
+            for url in pattern.find_iter(&text) {
+                if found.insert(url.as_str()) {
+                    problems.push(
+                        Diagnostic::error(format!("{} {why}", url.as_str()))
+                            .failure(Failure::Content)
+                            .in_file(&file.to_string_lossy())
+                            .note(format!("this is the {env} build")),
+                    );
+                }
+            }
+        }
+    }
+    problems
+}
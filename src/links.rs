@@ -0,0 +1,101 @@
+use std::{collections::HashMap, path::Path};
+
+use pulldown_cmark::{CowStr, Event, Tag};
+use regex::{Captures, Regex};
+
+use crate::{diagnostics::Diagnostic, Content};
+
+// links to other pages by their source, from the content folder
+const PREFIX: &str = "@/";
+
+fn key(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+// `../docs/setup.md#install` from `blog/post.md` as `@/docs/setup.md#install`,
+// if it's a link to another page's markdown
+fn mark_link(dest: &str, file_path: &str) -> Option<String> {
+    if dest.starts_with(PREFIX) {
+        return None;
+    }
+    let (path, fragment) = match dest.find('#') {
+        Some(i) => dest.split_at(i),
+        None => (dest, ""),
+    };
+    if !path.ends_with(".md") || path.starts_with('/') || path.contains(':') {
+        return None;
+    }
+
+    let parent = Path::new(file_path).parent().unwrap_or(Path::new(""));
+    let mut parts: Vec<String> = key(&parent.to_string_lossy())
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    Some(format!("{PREFIX}{}{fragment}", parts.join("/")))
+}
+
+/// `events` of the page at `file_path` (in the content folder), with links
+/// to other pages' markdown relative to it made relative to the content
+/// folder, to be pointed at the page with `resolve` once every page is
+/// compiled.
+pub fn mark<'a, I: Iterator<Item = Event<'a>>>(events: I, file_path: &str) -> Vec<Event<'a>> {
+    events
+        .map(|event| match event {
+            Event::Start(Tag::Link(kind, dest, title)) => match mark_link(&dest, file_path) {
+                Some(dest) => Event::Start(Tag::Link(kind, CowStr::from(dest), title)),
+                None => Event::Start(Tag::Link(kind, dest, title)),
+            },
+            event => event,
+        })
+        .collect()
+}
+
+/// Where each page is, by its path in the content folder.
+pub fn slugs(contents: &[Content]) -> HashMap<String, String> {
+    contents
+        .iter()
+        .map(|content| (key(&content.path), content.slug.to_string()))
+        .collect()
+}
+
+/// Points the links to other pages in `content`, like `@/docs/setup.md`, at
+/// where those pages are in `slugs`. A page of a docs version links to the
+/// same version's page first. Links to pages that aren't in the site are
+/// reported and left as they are.
+pub fn resolve(content: &mut Content, slugs: &HashMap<String, String>) {
+    if !content.content.contains("\"@/") {
+        return;
+    }
+    let pattern = Regex::new(r##"href="@/([^"#]*)(#[^"]*)?""##).unwrap();
+    let version = content.frontmatter.0.get("docs_version");
+    let path = content.path.as_str();
+
+    let resolved = pattern.replace_all(&content.content, |c: &Captures| {
+        let target = &c[1];
+        let fragment = c.get(2).map_or("", |fragment| fragment.as_str());
+        let slug = version
+            .and_then(|version| slugs.get(&format!("{version}/{target}")))
+            .or_else(|| slugs.get(target));
+        match slug {
+            Some(slug) => format!("href=\"{slug}{fragment}\""),
+            None => {
+                Diagnostic::warning(format!("Link to {target}, which isn't a page"))
+                    .in_file(path)
+                    .note("pages are linked by their path in the content folder")
+                    .print();
+                c[0].to_string()
+            }
+        }
+    });
+    content.content = resolved.to_string();
+}
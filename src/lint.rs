@@ -0,0 +1,353 @@
+use std::{collections::BTreeMap, io};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::diagnostics::{Diagnostic, Failure};
+
+/// What a lint rule's problems are reported as.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Warning,
+    /// Fails the build
+    Error,
+}
+
+/// The level of each lint rule, from `[lint_rules]` in the site config,
+/// like `bare_urls = "error"` or `line_length = "warning"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Urls written as text, which markdown doesn't make links
+    pub bare_urls: Level,
+    /// Headings more than one level below the heading before them
+    pub heading_increment: Level,
+    /// Headings with the same text as one before them on the page
+    pub duplicate_headings: Level,
+    /// Headings ending in `.`, `,`, `;`, `:` or `!`
+    pub heading_punctuation: Level,
+    /// Lines longer than `max_line_length`, outside code blocks
+    pub line_length: Level,
+    pub max_line_length: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            bare_urls: Level::Warning,
+            heading_increment: Level::Warning,
+            duplicate_headings: Level::Warning,
+            heading_punctuation: Level::Warning,
+            line_length: Level::Off,
+            max_line_length: 100,
+        }
+    }
+}
+
+/// Reads the `[lint_rules]` table of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<Rules> {
+    crate::config::table(config, "lint_rules")
+}
+
+/// Whether `file` gets linted: only markdown does.
+pub fn is_markdown(file: &str) -> bool {
+    file.to_ascii_lowercase().ends_with(".md")
+}
+
+/// A heading of the page, as the rules see it.
+struct Heading {
+    level: usize,
+    text: String,
+    /// Where it starts in the body
+    offset: usize,
+}
+
+/// The problems the enabled `rules` find in the markdown of `file`, whose
+/// text is `source` and whose body (after the frontmatter) starts at byte
+/// `body_start`. The markdown is read with `options`, the page's own.
+pub fn check(
+    file: &str,
+    source: &str,
+    body_start: usize,
+    rules: &Rules,
+    options: Options,
+) -> Vec<Diagnostic> {
+    let body_start = body_start.min(source.len());
+    // shortcodes, template tags and comments are blanked out, so urls given
+    // to them aren't bare, keeping every offset where it was. A comment
+    // starts with a space or goes no further than its line without a `}`,
+    // so a heading's `{#id}` isn't taken for one.
+    let tags = Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}|\{#-?\s.*?#\}|\{#[^}\n]*?#\}").unwrap();
+    let body = tags.replace_all(&source[body_start..], |c: &regex::Captures| {
+        c[0].chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect::<String>()
+    });
+    let urls = Regex::new(r"\b(?:https?://|www\.)[^\s<>()\[\]]+").unwrap();
+
+    let mut problems = Vec::new();
+    let mut report = |level: Level, rule: &str, message: String, offset: usize, len: usize| {
+        let diagnostic = match level {
+            Level::Off => return,
+            Level::Warning => Diagnostic::warning(message),
+            Level::Error => Diagnostic::error(message).failure(Failure::Content),
+        };
+        problems.push(
+            diagnostic
+                .at(file, source, body_start + offset, len)
+                .note(format!("`{rule}` in `[lint_rules]` of the site config")),
+        );
+    };
+
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut heading: Option<Heading> = None;
+    // links and code blocks, which urls can be in
+    let mut in_link = 0;
+    let mut in_code = false;
+    let mut code_blocks = Vec::new();
+    for (event, range) in Parser::new_ext(&body, options).into_offset_iter() {
+        match &event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                heading = Some(Heading {
+                    level: *level as usize,
+                    text: String::new(),
+                    offset: range.start,
+                })
+            }
+            Event::End(Tag::Heading(..)) => headings.extend(heading.take()),
+            Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => in_link += 1,
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => in_link -= 1,
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code = true;
+                code_blocks.push(range);
+            }
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = &mut heading {
+                    heading.text.push_str(text);
+                }
+                let is_text = matches!(event, Event::Text(_));
+                if is_text && in_link == 0 && !in_code {
+                    for url in urls.find_iter(text) {
+                        // the text's own offset, unless escapes or entities
+                        // moved it
+                        let offset = body[range.clone()]
+                            .find(url.as_str())
+                            .map_or(range.start, |i| range.start + i);
+                        report(
+                            rules.bare_urls,
+                            "bare_urls",
+                            format!("{} isn't a link", url.as_str()),
+                            offset,
+                            url.len(),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut previous: Option<usize> = None;
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    for heading in headings.iter() {
+        let text = heading.text.trim();
+        if let Some(previous) = previous.filter(|previous| heading.level > previous + 1) {
+            report(
+                rules.heading_increment,
+                "heading_increment",
+                format!(
+                    "Heading level {} comes right after level {previous}",
+                    heading.level
+                ),
+                heading.offset,
+                0,
+            );
+        }
+        previous = Some(heading.level);
+
+        if seen.insert(text.to_lowercase(), heading.offset).is_some() {
+            report(
+                rules.duplicate_headings,
+                "duplicate_headings",
+                format!("The page already has a heading `{text}`"),
+                heading.offset,
+                0,
+            );
+        }
+
+        if let Some(last) = text.chars().last().filter(|c| ".,;:!".contains(*c)) {
+            report(
+                rules.heading_punctuation,
+                "heading_punctuation",
+                format!("Heading `{text}` ends in `{last}`"),
+                heading.offset,
+                0,
+            );
+        }
+    }
+
+    if rules.line_length != Level::Off {
+        let mut offset = 0;
+        for line in body.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            let line = line.trim_end_matches(['\r', '\n']);
+            let length = line.chars().count();
+            if length <= rules.max_line_length {
+                continue;
+            }
+            if code_blocks.iter().any(|block| block.contains(&start)) {
+                continue;
+            }
+            // a long url or the like can't be wrapped
+            let over: String = line.chars().skip(rules.max_line_length).collect();
+            if !over.contains(char::is_whitespace) {
+                continue;
+            }
+            report(
+                rules.line_length,
+                "line_length",
+                format!(
+                    "Line is {length} characters long, more than {}",
+                    rules.max_line_length
+                ),
+                start,
+                length,
+            );
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_rule() -> Rules {
+        Rules {
+            line_length: Level::Warning,
+            max_line_length: 60,
+            ..Rules::default()
+        }
+    }
+
+    fn lint(markdown: &str, rules: &Rules) -> Vec<Diagnostic> {
+        check(
+            "page.md",
+            markdown,
+            0,
+            rules,
+            Options::ENABLE_HEADING_ATTRIBUTES,
+        )
+    }
+
+    fn messages(markdown: &str) -> Vec<String> {
+        lint(markdown, &every_rule())
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect()
+    }
+
+    #[test]
+    fn bare_urls() {
+        assert_eq!(
+            messages("See https://example.com today.\n"),
+            ["https://example.com isn't a link"]
+        );
+        assert_eq!(
+            messages("Or www.example.com\n"),
+            ["www.example.com isn't a link"]
+        );
+    }
+
+    #[test]
+    fn urls_that_arent_bare() {
+        assert!(messages("[x](https://a.com) <https://b.com> `https://c.com`\n").is_empty());
+        assert!(messages("```\nhttps://a.com\n```\n").is_empty());
+        assert!(messages("{{ video(src=\"https://a.com\") }}\n").is_empty());
+        assert!(messages("{% set url = \"https://a.com\" %}\n").is_empty());
+        assert!(messages("{# https://a.com\n  over lines #}\n").is_empty());
+        assert!(messages("{#https://a.com#}\n").is_empty());
+    }
+
+    #[test]
+    fn heading_ids_arent_comments() {
+        assert_eq!(
+            messages("# Top {#top}\n\nhttps://a.com\n\n{# note #}\n"),
+            ["https://a.com isn't a link"]
+        );
+    }
+
+    #[test]
+    fn heading_increment() {
+        assert_eq!(
+            messages("# One\n### Three\n"),
+            ["Heading level 3 comes right after level 1"]
+        );
+        assert!(messages("# One\n## Two\n# One again\n").is_empty());
+    }
+
+    #[test]
+    fn duplicate_headings() {
+        assert_eq!(
+            messages("## Setup\n## setup\n"),
+            ["The page already has a heading `setup`"]
+        );
+    }
+
+    #[test]
+    fn heading_punctuation() {
+        assert_eq!(messages("## Done.\n"), ["Heading `Done.` ends in `.`"]);
+        assert!(messages("## Done?\n").is_empty());
+    }
+
+    #[test]
+    fn line_length() {
+        let line = ["word"; 13].join(" ");
+        assert_eq!(
+            messages(&line),
+            ["Line is 64 characters long, more than 60"]
+        );
+        // what's over can't be wrapped, or is code
+        let url = format!("see <https://example.com/{}>", "a/".repeat(25));
+        assert!(messages(&url).is_empty());
+        assert!(messages(&format!("```\n{line}\n```\n")).is_empty());
+        assert!(lint(&line, &Rules::default()).is_empty());
+    }
+
+    #[test]
+    fn levels() {
+        let rules = Rules {
+            bare_urls: Level::Error,
+            heading_punctuation: Level::Off,
+            ..Rules::default()
+        };
+        let problems = lint("## Links:\n\nhttps://a.com\n", &rules);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, crate::diagnostics::Severity::Error);
+    }
+
+    #[test]
+    fn offsets_are_in_the_file() {
+        let source = "---\ntitle: Page\n---\n# One\n\nSee https://a.com\n### Three\n";
+        let body_start = source.find("# One").unwrap();
+        let problems: Vec<String> = check(
+            "page.md",
+            source,
+            body_start,
+            &every_rule(),
+            Options::empty(),
+        )
+        .iter()
+        .map(|problem| problem.render(false))
+        .collect();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("page.md:6:5"), "{}", problems[0]);
+        assert!(problems[1].contains("page.md:7:1"), "{}", problems[1]);
+    }
+}
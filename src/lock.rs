@@ -0,0 +1,102 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use crate::{build_info, output_dir, parallel, Content};
+
+/// The SHA-256 of every rendered page of a build, by slug.
+pub type Manifest = BTreeMap<String, String>;
+
+/// A page of the build and where it is rendered to.
+#[derive(Debug)]
+pub struct RenderedPage {
+    pub slug: String,
+    /// The page's source, relative to the content folder
+    pub path: String,
+    /// Its `index.html`
+    pub output: PathBuf,
+}
+
+/// Pages with `locked: true`, or dated before `before` (compared as
+/// `YYYY-MM-DD` dates), are locked.
+pub fn is_locked(content: &Content, before: Option<&str>) -> bool {
+    let frontmatter = &content.frontmatter.0;
+    if frontmatter.get("locked").map(|v| v.trim()) == Some("true") {
+        return true;
+    }
+
+    match (before, frontmatter.get("date")) {
+        (Some(before), Some(date)) => date.trim() < before,
+        _ => false,
+    }
+}
+
+/// Where each page will be written, to hash once the build is done.
+pub fn outputs(
+    contents: &[Content],
+    output: &str,
+    before: Option<&str>,
+) -> Vec<(RenderedPage, bool)> {
+    contents
+        .iter()
+        .filter_map(|content| {
+            let dir = output_dir(output, content)?;
+            let page = RenderedPage {
+                slug: content.slug.to_string(),
+                path: content.path.to_string(),
+                output: dir.join("index.html"),
+            };
+            Some((page, is_locked(content, before)))
+        })
+        .collect()
+}
+
+pub fn load(path: &str) -> io::Result<Manifest> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {err}"))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Manifest::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save(path: &str, manifest: &Manifest) -> io::Result<()> {
+    let text = serde_json::to_string_pretty(manifest)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(path, text + "\n")
+}
+
+/// Hashes the rendered pages.
+pub fn manifest(pages: &[(RenderedPage, bool)]) -> Manifest {
+    check(pages, &Manifest::new()).0
+}
+
+/// Hashes the rendered pages and returns the new manifest along with a
+/// problem for every locked page that differs from `previous`. Pages that
+/// weren't in the previous manifest can't be checked and are only recorded.
+pub fn check(pages: &[(RenderedPage, bool)], previous: &Manifest) -> (Manifest, Vec<String>) {
+    let mut manifest = Manifest::new();
+    let mut problems = Vec::new();
+
+    let hashes = parallel::map(pages, |(page, _)| build_info::hash_file(&page.output));
+    for ((page, locked), hash) in pages.iter().zip(hashes) {
+        let hash = match hash {
+            Ok(hash) => hash,
+            // pages that failed to render have already been reported
+            Err(_) => continue,
+        };
+
+        if *locked {
+            if let Some(expected) = previous.get(&page.slug) {
+                if expected != &hash {
+                    problems.push(format!(
+                        "{} is locked but its rendered output changed",
+                        page.path
+                    ));
+                }
+            }
+        }
+
+        manifest.insert(page.slug.to_string(), hash);
+    }
+
+    (manifest, problems)
+}
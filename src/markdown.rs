@@ -0,0 +1,103 @@
+use std::io;
+
+use pulldown_cmark::{Event, HeadingLevel, Options, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::Frontmatter;
+
+/// How markdown is parsed, from `[markdown]` in the site config. Pages
+/// override it with a `markdown` frontmatter field, like
+/// `markdown: { heading_offset: 1, hard_breaks: true }`, or single
+/// `markdown.<option>` fields.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownOptions {
+    /// Levels to add to every heading, so `#` becomes `<h2>` with 1
+    pub heading_offset: u8,
+    /// Line breaks in paragraphs are kept instead of joining the lines
+    pub hard_breaks: bool,
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub tasklists: bool,
+    /// Curly quotes, en and em dashes and ellipses, outside code and math
+    pub smart_punctuation: bool,
+    /// `# Heading {#id .class}`
+    pub heading_attributes: bool,
+}
+
+/// Reads the `[markdown]` table of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<MarkdownOptions> {
+    crate::config::table(config, "markdown")
+}
+
+impl MarkdownOptions {
+    /// These options with the page's overrides on top.
+    pub fn for_page(&self, frontmatter: &Frontmatter) -> Result<MarkdownOptions, String> {
+        let mut options = match serde_yaml::to_value(self) {
+            Ok(serde_yaml::Value::Mapping(options)) => options,
+            _ => return Ok(self.clone()),
+        };
+
+        if let Some(value) = frontmatter.0.get("markdown") {
+            match serde_yaml::from_str::<serde_yaml::Value>(value) {
+                Ok(serde_yaml::Value::Mapping(overrides)) => options.extend(overrides),
+                _ => {
+                    return Err(format!(
+                        "`markdown` should be a map of options, not {value}"
+                    ))
+                }
+            }
+        }
+        for (key, value) in frontmatter.0.iter() {
+            if let Some(key) = key.strip_prefix("markdown.") {
+                let value = serde_yaml::from_str(value)
+                    .unwrap_or_else(|_| serde_yaml::Value::String(value.to_string()));
+                options.insert(serde_yaml::Value::String(key.to_string()), value);
+            }
+        }
+
+        serde_yaml::from_value(serde_yaml::Value::Mapping(options)).map_err(|err| err.to_string())
+    }
+
+    /// The parser extensions to enable.
+    pub fn parser_options(&self) -> Options {
+        let mut options = Options::empty();
+        let flags = [
+            (self.tables, Options::ENABLE_TABLES),
+            (self.footnotes, Options::ENABLE_FOOTNOTES),
+            (self.strikethrough, Options::ENABLE_STRIKETHROUGH),
+            (self.tasklists, Options::ENABLE_TASKLISTS),
+            (self.smart_punctuation, Options::ENABLE_SMART_PUNCTUATION),
+            (self.heading_attributes, Options::ENABLE_HEADING_ATTRIBUTES),
+        ];
+        for (enabled, flag) in flags.into_iter() {
+            if enabled {
+                options.insert(flag);
+            }
+        }
+        options
+    }
+
+    fn offset(&self, level: HeadingLevel) -> HeadingLevel {
+        let level = (level as usize + self.heading_offset as usize).min(6);
+        HeadingLevel::try_from(level).unwrap_or(HeadingLevel::H6)
+    }
+
+    /// Applies the options the parser has no flag for: the heading offset
+    /// and hard breaks.
+    pub fn apply<'a, I: Iterator<Item = Event<'a>>>(&self, events: I) -> Vec<Event<'a>> {
+        events
+            .map(|event| match event {
+                Event::Start(Tag::Heading(level, id, classes)) if self.heading_offset > 0 => {
+                    Event::Start(Tag::Heading(self.offset(level), id, classes))
+                }
+                Event::End(Tag::Heading(level, id, classes)) if self.heading_offset > 0 => {
+                    Event::End(Tag::Heading(self.offset(level), id, classes))
+                }
+                Event::SoftBreak if self.hard_breaks => Event::HardBreak,
+                event => event,
+            })
+            .collect()
+    }
+}
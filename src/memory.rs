@@ -0,0 +1,263 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tera::Tera;
+
+use crate::{
+    assets, compile_content_map,
+    diagnostics::{Diagnostic, Failure},
+    feeds, functions, headers, icons, images, layout, load_site, output_dir, render_page, search,
+    short_urls, sitemap, static_files, taxonomies, Content, Options, Site,
+};
+
+/// What a request for a path of the site is answered with.
+pub enum Found {
+    /// A file sent as it is
+    File(PathBuf),
+    /// A rendered page
+    Page(String),
+    /// A compiled or minified static file
+    Text(String),
+    /// A resized or converted image
+    Bytes(Vec<u8>),
+    /// A page that didn't render, with why
+    Failed(String),
+}
+
+/// A site compiled without writing any of it, rendering each page the first
+/// time it's requested and keeping it until the site is compiled again.
+pub struct Memory {
+    templates: Tera,
+    site: Site,
+    /// The page written to each folder, by its index in the site's content
+    pages: BTreeMap<PathBuf, usize>,
+    /// Static files, resources and images, by where they'd be written
+    files: BTreeMap<PathBuf, PathBuf>,
+    taxonomies: Vec<String>,
+    taxonomy_layout: String,
+    search_index: bool,
+    sitemap: Option<String>,
+    robots: Option<String>,
+    icons: Option<String>,
+    /// The feeds, by where they'd be written
+    feeds: BTreeMap<PathBuf, String>,
+    /// Whether a `_headers` file is made, from the pages when it's asked for
+    headers: bool,
+    /// Pages rendered so far, by their folder
+    rendered: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl Memory {
+    /// Compiles the site, finding where everything in it would be written.
+    pub fn load(opts: &Options) -> io::Result<Self> {
+        let (templates, site, theme) = load_site(opts)?;
+
+        // later sources win, in the order a build copies them in
+        let mut copies = Vec::new();
+        if let Some(theme) = &theme {
+            copies.extend(static_files(&theme.static_files().to_string_lossy(), ""));
+        }
+        copies.extend(static_files(&opts.content, ""));
+        if let Some(player) = &opts.asciinema_player {
+            let assets = functions::Asciinema::assets(Path::new(player));
+            copies.extend(assets.into_iter().map(|(path, source)| (source, path)));
+        }
+        for (name, dir, _) in site.version_dirs.iter() {
+            copies.extend(static_files(dir, name));
+        }
+        let mut files: BTreeMap<PathBuf, PathBuf> =
+            copies.into_iter().map(|(from, to)| (to, from)).collect();
+
+        let mut pages = BTreeMap::new();
+        for (i, content) in site.content.iter().enumerate() {
+            let Some(dir) = output_dir("", content) else {
+                continue;
+            };
+            for resource in content.resources.iter().chain(content.images.iter()) {
+                files.insert(dir.join(&resource.src), resource.source.to_path_buf());
+            }
+            pages.insert(dir, i);
+        }
+
+        let sitemap = sitemap::sitemap(&site.content, &opts.site_values(), &opts.content);
+        let feeds = feeds::render(
+            &compile_content_map(&site.content),
+            &opts.site_values(),
+            &opts.feed_format,
+            &site.languages,
+            &site.feeds,
+        );
+        Ok(Self {
+            templates,
+            site,
+            pages,
+            files,
+            taxonomies: opts.taxonomies.clone(),
+            taxonomy_layout: opts.taxonomy_layout.to_string(),
+            search_index: opts.search_index,
+            sitemap,
+            robots: opts.robots.then(|| sitemap::robots(&opts.site_values())),
+            icons: icons::load(&opts.icons)?.sheet(),
+            feeds,
+            headers: opts.headers,
+            rendered: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn render(&self, content: &Content) -> Found {
+        let result = render_page(&self.templates, content, &self.site);
+        match result {
+            Ok(html) => Found::Page(self.site.finish(html)),
+            Err(err) => {
+                Diagnostic::error(format!("Couldn't render {}", layout(content)))
+                    .failure(Failure::Template)
+                    .in_file(&content.path)
+                    .causes(&err)
+                    .print();
+                Found::Failed(format!("Couldn't render {}: {err}", content.path))
+            }
+        }
+    }
+
+    fn remember(&self, dir: &Path, found: Found) -> Found {
+        if let Found::Page(html) = &found {
+            self.rendered
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(dir.to_path_buf(), html.to_string());
+        }
+        found
+    }
+
+    fn file(&self, source: &Path) -> Option<Found> {
+        match assets::output(source, self.site.minify).ok()? {
+            assets::Output::Copy => Some(Found::File(source.to_path_buf())),
+            assets::Output::Text(text) => Some(Found::Text(text)),
+            assets::Output::Skip => None,
+        }
+    }
+
+    // a variant of a static image, made when it's asked for
+    fn variant(&self, path: &Path) -> Option<Found> {
+        let (sources, width, format) = images::variant_sources(path)?;
+        let source = sources.iter().find_map(|source| self.files.get(source))?;
+        Some(match self.site.images.variant(source, width, &format) {
+            Ok(bytes) => Found::Bytes(bytes),
+            Err(err) => Found::Failed(format!("Couldn't resize {}: {err}", source.display())),
+        })
+    }
+
+    // a listing page, at `<taxonomy>/<term>`
+    fn taxonomy(&self, dir: &Path) -> Option<Found> {
+        let mut parts = dir.iter().map(|part| part.to_str());
+        let (Some(Some(name)), Some(Some(slug)), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        let layout = self.taxonomy_layout.as_str();
+        if !self
+            .templates
+            .get_template_names()
+            .any(|template| template == layout)
+        {
+            return None;
+        }
+
+        let taxonomies = taxonomies::collect(&self.site.content, &self.taxonomies);
+        let terms = taxonomies.get(name)?;
+        let term = terms.iter().find(|term| term.slug == slug)?;
+        let result = taxonomies::render(&self.templates, layout, name, terms, term, &self.site);
+        Some(match result {
+            Ok(html) => Found::Page(self.site.finish(html)),
+            Err(err) => {
+                Diagnostic::error(format!("Couldn't render {layout} for {}", dir.display()))
+                    .failure(Failure::Template)
+                    .causes(&err)
+                    .print();
+                Found::Failed(format!("Couldn't render {}: {err}", dir.display()))
+            }
+        })
+    }
+
+    // the `_headers` file, which renders every page for their headers
+    fn headers(&self) -> String {
+        let own = headers::own("", &self.site.content);
+        let mut all = headers::Headers::new();
+        for dir in self.pages.keys() {
+            let index = dir.join("index.html");
+            let (Some(url), Some(Found::Page(html))) =
+                (headers::url("", &index), self.find(&index))
+            else {
+                continue;
+            };
+            let page = self.site.page_headers(&html, own.get(&url));
+            if !page.is_empty() {
+                all.insert(url, page);
+            }
+        }
+        headers::text(&all)
+    }
+
+    /// What's at `path`, relative to the root of the site.
+    pub fn find(&self, path: &Path) -> Option<Found> {
+        let dir = match path.file_name() {
+            Some(name) if name == "index.html" => path.parent().unwrap_or(path),
+            _ => path,
+        };
+
+        let rendered = self.rendered.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(html) = rendered.get(dir) {
+            return Some(Found::Page(html.to_string()));
+        }
+        drop(rendered);
+
+        if let Some(&i) = self.pages.get(dir) {
+            let content = &self.site.content[i];
+            let found = self.render(content);
+            // changelogs are read from outside the site on every render
+            if content.frontmatter.0.contains_key("changelog") {
+                return Some(found);
+            }
+            return Some(self.remember(dir, found));
+        }
+        if let Some(source) = self.files.get(path) {
+            return self.file(source);
+        }
+        if let Some(found) = self.variant(path) {
+            return Some(found);
+        }
+        if self.search_index && path == Path::new("search_index.json") {
+            return Some(Found::Text(search::index(&self.site.content)));
+        }
+        let generated = match path.to_str() {
+            Some("sitemap.xml") => self.sitemap.as_ref(),
+            Some("robots.txt") => self.robots.as_ref(),
+            Some(icons::FILE_NAME) => self.icons.as_ref(),
+            _ => None,
+        };
+        if let Some(text) = generated.or_else(|| self.feeds.get(path)) {
+            return Some(Found::Text(text.to_string()));
+        }
+        if self.headers && path == Path::new(headers::FILE_NAME) {
+            return Some(Found::Text(self.headers()));
+        }
+        if let Some(found) = self.taxonomy(dir) {
+            return Some(self.remember(dir, found));
+        }
+        self.site
+            .short_urls
+            .iter()
+            .find(|url| Path::new(url.from.trim_start_matches('/')) == dir)
+            .map(|url| Found::Page(short_urls::redirect_page(&url.to)))
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.site.remove_checkouts();
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use regex::{Captures, Regex};
+use tera::Tera;
+
+use crate::{
+    assets::{self, Output},
+    csp::attribute,
+    diagnostics::{self, Diagnostic, Failure},
+    functions, integrity, schedule, Content, Site,
+};
+
+/// The layout newsletters are rendered with when the site has no
+/// `newsletter.html`: each post's title and content, one after another.
+const DEFAULT_LAYOUT: &str = r#"<!DOCTYPE html>
+<html lang="{{ lang }}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ site.title | default(value="Newsletter") }}</title>
+<style>
+body { margin: 0 auto; padding: 16px; max-width: 600px; font-family: Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #222222; }
+h1 { font-size: 26px; }
+h2 { font-size: 22px; margin-top: 32px; }
+a { color: #1a5fb4; }
+img { max-width: 100%; height: auto; }
+pre { overflow-x: auto; padding: 12px; background: #f4f4f4; }
+</style>
+</head>
+<body>
+{% for post in posts %}
+<article>
+<h2><a href="{{ post.permalink | default(value=post.slug) }}">{{ post.frontmatter.title | default(value=post.slug) }}</a></h2>
+{{ post.content | safe }}
+</article>
+{% endfor %}
+</body>
+</html>
+"#;
+
+/// The dated pages of `contents` from `since` on, in `section` if one is
+/// given, newest first.
+fn posts<'a>(contents: &'a [Content], since: &str, section: Option<&str>) -> Vec<&'a Content> {
+    let since = schedule::normalize(since);
+    let mut posts: Vec<(String, &Content)> = contents
+        .iter()
+        .filter(|content| {
+            section.is_none_or(|section| {
+                Path::new(&content.path)
+                    .components()
+                    .next()
+                    .is_some_and(|first| first.as_os_str() == section)
+            })
+        })
+        .filter_map(|content| {
+            let date = schedule::normalize(content.frontmatter.0.get("date")?);
+            (date >= since).then_some((date, content))
+        })
+        .collect();
+    posts.sort_by(|(a, _), (b, _)| b.cmp(a));
+    posts.into_iter().map(|(_, content)| content).collect()
+}
+
+/// `html` with the urls of the site in its links, images and styles made
+/// absolute on `base_url`, since an email isn't read on the site.
+pub fn absolute_urls(html: &str, base_url: &str) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let absolute = |url: &str| match url.starts_with('/') && !url.starts_with("//") {
+        true => format!("{base_url}{url}"),
+        false => url.to_string(),
+    };
+
+    let attributes =
+        Regex::new(r#"(?i)(\s(?:href|src|poster|background)\s*=\s*)(["'])([^"']*)(["'])"#).unwrap();
+    let html = attributes.replace_all(html, |c: &Captures| {
+        format!("{}{}{}{}", &c[1], &c[2], absolute(&c[3]), &c[4])
+    });
+    let srcsets = Regex::new(r#"(?i)(\ssrcset\s*=\s*)(["'])([^"']*)(["'])"#).unwrap();
+    let html = srcsets.replace_all(&html, |c: &Captures| {
+        let candidates: Vec<String> = c[3]
+            .split(',')
+            .map(|candidate| {
+                let candidate = candidate.trim();
+                match candidate.split_once(char::is_whitespace) {
+                    Some((url, width)) => format!("{} {width}", absolute(url)),
+                    None => absolute(candidate),
+                }
+            })
+            .collect();
+        format!("{}{}{}{}", &c[1], &c[2], candidates.join(", "), &c[4])
+    });
+    let css_urls = Regex::new(r#"(?i)url\(\s*(["']?)(/[^/"')][^"')]*)(["']?)\s*\)"#).unwrap();
+    css_urls
+        .replace_all(&html, |c: &Captures| {
+            format!("url({}{}{})", &c[1], absolute(&c[2]), &c[3])
+        })
+        .into_owned()
+}
+
+/// A rule of a stylesheet whose selector can be matched against a single
+/// tag: a tag name, classes and an id, like `p`, `.note` or `a#top`.
+struct Rule {
+    tag: Option<String>,
+    classes: Vec<String>,
+    id: Option<String>,
+    declarations: Vec<(String, String)>,
+}
+
+impl Rule {
+    fn parse(selector: &str, declarations: &[(String, String)]) -> Option<Self> {
+        let simple = Regex::new(r"^([a-zA-Z][a-zA-Z0-9-]*)?((?:[.#][\w-]+)*)$").unwrap();
+        let c = simple.captures(selector.trim())?;
+        if c.get(1).is_none() && c[2].is_empty() {
+            return None;
+        }
+        let mut rule = Rule {
+            tag: c.get(1).map(|tag| tag.as_str().to_ascii_lowercase()),
+            classes: Vec::new(),
+            id: None,
+            declarations: declarations.to_vec(),
+        };
+        let parts = Regex::new(r"([.#])([\w-]+)").unwrap();
+        for part in parts.captures_iter(&c[2]) {
+            match &part[1] {
+                "#" if rule.id.is_some() => return None,
+                "#" => rule.id = Some(part[2].to_string()),
+                _ => rule.classes.push(part[2].to_string()),
+            }
+        }
+        Some(rule)
+    }
+
+    fn specificity(&self) -> (usize, usize, usize) {
+        (
+            usize::from(self.id.is_some()),
+            self.classes.len(),
+            usize::from(self.tag.is_some()),
+        )
+    }
+
+    fn matches(&self, tag: &str, attributes: &str) -> bool {
+        if self.tag.as_ref().is_some_and(|name| name != tag) {
+            return false;
+        }
+        if let Some(id) = &self.id {
+            if attribute(attributes, "id") != Some(id.as_str()) {
+                return false;
+            }
+        }
+        let classes: Vec<&str> = attribute(attributes, "class")
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        self.classes
+            .iter()
+            .all(|class| classes.contains(&class.as_str()))
+    }
+}
+
+fn declarations(block: &str) -> Vec<(String, String)> {
+    block
+        .split(';')
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            let (property, value) = (property.trim(), value.trim());
+            (!property.is_empty() && !value.is_empty())
+                .then(|| (property.to_ascii_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+// `style` with `declarations` in place of those of the same properties
+fn set(style: &mut Vec<(String, String)>, declarations: &[(String, String)]) {
+    for (property, value) in declarations.iter() {
+        style.retain(|(existing, _)| existing != property);
+        style.push((property.to_string(), value.to_string()));
+    }
+}
+
+/// The rules of `css` that can be inlined, in order, and what's left of it
+/// for email clients that read `<style>`: `@media` queries, pseudo classes
+/// and selectors of more than one tag.
+fn rules(css: &str) -> (Vec<Rule>, String) {
+    // and the at-rules without a block, which would run into the next rule
+    let comments = Regex::new(r"(?s)/\*.*?\*/|@(?:charset|import|namespace)\b[^;{]*;").unwrap();
+    let css = comments.replace_all(css, "");
+    let mut rules = Vec::new();
+    let mut rest = String::new();
+
+    let mut remaining = css.as_ref();
+    while let Some(open) = remaining.find('{') {
+        let prelude = remaining[..open].trim();
+        // an at-rule's block holds blocks of its own
+        let mut depth = 0;
+        let mut close = remaining.len();
+        for (i, c) in remaining[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = open + i;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let block = &remaining[open + 1..close.min(remaining.len())];
+        if prelude.starts_with('@') {
+            rest.push_str(&format!("{prelude}{{{block}}}"));
+        } else {
+            let declarations = declarations(block);
+            for selector in prelude.split(',') {
+                match Rule::parse(selector, &declarations) {
+                    Some(rule) => rules.push(rule),
+                    None => rest.push_str(&format!("{}{{{block}}}", selector.trim())),
+                }
+            }
+        }
+        remaining = remaining.get(close + 1..).unwrap_or_default();
+    }
+    (rules, rest)
+}
+
+/// `html` with the rules of its stylesheets and `<style>` tags written into
+/// the `style` of each tag they select, since most email clients ignore
+/// stylesheets. `stylesheet` reads a stylesheet of the site by its url.
+/// Rules that can't be inlined stay in a `<style>` in the `<head>`.
+pub fn inline_styles(html: &str, stylesheet: impl Fn(&str) -> Option<String>) -> String {
+    let mut css = String::new();
+    let links = Regex::new(r"(?is)<link\b([^>]*)>").unwrap();
+    let html = links.replace_all(html, |c: &Captures| {
+        let attributes = &c[1];
+        let is_stylesheet =
+            attribute(attributes, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+        let local = attribute(attributes, "href")
+            .and_then(|href| href.split(['?', '#']).next())
+            .filter(|href| href.starts_with('/') && !href.starts_with("//"));
+        match (is_stylesheet, local.and_then(&stylesheet)) {
+            (true, Some(stylesheet)) => {
+                css.push_str(&stylesheet);
+                css.push('\n');
+                String::new()
+            }
+            _ => c[0].to_string(),
+        }
+    });
+    let styles = Regex::new(r"(?is)<style\b[^>]*>(.*?)</style\s*>").unwrap();
+    let html = styles.replace_all(&html, |c: &Captures| {
+        css.push_str(&c[1]);
+        css.push('\n');
+        String::new()
+    });
+
+    let (mut rules, rest) = rules(&css);
+    // the rules of the cascade: the more specific applies, then the later
+    rules.sort_by_key(|rule| rule.specificity());
+
+    let body_start = html.find("<body").unwrap_or(0);
+    let (head, body) = html.split_at(body_start);
+    let tags = Regex::new(r"(?s)<([a-zA-Z][a-zA-Z0-9-]*)(\s[^>]*?)?(/?)>").unwrap();
+    let body = tags.replace_all(body, |c: &Captures| {
+        let tag = c[1].to_ascii_lowercase();
+        let attributes = c.get(2).map_or("", |attributes| attributes.as_str());
+        let mut style: Vec<(String, String)> = Vec::new();
+        for rule in rules.iter().filter(|rule| rule.matches(&tag, attributes)) {
+            set(&mut style, &rule.declarations);
+        }
+        if style.is_empty() {
+            return c[0].to_string();
+        }
+        // the tag's own style wins over the stylesheets'
+        let own = attribute(attributes, "style").unwrap_or_default();
+        set(&mut style, &declarations(own));
+        let style: String = style
+            .iter()
+            .map(|(property, value)| format!("{property}:{value};"))
+            .collect();
+        let without_style = Regex::new(r#"(?is)\sstyle\s*=\s*(?:"[^"]*"|'[^']*')"#).unwrap();
+        let attributes = without_style.replace_all(attributes, "");
+        format!(
+            r#"<{}{} style="{}"{}>"#,
+            &c[1],
+            attributes.trim_end(),
+            style.replace('"', "'"),
+            &c[3]
+        )
+    });
+
+    let mut html = format!("{head}{body}");
+    if !rest.is_empty() {
+        let style = format!("<style>{rest}</style>");
+        match html.find("</head>") {
+            Some(end) => html.insert_str(end, &style),
+            None => html.insert_str(0, &style),
+        }
+    }
+    html
+}
+
+/// Renders the posts of `site` from `since` on as one html email, with its
+/// styles inlined and its urls absolute: with the site's `layout`, which
+/// gets them as `posts`, or a plain one when there's no such layout.
+pub fn export(
+    templates: &Tera,
+    site: &Site,
+    sources: Vec<(PathBuf, PathBuf)>,
+    layout: &str,
+    since: &str,
+    section: Option<&str>,
+) -> io::Result<Option<String>> {
+    let date = Regex::new(r"^\d{4}-\d{2}-\d{2}").unwrap();
+    if !date.is_match(since.trim()) {
+        Diagnostic::error(format!("`--since {since}` isn't a date"))
+            .failure(Failure::Config)
+            .note("write it like 2024-05-01, or 2024-05-01 09:30")
+            .print();
+        return Err(diagnostics::reported(Failure::Config));
+    }
+    let base_url = site
+        .context
+        .get("site")
+        .and_then(|site| site.get("base_url"))
+        .and_then(|url| url.as_str())
+        .map(str::to_string);
+    let Some(base_url) = base_url else {
+        Diagnostic::error("A newsletter needs absolute urls, and the site has no `base_url`")
+            .failure(Failure::Config)
+            .note("set `base_url` in the site config, or in the environment's")
+            .print();
+        return Err(diagnostics::reported(Failure::Config));
+    };
+
+    let posts = posts(&site.content, since, section);
+    if posts.is_empty() {
+        return Ok(None);
+    }
+
+    let mut context = site.context.clone();
+    context.insert("posts", &posts);
+    context.insert("since", since);
+    context.insert("lang", &posts[0].lang);
+    functions::start_page();
+    let html = if templates.get_template_names().any(|name| name == layout) {
+        site.escaping
+            .context(layout, context)
+            .and_then(|context| templates.render(layout, &context))
+    } else {
+        Tera::one_off(DEFAULT_LAYOUT, &context, true)
+    };
+    let html = html.map_err(|err| {
+        Diagnostic::error(format!("Couldn't render the newsletter with {layout}"))
+            .failure(Failure::Template)
+            .causes(&err)
+            .print();
+        diagnostics::reported(Failure::Template)
+    })?;
+
+    let sources: HashMap<String, PathBuf> = integrity::by_url(sources);
+    let stylesheet = |url: &str| {
+        let from = sources.get(url)?;
+        match assets::output(from, false).ok()? {
+            Output::Copy => std::fs::read_to_string(from).ok(),
+            Output::Text(css) => Some(css),
+            Output::Skip => None,
+        }
+    };
+    let html = inline_styles(&html, stylesheet);
+    Ok(Some(absolute_urls(&html, &base_url)))
+}
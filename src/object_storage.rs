@@ -0,0 +1,113 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use glob::glob;
+use sha2::{Digest, Sha256};
+
+use crate::{diagnostics, interrupt, parallel, serve, Options};
+
+// pages change with every build, so caches ask for them again each time
+const PAGE_CACHE_CONTROL: &str = "no-cache";
+
+/// Whether `--output` is a bucket, `s3://bucket/prefix`, rather than a
+/// folder.
+pub fn is_bucket(output: &str) -> bool {
+    output.starts_with("s3://")
+}
+
+// where the site for `bucket` is built before it's uploaded, apart from
+// other builds running at the same time, in this process or another
+fn temp_dir(bucket: &str) -> PathBuf {
+    let site = format!("{:x}", Sha256::digest(bucket.as_bytes()));
+    std::env::temp_dir().join(format!(
+        "roxy-output-{}-{}",
+        std::process::id(),
+        &site[..12]
+    ))
+}
+
+fn cache_control<'a>(file: &Path, cache_control: &'a str) -> &'a str {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "xml" | "json" | "txt") => PAGE_CACHE_CONTROL,
+        _ => cache_control,
+    }
+}
+
+fn upload_file(file: &Path, url: &str, opts: &Options) -> io::Result<()> {
+    let mut command = Command::new("aws");
+    if let Some(endpoint) = &opts.s3_endpoint {
+        command.args(["--endpoint-url", endpoint]);
+    }
+    command
+        .args(["s3", "cp", "--only-show-errors"])
+        .arg(file)
+        .arg(url)
+        .args(["--content-type", serve::content_type(file)])
+        .args([
+            "--cache-control",
+            cache_control(file, &opts.s3_cache_control),
+        ]);
+
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Couldn't upload {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Uploads every file in `dir` to the same place under `bucket` with the
+/// aws command line, a few at a time, each with its content type and
+/// cache control.
+pub fn upload(dir: &Path, bucket: &str, opts: &Options) -> io::Result<()> {
+    let pattern = format!("{}/**/*", glob::Pattern::escape(&dir.to_string_lossy()));
+    let files: Vec<PathBuf> = glob(&pattern)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect();
+
+    let bucket = bucket.trim_end_matches('/');
+    parallel::try_for_each(&files, |file| {
+        let Ok(relative) = file.strip_prefix(dir) else {
+            return Ok(());
+        };
+        let key = relative
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        upload_file(file, &format!("{bucket}/{key}"), opts)
+    })
+}
+
+/// Runs `f` with `opts`, or when `--output` is a bucket, with the site
+/// built to a temporary folder that is uploaded to the bucket when the
+/// build succeeded, then removed.
+pub fn with_output<T>(opts: &Options, f: impl FnOnce(&Options) -> io::Result<T>) -> io::Result<T> {
+    if !is_bucket(&opts.output) {
+        return f(opts);
+    }
+
+    let dest = temp_dir(&opts.output);
+    interrupt::remove_on_interrupt(opts, &dest);
+    let _ = fs::remove_dir_all(&dest);
+    let mut built = opts.clone();
+    built.output = format!("{}/", dest.to_string_lossy());
+    let result = f(&built).and_then(|value| {
+        // a build with errors keeps going, but isn't published
+        if diagnostics::failure(opts.max_warnings).is_none() {
+            upload(&dest, &opts.output, opts)?;
+        }
+        Ok(value)
+    });
+    let _ = fs::remove_dir_all(&dest);
+    result
+}
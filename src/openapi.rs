@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use tera::Value;
+
+use crate::{slugify, Content, Frontmatter};
+
+// how many `$ref`s inside each other are followed before giving up
+const MAX_REF_DEPTH: usize = 32;
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// The operations documented on one generated page.
+pub struct ApiGroup {
+    pub name: String,
+    pub slug: String,
+    pub description: Option<String>,
+    pub operations: Vec<Value>,
+}
+
+/// Reads an OpenAPI document, in YAML or JSON.
+pub fn load(path: &str) -> io::Result<Value> {
+    let text = fs::read_to_string(path)?;
+    let yaml: serde_yaml::Value = serde_yaml::from_str(&text)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {err}")))?;
+    serde_json::to_value(yaml)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {err}")))
+}
+
+/// Replaces every `{"$ref": "#/..."}` with the part of the document it
+/// points at. References which can't be followed are left in place, and so
+/// are those back to a schema they're inside of, like the `children` of a
+/// tree node, which would otherwise never end.
+pub fn resolve(root: &Value, value: &Value) -> Value {
+    resolve_within(root, value, &mut Vec::new())
+}
+
+// `resolving` holds the references being resolved, outermost first
+fn resolve_within(root: &Value, value: &Value, resolving: &mut Vec<String>) -> Value {
+    match value {
+        Value::Object(map) => {
+            if let Some(pointer) = map.get("$ref").and_then(Value::as_str) {
+                let cycle = resolving.iter().any(|outer| outer == pointer);
+                if !cycle && resolving.len() < MAX_REF_DEPTH {
+                    if let Some(target) = pointer.strip_prefix('#').and_then(|p| root.pointer(p)) {
+                        resolving.push(pointer.to_string());
+                        let resolved = resolve_within(root, target, resolving);
+                        resolving.pop();
+                        return resolved;
+                    }
+                }
+                return value.clone();
+            }
+
+            Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.to_string(), resolve_within(root, v, resolving)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|v| resolve_within(root, v, resolving))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// Groups the document's operations by their first tag, or by the first
+/// segment of their path when they have none, with schemas resolved.
+pub fn groups(spec: &Value) -> Vec<ApiGroup> {
+    let mut groups: Vec<ApiGroup> = Vec::new();
+
+    let paths = match spec.get("paths").and_then(Value::as_object) {
+        Some(paths) => paths,
+        None => return groups,
+    };
+
+    for (path, item) in paths.iter() {
+        let item = resolve(spec, item);
+        for method in METHODS.iter() {
+            let mut operation = match item.get(*method) {
+                Some(operation) => operation.clone(),
+                None => continue,
+            };
+
+            let group = operation
+                .get("tags")
+                .and_then(|tags| tags.get(0))
+                .and_then(Value::as_str)
+                .map(|tag| tag.to_string())
+                .unwrap_or_else(|| {
+                    path.trim_start_matches('/')
+                        .split('/')
+                        .next()
+                        .filter(|segment| !segment.is_empty())
+                        .unwrap_or("default")
+                        .to_string()
+                });
+
+            if let Value::Object(map) = &mut operation {
+                map.insert("method".to_string(), Value::String(method.to_uppercase()));
+                map.insert("path".to_string(), Value::String(path.to_string()));
+                // parameters shared by every method of the path
+                if let Some(shared) = item.get("parameters") {
+                    map.entry("parameters").or_insert_with(|| shared.clone());
+                }
+            }
+
+            match groups.iter_mut().find(|g| g.name == group) {
+                Some(existing) => existing.operations.push(operation),
+                None => groups.push(ApiGroup {
+                    slug: slugify(&group),
+                    name: group,
+                    description: None,
+                    operations: vec![operation],
+                }),
+            }
+        }
+    }
+
+    if let Some(tags) = spec.get("tags").and_then(Value::as_array) {
+        for tag in tags.iter() {
+            let name = tag.get("name").and_then(Value::as_str);
+            if let Some(group) = groups.iter_mut().find(|g| Some(g.name.as_str()) == name) {
+                group.description = tag
+                    .get("description")
+                    .and_then(Value::as_str)
+                    .map(|d| d.to_string());
+            }
+        }
+    }
+
+    groups
+}
+
+/// Turns each group into a page at `<prefix>/<group>`, rendered with
+/// `layout`. Pages get the group as `api`, the document's `info` and
+/// `servers` as `openapi`, and links to every group as `api_groups`.
+pub fn pages(spec: &Value, prefix: &str, layout: &str, lang: &str, lang_dir: &str) -> Vec<Content> {
+    let groups = groups(spec);
+    let prefix = prefix.trim_matches('/');
+
+    let links: Vec<Value> = groups
+        .iter()
+        .map(|group| {
+            serde_json::json!({
+                "name": group.name,
+                "slug": format!("/{prefix}/{}", group.slug),
+            })
+        })
+        .collect();
+
+    let openapi = serde_json::json!({
+        "info": spec.get("info"),
+        "servers": spec.get("servers"),
+    });
+
+    groups
+        .into_iter()
+        .map(|group| {
+            let mut frontmatter = HashMap::new();
+            frontmatter.insert("title".to_string(), group.name.to_string());
+            frontmatter.insert("layout".to_string(), layout.to_string());
+
+            let mut extra = HashMap::new();
+            extra.insert("openapi".to_string(), openapi.clone());
+            extra.insert("api_groups".to_string(), Value::Array(links.clone()));
+            extra.insert(
+                "api".to_string(),
+                serde_json::json!({
+                    "name": group.name,
+                    "description": group.description,
+                    "operations": group.operations,
+                }),
+            );
+
+            Content {
+                path: Path::new(prefix)
+                    .join(format!("{}.md", group.slug))
+                    .to_string_lossy()
+                    .to_string(),
+                slug: format!("/{prefix}/{}", group.slug),
+                permalink: None,
+                lang: lang.to_string(),
+                lang_dir: lang_dir.to_string(),
+                frontmatter: Frontmatter::from_text(frontmatter),
+                content: String::new(),
+                resources: Vec::new(),
+                images: Vec::new(),
+                text: String::new(),
+                numbering: Default::default(),
+                headings: Vec::new(),
+                docs_prev: None,
+                docs_next: None,
+                translations: Vec::new(),
+                feeds: Vec::new(),
+                extra,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn refs() {
+        let spec = json!({
+            "components": { "schemas": {
+                "Id": { "type": "integer" },
+                "User": { "properties": { "id": { "$ref": "#/components/schemas/Id" } } },
+            } },
+            "user": { "$ref": "#/components/schemas/User" },
+        });
+        assert_eq!(
+            resolve(&spec, &spec["user"]),
+            json!({ "properties": { "id": { "type": "integer" } } })
+        );
+    }
+
+    #[test]
+    fn refs_that_cant_be_followed() {
+        let spec = json!({
+            "missing": { "$ref": "#/components/schemas/Missing" },
+            "external": { "$ref": "other.yaml#/Pet" },
+        });
+        assert_eq!(resolve(&spec, &spec["missing"]), spec["missing"]);
+        assert_eq!(resolve(&spec, &spec["external"]), spec["external"]);
+    }
+
+    #[test]
+    fn recursive_refs() {
+        let node = json!({ "$ref": "#/components/schemas/Node" });
+        let spec = json!({
+            "components": { "schemas": { "Node": { "properties": {
+                "value": { "type": "string" },
+                "left": node,
+                "right": node,
+            } } } },
+        });
+        assert_eq!(
+            resolve(&spec, &node),
+            json!({ "properties": {
+                "value": { "type": "string" },
+                "left": node,
+                "right": node,
+            } })
+        );
+    }
+
+    #[test]
+    fn refs_used_twice_side_by_side() {
+        let id = json!({ "$ref": "#/components/schemas/Id" });
+        let spec = json!({
+            "components": { "schemas": { "Id": { "type": "integer" } } },
+            "pair": { "first": id, "second": id },
+        });
+        assert_eq!(
+            resolve(&spec, &spec["pair"]),
+            json!({ "first": { "type": "integer" }, "second": { "type": "integer" } })
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::Content;
+
+/// How much of every page the `data` and `languages` maps layouts get hold,
+/// from `--page-data` or `page_data` in the site config. A page's own layout
+/// always gets all of it as `page`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PageData {
+    /// Everything but the rendered content
+    Metadata,
+    /// Everything but the rendered content, with a `summary` of it
+    Summary,
+    /// Everything, including the rendered content
+    #[default]
+    Full,
+}
+
+/// The summary of a page: its `summary` field, or its `excerpt`.
+pub fn summary(content: &Content) -> Option<String> {
+    if let Some(summary) = content.frontmatter.0.get("summary") {
+        return Some(summary.to_string());
+    }
+    excerpt(&content.content)
+}
+
+/// The html before a `<!-- more -->` comment, or the first paragraph.
+pub fn excerpt(html: &str) -> Option<String> {
+    if let Some((excerpt, _)) = html.split_once("<!-- more -->") {
+        return Some(excerpt.trim().to_string());
+    }
+    let start = html.find("<p>")?;
+    let end = html[start..].find("</p>")? + start + "</p>".len();
+    Some(html[start..end].to_string())
+}
+
+fn slim_page(content: &Content, data: PageData) -> tera::Value {
+    let mut value = serde_json::to_value(content).unwrap_or_default();
+    if data == PageData::Full {
+        return value;
+    }
+    if let tera::Value::Object(map) = &mut value {
+        map.remove("content");
+        if data == PageData::Summary {
+            if let Some(summary) = summary(content) {
+                map.insert("summary".to_string(), tera::Value::String(summary));
+            }
+        }
+    }
+    value
+}
+
+/// `pages`, like the `data` map, holding only as much of each page as `data`
+/// says.
+pub fn slim(
+    pages: &HashMap<String, Vec<&Content>>,
+    data: PageData,
+) -> HashMap<String, Vec<tera::Value>> {
+    pages
+        .iter()
+        .map(|(key, pages)| {
+            let pages = pages.iter().map(|page| slim_page(page, data)).collect();
+            (key.to_string(), pages)
+        })
+        .collect()
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::diagnostics;
+
+fn workers(items: usize) -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items)
+        .max(1)
+}
+
+/// Runs `f` on every item on a thread per core, returning the results in
+/// the order of `items`. Each thread takes the next item nobody has started
+/// on as soon as it is free, so a few slow items don't hold up the rest.
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    map_with(items, || (), |_, item| f(item))
+}
+
+/// Like `map`, giving `f` state of its thread's own made by `init`, for
+/// work that needs something it can't share, like a `Tera` to render with.
+pub fn map_with<T, S, R, I, F>(items: &[T], init: I, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, &T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+    // problems found on the workers count towards the build that started them
+    let tally = diagnostics::current_tally();
+
+    thread::scope(|scope| {
+        for _ in 0..workers(items.len()) {
+            scope.spawn(|| {
+                diagnostics::counting(tally.clone(), || {
+                    let mut state = init();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        let result = f(&mut state, item);
+                        results.lock().unwrap_or_else(|err| err.into_inner())[i] = Some(result);
+                    }
+                })
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|err| err.into_inner())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Runs `f` on every item like `map`, returning the first error in the
+/// order of `items` if any failed.
+pub fn try_for_each<T, E, F>(items: &[T], f: F) -> Result<(), E>
+where
+    T: Sync,
+    E: Send,
+    F: Fn(&T) -> Result<(), E> + Sync,
+{
+    map(items, f).into_iter().collect()
+}
@@ -0,0 +1,195 @@
+use std::{collections::BTreeMap, io};
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::{schedule, slugify, Frontmatter};
+
+/// Url patterns like `/:year/:month/:slug/`, by the top level section they
+/// apply to, from `[permalinks]` in the site config. `"*"` applies to every
+/// page without one for its section.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Permalinks(BTreeMap<String, String>);
+
+/// Reads the `[permalinks]` table of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<Permalinks> {
+    crate::config::table(config, "permalinks")
+}
+
+fn normalize(url: &str) -> String {
+    let mut normalized = String::from("/");
+    for part in url.split('/').filter(|part| !part.is_empty()) {
+        normalized.push_str(part);
+        normalized.push('/');
+    }
+    normalized
+}
+
+impl Permalinks {
+    /// The url of the page at `file_path` (in the content folder), whose
+    /// url would otherwise be `slug`, if a pattern applies to it. The
+    /// page's own `permalink` field comes first, then its section's
+    /// pattern, then `"*"`. Urls always end in `/`.
+    ///
+    /// Patterns can use `:year`, `:month` and `:day` of the page's `date`,
+    /// `:slug` (its `slug` field, or the last part of its path), `:title`,
+    /// `:section` and `:path` (where the page would be without one).
+    pub fn for_page(
+        &self,
+        file_path: &str,
+        slug: &str,
+        frontmatter: &Frontmatter,
+    ) -> Result<Option<String>, String> {
+        let section = match file_path.split_once(['/', '\\']) {
+            Some((section, _)) => section,
+            None => "",
+        };
+        let pattern = frontmatter
+            .0
+            .get("permalink")
+            .or_else(|| self.0.get(section))
+            .or_else(|| self.0.get("*"));
+        let Some(pattern) = pattern else {
+            return Ok(None);
+        };
+
+        let date = frontmatter
+            .0
+            .get("date")
+            .map(|date| schedule::normalize(date));
+        let date_part = |start: usize, end: usize| {
+            date.as_ref()
+                .and_then(|date| date.get(start..end))
+                .map(|part| part.to_string())
+        };
+        let name = slug.rsplit('/').next().unwrap_or_default();
+
+        let placeholder = Regex::new(r":([a-z]+)").unwrap();
+        let mut missing = Vec::new();
+        let url = placeholder.replace_all(pattern, |captures: &Captures| {
+            let value = match &captures[1] {
+                "year" => date_part(0, 4),
+                "month" => date_part(5, 7),
+                "day" => date_part(8, 10),
+                "slug" => Some(match frontmatter.0.get("slug") {
+                    Some(slug) => slugify(slug),
+                    None => name.to_string(),
+                }),
+                "title" => frontmatter.0.get("title").map(|title| slugify(title)),
+                "section" => Some(section.to_string()),
+                "path" => Some(slug.trim_matches('/').to_string()),
+                _ => None,
+            };
+            value.unwrap_or_else(|| {
+                missing.push(captures[0].to_string());
+                String::new()
+            })
+        });
+
+        match missing.first() {
+            Some(placeholder) if matches!(placeholder.as_str(), ":year" | ":month" | ":day") => {
+                Err(format!(
+                    "`{pattern}` uses {placeholder}, but the page has no `date`"
+                ))
+            }
+            Some(placeholder) if placeholder == ":title" => Err(format!(
+                "`{pattern}` uses :title, but the page has no `title`"
+            )),
+            Some(placeholder) => Err(format!(
+                "`{pattern}` has an unknown placeholder {placeholder}"
+            )),
+            None => Ok(Some(normalize(&url))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn permalinks(patterns: &[(&str, &str)]) -> Permalinks {
+        Permalinks(
+            patterns
+                .iter()
+                .map(|(section, pattern)| (section.to_string(), pattern.to_string()))
+                .collect(),
+        )
+    }
+
+    fn frontmatter(fields: &[(&str, &str)]) -> Frontmatter {
+        Frontmatter(
+            fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn placeholders() {
+        let permalinks = permalinks(&[
+            ("blog", "/:year/:month/:day/:slug"),
+            ("*", "/:section/:title"),
+        ]);
+        let page = frontmatter(&[("date", "2024-05-01"), ("title", "Getting Started!")]);
+        assert_eq!(
+            permalinks.for_page("blog/hello.md", "/blog/hello", &page),
+            Ok(Some("/2024/05/01/hello/".to_string()))
+        );
+        assert_eq!(
+            permalinks.for_page("docs/intro.md", "/docs/intro", &page),
+            Ok(Some("/docs/getting-started/".to_string()))
+        );
+    }
+
+    #[test]
+    fn fields_come_first() {
+        let permalinks = permalinks(&[("*", "/:slug")]);
+        let page = frontmatter(&[("slug", "Hi There")]);
+        assert_eq!(
+            permalinks.for_page("blog/hello.md", "/blog/hello", &page),
+            Ok(Some("/hi-there/".to_string()))
+        );
+        let page = frontmatter(&[("permalink", "/old/:path")]);
+        assert_eq!(
+            permalinks.for_page("blog/hello.md", "/blog/hello", &page),
+            Ok(Some("/old/blog/hello/".to_string()))
+        );
+        assert_eq!(
+            Permalinks::default().for_page("blog/hello.md", "/blog/hello", &frontmatter(&[])),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn dates_are_in_utc() {
+        let permalinks = permalinks(&[("*", "/:year/:month/:day/:slug/")]);
+        let page = frontmatter(&[("date", "2024-05-01T01:00:00+02:00")]);
+        assert_eq!(
+            permalinks.for_page("hello.md", "/hello", &page),
+            Ok(Some("/2024/04/30/hello/".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let permalinks = permalinks(&[("*", "/:year/:slug/")]);
+        assert_eq!(
+            permalinks.for_page("hello.md", "/hello", &frontmatter(&[])),
+            Err("`/:year/:slug/` uses :year, but the page has no `date`".to_string())
+        );
+        let page = frontmatter(&[("permalink", "/:title/")]);
+        assert_eq!(
+            permalinks.for_page("hello.md", "/hello", &page),
+            Err("`/:title/` uses :title, but the page has no `title`".to_string())
+        );
+        let page = frontmatter(&[("permalink", "/:author/")]);
+        assert_eq!(
+            permalinks.for_page("hello.md", "/hello", &page),
+            Err("`/:author/` has an unknown placeholder :author".to_string())
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use regex::{NoExpand, Regex};
+use tera::Value;
+
+use crate::{
+    assets::{self, Output},
+    csp::attribute,
+    integrity,
+};
+
+/// The `name` of the `<meta>` `preload()` renders as, filled in with the
+/// page's hints once the page is rendered and minified.
+const PLACEHOLDER: &str = "roxy-preload";
+
+// fonts past the first few are likely bold or italic faces the first
+// paint can do without
+const DEFAULT_FONTS: u64 = 2;
+
+/// `preload()`, preload hints for the `<head>` of the page it's rendered
+/// in: its first stylesheet, the fonts its styles use and its first image.
+/// `style=false`, `image=false` and `fonts=0` leave those out, `fonts=1`
+/// hints fewer fonts, and `prefetch=["/next/"]` adds prefetch hints for
+/// pages readers likely go to next.
+pub fn preload(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let flag = |name: &str| match args.get(name) {
+        None => Ok(true),
+        Some(Value::Bool(on)) => Ok(*on),
+        Some(_) => Err(tera::Error::msg(format!(
+            "`preload`: `{name}` must be true or false"
+        ))),
+    };
+    let fonts = match args.get("fonts") {
+        None => DEFAULT_FONTS,
+        Some(fonts) => fonts
+            .as_u64()
+            .ok_or_else(|| tera::Error::msg("`preload`: `fonts` must be a number"))?,
+    };
+    let not_urls = || tera::Error::msg("`preload`: `prefetch` must be a url or a list of them");
+    let prefetch: Vec<&str> = match args.get("prefetch") {
+        None => Vec::new(),
+        Some(Value::String(url)) => vec![url.as_str()],
+        Some(Value::Array(urls)) => urls
+            .iter()
+            .map(|url| url.as_str().ok_or_else(not_urls))
+            .collect::<tera::Result<_>>()?,
+        Some(_) => return Err(not_urls()),
+    };
+
+    // the spaces between settings keep the minifier from taking the quotes
+    // off `content`
+    let mut settings = vec![
+        format!("style={}", flag("style")?),
+        format!("image={}", flag("image")?),
+        format!("fonts={fonts}"),
+    ];
+    settings.extend(prefetch.iter().map(|url| format!("prefetch={url}")));
+    Ok(Value::String(format!(
+        r#"<meta name="{PLACEHOLDER}" content="{}">"#,
+        settings.join(" ")
+    )))
+}
+
+struct Settings<'a> {
+    style: bool,
+    image: bool,
+    fonts: usize,
+    prefetch: Vec<&'a str>,
+}
+
+impl<'a> Settings<'a> {
+    fn parse(content: &'a str) -> Self {
+        let mut settings = Settings {
+            style: true,
+            image: true,
+            fonts: DEFAULT_FONTS as usize,
+            prefetch: Vec::new(),
+        };
+        for setting in content.split_whitespace() {
+            match setting.split_once('=') {
+                Some(("style", on)) => settings.style = on == "true",
+                Some(("image", on)) => settings.image = on == "true",
+                Some(("fonts", fonts)) => settings.fonts = fonts.parse().unwrap_or_default(),
+                Some(("prefetch", url)) => settings.prefetch.push(url),
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+// the path of a url of the site, without its query, if it's one
+fn local(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    (path.starts_with('/') && !path.starts_with("//")).then_some(path)
+}
+
+// `url` as it's linked from `base`, a stylesheet of the site or, for
+// inline styles, nothing
+fn resolve(base: Option<&str>, url: &str) -> Option<String> {
+    if url.starts_with('/') || url.contains("://") {
+        return Some(url.to_string());
+    }
+    if url.starts_with("data:") {
+        return None;
+    }
+    let base = base?;
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in url.split('/') {
+        match part {
+            "." => {}
+            ".." => {
+                if parts.len() > 1 {
+                    parts.pop();
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+// the woff2 font of each `@font-face` of `css`, where they're given
+fn fonts(base: Option<&str>, css: &str) -> Vec<String> {
+    let faces = Regex::new(r"(?is)@font-face\s*\{([^}]*)\}").unwrap();
+    let sources = Regex::new(r"(?is)(?:^|[;{\s])src\s*:([^;}]*)").unwrap();
+    let urls = Regex::new(r#"(?i)url\(\s*["']?([^"')]+?)["']?\s*\)"#).unwrap();
+    let mut fonts = Vec::new();
+    for face in faces.captures_iter(css) {
+        let font = sources
+            .captures_iter(&face[1])
+            .flat_map(|src| {
+                urls.captures_iter(src.get(1).map_or("", |src| src.as_str()))
+                    .map(|url| url[1].to_string())
+                    .collect::<Vec<_>>()
+            })
+            // other formats are older browsers' fallbacks, which preloading
+            // would download as well
+            .find(|url| {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                path.to_ascii_lowercase().ends_with(".woff2")
+            });
+        if let Some(font) = font.and_then(|font| resolve(base, &font)) {
+            fonts.push(font);
+        }
+    }
+    fonts
+}
+
+fn quote(value: &str) -> String {
+    value.replace('"', "&quot;")
+}
+
+/// Works out the preload hints of rendered pages that ask for them with
+/// `preload()`, reading the site's stylesheets for their fonts.
+pub struct Preload {
+    /// The source of each static file by its url
+    sources: HashMap<String, PathBuf>,
+    minify: bool,
+    /// The stylesheets read so far, as they're written
+    stylesheets: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl Preload {
+    pub fn new(files: Vec<(PathBuf, PathBuf)>, minify: bool) -> Self {
+        Self {
+            sources: integrity::by_url(files),
+            minify,
+            stylesheets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // the stylesheet of the site at `path`, compiled and minified
+    fn stylesheet(&self, path: &str) -> Option<String> {
+        let mut stylesheets = self
+            .stylesheets
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        stylesheets
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let from = self.sources.get(path)?;
+                match assets::output(from, self.minify).ok()? {
+                    Output::Copy => std::fs::read_to_string(from).ok(),
+                    Output::Text(css) => Some(css),
+                    Output::Skip => None,
+                }
+            })
+            .clone()
+    }
+
+    fn hints(&self, html: &str, settings: &Settings) -> String {
+        let links = Regex::new(r"(?is)<link\b([^>]*)>").unwrap();
+        let mut stylesheets = Vec::new();
+        // hints the layout gives itself aren't given twice
+        let mut hinted = BTreeSet::new();
+        for c in links.captures_iter(html) {
+            let attributes = c.get(1).map_or("", |attributes| attributes.as_str());
+            let rel = attribute(attributes, "rel").unwrap_or_default();
+            let Some(href) = attribute(attributes, "href") else {
+                continue;
+            };
+            match rel.to_ascii_lowercase().as_str() {
+                "stylesheet" => stylesheets.push(href),
+                "preload" | "prefetch" => {
+                    hinted.insert(href.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        let mut hints = Vec::new();
+        let mut hint = |href: &str, attributes: String| {
+            if hinted.insert(href.to_string()) {
+                hints.push(format!(r#"<link href="{}"{attributes}>"#, quote(href)));
+            }
+        };
+        if settings.style {
+            if let Some(first) = stylesheets.first() {
+                hint(first, r#" rel="preload" as="style""#.to_string());
+            }
+        }
+
+        if settings.fonts > 0 {
+            let mut found: Vec<String> = Vec::new();
+            if let Some(path) = stylesheets.first().and_then(|href| local(href)) {
+                if let Some(css) = self.stylesheet(path) {
+                    found.extend(fonts(Some(path), &css));
+                }
+            }
+            let styles = Regex::new(r"(?is)<style\b[^>]*>(.*?)</style\s*>").unwrap();
+            for c in styles.captures_iter(html) {
+                found.extend(fonts(None, &c[1]));
+            }
+            let mut seen = BTreeSet::new();
+            found.retain(|font| seen.insert(font.clone()));
+            for font in found.iter().take(settings.fonts) {
+                hint(
+                    font,
+                    r#" rel="preload" as="font" type="font/woff2" crossorigin"#.to_string(),
+                );
+            }
+        }
+
+        // the first image is the one likeliest to be the page's largest
+        // paint, unless its layout loads it lazily
+        if settings.image {
+            let images = Regex::new(r"(?is)<img\b([^>]*)>").unwrap();
+            if let Some(c) = images.captures(html) {
+                let attributes = &c[1];
+                let lazy = attribute(attributes, "loading")
+                    .is_some_and(|loading| loading.eq_ignore_ascii_case("lazy"));
+                if let (Some(src), false) = (attribute(attributes, "src"), lazy) {
+                    let mut image = r#" rel="preload" as="image""#.to_string();
+                    if let Some(srcset) = attribute(attributes, "srcset") {
+                        image.push_str(&format!(r#" imagesrcset="{}""#, quote(srcset)));
+                    }
+                    if let Some(sizes) = attribute(attributes, "sizes") {
+                        image.push_str(&format!(r#" imagesizes="{}""#, quote(sizes)));
+                    }
+                    image.push_str(r#" fetchpriority="high""#);
+                    hint(src, image);
+                }
+            }
+        }
+
+        for url in settings.prefetch.iter() {
+            hint(url, r#" rel="prefetch""#.to_string());
+        }
+        hints.concat()
+    }
+
+    /// `html` with the `preload()` calls in it filled in with its hints.
+    pub fn fill(&self, html: String) -> String {
+        let placeholder = Regex::new(&format!(
+            r#"(?i)<meta\b[^>]*\sname\s*=\s*["']?{PLACEHOLDER}\b[^>]*>"#
+        ))
+        .unwrap();
+        let Some(found) = placeholder.find(&html) else {
+            return html;
+        };
+        let content = attribute(found.as_str(), "content").unwrap_or_default();
+        let hints = self.hints(&html, &Settings::parse(content));
+        placeholder
+            .replace_all(&html, NoExpand(&hints))
+            .into_owned()
+    }
+}
+
+/// The `Link` header of a page with the preload and prefetch hints in its
+/// `html`, so they reach the browser before the page does.
+pub fn link_header(html: &str) -> Option<String> {
+    let links = Regex::new(r"(?is)<link\b([^>]*)>").unwrap();
+    let mut header = Vec::new();
+    for c in links.captures_iter(html) {
+        let attributes = &c[1];
+        let rel = attribute(attributes, "rel")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let Some(href) = attribute(attributes, "href") else {
+            continue;
+        };
+        if !matches!(rel.as_str(), "preload" | "prefetch") {
+            continue;
+        }
+        let mut link = format!("<{}>; rel={rel}", href.replace("&amp;", "&"));
+        if let Some(kind) = attribute(attributes, "as") {
+            link.push_str(&format!("; as={kind}"));
+        }
+        if let Some(mime) = attribute(attributes, "type") {
+            link.push_str(&format!("; type=\"{mime}\""));
+        }
+        if Regex::new(r"(?i)(?:^|\s)crossorigin\b")
+            .unwrap()
+            .is_match(attributes)
+        {
+            link.push_str("; crossorigin");
+        }
+        header.push(link);
+    }
+    (!header.is_empty()).then(|| header.join(", "))
+}
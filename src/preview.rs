@@ -0,0 +1,242 @@
+use std::{
+    error::Error,
+    io::{self, BufRead, Cursor},
+    path::Path,
+};
+
+use highlight_pulldown::PulldownHighlighter;
+use serde_json::{json, Value};
+use syntect::parsing::SyntaxSet;
+use tera::Tera;
+
+use crate::{
+    apply_cascade, compile_page, diagnostics, highlight_cache::HighlightCache, highlight_theme,
+    links, prepare, render_page, setup_templates, Options, PageSettings, Site, Transforms,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The page couldn't be compiled or rendered
+const RENDER_FAILED: i64 = -32000;
+
+/// The loaded site, kept between requests.
+struct Session {
+    templates: Tera,
+    site: Site,
+    highlight_cache: HighlightCache,
+}
+
+fn load(opts: &Options) -> io::Result<Session> {
+    opts.check_config()?;
+    let theme = opts.theme_package()?;
+    let build_info = opts.build_info(theme.as_ref())?;
+    let mut templates = setup_templates(opts, theme.as_ref(), &build_info)?;
+    let site = prepare(opts, &mut templates, theme.as_ref(), &build_info)?;
+    site.remove_checkouts();
+    Ok(Session {
+        templates,
+        site,
+        highlight_cache: HighlightCache::new(Path::new(&opts.cache_dir), &opts.theme),
+    })
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    causes: Vec<String>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            causes: Vec::new(),
+        }
+    }
+
+    fn from_error(code: i64, err: &dyn Error) -> Self {
+        let mut failure = Self::new(code, err.to_string());
+        let mut source = err.source();
+        while let Some(cause) = source {
+            failure.causes.push(cause.to_string());
+            source = cause.source();
+        }
+        failure
+    }
+}
+
+// compiles `text` as the page at `path` (under the content folder or
+// relative to it), puts it in the site in place of the saved page and
+// renders it
+fn render(
+    opts: &Options,
+    session: &mut Session,
+    highlighter: &PulldownHighlighter,
+    path: &str,
+    text: &str,
+) -> Result<Value, RpcError> {
+    let relative = Path::new(path)
+        .strip_prefix(&opts.content)
+        .unwrap_or(Path::new(path));
+    let entry = Path::new(&opts.content).join(relative);
+
+    let site = &session.site;
+    let transforms = Transforms {
+        replacements: &site.replacements,
+        markdown: &site.markdown,
+        permalinks: &site.permalinks,
+        glossary: site.glossary.as_ref(),
+        bibliography: site.bibliography.as_ref(),
+    };
+    let settings = PageSettings {
+        default_language: &opts.default_language,
+        languages: &site.languages,
+        rtl_languages: &opts.rtl_languages,
+        transforms: &transforms,
+        require_alt: opts.require_alt,
+        lint: None,
+        highlight_cache: &session.highlight_cache,
+        build_cache: &site.cache,
+    };
+    let mut page = compile_page(
+        &mut Cursor::new(text.as_bytes()),
+        &entry,
+        &opts.content,
+        &mut session.templates,
+        highlighter,
+        &settings,
+    )
+    .map_err(|err| RpcError::from_error(RENDER_FAILED, &err))?
+    .ok_or_else(|| RpcError::new(RENDER_FAILED, format!("{path} isn't a page")))?;
+    links::resolve(&mut page, &links::slugs(&session.site.content));
+
+    let contents = &mut session.site.content;
+    let index = match contents.iter().position(|c| c.path == page.path) {
+        Some(index) => {
+            let old = &mut contents[index];
+            let (docs_prev, docs_next) = (old.docs_prev.take(), old.docs_next.take());
+            let translations = std::mem::take(&mut old.translations);
+            let feeds = std::mem::take(&mut old.feeds);
+            *old = page;
+            old.docs_prev = docs_prev;
+            old.docs_next = docs_next;
+            old.translations = translations;
+            old.feeds = feeds;
+            index
+        }
+        None => {
+            contents.push(page);
+            contents.len() - 1
+        }
+    };
+    apply_cascade(contents, &opts.default_language, &opts.rtl_languages);
+
+    let site = &session.site;
+    let page = &site.content[index];
+    let html = render_page(&session.templates, page, site)
+        .map_err(|err| RpcError::from_error(RENDER_FAILED, &err))?;
+
+    Ok(json!({ "slug": page.slug, "html": html }))
+}
+
+// answers one message, returning the response (none for notifications) and
+// whether to stop
+fn handle(
+    opts: &Options,
+    session: &mut Session,
+    highlighter: &PulldownHighlighter,
+    message: &str,
+) -> (Option<Value>, bool) {
+    let request: Value = match serde_json::from_str(message) {
+        Ok(request) => request,
+        Err(err) => {
+            let failure = RpcError::new(PARSE_ERROR, err.to_string());
+            return (Some(response(&Value::Null, Err(failure))), false);
+        }
+    };
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let mut stop = false;
+    let result = match method {
+        "render" => {
+            let path = params.get("path").and_then(Value::as_str);
+            let text = params.get("text").and_then(Value::as_str);
+            match (path, text) {
+                (Some(path), Some(text)) => render(opts, session, highlighter, path, text),
+                _ => Err(RpcError::new(
+                    INVALID_PARAMS,
+                    "render takes a `path` and the buffer's `text`",
+                )),
+            }
+        }
+        "reload" => match load(opts) {
+            Ok(reloaded) => {
+                *session = reloaded;
+                Ok(Value::Null)
+            }
+            Err(err) => Err(RpcError::from_error(RENDER_FAILED, &err)),
+        },
+        "shutdown" => {
+            stop = true;
+            Ok(Value::Null)
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no such method `{method}`"),
+        )),
+    };
+
+    (id.map(|id| response(&id, result)), stop)
+}
+
+fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(failure) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": failure.code,
+                "message": failure.message,
+                "data": { "causes": failure.causes },
+            },
+        }),
+    }
+}
+
+/// Loads the site once, then answers JSON-RPC 2.0 requests on stdin, one
+/// per line, with responses on stdout:
+///
+/// - `render` with `path` and `text` renders the buffer as that page, with
+///   its layout and the rest of the site as loaded
+/// - `reload` loads the site again, after layouts or other pages change
+/// - `shutdown` stops
+///
+/// Diagnostics go to stderr so they don't get mixed in with responses.
+pub fn run(opts: &Options) -> io::Result<()> {
+    diagnostics::to_stderr();
+    let mut session = load(opts)?;
+    let theme = highlight_theme(&opts.theme);
+    let highlighter = PulldownHighlighter::new(SyntaxSet::load_defaults_newlines(), &theme);
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, stop) = handle(opts, &mut session, &highlighter, &line);
+        if let Some(response) = response {
+            println!("{response}");
+        }
+        if stop {
+            break;
+        }
+    }
+
+    Ok(())
+}
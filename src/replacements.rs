@@ -0,0 +1,100 @@
+use std::io;
+
+use regex::{NoExpand, Regex};
+use serde::Deserialize;
+
+/// When a rule is applied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// To the markdown of a page, before it is parsed
+    Markdown,
+    /// To a page's rendered html, before it is written
+    #[default]
+    Html,
+}
+
+/// A `[[replacements]]` entry of the site config.
+#[derive(Debug, Deserialize)]
+struct Rule {
+    pattern: String,
+    replacement: String,
+    /// Treat `pattern` as a regular expression, with `$1` style groups in
+    /// `replacement`
+    #[serde(default)]
+    regex: bool,
+    /// Only apply to content paths matching this glob, e.g. `blog/**`
+    paths: Option<String>,
+    #[serde(default)]
+    stage: Stage,
+}
+
+struct Compiled {
+    pattern: Regex,
+    replacement: String,
+    expand: bool,
+    paths: Option<glob::Pattern>,
+    stage: Stage,
+}
+
+/// The site's substitution rules, for site-wide fixes like rewriting an
+/// old domain without editing every page.
+#[derive(Default)]
+pub struct Replacements(Vec<Compiled>);
+
+fn invalid(config: &str, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{config}: {err}"))
+}
+
+/// Reads the `[[replacements]]` of `config`, which is fine to be missing.
+pub fn load(config: &str) -> io::Result<Replacements> {
+    let rules: Vec<Rule> = crate::config::table(config, "replacements")?;
+
+    let mut compiled = Vec::new();
+    for rule in rules.into_iter() {
+        let pattern = if rule.regex {
+            Regex::new(&rule.pattern)
+        } else {
+            Regex::new(&regex::escape(&rule.pattern))
+        }
+        .map_err(|err| invalid(config, err))?;
+
+        let paths = rule
+            .paths
+            .as_deref()
+            .map(glob::Pattern::new)
+            .transpose()
+            .map_err(|err| invalid(config, err))?;
+
+        compiled.push(Compiled {
+            pattern,
+            replacement: rule.replacement,
+            expand: rule.regex,
+            paths,
+            stage: rule.stage,
+        });
+    }
+
+    Ok(Replacements(compiled))
+}
+
+impl Replacements {
+    /// Applies the rules for `stage` that match the content `path`.
+    pub fn apply(&self, stage: Stage, path: &str, text: String) -> String {
+        self.0
+            .iter()
+            .filter(|rule| rule.stage == stage)
+            .filter(|rule| rule.paths.as_ref().is_none_or(|p| p.matches(path)))
+            .fold(text, |text, rule| {
+                if rule.expand {
+                    rule.pattern
+                        .replace_all(&text, rule.replacement.as_str())
+                        .to_string()
+                } else {
+                    rule.pattern
+                        .replace_all(&text, NoExpand(&rule.replacement))
+                        .to_string()
+                }
+            })
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use pulldown_cmark::{CowStr, Event, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::{build_info, diagnostics::Diagnostic};
+
+/// A file attached to a page with the `resources` frontmatter field, copied
+/// next to the page's output.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Resource {
+    pub src: String,
+    pub title: Option<String>,
+    /// Where the copied file is served from
+    #[serde(default)]
+    pub url: String,
+    /// Size in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// Hex encoded SHA-256 of the file
+    #[serde(default)]
+    pub sha256: String,
+    #[serde(skip)]
+    pub source: PathBuf,
+}
+
+impl Resource {
+    fn new(src: &str, title: Option<&str>) -> Self {
+        Self {
+            src: src.to_string(),
+            title: title.map(|t| t.to_string()),
+            url: String::new(),
+            size: 0,
+            sha256: String::new(),
+            source: PathBuf::new(),
+        }
+    }
+}
+
+/// Reads a `resources` field, either a YAML style list like
+/// `[{src: "slides.pdf", title: "Slides"}, "notes.txt"]` or a comma
+/// separated list of paths.
+pub fn parse(value: &str) -> Vec<Resource> {
+    match serde_yaml::from_str::<Vec<serde_yaml::Value>>(value) {
+        Ok(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                serde_yaml::Value::String(src) => Some(Resource::new(src, None)),
+                serde_yaml::Value::Mapping(_) => item["src"]
+                    .as_str()
+                    .map(|src| Resource::new(src, item["title"].as_str())),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => value
+            .split(',')
+            .map(|src| src.trim())
+            .filter(|src| !src.is_empty())
+            .map(|src| Resource::new(src, None))
+            .collect(),
+    }
+}
+
+fn is_contained(src: &str) -> bool {
+    Path::new(src)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Finds each resource relative to the page's source folder and fills in its
+/// url, size and checksum. Resources which are missing or point outside the
+/// page's folder are reported and dropped.
+pub fn resolve(
+    resources: Vec<Resource>,
+    page_path: &str,
+    source_dir: &Path,
+    slug: &str,
+) -> Vec<Resource> {
+    let mut resolved = Vec::new();
+
+    for mut resource in resources.into_iter() {
+        if !is_contained(&resource.src) {
+            Diagnostic::error(format!(
+                "Resource {} must be inside the page's folder",
+                resource.src
+            ))
+            .in_file(page_path)
+            .print();
+            continue;
+        }
+
+        let source = source_dir.join(&resource.src);
+        let metadata =
+            fs::metadata(&source).and_then(|m| Ok((m.len(), build_info::hash_file(&source)?)));
+        match metadata {
+            Ok((size, sha256)) => {
+                resource.url = format!(
+                    "{}/{}",
+                    slug.trim_end_matches('/'),
+                    resource.src.trim_start_matches("./")
+                );
+                resource.size = size;
+                resource.sha256 = sha256;
+                resource.source = source;
+                resolved.push(resource);
+            }
+            Err(err) => Diagnostic::error(format!("Couldn't read resource {}", resource.src))
+                .in_file(page_path)
+                .note(err.to_string())
+                .print(),
+        }
+    }
+
+    resolved
+}
+
+// `path` with `.` and `..` worked out, unless it climbs out of its root
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normal.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if normal.pop() => {}
+            _ => return None,
+        }
+    }
+    Some(normal)
+}
+
+// images elsewhere on the site, linked to on purpose, and ones embedded in
+// the page are left alone
+fn is_local(src: &str) -> bool {
+    !(src.is_empty()
+        || src.contains("://")
+        || src.starts_with('/')
+        || src.starts_with('#')
+        || src.starts_with("data:"))
+}
+
+/// Points images the markdown gives relative to the page (like
+/// `./diagram.png`) at copies next to the page's output, returning those
+/// copies as resources. Images in the page's folder are copied with the
+/// page; others elsewhere in the content folder are already copied with
+/// the static files, so they're only pointed at.
+pub fn images<'a>(
+    events: Vec<Event<'a>>,
+    page_path: &str,
+    content_dir: &Path,
+    slug: &str,
+) -> (Vec<Event<'a>>, Vec<Resource>) {
+    let page_dir = Path::new(page_path).parent().unwrap_or(Path::new(""));
+    let mut images: Vec<Resource> = Vec::new();
+
+    let mut rewrite = |src: &CowStr<'a>| -> Option<CowStr<'a>> {
+        if !is_local(src) {
+            return None;
+        }
+        let (path, suffix) = match src.find(['?', '#']) {
+            Some(end) => src.split_at(end),
+            None => (&src[..], ""),
+        };
+
+        if is_contained(path) {
+            if let Some(image) = images.iter().find(|image| image.src == path) {
+                return Some(CowStr::from(format!("{}{suffix}", image.url)));
+            }
+            let image = resolve(
+                vec![Resource::new(path, None)],
+                page_path,
+                &content_dir.join(page_dir),
+                slug,
+            )
+            .pop()?;
+            let url = format!("{}{suffix}", image.url);
+            images.push(image);
+            return Some(CowStr::from(url));
+        }
+
+        match normalize(&page_dir.join(path)) {
+            Some(shared) if content_dir.join(&shared).is_file() => Some(CowStr::from(format!(
+                "/{}{suffix}",
+                shared.to_string_lossy()
+            ))),
+            _ => {
+                Diagnostic::warning(format!("Image {path} isn't in the content folder"))
+                    .in_file(page_path)
+                    .print();
+                None
+            }
+        }
+    };
+
+    let mut current = None;
+    let events = events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Image(kind, src, title)) => {
+                let src = rewrite(&src).unwrap_or(src);
+                current = Some(src.clone());
+                Event::Start(Tag::Image(kind, src, title))
+            }
+            Event::End(Tag::Image(kind, src, title)) => {
+                Event::End(Tag::Image(kind, current.take().unwrap_or(src), title))
+            }
+            event => event,
+        })
+        .collect();
+
+    (events, images)
+}
+
+/// Copies resources into the page's output folder.
+pub fn copy(resources: &[Resource], out_dir: &Path) -> io::Result<()> {
+    for resource in resources.iter() {
+        let out_path = out_dir.join(&resource.src);
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&resource.source, out_path)?;
+    }
+
+    Ok(())
+}
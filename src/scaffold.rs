@@ -0,0 +1,143 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+// a 1x1 transparent png, to be replaced with a real screenshot
+const SCREENSHOT: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F, 0x15, 0xC4,
+    0x89, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x00, 0x01, 0x00, 0x00,
+    0x05, 0x00, 0x01, 0x0D, 0x0A, 0x2D, 0xB4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE,
+    0x42, 0x60, 0x82,
+];
+
+const BASE_LAYOUT: &str = r#"<!DOCTYPE html>
+<html lang="{{ page.lang }}" dir="{{ page.lang_dir }}">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>{% block title %}{{ page.frontmatter.title | default(value="") }}{% endblock title %}</title>
+  <link rel="stylesheet" href="/style.css">
+</head>
+<body>
+  <main>
+    {% block content %}{% endblock content %}
+  </main>
+</body>
+</html>
+"#;
+
+const INDEX_LAYOUT: &str = r#"{% extends "base.html" %}
+
+{% block content %}
+{{ page.content }}
+{% endblock content %}
+"#;
+
+const PAGE_LAYOUT: &str = r#"{% extends "base.html" %}
+
+{% block content %}
+<article>
+  {% if page.frontmatter.title %}<h1>{{ page.frontmatter.title }}</h1>{% endif %}
+  {{ page.content }}
+</article>
+{% endblock content %}
+"#;
+
+const SECTION_LAYOUT: &str = r#"{% extends "base.html" %}
+
+{% block content %}
+{{ page.content }}
+
+{% set section = page.path | split(pat="/") | first %}
+<ul>
+  {% for post in data[section] | default(value=[]) %}
+  {% if post.slug != page.slug %}
+  <li><a href="{{ post.slug }}">{{ post.frontmatter.title | default(value=post.slug) }}</a></li>
+  {% endif %}
+  {% endfor %}
+</ul>
+{% endblock content %}
+"#;
+
+const STYLE: &str = r#"$text: #222;
+$background: #fdfdfd;
+
+body {
+  color: $text;
+  background: $background;
+  font-family: system-ui, sans-serif;
+  line-height: 1.5;
+}
+
+main {
+  max-width: 42rem;
+  margin: 0 auto;
+}
+"#;
+
+fn theme_metadata(name: &str) -> String {
+    format!(
+        r#"name = "{name}"
+version = "0.1.0"
+description = ""
+author = ""
+# the oldest roxy release this theme works with
+min_roxy_version = "{}"
+# site settings the theme's layouts expect to exist
+required_config = []
+"#,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn theme_readme(name: &str) -> String {
+    format!(
+        "# {name}\n\nA theme for [Roxy](https://github.com/kitsunecafe/roxy).\n\n\
+         - `theme.toml` describes the theme\n\
+         - `layouts/` holds the Tera layouts; pages pick one with the `layout` frontmatter field\n\
+         - `sass/` holds the stylesheets\n\
+         - `static/` is copied into the site as is\n\
+         - `screenshot.png` is shown in theme listings\n"
+    )
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents)
+}
+
+/// Writes the starter layouts into `dir`.
+pub fn starter_layouts(dir: &Path) -> io::Result<()> {
+    write_file(dir.join("base.html"), BASE_LAYOUT.as_bytes())?;
+    write_file(dir.join("index.html"), INDEX_LAYOUT.as_bytes())?;
+    write_file(dir.join("page.html"), PAGE_LAYOUT.as_bytes())?;
+    write_file(dir.join("section.html"), SECTION_LAYOUT.as_bytes())
+}
+
+/// Creates the skeleton of a theme package named `name` inside `themes_dir`.
+pub fn new_theme(themes_dir: &str, name: &str) -> io::Result<()> {
+    let root = Path::new(themes_dir).join(name);
+    if root.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", root.to_string_lossy()),
+        ));
+    }
+
+    write_file(root.join("theme.toml"), theme_metadata(name).as_bytes())?;
+    write_file(root.join("README.md"), theme_readme(name).as_bytes())?;
+    starter_layouts(&root.join("layouts"))?;
+    write_file(root.join("sass/style.scss"), STYLE.as_bytes())?;
+    write_file(root.join("static/.gitkeep"), b"")?;
+    write_file(root.join("screenshot.png"), SCREENSHOT)?;
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Content;
+
+/// When the site next needs building because a page becomes publishable
+/// or expires.
+#[derive(Debug)]
+pub struct NextRebuild {
+    /// UTC, as `YYYY-MM-DDTHH:MM:SS`
+    pub at: String,
+    pub path: String,
+    /// `publish` or `expire`
+    pub reason: &'static str,
+}
+
+/// Days since 1970-01-01 to a (year, month, day) in the proleptic gregorian
+/// calendar, after Howard Hinnant's `civil_from_days`.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The other way around: a (year, month, day) to days since 1970-01-01.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The current time as `YYYY-MM-DDTHH:MM:SS`, in UTC.
+pub fn now() -> String {
+    timestamp(SystemTime::now())
+}
+
+/// `time` as `YYYY-MM-DDTHH:MM:SS`, in UTC.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    from_secs(secs)
+}
+
+// seconds since 1970-01-01 as `YYYY-MM-DDTHH:MM:SS`
+fn from_secs(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        time / 3600,
+        (time / 60) % 60,
+        time % 60
+    )
+}
+
+/// Turns a frontmatter date (`2024-05-01`, `2024-05-01 09:30`,
+/// `2024-05-01T09:30:00Z` or `2024-05-01T09:30:00+02:00`) into the form
+/// `now` uses so the two compare as strings. Times without an offset are
+/// taken to be UTC.
+pub fn normalize(date: &str) -> String {
+    let date = date.trim().trim_end_matches('Z').replacen(' ', "T", 1);
+    let (date, offset) = split_offset(&date);
+    let date = date.trim_end();
+    let date = match date.len() {
+        10 => format!("{date}T00:00:00"),
+        16 => format!("{date}:00"),
+        _ => date.to_string(),
+    };
+    match offset {
+        Some(minutes) if minutes != 0 => shift(&date, -minutes).unwrap_or(date),
+        _ => date,
+    }
+}
+
+// a time's `+HH:MM` or `-HHMM` offset, in minutes east of UTC, and the
+// date before it
+fn split_offset(date: &str) -> (&str, Option<i64>) {
+    let Some(at) = date
+        .get(11..)
+        .and_then(|time| time.rfind(['+', '-']))
+        .map(|at| at + 11)
+    else {
+        return (date, None);
+    };
+    let digits: String = date[at + 1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return (date, None);
+    }
+    let minutes =
+        digits[..2].parse::<i64>().unwrap_or(0) * 60 + digits[2..].parse::<i64>().unwrap_or(0);
+    let sign = if date[at..].starts_with('-') { -1 } else { 1 };
+    (&date[..at], Some(sign * minutes))
+}
+
+// a normalized `date` moved by `minutes`, keeping any fraction of a second
+fn shift(date: &str, minutes: i64) -> Option<String> {
+    let (day, time) = date.split_once('T')?;
+    let mut day = day.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (day.next()??, day.next()??, day.next()??);
+    let mut clock = time
+        .get(..8)?
+        .splitn(3, ':')
+        .map(|part| part.parse::<i64>().ok());
+    let (hours, mins, secs) = (clock.next()??, clock.next()??, clock.next()??);
+    let secs = days_from_civil(year as i64, month, day) * 86400
+        + hours * 3600
+        + mins * 60
+        + secs
+        + minutes * 60;
+    Some(from_secs(secs) + &time[8..])
+}
+
+/// The earliest `date` or `expiry_date` still in the future.
+pub fn next_rebuild(contents: &[Content], now: &str) -> Option<NextRebuild> {
+    contents
+        .iter()
+        .flat_map(|content| {
+            let frontmatter = &content.frontmatter.0;
+            [("date", "publish"), ("expiry_date", "expire")]
+                .into_iter()
+                .filter_map(move |(key, reason)| {
+                    let at = normalize(frontmatter.get(key)?);
+                    Some(NextRebuild {
+                        at,
+                        path: content.path.to_string(),
+                        reason,
+                    })
+                })
+        })
+        .filter(|next| next.at.as_str() > now)
+        .min_by(|a, b| a.at.cmp(&b.at))
+}
+
+/// Writes `next-rebuild.txt` (just the timestamp) and `next-rebuild.json`
+/// into the output, or removes them when nothing is scheduled.
+pub fn write(output: &str, next: Option<&NextRebuild>) -> io::Result<()> {
+    let txt = Path::new(output).join("next-rebuild.txt");
+    let json = Path::new(output).join("next-rebuild.json");
+
+    match next {
+        Some(next) => {
+            fs::write(txt, format!("{}Z\n", next.at))?;
+            let text = serde_json::to_string_pretty(&serde_json::json!({
+                "at": format!("{}Z", next.at),
+                "path": next.path,
+                "reason": next.reason,
+            }))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(json, text + "\n")
+        }
+        None => {
+            let _ = fs::remove_file(txt);
+            let _ = fs::remove_file(json);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(normalize("2024-05-01"), "2024-05-01T00:00:00");
+        assert_eq!(normalize("2024-05-01 09:30"), "2024-05-01T09:30:00");
+        assert_eq!(normalize(" 2024-05-01T09:30:00Z "), "2024-05-01T09:30:00");
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(
+            normalize("2024-05-01T09:30:00+02:00"),
+            "2024-05-01T07:30:00"
+        );
+        assert_eq!(normalize("2024-05-01T22:30:00-0300"), "2024-05-02T01:30:00");
+        assert_eq!(normalize("2024-03-01 00:15 +01:00"), "2024-02-29T23:15:00");
+        assert_eq!(
+            normalize("2024-05-01T09:30:00.250+00:00"),
+            "2024-05-01T09:30:00.250"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use pulldown_cmark::{Event, Tag};
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+use tera::Value;
+
+use crate::{i18n, Content};
+
+const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "he",
+    "her", "his", "i", "if", "in", "into", "is", "it", "its", "me", "my", "no", "not", "of", "on",
+    "or", "our", "she", "so", "such", "that", "the", "their", "then", "there", "these", "they",
+    "this", "to", "was", "we", "were", "will", "with", "you", "your",
+];
+
+const FRENCH_STOPWORDS: &[&str] = &[
+    "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "et", "eux", "il",
+    "je", "la", "le", "les", "leur", "lui", "ma", "mais", "me", "mes", "moi", "mon", "ne", "nos",
+    "notre", "nous", "on", "ou", "par", "pas", "pour", "qu", "que", "qui", "sa", "se", "ses",
+    "son", "sur", "ta", "te", "tes", "toi", "ton", "tu", "un", "une", "vos", "votre", "vous",
+];
+
+const GERMAN_STOPWORDS: &[&str] = &[
+    "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "bin", "bis", "das", "dass", "dem",
+    "den", "der", "des", "die", "du", "ein", "eine", "einem", "einen", "einer", "er", "es", "für",
+    "hat", "ich", "ihr", "im", "in", "ist", "mit", "nach", "nicht", "noch", "nur", "oder", "sie",
+    "sind", "so", "um", "und", "uns", "von", "vor", "war", "was", "wie", "wir", "zu", "zum", "zur",
+];
+
+const SPANISH_STOPWORDS: &[&str] = &[
+    "a", "al", "como", "con", "de", "del", "el", "ella", "en", "es", "esta", "este", "la", "las",
+    "le", "les", "lo", "los", "me", "mi", "no", "nos", "o", "para", "pero", "por", "que", "se",
+    "si", "sin", "su", "sus", "te", "tu", "un", "una", "uno", "y", "ya", "yo",
+];
+
+/// Splits text into normalised search terms for one language: lowercased,
+/// stopwords removed, and stemmed where a Snowball stemmer exists.
+pub struct Analyzer {
+    stemmer: Option<Stemmer>,
+    stopwords: &'static [&'static str],
+}
+
+impl Analyzer {
+    pub fn for_language(lang: &str) -> Self {
+        let primary = lang.split(['-', '_']).next().unwrap_or(lang);
+        let algorithm = match primary.to_ascii_lowercase().as_str() {
+            "ar" => Some(Algorithm::Arabic),
+            "da" => Some(Algorithm::Danish),
+            "de" => Some(Algorithm::German),
+            "el" => Some(Algorithm::Greek),
+            "en" => Some(Algorithm::English),
+            "es" => Some(Algorithm::Spanish),
+            "fi" => Some(Algorithm::Finnish),
+            "fr" => Some(Algorithm::French),
+            "hu" => Some(Algorithm::Hungarian),
+            "it" => Some(Algorithm::Italian),
+            "nl" => Some(Algorithm::Dutch),
+            "no" | "nb" | "nn" => Some(Algorithm::Norwegian),
+            "pt" => Some(Algorithm::Portuguese),
+            "ro" => Some(Algorithm::Romanian),
+            "ru" => Some(Algorithm::Russian),
+            "sv" => Some(Algorithm::Swedish),
+            "ta" => Some(Algorithm::Tamil),
+            "tr" => Some(Algorithm::Turkish),
+            _ => None,
+        };
+
+        let stopwords = match primary.to_ascii_lowercase().as_str() {
+            "en" => ENGLISH_STOPWORDS,
+            "fr" => FRENCH_STOPWORDS,
+            "de" => GERMAN_STOPWORDS,
+            "es" => SPANISH_STOPWORDS,
+            _ => &[],
+        };
+
+        Self {
+            stemmer: algorithm.map(Stemmer::create),
+            stopwords,
+        }
+    }
+
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        tokenize(text)
+            .into_iter()
+            .filter(|token| !self.stopwords.contains(&token.as_str()))
+            .map(|token| match &self.stemmer {
+                Some(stemmer) => stemmer.stem(&token).to_string(),
+                None => token,
+            })
+            .collect()
+    }
+}
+
+// scripts written without spaces between words, which are indexed per character
+fn is_unspaced(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF // hiragana, katakana
+        | 0x3400..=0x4DBF // CJK extension A
+        | 0x4E00..=0x9FFF // CJK unified ideographs
+        | 0x0E00..=0x0E7F // thai
+    )
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+
+    for c in text.chars() {
+        if is_unspaced(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push(c.to_string());
+        } else if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+    }
+
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+
+    tokens
+}
+
+/// Tera filter turning text into search terms, e.g.
+/// `page.content | striptags | search_terms(lang="fr")`. Defaults to the
+/// language of the page being rendered.
+pub fn search_terms(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("`search_terms` can only be applied to strings"))?;
+
+    let lang = args
+        .get("lang")
+        .and_then(Value::as_str)
+        .map(|s| s.to_string())
+        .or_else(i18n::current_language)
+        .unwrap_or_else(|| "en".to_string());
+
+    let terms = Analyzer::for_language(&lang).terms(text);
+    Ok(Value::Array(terms.into_iter().map(Value::String).collect()))
+}
+
+/// The plain text of a page's markdown, without its code blocks, for the
+/// search index.
+pub fn plain_text(events: &[Event]) -> String {
+    let mut text = String::new();
+    let mut in_code = false;
+    for event in events.iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            Event::Text(t) | Event::Code(t) if !in_code => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => {
+                if !text.is_empty() && !text.ends_with(' ') {
+                    text.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+// longer excerpts are cut at the last word that fits
+const EXCERPT_LENGTH: usize = 160;
+
+fn cut(text: &str) -> String {
+    if text.chars().count() <= EXCERPT_LENGTH {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(EXCERPT_LENGTH).collect();
+    match cut.rsplit_once(' ') {
+        Some((words, _)) => format!("{words}…"),
+        None => format!("{cut}…"),
+    }
+}
+
+fn excerpt(content: &Content) -> String {
+    let frontmatter = &content.frontmatter.0;
+    if let Some(summary) = frontmatter
+        .get("description")
+        .or_else(|| frontmatter.get("summary"))
+    {
+        return summary.to_string();
+    }
+    cut(&content.text)
+}
+
+/// One page, or one section of a page, in `search_index.json`.
+#[derive(Serialize)]
+struct Document<'a> {
+    title: &'a str,
+    slug: String,
+    excerpt: String,
+    body: &'a str,
+    lang: &'a str,
+    /// The title and body as `search_terms` makes them, for the language
+    terms: Vec<String>,
+    /// The title of the page a section is in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<&'a str>,
+}
+
+/// The search index of `contents`, a JSON array with the `title`, `slug`,
+/// `excerpt`, plain text `body`, `lang` and search `terms` of every page,
+/// then of each of its headings' sections, as lunr, elasticlunr and Fuse
+/// index it. Pages with `search: false` are left out.
+pub fn index(contents: &[Content]) -> String {
+    let mut documents: Vec<Document> = Vec::new();
+    for content in contents.iter() {
+        if content.frontmatter.0.get("search").map(|v| v.trim()) == Some("false") {
+            continue;
+        }
+        let analyzer = Analyzer::for_language(&content.lang);
+        let title = content
+            .frontmatter
+            .0
+            .get("title")
+            .map(|title| title.as_str())
+            .unwrap_or_default();
+        documents.push(Document {
+            title,
+            slug: content.slug.to_string(),
+            excerpt: excerpt(content),
+            body: &content.text,
+            lang: &content.lang,
+            terms: analyzer.terms(&format!("{title} {}", content.text)),
+            page: None,
+        });
+        for heading in content.headings.iter() {
+            documents.push(Document {
+                title: &heading.title,
+                slug: format!("{}#{}", content.slug, heading.anchor),
+                excerpt: cut(&heading.text),
+                body: &heading.text,
+                lang: &content.lang,
+                terms: analyzer.terms(&format!("{} {}", heading.title, heading.text)),
+                page: Some(title),
+            });
+        }
+    }
+    serde_json::to_string(&documents).unwrap_or_default()
+}
+
+/// Writes the search index of `contents` to `search_index.json` in `output`.
+pub fn write_index(output: &str, contents: &[Content]) -> io::Result<()> {
+    fs::write(Path::new(output).join("search_index.json"), index(contents))
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use glob::glob;
+
+use crate::{
+    build_site, diagnostics,
+    interrupt::{self, Interrupt},
+    memory::{Found, Memory},
+    Options,
+};
+
+/// Where pages listen for reloads.
+const RELOAD_PATH: &str = "/_roxy/reload";
+
+/// Added to every html page served, to reload it after each rebuild.
+const RELOAD_SCRIPT: &str =
+    r#"<script>new EventSource("/_roxy/reload").onmessage = () => location.reload();</script>"#;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Counts rebuilds, waking pages waiting for the next one.
+struct Reloads {
+    count: Mutex<u64>,
+    rebuilt: Condvar,
+    /// Stops the waiting on Ctrl-C
+    interrupt: Option<Arc<Interrupt>>,
+}
+
+impl Reloads {
+    fn current(&self) -> u64 {
+        *self.count.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn bump(&self) {
+        *self.count.lock().unwrap_or_else(|err| err.into_inner()) += 1;
+        self.rebuilt.notify_all();
+    }
+
+    // waits until there has been a rebuild since `seen`, or none if the
+    // server is stopped first
+    fn wait(&self, seen: u64) -> Option<u64> {
+        let mut count = self.count.lock().unwrap_or_else(|err| err.into_inner());
+        while *count == seen {
+            if self.interrupt.as_ref().is_some_and(|i| i.requested()) {
+                return None;
+            }
+            count = self
+                .rebuilt
+                .wait_timeout(count, POLL_INTERVAL)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+        Some(*count)
+    }
+}
+
+/// Everything a rebuild depends on: the content, layouts, translations,
+/// data, config and theme.
+fn watched(opts: &Options) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = [&opts.content, &opts.layouts, &opts.i18n, &opts.data]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    paths.push(PathBuf::from(&opts.config));
+    if let Some(theme) = &opts.site_theme {
+        paths.push(Path::new(&opts.themes).join(theme));
+    }
+    paths
+}
+
+/// When each watched file was last modified.
+fn snapshot(paths: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    let mut times = BTreeMap::new();
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    for path in paths.iter() {
+        if path.is_file() {
+            times.extend(modified(path).map(|time| (path.to_path_buf(), time)));
+            continue;
+        }
+
+        let pattern = format!("{}/**/*", glob::Pattern::escape(&path.to_string_lossy()));
+        for file in glob(&pattern).into_iter().flatten().flatten() {
+            if file.is_file() {
+                times.extend(modified(&file).map(|time| (file.to_path_buf(), time)));
+            }
+        }
+    }
+
+    times
+}
+
+/// The files added, changed or removed between two snapshots.
+fn changes(
+    before: &BTreeMap<PathBuf, SystemTime>,
+    after: &BTreeMap<PathBuf, SystemTime>,
+) -> BTreeSet<PathBuf> {
+    let changed = after
+        .iter()
+        .filter(|(path, time)| before.get(*path) != Some(*time))
+        .map(|(path, _)| path);
+    let removed = before.keys().filter(|path| !after.contains_key(*path));
+    changed.chain(removed).cloned().collect()
+}
+
+/// Where requests are answered from.
+enum Source {
+    /// The output folder, written by every rebuild
+    Output,
+    /// The site compiled in memory, once it has compiled
+    Memory(RwLock<Option<Memory>>),
+}
+
+impl Source {
+    fn find(&self, output: &str, path: &Path) -> Option<Found> {
+        match self {
+            Source::Output => resolve(output, path).map(Found::File),
+            Source::Memory(memory) => memory
+                .read()
+                .unwrap_or_else(|err| err.into_inner())
+                .as_ref()?
+                .find(path),
+        }
+    }
+}
+
+fn rebuild(opts: &Options, source: &Source) {
+    diagnostics::reset();
+    let result = match source {
+        Source::Output => build_site(opts).map(|_| ()),
+        // the last site that compiled is kept serving until one does again
+        Source::Memory(memory) => Memory::load(opts).map(|site| {
+            *memory.write().unwrap_or_else(|err| err.into_inner()) = Some(site);
+        }),
+    };
+    if let Err(err) = result {
+        diagnostics::report(&err);
+    }
+}
+
+// rebuilds whenever a watched file is added, changed or removed, once the
+// files have stopped changing for `debounce`. Editors save in bursts (a
+// temporary file, a rename, a chmod), which this turns into one rebuild, and
+// a file that's back to how it was, like a temporary one already removed,
+// doesn't count.
+fn watch(opts: &Options, source: &Source, reloads: &Reloads, debounce: Duration) {
+    let paths = watched(opts);
+    let mut last = snapshot(&paths);
+
+    while !interrupt::requested(opts) {
+        thread::sleep(POLL_INTERVAL);
+        let mut current = snapshot(&paths);
+        if current == last {
+            continue;
+        }
+
+        loop {
+            thread::sleep(debounce);
+            if interrupt::requested(opts) {
+                return;
+            }
+            let settled = snapshot(&paths);
+            if settled == current {
+                break;
+            }
+            current = settled;
+        }
+
+        let changed = changes(&last, &current);
+        last = current;
+        match (changed.len(), changed.first()) {
+            (0, _) => continue,
+            (1, Some(path)) => diagnostics::info(format!("{} changed, rebuilding", path.display())),
+            (count, _) => diagnostics::info(format!("{count} files changed, rebuilding")),
+        }
+        rebuild(opts, source);
+        reloads.bump();
+    }
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// The path of the site a request is for, relative to its root, unless it
+/// tries to reach outside of it.
+fn relative(path: &str) -> Option<PathBuf> {
+    let path = decode(path.split(['?', '#']).next().unwrap_or_default());
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|part| !matches!(part, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(relative.to_path_buf())
+}
+
+/// The file in `output` the `relative` path is served from, if there is one.
+fn resolve(output: &str, relative: &Path) -> Option<PathBuf> {
+    let file = Path::new(output).join(relative);
+    if file.is_dir() {
+        Some(file.join("index.html")).filter(|file| file.is_file())
+    } else if file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, kind: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {kind}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// holds the connection open, sending an event after every rebuild
+fn stream_reloads(mut stream: TcpStream, reloads: &Reloads) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let mut seen = reloads.current();
+    while let Some(count) = reloads.wait(seen) {
+        seen = count;
+        // fails once the page is closed, ending the thread
+        write!(stream, "data: reload\n\n")?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+fn handle(
+    mut stream: TcpStream,
+    output: &str,
+    source: &Source,
+    reloads: &Reloads,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+    if path == RELOAD_PATH {
+        return stream_reloads(stream, reloads);
+    }
+    // requests made while the site first compiles in memory wait for it
+    if matches!(source, Source::Memory(_)) && reloads.wait(0).is_none() {
+        return Ok(());
+    }
+
+    let found = relative(path).and_then(|path| {
+        let found = source.find(output, &path)?;
+        Some((path, found))
+    });
+    let Some((path, found)) = found else {
+        let not_found = Path::new("404.html");
+        let body = match source.find(output, not_found) {
+            Some(Found::File(file)) => fs::read(file).unwrap_or_default(),
+            Some(Found::Page(html)) => html.into_bytes(),
+            _ => b"Not found".to_vec(),
+        };
+        return respond(&mut stream, "404 Not Found", content_type(not_found), &body);
+    };
+
+    let (kind, mut body) = match found {
+        Found::File(file) if file.extension().and_then(|ext| ext.to_str()) != Some("html") => {
+            return respond_file(&mut stream, &file, method == "HEAD");
+        }
+        Found::File(file) => (
+            content_type(&file),
+            inject_reload(&String::from_utf8_lossy(&fs::read(&file)?)).into_bytes(),
+        ),
+        Found::Page(html) => (
+            content_type(Path::new("index.html")),
+            inject_reload(&html).into_bytes(),
+        ),
+        Found::Text(text) => (content_type(&path), text.into_bytes()),
+        Found::Bytes(bytes) => (content_type(&path), bytes),
+        Found::Failed(message) => {
+            return respond(
+                &mut stream,
+                "500 Internal Server Error",
+                "text/plain; charset=utf-8",
+                message.as_bytes(),
+            );
+        }
+    };
+    if method == "HEAD" {
+        body.clear();
+    }
+    respond(&mut stream, "200 OK", kind, &body)
+}
+
+// streams the file rather than reading it into memory, for large media
+fn respond_file(stream: &mut TcpStream, file: &Path, head: bool) -> io::Result<()> {
+    let mut reader = fs::File::open(file)?;
+    let len = reader.metadata()?.len();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {len}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        content_type(file)
+    )?;
+    if !head {
+        io::copy(&mut reader, stream)?;
+    }
+    stream.flush()
+}
+
+fn inject_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{RELOAD_SCRIPT}{}", &html[..end], &html[end..]),
+        None => format!("{html}{RELOAD_SCRIPT}"),
+    }
+}
+
+/// Builds the site and serves the output on `address`, rebuilding when the
+/// sources have changed and then been left alone for `debounce`, and
+/// reloading open pages once it's done. Ctrl-C stops it taking connections
+/// and closes the open pages' reload streams.
+///
+/// `in_memory` compiles the site without writing it, rendering each page
+/// the first time it's requested. The server starts taking requests right
+/// away, and they wait for the site to compile.
+pub fn run(opts: &Options, address: &str, debounce: Duration, in_memory: bool) -> io::Result<()> {
+    let source = if in_memory {
+        Source::Memory(RwLock::new(None))
+    } else {
+        Source::Output
+    };
+    if !in_memory {
+        rebuild(opts, &source);
+    }
+
+    let listener = TcpListener::bind(address)?;
+    interrupt::serving(opts, listener.local_addr()?);
+    if in_memory {
+        diagnostics::info(format!("Serving the site from memory on http://{address}"));
+    } else {
+        diagnostics::info(format!("Serving {} on http://{address}", opts.output));
+    }
+
+    let reloads = Reloads {
+        count: Mutex::new(0),
+        rebuilt: Condvar::new(),
+        interrupt: opts.interrupt.clone(),
+    };
+    // problems found while serving count towards the command's
+    let tally = diagnostics::current_tally();
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            diagnostics::counting(tally.clone(), || {
+                if in_memory {
+                    rebuild(opts, &source);
+                    reloads.bump();
+                }
+                watch(opts, &source, &reloads, debounce)
+            })
+        });
+
+        for stream in listener.incoming().flatten() {
+            if interrupt::requested(opts) {
+                diagnostics::info("Stopping");
+                break;
+            }
+            let (source, reloads, tally) = (&source, &reloads, tally.clone());
+            scope.spawn(move || {
+                diagnostics::counting(tally, || {
+                    let _ = handle(stream, &opts.output, source, reloads);
+                })
+            });
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{diagnostics::Diagnostic, Content};
+
+// hex digits of the slug's hash used for generated short urls, grown when
+// two pages collide
+const CODE_LENGTH: usize = 4;
+
+/// A short url and the page it redirects to.
+#[derive(Debug, Serialize)]
+pub struct ShortUrl {
+    pub from: String,
+    pub to: String,
+}
+
+// the url with one leading slash, unless it's the root or leaves the
+// output with `..`
+fn normalize(url: &str) -> Option<String> {
+    let path = url.trim_matches('/');
+    if path.is_empty() || path.split('/').any(|part| part == "." || part == "..") {
+        return None;
+    }
+    Some(format!("/{path}"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Resolves the `short_url` frontmatter of every page. `short_url: auto`
+/// is replaced with `/<prefix>/<code>`, where the code is taken from a hash
+/// of the page's slug so it stays the same between builds. Short urls
+/// where a page is written are refused, so a redirect never replaces one.
+pub fn assign(contents: &mut [Content], prefix: &str) -> Vec<ShortUrl> {
+    let prefix = prefix.trim_matches('/');
+    let mut urls: Vec<ShortUrl> = Vec::new();
+    let pages: HashSet<PathBuf> = contents
+        .iter()
+        .filter_map(|content| crate::output_dir("", content))
+        .collect();
+
+    // explicit urls are claimed first so generated ones don't take them
+    for generated in [false, true] {
+        for content in contents.iter_mut() {
+            let short_url = match content.frontmatter.0.get("short_url") {
+                Some(short_url) => short_url.trim(),
+                None => continue,
+            };
+
+            let is_auto = short_url.eq_ignore_ascii_case("auto");
+            if is_auto != generated {
+                continue;
+            }
+
+            let from = if is_auto {
+                let hash = format!("{:x}", Sha256::digest(content.slug.as_bytes()));
+                (CODE_LENGTH..=hash.len())
+                    .map(|len| format!("/{prefix}/{}", &hash[..len]))
+                    .find(|from| urls.iter().all(|url| &url.from != from))
+                    .unwrap_or_else(|| format!("/{prefix}/{hash}"))
+            } else {
+                match normalize(short_url) {
+                    Some(from) => from,
+                    None => {
+                        Diagnostic::error(format!("Short url `{short_url}` isn't a page's url"))
+                            .in_file(&content.path)
+                            .note("give it a path like /go/post, which isn't the site's root")
+                            .print();
+                        continue;
+                    }
+                }
+            };
+
+            if pages.contains(Path::new(from.trim_start_matches('/'))) {
+                Diagnostic::error(format!("Short url {from} is where a page is written"))
+                    .in_file(&content.path)
+                    .note("a redirect there would replace the page")
+                    .print();
+                continue;
+            }
+
+            if let Some(existing) = urls.iter().find(|url| url.from == from) {
+                Diagnostic::error(format!(
+                    "Short url {from} is already used by {}",
+                    existing.to
+                ))
+                .in_file(&content.path)
+                .print();
+                continue;
+            }
+
+            content
+                .frontmatter
+                .0
+                .insert("short_url".to_string(), from.to_string());
+            urls.push(ShortUrl {
+                from,
+                to: content.slug.to_string(),
+            });
+        }
+    }
+
+    urls.sort_by(|a, b| a.from.cmp(&b.from));
+    urls
+}
+
+pub fn redirect_page(to: &str) -> String {
+    let to = escape(to);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Redirecting…</title>
+<link rel="canonical" href="{to}">
+<meta http-equiv="refresh" content="0; url={to}">
+</head>
+<body><a href="{to}">{to}</a></body>
+</html>
+"#
+    )
+}
+
+/// Writes a redirecting page for every short url, for hosts that only serve
+/// files, and the whole map as `_redirects` for hosts that read one.
+pub fn write(output: &str, urls: &[ShortUrl]) -> io::Result<()> {
+    if urls.is_empty() {
+        return Ok(());
+    }
+
+    let mut redirects = String::new();
+    for url in urls.iter() {
+        let dir = Path::new(output).join(url.from.trim_start_matches('/'));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("index.html"), redirect_page(&url.to))?;
+        redirects.push_str(&format!("{} {} 301\n", url.from, url.to));
+    }
+
+    fs::write(Path::new(output).join("_redirects"), redirects)
+}
@@ -0,0 +1,468 @@
+use std::{ops::Range, sync::OnceLock};
+
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Options, Parser, Tag};
+use regex::Regex;
+use tera::{Context, Tera};
+
+use crate::{
+    diagnostics::{Diagnostic, Failure},
+    typography,
+};
+
+// marks where a shortcode was in the markdown, so its html can be put back
+// once the markdown is parsed; private use characters mean nothing to
+// markdown
+const MARK: char = '\u{E000}';
+
+fn tag_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?s)\{\{<\s*(/)?\s*([A-Za-z0-9_-]+)(.*?)(/)?\s*>\}\}").unwrap()
+    })
+}
+
+/// The templates `markdown` calls as shortcodes, by name.
+pub fn templates(markdown: &str) -> Vec<String> {
+    tag_pattern()
+        .captures_iter(markdown)
+        .map(|c| format!("shortcodes/{}.html", &c[2]))
+        .collect()
+}
+
+/// A `{{< name ... >}}` or `{{< /name >}}` in the markdown.
+struct ShortcodeTag {
+    range: Range<usize>,
+    name: String,
+    args: String,
+    closing: bool,
+    /// Written `{{< name />}}`, so it has no body
+    self_closing: bool,
+}
+
+// code blocks and inline code, where shortcodes are left as they are
+fn code_ranges(markdown: &str, options: Options) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, options)
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Start(Tag::CodeBlock(_)) | Event::Code(_)))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+fn tags(markdown: &str, options: Options) -> Vec<ShortcodeTag> {
+    let code = code_ranges(markdown, options);
+    tag_pattern()
+        .captures_iter(markdown)
+        .filter_map(|c| {
+            let whole = c.get(0)?;
+            if code.iter().any(|range| range.contains(&whole.start())) {
+                return None;
+            }
+            Some(ShortcodeTag {
+                range: whole.range(),
+                name: c[2].to_string(),
+                args: c[3].to_string(),
+                closing: c.get(1).is_some(),
+                self_closing: c.get(4).is_some(),
+            })
+        })
+        .collect()
+}
+
+// `id="abc" start=30 autoplay=true`, as the context a shortcode's template
+// is rendered with
+fn arguments(args: &str) -> Result<Context, String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r#"([A-Za-z_][A-Za-z0-9_]*)\s*=\s*("(?:[^"\\]|\\.)*"|'[^']*'|[^\s"']+)"#)
+            .unwrap()
+    });
+    let mut context = Context::new();
+    let mut rest = args.to_string();
+    for c in pattern.captures_iter(args) {
+        let value = &c[2];
+        let value = if let Some(value) = value.strip_prefix('\'') {
+            tera::Value::String(value.trim_end_matches('\'').to_string())
+        } else if value.starts_with('"') {
+            serde_json::from_str(value).map_err(|err| format!("{value}: {err}"))?
+        } else {
+            // numbers and booleans, anything else is a string
+            serde_json::from_str(value).unwrap_or_else(|_| tera::Value::String(value.to_string()))
+        };
+        context.insert(&c[1], &value);
+        rest = rest.replacen(&c[0], "", 1);
+    }
+
+    match rest.trim() {
+        "" => Ok(context),
+        rest => Err(format!("`{rest}` isn't a `name=value` argument")),
+    }
+}
+
+fn escaped(text: &str) -> String {
+    let mut out = String::new();
+    let _ = escape_html(&mut out, text);
+    out
+}
+
+/// A page's markdown with its shortcodes taken out, to be parsed without
+/// them and have their html put back with `insert`.
+///
+/// `{{< youtube id="abc" >}}` renders `shortcodes/youtube.html` with `id`,
+/// and `{{< note >}}text{{< /note >}}` renders `shortcodes/note.html` with
+/// the text, as html, in `body`. Shortcodes in code are left as they are.
+pub struct Expanded {
+    pub markdown: String,
+    html: Vec<String>,
+    /// Unknown shortcodes and ones that didn't render, which are left in the
+    /// page as they were written
+    pub problems: Vec<Diagnostic>,
+}
+
+/// Takes the shortcodes out of `markdown`, from the page at `file`, and
+/// renders them with `templates`.
+pub fn expand(markdown: &str, options: Options, templates: &Tera, file: &str) -> Expanded {
+    expand_with(markdown, options, templates, file, true)
+}
+
+// with `raw`, the html of each shortcode is kept out of rendering the
+// page's html as a template after, which it already was; a body is only
+// made raw with the shortcode it's in
+fn expand_with(
+    markdown: &str,
+    options: Options,
+    templates: &Tera,
+    file: &str,
+    raw: bool,
+) -> Expanded {
+    let mut expanded = Expanded {
+        markdown: String::new(),
+        html: Vec::new(),
+        problems: Vec::new(),
+    };
+    let tags = tags(markdown, options);
+
+    let mut end = 0;
+    let mut i = 0;
+    while i < tags.len() {
+        let tag = &tags[i];
+        i += 1;
+        if tag.closing || tag.range.start < end {
+            continue;
+        }
+
+        // a body runs to the matching closing tag, shortcodes in it included
+        let mut depth = 0;
+        let mut close = None;
+        for (j, other) in tags.iter().enumerate().skip(i) {
+            if other.name != tag.name {
+                continue;
+            }
+            if !other.closing {
+                depth += 1;
+            } else if depth > 0 {
+                depth -= 1;
+            } else {
+                close = Some(j);
+                break;
+            }
+        }
+        let (range, body) = match close {
+            Some(j) if !tag.self_closing => {
+                i = j + 1;
+                let body = &markdown[tag.range.end..tags[j].range.start];
+                (tag.range.start..tags[j].range.end, Some(body))
+            }
+            _ => (tag.range.clone(), None),
+        };
+
+        expanded.markdown.push_str(&markdown[end..range.start]);
+        let html = match render(tag, body, options, templates, file, &mut expanded.problems) {
+            Ok(html) => html,
+            Err(problem) => {
+                expanded.problems.push(problem);
+                escaped(&markdown[range.clone()])
+            }
+        };
+        expanded
+            .markdown
+            .push_str(&format!("{MARK}{}{MARK}", expanded.html.len()));
+        expanded.html.push(if raw {
+            format!("{{% raw %}}{html}{{% endraw %}}")
+        } else {
+            html
+        });
+        end = range.end;
+    }
+    expanded.markdown.push_str(&markdown[end..]);
+    expanded
+}
+
+fn render(
+    tag: &ShortcodeTag,
+    body: Option<&str>,
+    options: Options,
+    templates: &Tera,
+    file: &str,
+    problems: &mut Vec<Diagnostic>,
+) -> Result<String, Diagnostic> {
+    let template = format!("shortcodes/{}.html", tag.name);
+    if !templates.get_template_names().any(|name| name == template) {
+        return Err(
+            Diagnostic::error(format!("Unknown shortcode `{}`", tag.name))
+                .failure(Failure::Template)
+                .in_file(file)
+                .note(format!("shortcodes are layouts in {template}")),
+        );
+    }
+
+    let mut context = arguments(&tag.args).map_err(|err| {
+        Diagnostic::error(format!("Couldn't read the arguments of `{}`", tag.name))
+            .failure(Failure::Template)
+            .in_file(file)
+            .note(err)
+    })?;
+    if let Some(body) = body {
+        let expanded = expand_with(body, options, templates, file, false);
+        let mut html = String::new();
+        let parser = typography::parse(&expanded.markdown, options);
+        pulldown_cmark::html::push_html(&mut html, expanded.insert(parser.into_iter()).into_iter());
+        context.insert("body", &html);
+        problems.extend(expanded.problems);
+    }
+
+    templates.render(&template, &context).map_err(|err| {
+        Diagnostic::error(format!("Couldn't render shortcode `{}`", tag.name))
+            .failure(Failure::Template)
+            .in_file(file)
+            .causes(&err)
+    })
+}
+
+impl Expanded {
+    fn html(&self, index: &str) -> Option<&str> {
+        let index = index.parse::<usize>().ok()?;
+        self.html.get(index).map(|html| html.as_str())
+    }
+
+    // the html of the shortcode `text` is the mark of, if that's all it is
+    fn alone(&self, text: &str) -> Option<&str> {
+        self.html(text.trim().strip_prefix(MARK)?.strip_suffix(MARK)?)
+    }
+
+    // `text` split into the text around marks, and the html of each
+    fn parts<'t>(&'t self, text: &'t str) -> Vec<Part<'t>> {
+        let mut parts = Vec::new();
+        let mut split = text.split(MARK);
+        parts.extend(split.next().map(Part::Text));
+        // marks come in pairs around an index, so every other part is one
+        while let (Some(index), rest) = (split.next(), split.next()) {
+            match self.html(index) {
+                Some(html) => parts.push(Part::Html(html)),
+                None => parts.push(Part::Text(index)),
+            }
+            parts.extend(rest.map(Part::Text));
+        }
+        parts.retain(|part| !matches!(part, Part::Text("")));
+        parts
+    }
+
+    fn flush<'a>(&self, text: &mut String, out: &mut Vec<Event<'a>>) {
+        for part in self.parts(text) {
+            out.push(match part {
+                Part::Text(text) => Event::Text(CowStr::from(text.to_string())),
+                Part::Html(html) => Event::Html(CowStr::from(html.to_string())),
+            });
+        }
+        text.clear();
+    }
+
+    /// `events`, parsed from `markdown`, with the html of each shortcode
+    /// where it was. A shortcode that is a paragraph of its own replaces
+    /// the paragraph.
+    pub fn insert<'a, I: Iterator<Item = Event<'a>>>(&self, events: I) -> Vec<Event<'a>> {
+        let mut out = Vec::new();
+        let mut text = String::new();
+
+        // markdown can split text, so neighbouring text is joined up before
+        // looking for marks
+        for event in events {
+            match event {
+                Event::Text(t) => text.push_str(&t),
+                // shortcodes inside html written in the markdown
+                Event::Html(html) if html.contains(MARK) => {
+                    self.flush(&mut text, &mut out);
+                    let html: String = self
+                        .parts(&html)
+                        .into_iter()
+                        .map(|part| match part {
+                            Part::Text(text) => text.to_string(),
+                            Part::Html(html) => html.to_string(),
+                        })
+                        .collect();
+                    out.push(Event::Html(CowStr::from(html)));
+                }
+                event => {
+                    let paragraph = matches!(event, Event::End(Tag::Paragraph))
+                        && matches!(out.last(), Some(Event::Start(Tag::Paragraph)));
+                    if let Some(html) = self.alone(&text).filter(|_| paragraph) {
+                        out.pop();
+                        out.push(Event::Html(CowStr::from(html.to_string())));
+                        text.clear();
+                        continue;
+                    }
+                    self.flush(&mut text, &mut out);
+                    out.push(event);
+                }
+            }
+        }
+        self.flush(&mut text, &mut out);
+        out
+    }
+}
+
+enum Part<'t> {
+    Text(&'t str),
+    Html(&'t str),
+}
+
+/// `events` with code blocks and inline code kept out of rendering the
+/// page's html as a template, so `{{` in code is shown as it is.
+pub fn raw_code<'a, I: Iterator<Item = Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut out = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => {
+                let code = matches!(event, Event::Code(_));
+                out.push(Event::Html(CowStr::Borrowed("{% raw %}")));
+                out.push(event);
+                if code {
+                    out.push(Event::Html(CowStr::Borrowed("{% endraw %}")));
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                out.push(event);
+                out.push(Event::Html(CowStr::Borrowed("{% endraw %}")));
+            }
+            event => out.push(event),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> Tera {
+        let mut templates = Tera::default();
+        templates
+            .add_raw_templates(vec![
+                (
+                    "shortcodes/youtube.html",
+                    "<iframe src=\"{{ id }}\" start=\"{{ start }}\"></iframe>",
+                ),
+                ("shortcodes/note.html", "<aside>{{ body | safe }}</aside>"),
+            ])
+            .unwrap();
+        templates
+    }
+
+    // `markdown` as html, after the page's template has rendered it, and
+    // the problems with its shortcodes
+    fn page(markdown: &str) -> (String, Vec<String>) {
+        let expanded = expand(markdown, Options::empty(), &templates(), "page.md");
+        let events =
+            expanded.insert(typography::parse(&expanded.markdown, Options::empty()).into_iter());
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, raw_code(events.into_iter()).into_iter());
+        let html = Tera::one_off(&html, &Context::new(), false).unwrap();
+        let problems = expanded
+            .problems
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect();
+        (html, problems)
+    }
+
+    fn html(markdown: &str) -> String {
+        let (html, problems) = page(markdown);
+        assert!(problems.is_empty(), "{problems:?}");
+        html
+    }
+
+    #[test]
+    fn arguments_are_typed() {
+        let context =
+            arguments(r#" id="abc" start=30 autoplay=true name='x y' quote="say \"hi\"" "#)
+                .unwrap();
+        assert_eq!(
+            context.into_json(),
+            serde_json::json!({
+                "id": "abc",
+                "start": 30,
+                "autoplay": true,
+                "name": "x y",
+                "quote": "say \"hi\"",
+            })
+        );
+        assert_eq!(
+            arguments("id=1 stray").unwrap_err(),
+            "`stray` isn't a `name=value` argument"
+        );
+    }
+
+    #[test]
+    fn shortcodes() {
+        assert_eq!(
+            html("{{< youtube id=\"abc\" start=30 >}}\n"),
+            "<iframe src=\"abc\" start=\"30\"></iframe>"
+        );
+        assert_eq!(
+            html("Watch {{< youtube id=\"a\" start=1 />}} now\n"),
+            "<p>Watch <iframe src=\"a\" start=\"1\"></iframe> now</p>\n"
+        );
+    }
+
+    #[test]
+    fn bodies() {
+        assert_eq!(
+            html("{{< note >}}\n*hi*\n{{< /note >}}\n"),
+            "<aside><p><em>hi</em></p>\n</aside>"
+        );
+        assert_eq!(
+            html("{{< note >}}a {{< note >}}b{{< /note >}}{{< /note >}}\n"),
+            "<aside><p>a <aside><p>b</p>\n</aside></p>\n</aside>"
+        );
+    }
+
+    #[test]
+    fn code_is_left_alone() {
+        assert_eq!(
+            html("`{{< youtube id=\"x\" >}}`\n"),
+            "<p><code>{{&lt; youtube id=&quot;x&quot; &gt;}}</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn unknown_shortcodes() {
+        assert_eq!(
+            page("{{< nope >}}\n"),
+            (
+                "{{&lt; nope &gt;}}".to_string(),
+                vec!["Unknown shortcode `nope`".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn template_names() {
+        assert_eq!(
+            super::templates("{{< a >}}x{{< /a >}} {{< b />}}"),
+            [
+                "shortcodes/a.html",
+                "shortcodes/a.html",
+                "shortcodes/b.html"
+            ]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fs, io, path::Path};
+
+use crate::{schedule, Content};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn base_url(site: &toml::Table) -> Option<&str> {
+    site.get("base_url")
+        .and_then(|url| url.as_str())
+        .map(|url| url.trim_end_matches('/'))
+}
+
+// the day the page last changed: its `date`, or when its source was written
+fn last_modified(content: &Content, content_dir: &str) -> Option<String> {
+    let date = match content.frontmatter.0.get("date") {
+        Some(date) => schedule::normalize(date),
+        None => {
+            let source = Path::new(content_dir).join(&content.path);
+            schedule::timestamp(fs::metadata(source).ok()?.modified().ok()?)
+        }
+    };
+    date.get(..10).map(|day| day.to_string())
+}
+
+// the urlset of `contents`, each with the versions of it in other
+// languages as hreflang alternates
+fn urlset<'a>(
+    contents: impl Iterator<Item = &'a Content>,
+    base_url: &str,
+    content_dir: &str,
+) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">\n");
+    for content in contents {
+        if content.frontmatter.0.get("sitemap").map(|v| v.trim()) == Some("false") {
+            continue;
+        }
+        xml.push_str("  <url>\n");
+        xml.push_str(&format!(
+            "    <loc>{}</loc>\n",
+            escape(&format!("{base_url}{}", content.slug))
+        ));
+        if let Some(day) = last_modified(content, content_dir) {
+            xml.push_str(&format!("    <lastmod>{day}</lastmod>\n"));
+        }
+        for translation in content.translations.iter() {
+            xml.push_str(&format!(
+                "    <xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>\n",
+                escape(&translation.lang),
+                escape(&format!("{base_url}{}", translation.slug))
+            ));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// The `sitemap.xml` of `contents`, whose sources are in `content_dir`.
+/// Sitemaps need absolute urls, so there is none without a `base_url`.
+/// Pages with `sitemap: false` are left out.
+pub fn sitemap(contents: &[Content], site: &toml::Table, content_dir: &str) -> Option<String> {
+    Some(urlset(contents.iter(), base_url(site)?, content_dir))
+}
+
+/// The `sitemap.xml` of only the pages of `contents` in `lang`.
+pub fn language_sitemap(
+    contents: &[Content],
+    lang: &str,
+    site: &toml::Table,
+    content_dir: &str,
+) -> Option<String> {
+    let pages = contents.iter().filter(|content| content.lang == lang);
+    Some(urlset(pages, base_url(site)?, content_dir))
+}
+
+/// A `robots.txt` allowing every crawler everywhere, and pointing them at
+/// the sitemap when there is one.
+pub fn robots(site: &toml::Table) -> String {
+    let mut text = String::from("User-agent: *\nAllow: /\n");
+    if let Some(base_url) = base_url(site) {
+        text.push_str(&format!("\nSitemap: {base_url}/sitemap.xml\n"));
+    }
+    text
+}
+
+/// Writes `sitemap.xml` and, with `robots`, `robots.txt` to `output`. Both
+/// are written before the static files, so a site's own replaces them. A
+/// site in more than one of `languages` also gets a `/<lang>/sitemap.xml`
+/// for each language it has pages in.
+pub fn write(
+    output: &str,
+    contents: &[Content],
+    site: &toml::Table,
+    content_dir: &str,
+    robots: bool,
+    languages: &[String],
+) -> io::Result<()> {
+    if let Some(xml) = sitemap(contents, site, content_dir) {
+        fs::write(Path::new(output).join("sitemap.xml"), xml)?;
+    }
+    if languages.len() > 1 {
+        for lang in languages.iter() {
+            if !contents.iter().any(|content| &content.lang == lang) {
+                continue;
+            }
+            if let Some(xml) = language_sitemap(contents, lang, site, content_dir) {
+                let dir = Path::new(output).join(lang);
+                fs::create_dir_all(&dir)?;
+                fs::write(dir.join("sitemap.xml"), xml)?;
+            }
+        }
+    }
+    if robots {
+        fs::write(Path::new(output).join("robots.txt"), self::robots(site))?;
+    }
+    Ok(())
+}
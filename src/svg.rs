@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use regex::{NoExpand, Regex};
+use tera::{Function, Value};
+
+use crate::integrity;
+
+/// Whether `path` is an SVG image.
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+}
+
+/// `svg` without what editors leave in it that browsers don't draw: the
+/// XML declaration and doctype, comments, `<metadata>`, the elements and
+/// attributes of Inkscape, Sodipodi and Sketch, and the line breaks
+/// between tags.
+pub fn optimize(svg: &str) -> String {
+    let elements = Regex::new(
+        r"(?is)<\?xml.*?\?>|<!DOCTYPE[^>]*>|<!--.*?-->|<metadata\b.*?</metadata\s*>|<(?:sodipodi|inkscape|sketch):[a-z-]+\b[^>]*/>|<(?:sodipodi|inkscape|sketch):[a-z-]+\b.*?</(?:sodipodi|inkscape|sketch):[a-z-]+\s*>",
+    )
+    .unwrap();
+    let attributes = Regex::new(
+        r#"(?i)\s+(?:(?:sodipodi|inkscape|sketch):[a-z-]+|xmlns:(?:sodipodi|inkscape|sketch|rdf|dc|cc))\s*=\s*(?:"[^"]*"|'[^']*')"#,
+    )
+    .unwrap();
+    // spaces between tags can be text, line breaks are only layout
+    let line_breaks = Regex::new(r">[ \t]*\r?\n\s*<").unwrap();
+
+    let svg = elements.replace_all(svg, "");
+    let svg = attributes.replace_all(&svg, "");
+    line_breaks.replace_all(&svg, "><").trim().to_string()
+}
+
+fn attribute_value(name: &str, value: &Value) -> tera::Result<String> {
+    let value = match value {
+        Value::String(value) => value.to_string(),
+        Value::Number(_) | Value::Bool(_) => value.to_string(),
+        _ => {
+            return Err(tera::Error::msg(format!(
+                "`inline_svg`: `{name}` must be a string, number or boolean"
+            )))
+        }
+    };
+    Ok(value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;"))
+}
+
+/// `svg` with `attributes` set on its root `<svg>` tag, in place of those
+/// it has, except `class`, which is added to.
+fn set_attributes(svg: &str, attributes: &[(String, String)]) -> String {
+    let root = Regex::new(r"(?is)<svg\b[^>]*>").unwrap();
+    let Some(found) = root.find(svg) else {
+        return svg.to_string();
+    };
+    let tag = found.as_str();
+    let end = if tag.ends_with("/>") { 2 } else { 1 };
+    let mut tag = tag[..tag.len() - end].to_string();
+    for (name, value) in attributes.iter() {
+        let existing = Regex::new(&format!(
+            r#"(?i)\s{}\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+            regex::escape(name)
+        ))
+        .unwrap();
+        let mut value = value.to_string();
+        if let Some(c) = existing.captures(&tag) {
+            if name == "class" {
+                let classes = c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3));
+                value = format!("{} {value}", classes.map_or("", |classes| classes.as_str()));
+                value = value.trim().to_string();
+            }
+            tag = existing.replace(&tag, NoExpand("")).into_owned();
+        }
+        tag.push_str(&format!(r#" {name}="{value}""#));
+    }
+    tag.push_str(&found.as_str()[found.len() - end..]);
+    format!("{}{tag}{}", &svg[..found.start()], &svg[found.end()..])
+}
+
+/// `inline_svg(path="/icons/logo.svg", class="logo")` inlines an SVG
+/// static file of the site, optimized, so stylesheets can style its
+/// shapes. Its other arguments are set as attributes of the `<svg>`, with
+/// `_` for `-`: `aria_label="Home"` gives `aria-label="Home"`.
+pub struct InlineSvg {
+    /// The source of each static file by its url
+    sources: HashMap<String, PathBuf>,
+    /// The SVGs read so far, optimized
+    svgs: Mutex<HashMap<String, String>>,
+}
+
+impl InlineSvg {
+    pub fn new(files: Vec<(PathBuf, PathBuf)>) -> Self {
+        Self {
+            sources: integrity::by_url(files),
+            svgs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn svg(&self, url: &str) -> tera::Result<String> {
+        let mut svgs = self.svgs.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(svg) = svgs.get(url) {
+            return Ok(svg.to_string());
+        }
+        let from = self
+            .sources
+            .get(url)
+            .filter(|from| is_svg(from))
+            .ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "`inline_svg`: {url} isn't an SVG static file of the site"
+                ))
+            })?;
+        let svg = fs::read_to_string(from)
+            .map(|svg| optimize(&svg))
+            .map_err(|err| {
+                tera::Error::msg(format!("Couldn't read {}: {err}", from.to_string_lossy()))
+            })?;
+        svgs.insert(url.to_string(), svg.to_string());
+        Ok(svg)
+    }
+}
+
+impl Function for InlineSvg {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("`inline_svg` requires a `path` argument"))?;
+        let url = path.split(['?', '#']).next().unwrap_or_default();
+        let svg = self.svg(&format!("/{}", url.trim_start_matches('/')))?;
+
+        let mut attributes = Vec::new();
+        for (name, value) in args.iter().filter(|(name, _)| *name != "path") {
+            attributes.push((name.replace('_', "-"), attribute_value(name, value)?));
+        }
+        // the same call renders the same markup every time
+        attributes.sort();
+        Ok(Value::String(set_attributes(&svg, &attributes)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+use tera::Tera;
+
+use crate::{
+    diagnostics::{Diagnostic, Failure},
+    escaping::Escaping,
+    functions,
+    replacements::Stage,
+    slugify, Content, Site,
+};
+
+/// One term of a taxonomy, like a single tag, with the pages that have it.
+#[derive(Serialize)]
+pub struct Term<'a> {
+    pub name: String,
+    pub slug: String,
+    /// Where the term's listing page is served from
+    pub url: String,
+    pub pages: Vec<&'a Content>,
+}
+
+impl<'a> Term<'a> {
+    // term names are written in frontmatter, so they're escaped with it
+    fn escaped(&self, escaping: &Escaping, layout: &str) -> Term<'a> {
+        Term {
+            name: escaping.text(layout, &self.name),
+            slug: self.slug.clone(),
+            url: self.url.clone(),
+            pages: self.pages.clone(),
+        }
+    }
+}
+
+/// Each taxonomy's terms, by name.
+pub type Taxonomies<'a> = BTreeMap<String, Vec<Term<'a>>>;
+
+/// Collects the terms of each taxonomy in `names` (a frontmatter field each,
+/// like `tags`) from every page. Terms are matched by their slug, so `Rust`
+/// and `rust` are the same term, and sorted by name.
+pub fn collect<'a>(contents: &'a [Content], names: &[String]) -> Taxonomies<'a> {
+    let mut taxonomies = Taxonomies::new();
+
+    for name in names.iter() {
+        let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+        for content in contents.iter() {
+            for term in content.frontmatter.list(name).unwrap_or_default() {
+                let slug = slugify(&term);
+                if slug.is_empty() {
+                    continue;
+                }
+                let entry = terms.entry(slug.to_string()).or_insert_with(|| Term {
+                    name: term.to_string(),
+                    url: format!("/{name}/{slug}"),
+                    slug,
+                    pages: Vec::new(),
+                });
+                if !entry.pages.iter().any(|page| page.path == content.path) {
+                    entry.pages.push(content);
+                }
+            }
+        }
+
+        let mut terms: Vec<Term> = terms.into_values().collect();
+        terms.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        taxonomies.insert(name.to_string(), terms);
+    }
+
+    taxonomies
+}
+
+/// Renders the listing page of `term` with `layout`, given the name of its
+/// taxonomy and all of its terms. It's written to `<taxonomy>/<slug>/`.
+pub fn render(
+    templates: &Tera,
+    layout: &str,
+    taxonomy: &str,
+    terms: &[Term],
+    term: &Term,
+    site: &Site,
+) -> tera::Result<String> {
+    let terms: Vec<Term> = terms
+        .iter()
+        .map(|term| term.escaped(&site.escaping, layout))
+        .collect();
+    let mut context = site.context.clone();
+    context.insert("taxonomy", taxonomy);
+    context.insert("term", &term.escaped(&site.escaping, layout));
+    context.insert("terms", &terms);
+    let context = site.escaping.context(layout, context)?;
+
+    functions::start_page();
+    let html = templates.render(layout, &context)?;
+    let path = format!("{taxonomy}/{}", term.slug);
+    Ok(site.replacements.apply(Stage::Html, &path, html))
+}
+
+/// Writes a listing page for every term to `<taxonomy>/<term>/index.html`,
+/// rendered with `layout` and given the `taxonomy`'s name, the `term` and
+/// all of the taxonomy's `terms`. Sites without the layout get no listing
+/// pages. Returns how many failed to render.
+pub fn write(
+    output: &str,
+    templates: &Tera,
+    layout: &str,
+    taxonomies: &Taxonomies,
+    site: &Site,
+) -> io::Result<usize> {
+    if !templates.get_template_names().any(|name| name == layout) {
+        return Ok(0);
+    }
+
+    let mut failed = 0;
+    for (taxonomy, terms) in taxonomies.iter() {
+        for term in terms.iter() {
+            let path = format!("{taxonomy}/{}", term.slug);
+            match render(templates, layout, taxonomy, terms, term, site) {
+                Ok(html) => {
+                    let dir = Path::new(output).join(&path);
+                    fs::create_dir_all(&dir)?;
+                    let html = site.finish(html);
+                    fs::File::create(dir.join("index.html"))?.write_all(html.as_bytes())?;
+                }
+                Err(err) => {
+                    Diagnostic::error(format!("Couldn't render {layout} for {path}"))
+                        .failure(Failure::Template)
+                        .causes(&err)
+                        .print();
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    Ok(failed)
+}
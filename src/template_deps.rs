@@ -0,0 +1,158 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    sync::OnceLock,
+};
+
+use glob::glob;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::shortcodes;
+
+fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts.iter() {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// the templates named in `{% extends %}`, `{% include %}` and `{% import %}`
+// tags, in `cache(template=...)` calls and called as shortcodes
+fn references(source: &str) -> BTreeSet<String> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static CACHED: OnceLock<Regex> = OnceLock::new();
+    static STRING: OnceLock<Regex> = OnceLock::new();
+    let tag =
+        TAG.get_or_init(|| Regex::new(r"\{%-?\s*(?:extends|include|import)\s+(.*?)-?%\}").unwrap());
+    let cached = CACHED
+        .get_or_init(|| Regex::new(r#"\bcache\s*\([^)]*\btemplate\s*=\s*"([^"]*)""#).unwrap());
+    let string = STRING.get_or_init(|| Regex::new(r#""([^"]*)"|'([^']*)'|`([^`]*)`"#).unwrap());
+
+    let mut names = BTreeSet::new();
+    for args in tag.captures_iter(source).filter_map(|c| c.get(1)) {
+        // `import "macros.html" as m` names one, `include [...]` can name several
+        let args = args.as_str().split(" as ").next().unwrap_or_default();
+        names.extend(
+            string
+                .captures_iter(args)
+                .filter_map(|c| c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3)))
+                .map(|name| name.as_str().to_string()),
+        );
+    }
+    names.extend(cached.captures_iter(source).map(|c| c[1].to_string()));
+    names.extend(shortcodes::templates(source));
+    names
+}
+
+// every name in the template's tags, which includes each variable of the
+// context it reads, along with the names of filters, attributes and such
+fn names(source: &str) -> BTreeSet<String> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static NAME: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap());
+    let name = NAME.get_or_init(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap());
+    tag.find_iter(source)
+        .flat_map(|tag| name.find_iter(tag.as_str()))
+        .map(|name| name.as_str().to_string())
+        .collect()
+}
+
+/// Which templates each layout extends, includes or imports macros from, so
+/// a page is only rendered again when a template its layout uses changes.
+pub struct TemplateGraph {
+    /// The hash of each template's source, by name
+    hashes: BTreeMap<String, String>,
+    /// The templates each template uses directly
+    uses: BTreeMap<String, BTreeSet<String>>,
+    /// The names in each template's tags, see `names`
+    names: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl TemplateGraph {
+    /// The templates in `dirs`, named the way Tera names them. One in an
+    /// earlier folder hides any of the same name in later ones, the way a
+    /// site's layouts hide its theme's.
+    pub fn load(dirs: &[&str]) -> io::Result<Self> {
+        let mut hashes = BTreeMap::new();
+        let mut uses = BTreeMap::new();
+        let mut all_names = BTreeMap::new();
+
+        for dir in dirs.iter() {
+            let pattern = format!("{}/**/*", glob::Pattern::escape(dir));
+            for path in glob(&pattern).into_iter().flatten().flatten() {
+                let Ok(relative) = path.strip_prefix(dir) else {
+                    continue;
+                };
+                let name = relative.to_string_lossy().replace('\\', "/");
+                if !path.is_file() || hashes.contains_key(&name) {
+                    continue;
+                }
+
+                let source = String::from_utf8_lossy(&fs::read(&path)?).to_string();
+                uses.insert(name.to_string(), references(&source));
+                all_names.insert(name.to_string(), names(&source));
+                hashes.insert(name, hash(&[&source]));
+            }
+        }
+
+        Ok(Self {
+            hashes,
+            uses,
+            names: all_names,
+        })
+    }
+
+    /// `layout` and every template it uses, following the ones they use.
+    fn closure<'a>(&'a self, layout: &'a str) -> BTreeSet<&'a str> {
+        let mut seen = BTreeSet::new();
+        let mut next = vec![layout];
+        while let Some(name) = next.pop() {
+            if seen.insert(name) {
+                next.extend(
+                    self.uses
+                        .get(name)
+                        .into_iter()
+                        .flatten()
+                        .map(|n| n.as_str()),
+                );
+            }
+        }
+        seen
+    }
+
+    /// The hash of `layout` and every template it uses. Templates that don't
+    /// exist count by their name, so adding one changes it too.
+    pub fn key(&self, layout: &str) -> String {
+        let mut parts = Vec::new();
+        for name in self.closure(layout) {
+            parts.push(name);
+            parts.push(self.hashes.get(name).map(|h| h.as_str()).unwrap_or(""));
+        }
+        hash(&parts)
+    }
+
+    /// Whether `layout`, or a template it uses, has `name` in its tags, as it
+    /// does a variable of the context it reads.
+    pub fn mentions(&self, layout: &str, name: &str) -> bool {
+        self.closure(layout).into_iter().any(|template| {
+            self.names
+                .get(template)
+                .is_some_and(|names| names.contains(name))
+        })
+    }
+
+    /// The templates `source`, like a page's markdown, uses directly, each
+    /// with its `key`.
+    pub fn used_by(&self, source: &str) -> BTreeMap<String, String> {
+        references(source)
+            .into_iter()
+            .map(|name| {
+                let key = self.key(&name);
+                (name, key)
+            })
+            .collect()
+    }
+}
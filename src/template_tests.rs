@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use tera::{Context, Tera};
+
+use crate::{
+    build_info::BuildInfo, data_files, diagnostics, glossary, i18n, images, page_context,
+    short_urls, site_context, theme_package::ThemePackage, versions::DocsVersion, Content,
+    Frontmatter, Options, SiteParts,
+};
+
+fn fixture_content(default_language: &str, rtl_languages: &[String]) -> Vec<Content> {
+    let mut frontmatter = HashMap::new();
+    frontmatter.insert("title".to_string(), "Lorem ipsum".to_string());
+
+    let lang = default_language.to_string();
+    let lang_dir = i18n::text_direction(&lang, rtl_languages).to_string();
+
+    vec![Content {
+        path: "fixture.md".to_string(),
+        slug: "/fixture".to_string(),
+        permalink: None,
+        lang,
+        lang_dir,
+        frontmatter: Frontmatter::from_text(frontmatter),
+        content: "<p>Lorem ipsum dolor sit amet.</p>".to_string(),
+        resources: Vec::new(),
+        images: Vec::new(),
+        text: String::new(),
+        numbering: Default::default(),
+        headings: Vec::new(),
+        docs_prev: None,
+        docs_next: None,
+        translations: Vec::new(),
+        feeds: Vec::new(),
+        extra: HashMap::new(),
+    }]
+}
+
+// fixtures are `<template name>.json` files, e.g. `fixtures/post.html.json`,
+// whose top level keys override the synthetic context
+fn load_fixture(fixtures: &str, template: &str) -> Result<Option<Context>, String> {
+    let path = Path::new(fixtures).join(format!("{template}.json"));
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let text = fs::read_to_string(&path).map_err(|err| format!("{path:?}: {err}"))?;
+    let value: tera::Value =
+        serde_json::from_str(&text).map_err(|err| format!("{path:?}: {err}"))?;
+    Context::from_value(value)
+        .map(Some)
+        .map_err(|err| format!("{path:?}: {err:?}"))
+}
+
+/// Renders every loaded layout against a synthetic page (or its fixture) and
+/// reports the ones that fail. Returns whether all of them rendered.
+pub fn run(
+    templates: &Tera,
+    fixtures: &str,
+    opts: &Options,
+    theme: Option<&ThemePackage>,
+    build: &BuildInfo,
+) -> bool {
+    let mut contents = fixture_content(&opts.default_language, &opts.rtl_languages);
+    let short_urls = short_urls::assign(&mut contents, &opts.short_url_prefix);
+    let data = data_files::load(&opts.data).unwrap_or_default();
+    let glossary = glossary::load(&opts.data).ok().flatten();
+    // the versions aren't checked out, but layouts can still list them
+    let docs_versions: Vec<DocsVersion> = opts
+        .docs_version
+        .iter()
+        .map(|(name, rev)| DocsVersion::new(name, rev))
+        .collect();
+    let mut templates = templates.clone();
+    let parts = SiteParts {
+        content: &contents,
+        data: &data,
+        glossary: glossary.as_ref(),
+        docs_versions: &docs_versions,
+        short_urls: &short_urls,
+    };
+    let base_context = site_context(opts, &mut templates, parts, theme, build);
+    if let Ok(images) = opts.images(theme) {
+        templates.register_function("image", images::Image::new(Arc::new(images)));
+    }
+
+    let mut names: Vec<&str> = templates.get_template_names().collect();
+    names.sort();
+
+    let mut failures = 0;
+    for name in names.iter() {
+        let context = page_context(&contents[0], &base_context).map_err(|err| format!("{err:?}"));
+        let context = context.and_then(|mut context| {
+            if let Some(fixture) = load_fixture(fixtures, name)? {
+                context.extend(fixture);
+            }
+            Ok(context)
+        });
+
+        i18n::set_current_language(&contents[0].lang);
+        let result = context.and_then(|context| {
+            templates
+                .render(name, &context)
+                .map_err(|err| format!("{err:?}"))
+        });
+
+        match result {
+            Ok(_) => diagnostics::detail(format!("ok      {name}")),
+            Err(err) => {
+                failures += 1;
+                diagnostics::info(format!("FAILED  {name}: {err}"));
+            }
+        }
+    }
+
+    diagnostics::info(format!(
+        "{} templates rendered, {} failed",
+        names.len() - failures,
+        failures
+    ));
+
+    failures == 0
+}
@@ -0,0 +1,166 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use toml_edit::{value, Document, Item};
+
+fn git(dir: Option<&Path>, args: &[&str]) -> io::Result<String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+
+    let output = command.args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Guesses a theme name from its repository url, e.g.
+/// `https://github.com/someone/fox-theme.git` gives `fox-theme`.
+pub fn name_from_url(url: &str) -> Option<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+// clones the theme if needed, then checks out `rev` (or the remote's default
+// branch) and returns the commit it ended up on. A theme already in `dir`
+// has to have been cloned from `url`.
+fn checkout(dir: &Path, url: &str, rev: Option<&str>) -> io::Result<String> {
+    if let Some(rev) = rev.filter(|rev| rev.starts_with('-')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{rev} isn't a git revision"),
+        ));
+    }
+
+    if dir.exists() {
+        let origin = git(Some(dir), &["remote", "get-url", "origin"])?;
+        if origin != url {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} is a clone of {origin}, not {url}; remove it or pick another name with --name",
+                    dir.to_string_lossy()
+                ),
+            ));
+        }
+        git(Some(dir), &["fetch", "--tags", "origin"])?;
+    } else {
+        let dir = dir.to_string_lossy();
+        git(None, &["clone", "--", url, &*dir])?;
+    }
+
+    git(
+        Some(dir),
+        &["checkout", "--detach", rev.unwrap_or("origin/HEAD")],
+    )?;
+    git(Some(dir), &["rev-parse", "HEAD"])
+}
+
+fn read_config(config: &str) -> io::Result<Document> {
+    let text = match fs::read_to_string(config) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    text.parse::<Document>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{config}: {err}")))
+}
+
+// records the theme under `[themes.<name>]`, leaving the rest of the file as is
+fn pin(config: &str, name: &str, url: &str, commit: &str) -> io::Result<()> {
+    let mut doc = read_config(config)?;
+    doc["themes"][name]["git"] = value(url);
+    doc["themes"][name]["commit"] = value(commit);
+    if let Some(themes) = doc["themes"].as_table_mut() {
+        themes.set_implicit(true);
+    }
+
+    fs::write(config, doc.to_string())
+}
+
+/// Clones (or updates) a theme from git into `themes_dir` and pins the
+/// checked out commit in the site config.
+pub fn install(
+    themes_dir: &str,
+    config: &str,
+    url: &str,
+    name: Option<&str>,
+    rev: Option<&str>,
+) -> io::Result<(String, String)> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => name_from_url(url).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Couldn't work out a theme name from {url}, pass one with --name"),
+            )
+        })?,
+    };
+
+    let dir = PathBuf::from(themes_dir).join(&name);
+    let commit = checkout(&dir, url, rev)?;
+    pin(config, &name, url, &commit)?;
+
+    Ok((name, commit))
+}
+
+/// Moves pinned themes (or just `only`) to the latest commit of their
+/// remote's default branch, cloning any that are missing. Returns the
+/// updated names and commits.
+pub fn update(
+    themes_dir: &str,
+    config: &str,
+    only: Option<&str>,
+) -> io::Result<Vec<(String, String)>> {
+    let doc = read_config(config)?;
+    let mut pinned = Vec::new();
+
+    if let Some(themes) = doc.get("themes").and_then(Item::as_table) {
+        for (name, theme) in themes.iter() {
+            if only.is_none_or(|only| only == name) {
+                if let Some(url) = theme.get("git").and_then(Item::as_str) {
+                    pinned.push((name.to_string(), url.to_string()));
+                }
+            }
+        }
+    }
+
+    if let Some(only) = only {
+        if pinned.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No theme named {only} in {config}, install it with `roxy theme install <git-url>`"),
+            ));
+        }
+    }
+
+    let mut updated = Vec::new();
+    for (name, url) in pinned {
+        let dir = PathBuf::from(themes_dir).join(&name);
+        let commit = checkout(&dir, &url, None)?;
+        pin(config, &name, &url, &commit)?;
+        updated.push((name, commit));
+    }
+
+    Ok(updated)
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+/// The contents of a theme's `theme.toml`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThemeMetadata {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// The oldest roxy release the theme works with
+    #[serde(default)]
+    pub min_roxy_version: Option<String>,
+    /// Site settings the theme's layouts expect to exist
+    #[serde(default)]
+    pub required_config: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ThemePackage {
+    pub root: PathBuf,
+    pub metadata: ThemeMetadata,
+}
+
+impl ThemePackage {
+    pub fn layouts(&self) -> PathBuf {
+        self.root.join("layouts")
+    }
+
+    pub fn static_files(&self) -> PathBuf {
+        self.root.join("static")
+    }
+}
+
+fn read_metadata(root: &Path, name: &str) -> Result<ThemeMetadata, String> {
+    let path = root.join("theme.toml");
+    let text = fs::read_to_string(&path).map_err(|err| {
+        format!(
+            "Couldn't read {} for theme `{name}` ({err}). Check the theme is installed in {}, or create one with `roxy new theme {name}`.",
+            path.to_string_lossy(),
+            root.parent().unwrap_or(root).to_string_lossy()
+        )
+    })?;
+
+    toml::from_str(&text).map_err(|err| format!("Invalid {}: {err}", path.to_string_lossy()))
+}
+
+/// Checks that the theme can run on this roxy and that the site provides
+/// every setting the theme requires, returning one message per problem.
+pub fn validate(metadata: &ThemeMetadata, settings: &HashMap<String, String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let name = &metadata.name;
+
+    if Version::parse(&metadata.version).is_err() {
+        problems.push(format!(
+            "Theme `{name}` has version `{}`, which isn't a semantic version like `1.2.0`.",
+            metadata.version
+        ));
+    }
+
+    if let Some(min) = &metadata.min_roxy_version {
+        let current = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+        match VersionReq::parse(&format!(">={min}")) {
+            Ok(req) if !req.matches(&current) => problems.push(format!(
+                "Theme `{name}` needs roxy {min} or newer, but this is roxy {current}. Update roxy or use an older release of the theme."
+            )),
+            Ok(_) => {}
+            Err(_) => problems.push(format!(
+                "Theme `{name}` has min_roxy_version `{min}`, which isn't a version like `0.1.0`."
+            )),
+        }
+    }
+
+    for key in metadata.required_config.iter() {
+        if !settings.contains_key(key) {
+            problems.push(format!(
+                "Theme `{name}` expects the site setting `{key}`, but it isn't set. Add it with `--site {key}=<value>`."
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Loads the theme `name` from `themes_dir` and validates it against the
+/// site settings.
+pub fn load(
+    themes_dir: &str,
+    name: &str,
+    settings: &HashMap<String, String>,
+) -> Result<ThemePackage, Vec<String>> {
+    let root = Path::new(themes_dir).join(name);
+    let metadata = read_metadata(&root, name).map_err(|err| vec![err])?;
+
+    let problems = validate(&metadata, settings);
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    Ok(ThemePackage { root, metadata })
+}
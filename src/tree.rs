@@ -0,0 +1,167 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{is_section_index, Content};
+
+/// A page, or a section and everything below it, ordered by weight.
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub title: String,
+    /// Empty for sections without an index page
+    pub slug: String,
+    pub path: String,
+    pub weight: Option<i64>,
+    pub children: Vec<TreeNode>,
+}
+
+fn weight(content: &Content) -> Option<i64> {
+    content
+        .frontmatter
+        .0
+        .get("weight")
+        .and_then(|w| w.trim().parse().ok())
+}
+
+fn title(content: &Content) -> String {
+    content
+        .frontmatter
+        .0
+        .get("title")
+        .cloned()
+        .unwrap_or_else(|| {
+            Path::new(&content.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+}
+
+fn leaf(content: &Content) -> TreeNode {
+    TreeNode {
+        title: title(content),
+        slug: content.slug.to_string(),
+        path: content.path.to_string(),
+        weight: weight(content),
+        children: Vec::new(),
+    }
+}
+
+fn parent(content: &Content) -> &Path {
+    Path::new(&content.path).parent().unwrap_or(Path::new(""))
+}
+
+// weighted entries first, lightest first, then the rest by title
+fn sort(children: &mut [TreeNode]) {
+    children.sort_by(|a, b| match (a.weight, b.weight) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.title.cmp(&b.title)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.title.cmp(&b.title),
+    });
+}
+
+/// Builds the tree for the section at `dir` (relative to the content
+/// folder, `""` for the whole site) from the folder hierarchy.
+pub fn build(contents: &[Content], dir: &Path) -> TreeNode {
+    let index = contents
+        .iter()
+        .find(|c| is_section_index(&c.path) && parent(c) == dir);
+
+    let mut node = match index {
+        Some(index) => leaf(index),
+        None => TreeNode {
+            title: dir
+                .file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            slug: String::new(),
+            path: dir.to_string_lossy().to_string(),
+            weight: None,
+            children: Vec::new(),
+        },
+    };
+
+    let mut subsections = BTreeSet::new();
+    for content in contents.iter() {
+        let parent = parent(content);
+        if parent == dir {
+            if !is_section_index(&content.path) {
+                node.children.push(leaf(content));
+            }
+        } else if let Ok(rest) = parent.strip_prefix(dir) {
+            if let Some(first) = rest.components().next() {
+                subsections.insert(dir.join(first));
+            }
+        }
+    }
+
+    for subsection in subsections.iter() {
+        node.children.push(build(contents, subsection));
+    }
+
+    sort(&mut node.children);
+    node
+}
+
+/// Builds the tree for a section given as a path like `docs`.
+pub fn for_section(contents: &[Content], section: &str) -> TreeNode {
+    build(contents, &PathBuf::from(section.trim_matches('/')))
+}
+
+/// Just enough of a page to link to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageLink {
+    pub title: String,
+    pub slug: String,
+}
+
+fn reading_order<'a>(node: &'a TreeNode, order: &mut Vec<&'a TreeNode>) {
+    if !node.slug.is_empty() {
+        order.push(node);
+    }
+
+    for child in node.children.iter() {
+        reading_order(child, order);
+    }
+}
+
+/// Sets `docs_prev` and `docs_next` on every page in a section, following a
+/// depth first walk of the section tree so reading continues across
+/// subsection boundaries.
+pub fn link_reading_order(contents: &mut [Content]) {
+    let sections: BTreeSet<PathBuf> = contents
+        .iter()
+        .filter_map(|c| Path::new(&c.path).components().next())
+        .map(|first| PathBuf::from(first.as_os_str()))
+        .filter(|first| contents.iter().any(|c| parent(c).starts_with(first)))
+        .collect();
+
+    let mut links: HashMap<String, (Option<PageLink>, Option<PageLink>)> = HashMap::new();
+    for section in sections.iter() {
+        let tree = build(contents, section);
+        let mut order = Vec::new();
+        reading_order(&tree, &mut order);
+
+        let link = |node: &TreeNode| PageLink {
+            title: node.title.to_string(),
+            slug: node.slug.to_string(),
+        };
+
+        for (i, node) in order.iter().enumerate() {
+            let prev = i.checked_sub(1).map(|i| link(order[i]));
+            let next = order.get(i + 1).map(|&node| link(node));
+            links.insert(node.path.to_string(), (prev, next));
+        }
+    }
+
+    for content in contents.iter_mut() {
+        if let Some((prev, next)) = links.remove(&content.path) {
+            content.docs_prev = prev;
+            content.docs_next = next;
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::ops::Range;
+
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use regex::Regex;
+
+// a character that isn't space or opening punctuation, for code and html
+// between text, so a quote after them closes
+const WORD: char = 'a';
+
+/// Parses `markdown` with `options`. Smart punctuation is applied here
+/// rather than by the parser, so it can leave math alone as well as code.
+pub fn parse(markdown: &str, options: Options) -> Vec<Event<'_>> {
+    let mut parser_options = options;
+    parser_options.remove(Options::ENABLE_SMART_PUNCTUATION);
+    let events = Parser::new_ext(markdown, parser_options);
+    if options.contains(Options::ENABLE_SMART_PUNCTUATION) {
+        smarten(events)
+    } else {
+        events.collect()
+    }
+}
+
+/// `events` with curly quotes, en and em dashes and ellipses in their text,
+/// except in code blocks, inline code, html `<code>`, `<pre>` and the like,
+/// and `$math$` or `$$math$$`.
+pub fn smarten<'a, I: Iterator<Item = Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut state = State::default();
+    for event in events {
+        state.push(event);
+    }
+    state.flush();
+    state.out
+}
+
+#[derive(Default)]
+struct State<'a> {
+    out: Vec<Event<'a>>,
+    /// Text since the last event that isn't text or a soft break, which
+    /// are `\n` in it, since math can span them
+    run: String,
+    /// The character before `run`, which decides which way quotes face
+    last: Option<char>,
+    code_block: bool,
+    /// How many html elements whose text is left alone are open
+    raw_html: usize,
+}
+
+impl<'a> State<'a> {
+    fn push(&mut self, event: Event<'a>) {
+        match event {
+            Event::Text(text) if !self.code_block && self.raw_html == 0 => {
+                self.run.push_str(&text);
+            }
+            Event::SoftBreak if !self.code_block && self.raw_html == 0 => self.run.push('\n'),
+            event => {
+                self.flush();
+                match &event {
+                    Event::Start(Tag::CodeBlock(_)) => self.code_block = true,
+                    Event::End(Tag::CodeBlock(_)) => self.code_block = false,
+                    Event::Html(html) => {
+                        self.raw_html = raw_depth(self.raw_html, html);
+                        self.last = Some(WORD);
+                    }
+                    Event::Start(tag) | Event::End(tag) if inline(tag) => {}
+                    // quotes never carry over from one block to the next
+                    Event::Start(_) | Event::End(_) => self.last = None,
+                    Event::HardBreak | Event::SoftBreak => self.last = Some('\n'),
+                    _ => self.last = Some(WORD),
+                }
+                self.out.push(event);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.run.is_empty() {
+            return;
+        }
+        let run = std::mem::take(&mut self.run);
+        let text = smarten_text(&run, &math_ranges(&run), self.last);
+        self.last = run.chars().last();
+
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.out.push(Event::SoftBreak);
+            }
+            if !line.is_empty() {
+                self.out.push(Event::Text(CowStr::from(line.to_string())));
+            }
+        }
+    }
+}
+
+fn inline(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+// how many elements whose text is left alone are open after `html`
+fn raw_depth(depth: usize, html: &str) -> usize {
+    let pattern = Regex::new(r"(?i)<(/?)(code|pre|kbd|samp|script|style|math)\b[^>]*>").unwrap();
+    pattern.captures_iter(html).fold(depth, |depth, c| {
+        if c.get(1).is_some_and(|slash| !slash.is_empty()) {
+            depth.saturating_sub(1)
+        } else {
+            depth + 1
+        }
+    })
+}
+
+// where `$math$` and `$$math$$` are in `text`. Like pandoc, inline math
+// doesn't start before a space or end after one or before a digit, so
+// `$5 and $10` are prices. Dollars that aren't closed are text.
+fn math_ranges(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+        if bytes.get(i + 1) == Some(&b'$') {
+            if let Some(end) = text[i + 2..].find("$$") {
+                let end = i + 2 + end + 2;
+                ranges.push(i..end);
+                i = end;
+                continue;
+            }
+            i += 2;
+            continue;
+        }
+
+        let opens = bytes
+            .get(i + 1)
+            .is_some_and(|next| !next.is_ascii_whitespace() && *next != b'$');
+        let close = (i + 2..bytes.len()).find(|&j| {
+            bytes[j] == b'$'
+                && !bytes[j - 1].is_ascii_whitespace()
+                && !bytes.get(j + 1).is_some_and(|next| next.is_ascii_digit())
+        });
+        match close.filter(|_| opens) {
+            Some(close) => {
+                ranges.push(i..close + 1);
+                i = close + 1;
+            }
+            None => i += 1,
+        }
+    }
+    ranges
+}
+
+fn opens(before: Option<char>) -> bool {
+    match before {
+        None => true,
+        Some(c) => c.is_whitespace() || "([{-\u{2013}\u{2014}\u{2018}\u{201C}".contains(c),
+    }
+}
+
+// `--` to `–` and `---` to `—`, and runs of more like smartypants
+fn dashes(count: usize) -> String {
+    let (em, en) = if count % 3 == 0 {
+        (count / 3, 0)
+    } else if count % 2 == 0 {
+        (0, count / 2)
+    } else if count % 3 == 2 {
+        (count / 3, 1)
+    } else {
+        (count / 3 - 1, 2)
+    };
+    "\u{2014}".repeat(em) + &"\u{2013}".repeat(en)
+}
+
+fn smarten_text(text: &str, math: &[Range<usize>], mut last: Option<char>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if math.iter().any(|range| range.contains(&i)) {
+            out.push(c);
+            last = Some(c);
+            continue;
+        }
+        match c {
+            '\'' => out.push(if opens(last) { '\u{2018}' } else { '\u{2019}' }),
+            '"' => out.push(if opens(last) { '\u{201C}' } else { '\u{201D}' }),
+            '.' if text[i..].starts_with("...") => {
+                chars.next();
+                chars.next();
+                out.push('\u{2026}');
+            }
+            '-' if text[i..].starts_with("--") => {
+                let mut count = 1;
+                while chars.next_if(|(_, c)| *c == '-').is_some() {
+                    count += 1;
+                }
+                out.push_str(&dashes(count));
+            }
+            c => out.push(c),
+        }
+        last = Some(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(markdown: &str) -> String {
+        let mut html = String::new();
+        let events = parse(markdown, Options::ENABLE_SMART_PUNCTUATION);
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        html
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            html("\"Hello\" -- it's 'fine'... --- really"),
+            "<p>\u{201C}Hello\u{201D} \u{2013} it\u{2019}s \u{2018}fine\u{2019}\u{2026} \u{2014} really</p>\n"
+        );
+    }
+
+    #[test]
+    fn without_smart_punctuation() {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(
+            &mut html,
+            parse("it's -- \"x\"", Options::empty()).into_iter(),
+        );
+        assert_eq!(html, "<p>it's -- &quot;x&quot;</p>\n");
+    }
+
+    #[test]
+    fn inline_code() {
+        assert_eq!(
+            html("\"see `it's -- \"x\"` here\""),
+            "<p>\u{201C}see <code>it's -- &quot;x&quot;</code> here\u{201D}</p>\n"
+        );
+        // a quote right after code closes, like after a word
+        assert_eq!(html("`x`'s"), "<p><code>x</code>\u{2019}s</p>\n");
+    }
+
+    #[test]
+    fn code_block_in_a_list_in_a_quote() {
+        let html = html("> - it's\n>\n>   ```\n>   it's -- \"x\"\n>   ```\n>\n> it's\n");
+        assert!(html.contains("<li>\n<p>it\u{2019}s</p>"), "{html}");
+        assert!(
+            html.contains("<code>it's -- &quot;x&quot;\n</code>"),
+            "{html}"
+        );
+        assert!(html.contains("<p>it\u{2019}s</p>\n</blockquote>"), "{html}");
+    }
+
+    #[test]
+    fn inline_math() {
+        assert_eq!(
+            html("$f'(x) -- g''$ isn't"),
+            "<p>$f'(x) -- g''$ isn\u{2019}t</p>\n"
+        );
+    }
+
+    #[test]
+    fn display_math_over_lines() {
+        assert_eq!(
+            html("$$\na'' -- b...\n$$\n\nit's"),
+            "<p>$$\na'' -- b...\n$$</p>\n<p>it\u{2019}s</p>\n"
+        );
+    }
+
+    #[test]
+    fn math_in_emphasis_in_quotes() {
+        assert_eq!(
+            html("\"*see $x'$* -- or **$y''$**\""),
+            "<p>\u{201C}<em>see $x'$</em> \u{2013} or <strong>$y''$</strong>\u{201D}</p>\n"
+        );
+    }
+
+    #[test]
+    fn prices_are_not_math() {
+        assert_eq!(
+            html("$5 and $10 -- \"cheap\""),
+            "<p>$5 and $10 \u{2013} \u{201C}cheap\u{201D}</p>\n"
+        );
+    }
+
+    #[test]
+    fn unclosed_math_is_text() {
+        assert_eq!(html("$x it's"), "<p>$x it\u{2019}s</p>\n");
+    }
+
+    #[test]
+    fn html_code() {
+        assert_eq!(
+            html("<code>it's <b>--</b></code> and it's"),
+            "<p><code>it's <b>--</b></code> and it\u{2019}s</p>\n"
+        );
+        assert_eq!(
+            html("<pre>\n\"x\" -- y\n</pre>\n\nit's"),
+            "<pre>\n\"x\" -- y\n</pre>\n<p>it\u{2019}s</p>\n"
+        );
+    }
+}
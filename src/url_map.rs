@@ -0,0 +1,39 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::Content;
+
+/// Every content file and the urls it ends up served at: the page itself
+/// and its short url, if it has one.
+pub fn compile(contents: &[Content]) -> BTreeMap<String, Vec<String>> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for content in contents.iter() {
+        let urls = map.entry(content.path.to_string()).or_default();
+        urls.push(content.slug.to_string());
+        if let Some(short_url) = content.frontmatter.0.get("short_url") {
+            urls.push(short_url.to_string());
+        }
+    }
+
+    map
+}
+
+/// Writes the map as JSON when `path` ends in `.json`, otherwise as lines
+/// of `<source> <url>...`.
+pub fn write(path: &str, map: &BTreeMap<String, Vec<String>>) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let text = if path.ends_with(".json") {
+        serde_json::to_string_pretty(map)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+    } else {
+        map.iter()
+            .map(|(source, urls)| format!("{source} {}", urls.join(" ")))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    fs::write(path, text + "\n")
+}
@@ -0,0 +1,131 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// One entry of the version switcher.
+#[derive(Debug, Serialize)]
+pub struct DocsVersion {
+    pub name: String,
+    /// The git tag, branch or commit it was built from
+    pub rev: String,
+    pub url: String,
+}
+
+impl DocsVersion {
+    pub fn new(name: &str, rev: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rev: rev.to_string(),
+            url: format!("/{}/", name.trim_matches('/')),
+        }
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output.stdout)
+}
+
+// the root of the repository `path` is in, and `path` from there, as git's
+// trees name it
+fn in_repository(path: &str) -> io::Result<(PathBuf, PathBuf)> {
+    let path = Path::new(path);
+    let absolute =
+        fs::canonicalize(path).or_else(|_| std::env::current_dir().map(|dir| dir.join(path)))?;
+    // the repository is the one `path` is in, wherever roxy is run from
+    let dir = absolute
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(&absolute);
+    let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+    let root = fs::canonicalize(String::from_utf8_lossy(&root).trim())?;
+    let relative = absolute
+        .strip_prefix(&root)
+        .map(Path::to_path_buf)
+        .map_err(|_| {
+            io::Error::other(format!(
+                "{} isn't in the git repository at {}",
+                path.to_string_lossy(),
+                root.to_string_lossy()
+            ))
+        })?;
+    Ok((root, relative))
+}
+
+/// Writes the files under `path` as they were at `rev` into `dest` and
+/// returns the folder holding them. `path` can be absolute or relative to
+/// the working directory, but has to be in its git repository.
+pub fn checkout(path: &str, rev: &str, dest: &Path) -> io::Result<PathBuf> {
+    // anything else would be read as an option of `git archive`
+    if rev.starts_with('-') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{rev} isn't a git revision"),
+        ));
+    }
+    let (root, relative) = in_repository(path)?;
+    let pathspec = match relative.as_os_str().is_empty() {
+        true => ".".to_string(),
+        false => relative.to_string_lossy().replace('\\', "/"),
+    };
+    fs::create_dir_all(dest)?;
+
+    // one archive of the whole folder, rather than a `git show` per file
+    let mut archive = Command::new("git")
+        .arg("-C")
+        .arg(&root)
+        .args(["archive", "--format=tar", rev, "--", &pathspec])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let tar = archive.stdout.take().map(|stdout| {
+        Command::new("tar")
+            .arg("-x")
+            .arg("-C")
+            .arg(dest)
+            .stdin(stdout)
+            .output()
+    });
+    let archived = archive.wait_with_output()?;
+    if !archived.status.success() {
+        return Err(io::Error::other(format!(
+            "git archive {rev} -- {pathspec} failed: {}",
+            String::from_utf8_lossy(&archived.stderr).trim()
+        )));
+    }
+    if let Some(tar) = tar {
+        let tar = tar?;
+        if !tar.status.success() {
+            return Err(io::Error::other(format!(
+                "tar -x -C {} failed: {}",
+                dest.to_string_lossy(),
+                String::from_utf8_lossy(&tar.stderr).trim()
+            )));
+        }
+    }
+
+    Ok(dest.join(relative))
+}
+
+/// Where the version `name` of the site built to `output` is checked out,
+/// apart from other builds running at the same time, in this process or
+/// another.
+pub fn temp_dir(output: &str, name: &str) -> PathBuf {
+    let site = format!("{:x}", Sha256::digest(output.as_bytes()));
+    std::env::temp_dir()
+        .join(format!("roxy-docs-{}-{}", std::process::id(), &site[..12]))
+        .join(name)
+}